@@ -0,0 +1,13 @@
+-- Payments through PayPal next to MTN MoMo, and refunds
+ALTER TYPE payment_status ADD VALUE IF NOT EXISTS 'refunded';
+
+ALTER TABLE payments RENAME COLUMN mtn_reference_id TO provider_reference_id;
+ALTER TABLE payments DROP CONSTRAINT payments_mtn_reference_id_key;
+ALTER TABLE payments
+    ADD CONSTRAINT payments_provider_provider_reference_id_key
+    UNIQUE (provider, provider_reference_id);
+
+-- PayPal payments have no phone number
+ALTER TABLE payments ALTER COLUMN phone_number DROP NOT NULL;
+
+ALTER TABLE payments ADD COLUMN refunded_amount DECIMAL(10,2) NOT NULL DEFAULT 0;
@@ -0,0 +1,65 @@
+-- Plans come from a catalog instead of being hard-coded
+CREATE TYPE billing_period AS ENUM ('none', 'monthly', 'yearly');
+
+CREATE TABLE plans (
+    id SERIAL PRIMARY KEY,
+    code VARCHAR(50) NOT NULL UNIQUE,
+    name VARCHAR(100) NOT NULL,
+    storage_limit_bytes BIGINT NOT NULL,
+    billing_period billing_period NOT NULL DEFAULT 'monthly',
+    features JSONB NOT NULL DEFAULT '{}',
+    is_active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE plan_prices (
+    id SERIAL PRIMARY KEY,
+    plan_id INTEGER NOT NULL REFERENCES plans(id) ON DELETE CASCADE,
+    currency VARCHAR(3) NOT NULL,
+    amount DECIMAL(10,2) NOT NULL,
+    UNIQUE (plan_id, currency)
+);
+
+CREATE TRIGGER update_plans_updated_at
+    BEFORE UPDATE ON plans
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
+
+INSERT INTO plans (code, name, storage_limit_bytes, billing_period, features) VALUES
+    ('none', 'Free', 104857600, 'none',
+        '{"sharing": false, "support": "community"}'),
+    ('basic', 'Basic', 1073741824, 'monthly',
+        '{"sharing": "limited", "support": "basic"}'),
+    ('premium', 'Premium', 5368709120, 'monthly',
+        '{"sharing": "enhanced", "support": "priority"}'),
+    ('enterprise', 'Enterprise', 10737418240, 'monthly',
+        '{"sharing": "full", "support": "dedicated", "advanced_security": true}');
+
+INSERT INTO plan_prices (plan_id, currency, amount)
+SELECT plans.id, prices.currency, prices.amount
+FROM plans
+JOIN (VALUES
+    ('basic', 'XAF', 100), ('premium', 'XAF', 500), ('enterprise', 'XAF', 1000),
+    ('basic', 'EUR', 100), ('premium', 'EUR', 500), ('enterprise', 'EUR', 1000)
+) AS prices(code, currency, amount) ON prices.code = plans.code;
+
+-- Subscriptions named their plan; unknown names fall back to the free plan
+ALTER TABLE subscriptions ADD COLUMN plan_id INTEGER REFERENCES plans(id);
+UPDATE subscriptions SET plan_id = COALESCE(
+    (SELECT id FROM plans WHERE plans.code = subscriptions.plan),
+    (SELECT id FROM plans WHERE code = 'none')
+);
+ALTER TABLE subscriptions ALTER COLUMN plan_id SET NOT NULL;
+ALTER TABLE subscriptions DROP COLUMN plan, DROP COLUMN storage_limit_bytes;
+
+-- Earlier payments did not record a plan: take the one with that price
+ALTER TABLE payments ADD COLUMN plan_id INTEGER REFERENCES plans(id);
+UPDATE payments SET plan_id = COALESCE(
+    (SELECT plan_id FROM plan_prices
+        WHERE plan_prices.currency = payments.currency
+            AND plan_prices.amount = payments.amount
+        ORDER BY plan_id LIMIT 1),
+    (SELECT id FROM plans WHERE code = 'none')
+);
+ALTER TABLE payments ALTER COLUMN plan_id SET NOT NULL;
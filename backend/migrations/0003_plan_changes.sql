@@ -0,0 +1,15 @@
+-- Audit of plan changes
+CREATE TYPE plan_change_source AS ENUM ('payment', 'admin');
+
+CREATE TABLE plan_changes (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    previous_plan_id INTEGER REFERENCES plans(id),
+    new_plan_id INTEGER NOT NULL REFERENCES plans(id),
+    changed_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    source plan_change_source NOT NULL,
+    payment_id INTEGER REFERENCES payments(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_plan_changes_user ON plan_changes(user_id);
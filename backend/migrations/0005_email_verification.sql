@@ -0,0 +1,4 @@
+-- New accounts verify their address before they can log in. Existing
+-- accounts could log in until now and are not locked out.
+ALTER TABLE users ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT FALSE;
+UPDATE users SET email_verified = TRUE;
@@ -0,0 +1,28 @@
+-- Email changes wait for confirmation from the new address
+ALTER TABLE users ADD COLUMN pending_email VARCHAR(255);
+
+-- Deleting an account deletes everything it owns
+ALTER TABLE subscriptions
+    DROP CONSTRAINT subscriptions_user_id_fkey,
+    ADD CONSTRAINT subscriptions_user_id_fkey
+        FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+ALTER TABLE pdfs
+    DROP CONSTRAINT pdfs_user_id_fkey,
+    ADD CONSTRAINT pdfs_user_id_fkey
+        FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+CREATE TYPE export_status AS ENUM ('pending', 'running', 'completed', 'failed');
+
+CREATE TABLE account_exports (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    status export_status NOT NULL DEFAULT 'pending',
+    storage_key VARCHAR(255),
+    file_size BIGINT,
+    error_message TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    completed_at TIMESTAMPTZ,
+    expires_at TIMESTAMPTZ
+);
+
+CREATE INDEX idx_account_exports_user ON account_exports(user_id);
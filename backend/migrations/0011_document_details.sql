@@ -0,0 +1,11 @@
+-- Editable document title, description, author and metadata
+ALTER TABLE documents
+    ADD COLUMN title VARCHAR(255),
+    ADD COLUMN description TEXT,
+    ADD COLUMN author VARCHAR(255),
+    ADD COLUMN metadata JSONB NOT NULL DEFAULT '{}';
+
+CREATE TRIGGER update_documents_updated_at
+    BEFORE UPDATE ON documents
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
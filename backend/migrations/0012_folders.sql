@@ -0,0 +1,20 @@
+-- Folders; existing documents stay at the top level
+CREATE TABLE folders (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    parent_id INTEGER REFERENCES folders(id),
+    name VARCHAR(255) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+ALTER TABLE documents ADD COLUMN folder_id INTEGER REFERENCES folders(id);
+
+CREATE INDEX idx_documents_folder ON documents(folder_id);
+CREATE INDEX idx_folders_user ON folders(user_id);
+CREATE UNIQUE INDEX idx_folders_sibling_name ON folders(user_id, COALESCE(parent_id, 0), name);
+
+CREATE TRIGGER update_folders_updated_at
+    BEFORE UPDATE ON folders
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
@@ -0,0 +1,15 @@
+-- Extracted text for full-text search. Existing documents start out
+-- pending and are extracted in the background when the server starts.
+CREATE TYPE text_status AS ENUM ('pending', 'completed', 'failed', 'unsupported');
+
+ALTER TABLE documents ADD COLUMN text_status text_status NOT NULL DEFAULT 'pending';
+
+CREATE TABLE document_pages (
+    document_id INTEGER NOT NULL REFERENCES documents(id) ON DELETE CASCADE,
+    page_number INTEGER NOT NULL,
+    content TEXT NOT NULL,
+    content_tsv TSVECTOR GENERATED ALWAYS AS (to_tsvector('english', content)) STORED,
+    PRIMARY KEY (document_id, page_number)
+);
+
+CREATE INDEX idx_document_pages_tsv ON document_pages USING GIN (content_tsv);
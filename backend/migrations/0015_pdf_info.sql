@@ -0,0 +1,4 @@
+-- PDF page count, version and document information, read on upload
+ALTER TABLE documents
+    ADD COLUMN page_count INTEGER,
+    ADD COLUMN pdf_info JSONB;
@@ -0,0 +1,7 @@
+-- Duplicate detection. Linked documents share a stored file, so s3_key is
+-- no longer unique. Hashes of existing documents are filled in by the
+-- server in the background.
+ALTER TABLE documents DROP CONSTRAINT documents_s3_key_key;
+ALTER TABLE documents ADD COLUMN content_hash VARCHAR(64);
+
+CREATE INDEX idx_documents_content_hash ON documents(user_id, content_hash);
@@ -6,9 +6,10 @@ pub async fn initialize_database(database_url: &str) -> Result<DatabaseConnectio
     println!("Connecting to database...");
     let db = sea_orm::Database::connect(database_url).await?;
 
-    // The init.sql file is already executed by the Postgres container
-    // We just need to verify the connection
+    // The init.sql file is already executed by the Postgres container;
+    // databases created from an older one are brought up to date
     println!("Database connection established successfully");
+    super::migrations::run(&db).await?;
 
     Ok(db)
 }
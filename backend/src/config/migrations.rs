@@ -0,0 +1,300 @@
+//! Versioned schema changes, for databases created from an older
+//! `docker/postgres/init.sql`.
+//!
+//! init.sql always holds the whole current schema and lists the migrations
+//! it already contains in `schema_migrations`, so a new database skips them.
+//! Each migration lives in `backend/migrations` and is applied once, in its
+//! own transaction, when the server starts.
+
+use sea_orm::{ConnectionTrait, DatabaseConnection, DbBackend, DbErr, Statement, TransactionTrait};
+
+/// Every migration, oldest first. Applied migrations are never edited;
+/// later changes get a new one.
+const MIGRATIONS: &[(&str, &str)] = &[
+    (
+        "0001_payment_providers",
+        include_str!("../../migrations/0001_payment_providers.sql"),
+    ),
+    (
+        "0002_plan_catalog",
+        include_str!("../../migrations/0002_plan_catalog.sql"),
+    ),
+    (
+        "0003_plan_changes",
+        include_str!("../../migrations/0003_plan_changes.sql"),
+    ),
+    (
+        "0004_sessions",
+        include_str!("../../migrations/0004_sessions.sql"),
+    ),
+    (
+        "0005_email_verification",
+        include_str!("../../migrations/0005_email_verification.sql"),
+    ),
+    (
+        "0006_two_factor",
+        include_str!("../../migrations/0006_two_factor.sql"),
+    ),
+    (
+        "0007_social_login",
+        include_str!("../../migrations/0007_social_login.sql"),
+    ),
+    (
+        "0008_personal_access_tokens",
+        include_str!("../../migrations/0008_personal_access_tokens.sql"),
+    ),
+    (
+        "0009_login_throttles",
+        include_str!("../../migrations/0009_login_throttles.sql"),
+    ),
+    (
+        "0010_account_management",
+        include_str!("../../migrations/0010_account_management.sql"),
+    ),
+    (
+        "0011_document_details",
+        include_str!("../../migrations/0011_document_details.sql"),
+    ),
+    (
+        "0012_folders",
+        include_str!("../../migrations/0012_folders.sql"),
+    ),
+    (
+        "0013_tags_and_collections",
+        include_str!("../../migrations/0013_tags_and_collections.sql"),
+    ),
+    (
+        "0014_document_text",
+        include_str!("../../migrations/0014_document_text.sql"),
+    ),
+    (
+        "0015_pdf_info",
+        include_str!("../../migrations/0015_pdf_info.sql"),
+    ),
+    (
+        "0016_content_hash",
+        include_str!("../../migrations/0016_content_hash.sql"),
+    ),
+];
+
+/// Applies the migrations this database has not seen yet.
+pub async fn run(db: &DatabaseConnection) -> Result<(), DbErr> {
+    db.execute_unprepared(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version VARCHAR(100) PRIMARY KEY,
+            applied_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )",
+    )
+    .await?;
+
+    for (version, sql) in MIGRATIONS {
+        let txn = db.begin().await?;
+        // Another instance starting at the same time waits here
+        txn.execute_unprepared("LOCK TABLE schema_migrations IN EXCLUSIVE MODE")
+            .await?;
+
+        let applied = txn
+            .query_one(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                "SELECT 1 FROM schema_migrations WHERE version = $1",
+                [(*version).into()],
+            ))
+            .await?
+            .is_some();
+        if applied {
+            txn.rollback().await?;
+            continue;
+        }
+
+        println!("Applying migration {}", version);
+        txn.execute_unprepared(sql).await?;
+        txn.execute(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            "INSERT INTO schema_migrations (version) VALUES ($1)",
+            [(*version).into()],
+        ))
+        .await?;
+        txn.commit().await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestDb;
+
+    /// init.sql as it was before there were migrations
+    const BASELINE_SQL: &str = include_str!("../../testdata/baseline-init.sql");
+
+    /// The first column of every row, sorted.
+    async fn lines(db: &DatabaseConnection, sql: &str) -> Vec<String> {
+        let rows = db
+            .query_all(Statement::from_string(DbBackend::Postgres, sql))
+            .await
+            .unwrap();
+        let mut lines: Vec<String> = rows
+            .iter()
+            .map(|row| row.try_get_by_index(0).unwrap())
+            .collect();
+        lines.sort();
+        lines
+    }
+
+    /// Tables, columns, constraints, indexes, enum types, triggers and
+    /// catalog rows, one line each, without anything schema specific.
+    async fn describe(db: &DatabaseConnection) -> Vec<String> {
+        let queries = [
+            "SELECT concat_ws(' ', 'column', table_name, column_name, data_type, udt_name,
+                    character_maximum_length, numeric_precision, numeric_scale, is_nullable,
+                    column_default, is_generated, generation_expression)
+             FROM information_schema.columns WHERE table_schema = current_schema()",
+            "SELECT concat_ws(' ', 'constraint', conrelid::regclass, conname,
+                    pg_get_constraintdef(oid))
+             FROM pg_constraint WHERE connamespace = current_schema()::regnamespace",
+            "SELECT concat_ws(' ', 'index', tablename,
+                    replace(indexdef, current_schema() || '.', ''))
+             FROM pg_indexes WHERE schemaname = current_schema()",
+            "SELECT concat_ws(' ', 'type', t.typname,
+                    string_agg(e.enumlabel, ',' ORDER BY e.enumsortorder))
+             FROM pg_type t JOIN pg_enum e ON e.enumtypid = t.oid
+             WHERE t.typnamespace = current_schema()::regnamespace GROUP BY t.typname",
+            "SELECT concat_ws(' ', 'trigger', event_object_table, trigger_name, action_timing,
+                    event_manipulation, action_statement)
+             FROM information_schema.triggers WHERE trigger_schema = current_schema()",
+            "SELECT concat_ws(' ', 'migration', version) FROM schema_migrations",
+            "SELECT concat_ws(' ', 'plan', code, name, storage_limit_bytes, billing_period,
+                    features, is_active)
+             FROM plans",
+            "SELECT concat_ws(' ', 'price', plans.code, currency, amount)
+             FROM plan_prices JOIN plans ON plans.id = plan_id",
+        ];
+
+        let mut described = Vec::new();
+        for sql in queries {
+            described.extend(lines(db, sql).await);
+        }
+        described.sort();
+        described
+    }
+
+    /// A database created from the baseline with `rows` in it, then migrated.
+    async fn migrated(rows: &str) -> Option<TestDb> {
+        let db = TestDb::with_schema(&format!("{}\n{}", BASELINE_SQL, rows)).await?;
+        run(&db).await.unwrap();
+        Some(db)
+    }
+
+    #[actix_web::test]
+    async fn migrations_bring_the_baseline_up_to_init_sql() {
+        let Some(current) = TestDb::new().await else {
+            return;
+        };
+        let Some(migrated) = migrated("").await else {
+            return;
+        };
+
+        // Nothing left to do for a database created from init.sql
+        let before = describe(&current).await;
+        run(&current).await.unwrap();
+        assert_eq!(describe(&current).await, before);
+
+        let migrated = describe(&migrated).await;
+        let missing: Vec<_> = before
+            .iter()
+            .filter(|line| !migrated.contains(line))
+            .collect();
+        let extra: Vec<_> = migrated
+            .iter()
+            .filter(|line| !before.contains(line))
+            .collect();
+        assert!(
+            missing.is_empty() && extra.is_empty(),
+            "only in init.sql: {:#?}\nonly after migrating: {:#?}",
+            missing,
+            extra
+        );
+    }
+
+    const BASELINE_USER: &str = "INSERT INTO users (email, password_hash, full_name)
+        VALUES ('reader@example.com', 'hash', 'Reader');";
+
+    #[actix_web::test]
+    async fn momo_payments_keep_their_reference() {
+        let rows = format!(
+            "{}
+            INSERT INTO payments (user_id, reference_id, mtn_reference_id, amount, currency,
+                phone_number, provider, status)
+            VALUES (1, 'ref', 'momo-ref', 500, 'EUR', '237670000000', 'mtn_momo', 'successful');",
+            BASELINE_USER
+        );
+        let Some(db) = migrated(&rows).await else {
+            return;
+        };
+
+        let payments = lines(
+            &db,
+            "SELECT concat_ws(' ', reference_id, provider, provider_reference_id, status,
+                    refunded_amount)
+             FROM payments",
+        )
+        .await;
+        assert_eq!(payments, ["ref mtn_momo momo-ref successful 0.00"]);
+    }
+
+    #[actix_web::test]
+    async fn subscriptions_and_payments_are_matched_to_catalog_plans() {
+        let rows = format!(
+            "{}
+            INSERT INTO subscriptions (user_id, stripe_customer_id, stripe_subscription_id,
+                status, plan, storage_limit_bytes, current_period_end)
+            VALUES (1, '', 'premium-sub', 'active', 'premium', 5368709120, now()),
+                (1, '', 'unknown-sub', 'active', 'gold', 0, now());
+            INSERT INTO payments (user_id, reference_id, mtn_reference_id, amount, currency,
+                phone_number, provider)
+            VALUES (1, 'basic-payment', 'a', 100, 'XAF', '237670000000', 'mtn_momo'),
+                (1, 'odd-payment', 'b', 42, 'EUR', '237670000000', 'mtn_momo');",
+            BASELINE_USER
+        );
+        let Some(db) = migrated(&rows).await else {
+            return;
+        };
+
+        let subscriptions = lines(
+            &db,
+            "SELECT concat_ws(' ', stripe_subscription_id, plans.code)
+             FROM subscriptions JOIN plans ON plans.id = plan_id",
+        )
+        .await;
+        assert_eq!(subscriptions, ["premium-sub premium", "unknown-sub none"]);
+
+        let payments = lines(
+            &db,
+            "SELECT concat_ws(' ', reference_id, plans.code)
+             FROM payments JOIN plans ON plans.id = plan_id",
+        )
+        .await;
+        assert_eq!(payments, ["basic-payment basic", "odd-payment none"]);
+    }
+
+    #[actix_web::test]
+    async fn existing_accounts_count_as_verified() {
+        let Some(db) = migrated(BASELINE_USER).await else {
+            return;
+        };
+        db.execute_unprepared(
+            "INSERT INTO users (email, password_hash, full_name)
+             VALUES ('new@example.com', 'hash', 'New')",
+        )
+        .await
+        .unwrap();
+
+        let users = lines(
+            &db,
+            "SELECT concat_ws(' ', email, email_verified) FROM users",
+        )
+        .await;
+        assert_eq!(users, ["new@example.com f", "reader@example.com t"]);
+    }
+}
@@ -13,6 +13,18 @@ pub enum AppError {
 
     #[display(fmt = "Not Found: {}", _0)]
     NotFound(String),
+
+    #[display(fmt = "Conflict: {}", _0)]
+    Conflict(String),
+
+    #[display(fmt = "Unauthorized: {}", _0)]
+    Unauthorized(String),
+
+    #[display(fmt = "Forbidden: {}", _0)]
+    Forbidden(String),
+
+    #[display(fmt = "Too Many Requests: {}", _0)]
+    TooManyRequests(String),
 }
 
 impl From<DbErr> for AppError {
@@ -33,6 +45,18 @@ impl ResponseError for AppError {
             AppError::NotFound(ref message) => {
                 HttpResponse::NotFound().json(json!({ "error": message }))
             }
+            AppError::Conflict(ref message) => {
+                HttpResponse::Conflict().json(json!({ "error": message }))
+            }
+            AppError::Unauthorized(ref message) => {
+                HttpResponse::Unauthorized().json(json!({ "error": message }))
+            }
+            AppError::Forbidden(ref message) => {
+                HttpResponse::Forbidden().json(json!({ "error": message }))
+            }
+            AppError::TooManyRequests(ref message) => {
+                HttpResponse::TooManyRequests().json(json!({ "error": message }))
+            }
         }
     }
 }
@@ -0,0 +1,653 @@
+use crate::{
+    error::AppError,
+    handlers::{auth::send_account_email, two_factor::throttled},
+    middleware::auth::AuthenticatedUser,
+    models::{
+        account_export::{self, Entity as AccountExport, ExportStatus},
+        user::{self, Entity as User},
+    },
+    services::{
+        account_export as export,
+        account_token::{AccountTokenKey, TokenPurpose},
+        login_throttle::{self, ThrottleKey},
+        mailer::MailService,
+        session,
+        storage::StorageService,
+        two_factor,
+    },
+};
+use actix_web::{web, HttpResponse};
+use bcrypt::{hash, verify, DEFAULT_COST};
+use chrono::Utc;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
+use serde::{Deserialize, Serialize};
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+const MAX_NAME_LENGTH: usize = 255;
+
+#[derive(Serialize)]
+pub struct MeResponse {
+    pub id: i32,
+    pub email: String,
+    pub pending_email: Option<String>,
+    pub full_name: String,
+    pub email_verified: bool,
+    pub is_admin: bool,
+    pub has_password: bool,
+    pub two_factor_enabled: bool,
+    pub created_at: String,
+}
+
+impl From<user::Model> for MeResponse {
+    fn from(user: user::Model) -> Self {
+        MeResponse {
+            id: user.id,
+            email: user.email,
+            pending_email: user.pending_email,
+            full_name: user.full_name,
+            email_verified: user.email_verified,
+            is_admin: user.is_admin,
+            has_password: user.password_hash.is_some(),
+            two_factor_enabled: user.totp_enabled,
+            created_at: user.created_at.to_rfc3339(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UpdateMeRequest {
+    pub full_name: Option<String>,
+}
+
+/// How an account without a password confirms a sensitive change: with a
+/// TOTP or recovery code when two-factor authentication is on, otherwise
+/// with the token emailed by `POST /me/confirmations`.
+#[derive(Deserialize, Default)]
+pub struct PasswordlessProof {
+    pub code: Option<String>,
+    pub confirmation_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordRequest {
+    #[serde(default)]
+    pub current_password: String, // Ignored for accounts without a password
+    pub new_password: String,
+    #[serde(flatten)]
+    pub proof: PasswordlessProof,
+}
+
+#[derive(Deserialize)]
+pub struct ChangeEmailRequest {
+    pub new_email: String,
+    #[serde(default)]
+    pub password: String,
+    #[serde(flatten)]
+    pub proof: PasswordlessProof,
+}
+
+#[derive(Deserialize)]
+pub struct ConfirmEmailRequest {
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+pub struct DeleteAccountRequest {
+    #[serde(default)]
+    pub password: String,
+    #[serde(flatten)]
+    pub proof: PasswordlessProof,
+}
+
+async fn load_user(db: &DatabaseConnection, user_id: i32) -> Result<user::Model, AppError> {
+    User::find_by_id(user_id)
+        .one(db)
+        .await?
+        .ok_or(AppError::NotFound("User not found".into()))
+}
+
+/// Re-checks the password before a sensitive change. Failures count
+/// towards the login throttle, so a stolen access token cannot be used to
+/// guess the password. Accounts created through social login have none and
+/// give a `PasswordlessProof` instead.
+async fn confirm_password(
+    db: &DatabaseConnection,
+    tokens: &AccountTokenKey,
+    account: &user::Model,
+    password: &str,
+    proof: &PasswordlessProof,
+) -> Result<(), AppError> {
+    let password_hash = match &account.password_hash {
+        Some(password_hash) => password_hash,
+        None => return confirm_without_password(db, tokens, account, proof).await,
+    };
+
+    let key = ThrottleKey::Email(&account.email);
+    login_throttle::begin_attempt(db, key).await?;
+
+    if !verify(password, password_hash).unwrap_or(false) {
+        return Err(AppError::BadRequest("Current password is incorrect".into()));
+    }
+
+    login_throttle::record_success(db, key).await
+}
+
+async fn confirm_without_password(
+    db: &DatabaseConnection,
+    tokens: &AccountTokenKey,
+    account: &user::Model,
+    proof: &PasswordlessProof,
+) -> Result<(), AppError> {
+    if account.totp_enabled {
+        let code = proof.code.as_deref().ok_or_else(|| {
+            AppError::BadRequest("A two-factor code is required for this change".into())
+        })?;
+        let verified = two_factor::verify_second_factor(db, account, code);
+        if !throttled(db, account.id, verified).await? {
+            return Err(AppError::BadRequest("Invalid code".into()));
+        }
+        return Ok(());
+    }
+
+    let token = proof.confirmation_token.as_deref().ok_or_else(|| {
+        AppError::BadRequest(
+            "Confirm this change from your email first (POST /api/me/confirmations)".into(),
+        )
+    })?;
+    let confirmed = tokens
+        .verify(db, token, TokenPurpose::ConfirmChange)
+        .await?;
+    if confirmed.id != account.id {
+        return Err(AppError::BadRequest("Invalid or expired token".into()));
+    }
+    Ok(())
+}
+
+/// Emails a change confirmation to an account without a password. Accounts
+/// with a password, or with two-factor authentication, confirm with those.
+pub async fn request_confirmation(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    mailer: web::Data<MailService>,
+    tokens: web::Data<AccountTokenKey>,
+) -> Result<HttpResponse, AppError> {
+    let account = load_user(db.get_ref(), user.id).await?;
+    if account.password_hash.is_some() || account.totp_enabled {
+        return Err(AppError::BadRequest(
+            "Confirm changes with your password or two-factor code".into(),
+        ));
+    }
+
+    send_account_email(
+        mailer.get_ref().clone(),
+        &tokens,
+        &account,
+        TokenPurpose::ConfirmChange,
+    )
+    .map_err(|e| AppError::InternalServerError(e.to_string()))?;
+
+    Ok(HttpResponse::Accepted().json(serde_json::json!({
+        "message": "Check your email for a confirmation link"
+    })))
+}
+
+fn validate_email(email: &str) -> Result<(), AppError> {
+    let valid = match email.split_once('@') {
+        Some((local, domain)) => !local.is_empty() && domain.contains('.'),
+        None => false,
+    };
+
+    if !valid || email.len() > 255 || email.contains(char::is_whitespace) {
+        return Err(AppError::BadRequest("Invalid email address".into()));
+    }
+    Ok(())
+}
+
+async fn email_taken(db: &DatabaseConnection, email: &str) -> Result<bool, AppError> {
+    Ok(User::find()
+        .filter(user::Column::Email.eq(email))
+        .one(db)
+        .await?
+        .is_some())
+}
+
+pub async fn get_me(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, AppError> {
+    let account = load_user(db.get_ref(), user.id).await?;
+    Ok(HttpResponse::Ok().json(MeResponse::from(account)))
+}
+
+pub async fn update_me(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    body: web::Json<UpdateMeRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut changes = user::ActiveModel {
+        updated_at: Set(Utc::now().into()),
+        ..Default::default()
+    };
+
+    if let Some(full_name) = &body.full_name {
+        let full_name = full_name.trim();
+        if full_name.is_empty() || full_name.len() > MAX_NAME_LENGTH {
+            return Err(AppError::BadRequest(format!(
+                "Name must be between 1 and {} characters",
+                MAX_NAME_LENGTH
+            )));
+        }
+        changes.full_name = Set(full_name.to_string());
+    }
+
+    User::update_many()
+        .set(changes)
+        .filter(user::Column::Id.eq(user.id))
+        .exec(db.get_ref())
+        .await?;
+
+    let account = load_user(db.get_ref(), user.id).await?;
+    Ok(HttpResponse::Ok().json(MeResponse::from(account)))
+}
+
+/// Sets a new password. Every other session is logged out; the one making
+/// the change stays logged in.
+pub async fn change_password(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    tokens: web::Data<AccountTokenKey>,
+    body: web::Json<ChangePasswordRequest>,
+) -> Result<HttpResponse, AppError> {
+    if body.new_password.len() < MIN_PASSWORD_LENGTH {
+        return Err(AppError::BadRequest("Password is too short".into()));
+    }
+
+    let account = load_user(db.get_ref(), user.id).await?;
+    confirm_password(
+        db.get_ref(),
+        &tokens,
+        &account,
+        &body.current_password,
+        &body.proof,
+    )
+    .await?;
+
+    let password_hash = hash(body.new_password.as_bytes(), DEFAULT_COST)
+        .map_err(|_| AppError::InternalServerError("Password hashing failed".into()))?;
+
+    let changes = user::ActiveModel {
+        password_hash: Set(Some(password_hash)),
+        updated_at: Set(Utc::now().into()),
+        ..Default::default()
+    };
+
+    // Conditional on the hash that was checked, so two concurrent changes
+    // cannot both pass with the same old password
+    let result = User::update_many()
+        .set(changes)
+        .filter(user::Column::Id.eq(account.id))
+        .filter(match &account.password_hash {
+            Some(password_hash) => user::Column::PasswordHash.eq(password_hash.clone()),
+            None => user::Column::PasswordHash.is_null(),
+        })
+        .exec(db.get_ref())
+        .await?;
+
+    if result.rows_affected != 1 {
+        return Err(AppError::Conflict(
+            "Password was changed in the meantime".into(),
+        ));
+    }
+
+    login_throttle::clear(db.get_ref(), ThrottleKey::Email(&account.email)).await?;
+
+    let revoked = session::revoke_others(db.get_ref(), account.id, user.session_id).await?;
+    println!(
+        "Password changed for user {}, {} other sessions revoked",
+        account.id, revoked
+    );
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "Password updated"
+    })))
+}
+
+/// Starts an email change. The account keeps its address until the link
+/// sent to the new one is followed; the old address gets a notice.
+pub async fn request_email_change(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    mailer: web::Data<MailService>,
+    tokens: web::Data<AccountTokenKey>,
+    body: web::Json<ChangeEmailRequest>,
+) -> Result<HttpResponse, AppError> {
+    let new_email = body.new_email.trim().to_string();
+    validate_email(&new_email)?;
+
+    let account = load_user(db.get_ref(), user.id).await?;
+    confirm_password(db.get_ref(), &tokens, &account, &body.password, &body.proof).await?;
+
+    if new_email.eq_ignore_ascii_case(&account.email) {
+        return Err(AppError::BadRequest(
+            "This is already your email address".into(),
+        ));
+    }
+    if email_taken(db.get_ref(), &new_email).await? {
+        return Err(AppError::Conflict("Email address is already in use".into()));
+    }
+
+    let changes = user::ActiveModel {
+        pending_email: Set(Some(new_email.clone())),
+        updated_at: Set(Utc::now().into()),
+        ..Default::default()
+    };
+    User::update_many()
+        .set(changes)
+        .filter(user::Column::Id.eq(account.id))
+        .exec(db.get_ref())
+        .await?;
+
+    let account = load_user(db.get_ref(), user.id).await?;
+    send_account_email(
+        mailer.get_ref().clone(),
+        &tokens,
+        &account,
+        TokenPurpose::ChangeEmail,
+    )
+    .map_err(|e| AppError::InternalServerError(e.to_string()))?;
+
+    let (mailer, email, name) = (mailer.get_ref().clone(), account.email, account.full_name);
+    actix_web::rt::spawn(async move {
+        if let Err(e) = mailer
+            .send_email_change_notice(&email, &name, &new_email)
+            .await
+        {
+            println!("Failed to send email change notice to {}: {}", email, e);
+        }
+    });
+
+    Ok(HttpResponse::Accepted().json(serde_json::json!({
+        "message": "Check your new address for a confirmation link"
+    })))
+}
+
+/// Switches the account to the address an email change link was sent to.
+/// Public, as the link may be opened on a device that is not logged in.
+pub async fn confirm_email_change(
+    db: web::Data<DatabaseConnection>,
+    tokens: web::Data<AccountTokenKey>,
+    body: web::Json<ConfirmEmailRequest>,
+) -> Result<HttpResponse, AppError> {
+    let account = tokens
+        .verify(db.get_ref(), &body.token, TokenPurpose::ChangeEmail)
+        .await?;
+    let new_email = account
+        .pending_email
+        .clone()
+        .ok_or(AppError::BadRequest("Invalid or expired token".into()))?;
+
+    if email_taken(db.get_ref(), &new_email).await? {
+        return Err(AppError::Conflict("Email address is already in use".into()));
+    }
+
+    let changes = user::ActiveModel {
+        email: Set(new_email.clone()),
+        pending_email: Set(None),
+        email_verified: Set(true),
+        updated_at: Set(Utc::now().into()),
+        ..Default::default()
+    };
+
+    // Conditional on the state the token was checked against, so the link
+    // only works once. A unique violation means the address was taken since.
+    let result = User::update_many()
+        .set(changes)
+        .filter(user::Column::Id.eq(account.id))
+        .filter(user::Column::Email.eq(account.email.clone()))
+        .filter(user::Column::PendingEmail.eq(new_email.clone()))
+        .exec(db.get_ref())
+        .await
+        .map_err(|_| AppError::Conflict("Email address is already in use".into()))?;
+
+    if result.rows_affected != 1 {
+        return Err(AppError::BadRequest("Invalid or expired token".into()));
+    }
+
+    println!(
+        "User {} changed email from {} to {}",
+        account.id, account.email, new_email
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "Email address updated"
+    })))
+}
+
+/// Closes the account: every stored object under the user's prefix is
+/// deleted, then the user row, which cascades to all their records.
+pub async fn delete_me(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    storage: web::Data<StorageService>,
+    tokens: web::Data<AccountTokenKey>,
+    body: web::Json<DeleteAccountRequest>,
+) -> Result<HttpResponse, AppError> {
+    let account = load_user(db.get_ref(), user.id).await?;
+    confirm_password(db.get_ref(), &tokens, &account, &body.password, &body.proof).await?;
+
+    // Objects go first: if storage fails, the account is still there and
+    // the request can be retried
+    let objects = storage
+        .list_files(&format!("{}/", account.id))
+        .await
+        .map_err(|e| AppError::InternalServerError(e.to_string()))?;
+    for object in &objects {
+        storage
+            .delete_file(&object.key)
+            .await
+            .map_err(|e| AppError::InternalServerError(e.to_string()))?;
+    }
+
+    User::delete_by_id(account.id).exec(db.get_ref()).await?;
+    login_throttle::clear(db.get_ref(), ThrottleKey::Email(&account.email)).await?;
+    login_throttle::clear(db.get_ref(), ThrottleKey::TwoFactor(account.id)).await?;
+
+    println!(
+        "User {} deleted their account, {} objects removed",
+        account.id,
+        objects.len()
+    );
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub async fn create_export(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    storage: web::Data<StorageService>,
+) -> Result<HttpResponse, AppError> {
+    let export = export::start(db.get_ref(), storage.get_ref(), user.id).await?;
+    println!("Export {} queued for user {}", export.id, user.id);
+
+    Ok(HttpResponse::Accepted().json(export))
+}
+
+async fn find_export(
+    db: &DatabaseConnection,
+    user_id: i32,
+    export_id: i32,
+) -> Result<account_export::Model, AppError> {
+    AccountExport::find_by_id(export_id)
+        .filter(account_export::Column::UserId.eq(user_id))
+        .one(db)
+        .await?
+        .ok_or(AppError::NotFound("Export not found".into()))
+}
+
+pub async fn get_export(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    let export = find_export(db.get_ref(), user.id, path.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(export))
+}
+
+pub async fn download_export(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    storage: web::Data<StorageService>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    let export = find_export(db.get_ref(), user.id, path.into_inner()).await?;
+
+    let (key, size) = match (&export.status, export.storage_key, export.file_size) {
+        (ExportStatus::Completed, Some(key), Some(size)) => (key, size),
+        _ => return Err(AppError::Conflict("Export is not ready".into())),
+    };
+    if export.expires_at.is_some_and(|at| at < Utc::now()) {
+        return Err(AppError::NotFound("Export has expired".into()));
+    }
+
+    let stream = storage
+        .download_file(&key, None)
+        .await
+        .map_err(|e| AppError::InternalServerError(e.to_string()))?;
+    let filename = format!("shelf-export-{}.zip", export.created_at.format("%Y-%m-%d"));
+
+    Ok(HttpResponse::Ok()
+        .append_header(("Content-Type", "application/zip"))
+        .append_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", filename),
+        ))
+        .append_header(("Cache-Control", "no-store"))
+        .no_chunking(size as u64)
+        .streaming(stream))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::storage::MemoryBackend;
+    use crate::test_support::TestDb;
+    use std::sync::Arc;
+
+    fn proof(confirmation_token: Option<String>) -> PasswordlessProof {
+        PasswordlessProof {
+            code: None,
+            confirmation_token,
+        }
+    }
+
+    async fn delete(
+        db: &TestDb,
+        tokens: &AccountTokenKey,
+        user_id: i32,
+        proof: PasswordlessProof,
+    ) -> Result<HttpResponse, AppError> {
+        let user = AuthenticatedUser {
+            id: user_id,
+            email: "social@example.com".to_string(),
+            session_id: None,
+        };
+        let storage = StorageService::new(Arc::new(MemoryBackend::new()));
+        delete_me(
+            user,
+            web::Data::new(db.connection()),
+            web::Data::new(storage),
+            web::Data::new(tokens.clone()),
+            web::Json(DeleteAccountRequest {
+                password: String::new(),
+                proof,
+            }),
+        )
+        .await
+    }
+
+    #[actix_web::test]
+    async fn social_account_is_not_deleted_without_confirmation() {
+        let Some(db) = TestDb::new().await else {
+            return;
+        };
+        let tokens = AccountTokenKey::new(b"secret");
+        let user_id = db.user("social@example.com").await;
+
+        let result = delete(&db, &tokens, user_id, proof(None)).await;
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+        let result = delete(&db, &tokens, user_id, proof(Some("forged".into()))).await;
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+        assert!(User::find_by_id(user_id).one(&*db).await.unwrap().is_some());
+    }
+
+    #[actix_web::test]
+    async fn emailed_confirmation_deletes_only_its_own_account() {
+        let Some(db) = TestDb::new().await else {
+            return;
+        };
+        let tokens = AccountTokenKey::new(b"secret");
+        let user_id = db.user("social@example.com").await;
+        let other_id = db.user("other@example.com").await;
+        let other = load_user(&db, other_id).await.unwrap();
+        let account = load_user(&db, user_id).await.unwrap();
+
+        let foreign = tokens.issue(&other, TokenPurpose::ConfirmChange).unwrap();
+        let result = delete(&db, &tokens, user_id, proof(Some(foreign))).await;
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+
+        let token = tokens.issue(&account, TokenPurpose::ConfirmChange).unwrap();
+        let response = delete(&db, &tokens, user_id, proof(Some(token)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), actix_web::http::StatusCode::NO_CONTENT);
+        assert!(User::find_by_id(user_id).one(&*db).await.unwrap().is_none());
+        assert!(User::find_by_id(other_id)
+            .one(&*db)
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[actix_web::test]
+    async fn confirmation_is_spent_by_an_email_change() {
+        let Some(db) = TestDb::new().await else {
+            return;
+        };
+        let tokens = AccountTokenKey::new(b"secret");
+        let user_id = db.user("social@example.com").await;
+        let account = load_user(&db, user_id).await.unwrap();
+        let token = tokens.issue(&account, TokenPurpose::ConfirmChange).unwrap();
+        let proof = proof(Some(token));
+
+        confirm_password(&db, &tokens, &account, "", &proof)
+            .await
+            .unwrap();
+        User::update_many()
+            .col_expr(
+                user::Column::PendingEmail,
+                sea_orm::sea_query::Expr::value("new@example.com"),
+            )
+            .filter(user::Column::Id.eq(user_id))
+            .exec(&*db)
+            .await
+            .unwrap();
+
+        let account = load_user(&db, user_id).await.unwrap();
+        let result = confirm_password(&db, &tokens, &account, "", &proof).await;
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+    }
+
+    #[actix_web::test]
+    async fn social_account_with_two_factor_needs_a_code() {
+        let Some(db) = TestDb::new().await else {
+            return;
+        };
+        let tokens = AccountTokenKey::new(b"secret");
+        let user_id = db.user("social@example.com").await;
+        let mut account = load_user(&db, user_id).await.unwrap();
+        account.totp_enabled = true;
+
+        // An emailed confirmation does not replace the second factor
+        let token = tokens.issue(&account, TokenPurpose::ConfirmChange).unwrap();
+        let result = confirm_password(&db, &tokens, &account, "", &proof(Some(token))).await;
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+    }
+}
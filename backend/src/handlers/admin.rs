@@ -0,0 +1,383 @@
+use crate::{
+    error::AppError,
+    middleware::auth::RequireAdmin,
+    models::{
+        document::{self, Entity as Document},
+        plan::Entity as Plan,
+        plan_change::PlanChangeSource,
+        subscription::{self, Entity as Subscription},
+        user::{self, Entity as User},
+    },
+    services::{
+        login_throttle::{self, ThrottleKey},
+        plan::{self, PlanChange},
+        session,
+    },
+};
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use sea_orm::{
+    sea_query::{extension::postgres::PgExpr, Alias, Condition, Expr},
+    ColumnTrait, DatabaseConnection, EntityTrait, FromQueryResult, PaginatorTrait, QueryFilter,
+    QueryOrder, QuerySelect, Set,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const DEFAULT_PAGE_SIZE: u64 = 20;
+const MAX_PAGE_SIZE: u64 = 100;
+const LARGEST_DOCUMENTS: u64 = 10;
+
+#[derive(Deserialize)]
+pub struct UserListQuery {
+    pub q: Option<String>,      // Matches email or name, case-insensitively
+    pub status: Option<String>, // "active" or "suspended"
+    pub page: Option<u64>,      // 1-based
+    pub per_page: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct ForcePlanRequest {
+    pub plan: String,
+}
+
+#[derive(Serialize)]
+pub struct AdminUserResponse {
+    pub id: i32,
+    pub email: String,
+    pub full_name: String,
+    pub is_admin: bool,
+    pub is_active: bool,
+    pub email_verified: bool,
+    pub totp_enabled: bool,
+    pub plan: String,
+    pub storage_used_bytes: i64,
+    pub created_at: String,
+}
+
+#[derive(Serialize)]
+pub struct UserListResponse {
+    pub users: Vec<AdminUserResponse>,
+    pub total: u64,
+    pub page: u64,
+    pub per_page: u64,
+}
+
+#[derive(Serialize, FromQueryResult)]
+pub struct MimeTypeUsage {
+    pub mime_type: String,
+    pub documents: i64,
+    pub bytes: i64,
+}
+
+#[derive(Serialize)]
+pub struct LargeDocument {
+    pub id: i32,
+    pub filename: String,
+    pub file_size: i64,
+    pub created_at: String,
+}
+
+#[derive(Serialize)]
+pub struct StorageBreakdownResponse {
+    pub user_id: i32,
+    pub plan: String,
+    pub storage_limit_bytes: i64,
+    pub storage_used_bytes: i64,
+    pub document_count: i64,
+    pub by_mime_type: Vec<MimeTypeUsage>,
+    pub largest_documents: Vec<LargeDocument>,
+}
+
+async fn load_user(db: &DatabaseConnection, user_id: i32) -> Result<user::Model, AppError> {
+    User::find_by_id(user_id)
+        .one(db)
+        .await?
+        .ok_or(AppError::NotFound("User not found".into()))
+}
+
+/// Plan codes of the given users; users without a subscription in effect
+/// are on the free plan.
+async fn plans_of(
+    db: &DatabaseConnection,
+    user_ids: &[i32],
+) -> Result<HashMap<i32, String>, AppError> {
+    let subscriptions = Subscription::find()
+        .filter(subscription::Column::UserId.is_in(user_ids.to_vec()))
+        .find_also_related(Plan)
+        .all(db)
+        .await?;
+    let now = Utc::now().fixed_offset();
+
+    Ok(subscriptions
+        .into_iter()
+        .filter_map(|(subscription, plan)| {
+            plan.filter(|plan| plan::is_in_effect(&subscription, plan, now))
+                .map(|plan| (subscription.user_id, plan.code))
+        })
+        .collect())
+}
+
+fn admin_user_response(
+    user: user::Model,
+    plans: &HashMap<i32, String>,
+    usage: &HashMap<i32, i64>,
+) -> AdminUserResponse {
+    AdminUserResponse {
+        plan: plans
+            .get(&user.id)
+            .cloned()
+            .unwrap_or_else(|| plan::FREE_PLAN_CODE.to_string()),
+        storage_used_bytes: usage.get(&user.id).copied().unwrap_or(0),
+        id: user.id,
+        email: user.email,
+        full_name: user.full_name,
+        is_admin: user.is_admin,
+        is_active: user.is_active,
+        email_verified: user.email_verified,
+        totp_enabled: user.totp_enabled,
+        created_at: user.created_at.to_rfc3339(),
+    }
+}
+
+/// Escapes `LIKE` wildcards so a search for "50%" matches literally.
+fn like_pattern(search: &str) -> String {
+    let escaped = search
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+pub async fn list_users(
+    _admin: RequireAdmin,
+    query: web::Query<UserListQuery>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, AppError> {
+    let per_page = query
+        .per_page
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let page = query.page.unwrap_or(1).max(1);
+
+    let mut select = User::find().order_by_asc(user::Column::Id);
+
+    if let Some(search) = query.q.as_deref().map(str::trim).filter(|q| !q.is_empty()) {
+        let pattern = like_pattern(search);
+        select = select.filter(
+            Condition::any()
+                .add(Expr::col(user::Column::Email).ilike(pattern.clone()))
+                .add(Expr::col(user::Column::FullName).ilike(pattern)),
+        );
+    }
+
+    match query.status.as_deref() {
+        None => {}
+        Some("active") => select = select.filter(user::Column::IsActive.eq(true)),
+        Some("suspended") => select = select.filter(user::Column::IsActive.eq(false)),
+        Some(other) => {
+            return Err(AppError::BadRequest(format!("Unknown status: {}", other)));
+        }
+    }
+
+    let paginator = select.paginate(db.get_ref(), per_page);
+    let total = paginator.num_items().await?;
+    let users = paginator.fetch_page(page - 1).await?;
+
+    let user_ids: Vec<i32> = users.iter().map(|user| user.id).collect();
+    let plans = plans_of(db.get_ref(), &user_ids).await?;
+    let usage = plan::storage_used_by(db.get_ref(), &user_ids).await?;
+
+    Ok(HttpResponse::Ok().json(UserListResponse {
+        users: users
+            .into_iter()
+            .map(|user| admin_user_response(user, &plans, &usage))
+            .collect(),
+        total,
+        page,
+        per_page,
+    }))
+}
+
+pub async fn get_user(
+    _admin: RequireAdmin,
+    path: web::Path<i32>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, AppError> {
+    let user = load_user(db.get_ref(), path.into_inner()).await?;
+
+    let plans = plans_of(db.get_ref(), &[user.id]).await?;
+    let usage = plan::storage_used_by(db.get_ref(), &[user.id]).await?;
+
+    Ok(HttpResponse::Ok().json(admin_user_response(user, &plans, &usage)))
+}
+
+/// Sets `is_active`. Admins cannot suspend themselves, so nobody can lock
+/// themselves out of the admin API by accident.
+async fn set_active(
+    admin: &RequireAdmin,
+    db: &DatabaseConnection,
+    user_id: i32,
+    active: bool,
+) -> Result<user::Model, AppError> {
+    if !active && user_id == admin.0.id {
+        return Err(AppError::BadRequest(
+            "You cannot suspend your own account".into(),
+        ));
+    }
+
+    let user = load_user(db, user_id).await?;
+
+    let changes = user::ActiveModel {
+        is_active: Set(active),
+        updated_at: Set(Utc::now().into()),
+        ..Default::default()
+    };
+    User::update_many()
+        .set(changes)
+        .filter(user::Column::Id.eq(user.id))
+        .exec(db)
+        .await?;
+
+    Ok(user)
+}
+
+/// Suspending also ends every session, so the user is logged out at once
+/// rather than when their access token expires.
+pub async fn suspend_user(
+    admin: RequireAdmin,
+    path: web::Path<i32>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, AppError> {
+    let user = set_active(&admin, db.get_ref(), path.into_inner(), false).await?;
+    let revoked = session::revoke_all_for_user(db.get_ref(), user.id).await?;
+
+    println!(
+        "User {} suspended by admin {}, {} sessions revoked",
+        user.id, admin.0.id, revoked
+    );
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "User suspended",
+        "sessions_revoked": revoked,
+    })))
+}
+
+pub async fn reactivate_user(
+    admin: RequireAdmin,
+    path: web::Path<i32>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, AppError> {
+    let user = set_active(&admin, db.get_ref(), path.into_inner(), true).await?;
+
+    println!("User {} reactivated by admin {}", user.id, admin.0.id);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "User reactivated"
+    })))
+}
+
+/// Moves a user onto a plan without payment, e.g. for support or refunds.
+/// Recorded in `plan_changes` with the admin as the author.
+pub async fn force_plan(
+    admin: RequireAdmin,
+    path: web::Path<i32>,
+    data: web::Json<ForcePlanRequest>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, AppError> {
+    let user = load_user(db.get_ref(), path.into_inner()).await?;
+    let plan = plan::find_by_code(db.get_ref(), &data.plan).await?;
+
+    let change = PlanChange {
+        changed_by: admin.0.id,
+        source: PlanChangeSource::Admin,
+        payment_id: None,
+    };
+    let subscription = plan::change_plan(db.get_ref(), user.id, &plan, change).await?;
+
+    println!(
+        "User {} moved to plan {} by admin {}",
+        user.id, plan.code, admin.0.id
+    );
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "plan": plan.code,
+        "storage_limit_bytes": plan.storage_limit_bytes,
+        "status": subscription.status,
+        "current_period_end": subscription.current_period_end
+    })))
+}
+
+pub async fn storage_breakdown(
+    _admin: RequireAdmin,
+    path: web::Path<i32>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, AppError> {
+    let user = load_user(db.get_ref(), path.into_inner()).await?;
+    let plan = plan::plan_for_user(db.get_ref(), user.id).await?;
+
+    let by_mime_type = Document::find()
+        .select_only()
+        .column(document::Column::MimeType)
+        .column_as(document::Column::Id.count(), "documents")
+        .column_as(
+            Expr::expr(Expr::col(document::Column::FileSize).sum()).cast_as(Alias::new("BIGINT")),
+            "bytes",
+        )
+        .filter(document::Column::UserId.eq(user.id))
+        .group_by(document::Column::MimeType)
+        .order_by_desc(Expr::col(Alias::new("bytes")))
+        .into_model::<MimeTypeUsage>()
+        .all(db.get_ref())
+        .await?;
+
+    let largest_documents = Document::find()
+        .filter(document::Column::UserId.eq(user.id))
+        .order_by_desc(document::Column::FileSize)
+        .limit(LARGEST_DOCUMENTS)
+        .all(db.get_ref())
+        .await?
+        .into_iter()
+        .map(|document| LargeDocument {
+            id: document.id,
+            filename: document.filename,
+            file_size: document.file_size,
+            created_at: document.created_at.to_rfc3339(),
+        })
+        .collect();
+
+    // Documents linked to the same file share its bytes
+    let usage = plan::storage_used_by(db.get_ref(), &[user.id]).await?;
+
+    Ok(HttpResponse::Ok().json(StorageBreakdownResponse {
+        user_id: user.id,
+        plan: plan.code,
+        storage_limit_bytes: plan.storage_limit_bytes,
+        storage_used_bytes: usage.get(&user.id).copied().unwrap_or(0),
+        document_count: by_mime_type.iter().map(|usage| usage.documents).sum(),
+        by_mime_type,
+        largest_documents,
+    }))
+}
+
+/// Lifts a lockout from too many failed logins or second-factor codes before
+/// it expires on its own. Lockouts of client addresses are left alone.
+pub async fn unlock_user(
+    admin: RequireAdmin,
+    path: web::Path<i32>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, AppError> {
+    let user = load_user(db.get_ref(), path.into_inner()).await?;
+
+    let password_cleared =
+        login_throttle::clear(db.get_ref(), ThrottleKey::Email(&user.email)).await?;
+    let two_factor_cleared =
+        login_throttle::clear(db.get_ref(), ThrottleKey::TwoFactor(user.id)).await?;
+
+    println!("User {} unlocked by admin {}", user.id, admin.0.id);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "Failed login attempts cleared",
+        "was_throttled": password_cleared || two_factor_cleared,
+    })))
+}
@@ -1,11 +1,19 @@
-use actix_web::{error::Error as ActixError, web, HttpResponse};
+use actix_web::{error::Error as ActixError, http::header, web, HttpRequest, HttpResponse};
 use bcrypt::{hash, verify, DEFAULT_COST};
-use chrono::{Duration, Utc};
-use jsonwebtoken::{encode, EncodingKey, Header};
-use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
+use chrono::Utc;
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
 use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
 
 use crate::models::user::{self, Entity as User};
+use crate::services::account_token::{AccountTokenKey, TokenPurpose};
+use crate::services::jwt::JwtKeys;
+use crate::services::login_throttle::{self, ThrottleKey};
+use crate::services::mailer::MailService;
+use crate::services::session;
+use crate::services::two_factor;
+
+const MIN_PASSWORD_LENGTH: usize = 8;
 
 #[derive(Deserialize)]
 pub struct LoginRequest {
@@ -16,9 +24,35 @@ pub struct LoginRequest {
 #[derive(Serialize)]
 pub struct LoginResponse {
     token: String,
+    refresh_token: String,
+    expires_in: i64, // Access token lifetime in seconds
     user: UserResponse,
 }
 
+#[derive(Serialize)]
+pub struct TwoFactorChallengeResponse {
+    two_factor_required: bool,
+    challenge_token: String,
+}
+
+#[derive(Deserialize)]
+pub struct TwoFactorLoginRequest {
+    challenge_token: String,
+    code: String, // TOTP code or recovery code
+}
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    refresh_token: String,
+}
+
+#[derive(Serialize)]
+pub struct RefreshResponse {
+    token: String,
+    refresh_token: String,
+    expires_in: i64,
+}
+
 #[derive(Serialize)]
 pub struct UserResponse {
     id: i32,
@@ -33,86 +67,478 @@ pub struct RegisterRequest {
     full_name: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Claims {
-    sub: String,
+#[derive(Deserialize)]
+pub struct EmailRequest {
     email: String,
-    exp: usize,
+}
+
+#[derive(Deserialize)]
+pub struct TokenRequest {
+    token: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordRequest {
+    token: String,
+    password: String,
+}
+
+/// Signs a short-lived access token bound to `session_id`.
+fn issue_access_token(
+    keys: &JwtKeys,
+    user: &user::Model,
+    session_id: i32,
+) -> Result<String, ActixError> {
+    Ok(keys.issue_access_token(
+        user.id,
+        &user.email,
+        session_id,
+        session::access_token_ttl(),
+    )?)
 }
 
 pub async fn login(
+    req: HttpRequest,
     db: web::Data<DatabaseConnection>,
+    keys: web::Data<JwtKeys>,
+    tokens: web::Data<AccountTokenKey>,
     credentials: web::Json<LoginRequest>,
 ) -> Result<HttpResponse, ActixError> {
+    // Throttled attempts are turned away before any bcrypt work. The
+    // others count as failures until the password has been checked.
+    let client_ip = client_ip(&req);
+    if let Some(ip) = &client_ip {
+        login_throttle::begin_attempt(db.get_ref(), ThrottleKey::Ip(ip)).await?;
+    }
+    login_throttle::begin_attempt(db.get_ref(), ThrottleKey::Email(&credentials.email)).await?;
+
     // Find user by email
-    let user = User::find()
-        .filter(user::Column::Email.eq(&credentials.email))
-        .one(db.get_ref())
-        .await
-        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+    let user = find_by_email(db.get_ref(), &credentials.email).await?;
+
+    // Verify password. Unknown emails and accounts created through social
+    // login have no hash, so check against a dummy one to take the same time.
+    let password_hash = user
+        .as_ref()
+        .and_then(|user| user.password_hash.as_deref())
+        .unwrap_or_else(|| dummy_password_hash());
+    let password_ok = verify(&credentials.password, password_hash)
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Password verification failed"))?;
+
+    match user {
+        Some(user) if password_ok => {
+            if let Some(ip) = &client_ip {
+                login_throttle::record_success(db.get_ref(), ThrottleKey::Ip(ip)).await?;
+            }
+            login_throttle::record_success(db.get_ref(), ThrottleKey::Email(&credentials.email))
+                .await?;
+            complete_login(&req, db.get_ref(), keys.get_ref(), tokens.get_ref(), user).await
+        }
+        _ => Err(actix_web::error::ErrorUnauthorized("Invalid credentials")),
+    }
+}
 
-    if let Some(user) = user {
-        // Verify password
-        if verify(&credentials.password, &user.password_hash).map_err(|_| {
-            actix_web::error::ErrorInternalServerError("Password verification failed")
-        })? {
-            // Generate JWT token
-            let claims = Claims {
-                sub: user.id.to_string(),
-                email: user.email.clone(),
-                exp: (Utc::now() + Duration::hours(24)).timestamp() as usize,
-            };
-
-            let token = encode(
-                &Header::default(),
-                &claims,
-                &EncodingKey::from_secret(std::env::var("JWT_SECRET").unwrap().as_bytes()),
-            )
-            .map_err(|_| actix_web::error::ErrorInternalServerError("Token generation failed"))?;
-
-            let response = LoginResponse {
-                token,
-                user: UserResponse {
-                    id: user.id,
-                    email: user.email,
-                    name: user.full_name,
-                },
-            };
-
-            Ok(HttpResponse::Ok().json(response))
-        } else {
-            Err(actix_web::error::ErrorUnauthorized("Invalid credentials"))
+/// A bcrypt hash of a random password with the cost real hashes use, for
+/// logins that have nothing to verify against.
+fn dummy_password_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| {
+        hash(hex::encode(rand::random::<[u8; 16]>()), DEFAULT_COST)
+            .expect("Failed to hash dummy password")
+    })
+}
+
+/// Address failed logins are counted against. Behind a reverse proxy
+/// (`TRUST_PROXY=true`) that is the last `X-Forwarded-For` entry, the one the
+/// proxy added; earlier entries come from the client and cannot be trusted.
+fn client_ip(req: &HttpRequest) -> Option<String> {
+    let trust_proxy = std::env::var("TRUST_PROXY")
+        .map(|value| value == "true")
+        .unwrap_or(false);
+
+    if trust_proxy {
+        let forwarded = req
+            .headers()
+            .get("X-Forwarded-For")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .map(|ip| ip.trim().to_string())
+            .filter(|ip| !ip.is_empty());
+        if forwarded.is_some() {
+            return forwarded;
         }
-    } else {
-        Err(actix_web::error::ErrorUnauthorized("Invalid credentials"))
     }
+
+    req.peer_addr().map(|addr| addr.ip().to_string())
+}
+
+/// Finishes a login once the user has proven who they are (password or
+/// social login): checks the account state, then either starts a session or
+/// asks for the second factor.
+pub(crate) async fn complete_login(
+    req: &HttpRequest,
+    db: &DatabaseConnection,
+    keys: &JwtKeys,
+    tokens: &AccountTokenKey,
+    user: user::Model,
+) -> Result<HttpResponse, ActixError> {
+    if !user.is_active {
+        return Err(actix_web::error::ErrorForbidden("Account disabled"));
+    }
+    if !user.email_verified {
+        return Err(actix_web::error::ErrorForbidden(
+            "Email address not verified",
+        ));
+    }
+
+    // The first factor alone is not enough; hand out a short-lived
+    // challenge to be completed at /auth/login/2fa
+    if user.totp_enabled {
+        let challenge_token = tokens.issue(&user, TokenPurpose::TwoFactorLogin)?;
+        return Ok(HttpResponse::Ok().json(TwoFactorChallengeResponse {
+            two_factor_required: true,
+            challenge_token,
+        }));
+    }
+
+    start_session(req, db, keys, user).await
+}
+
+/// Second login step for accounts with two-factor authentication: exchanges
+/// the challenge from `login` plus a TOTP or recovery code for tokens.
+pub async fn login_two_factor(
+    req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    keys: web::Data<JwtKeys>,
+    tokens: web::Data<AccountTokenKey>,
+    body: web::Json<TwoFactorLoginRequest>,
+) -> Result<HttpResponse, ActixError> {
+    let user = tokens
+        .verify(
+            db.get_ref(),
+            &body.challenge_token,
+            TokenPurpose::TwoFactorLogin,
+        )
+        .await
+        .map_err(|_| actix_web::error::ErrorUnauthorized("Invalid or expired challenge"))?;
+
+    if !user.is_active {
+        return Err(actix_web::error::ErrorForbidden("Account disabled"));
+    }
+
+    // Six digits are guessable without a limit, even within one challenge
+    login_throttle::begin_attempt(db.get_ref(), ThrottleKey::TwoFactor(user.id)).await?;
+    if !two_factor::verify_second_factor(db.get_ref(), &user, &body.code).await? {
+        return Err(actix_web::error::ErrorUnauthorized("Invalid code"));
+    }
+    login_throttle::record_success(db.get_ref(), ThrottleKey::TwoFactor(user.id)).await?;
+
+    start_session(&req, db.get_ref(), keys.get_ref(), user).await
+}
+
+/// Starts a session for a fully authenticated user and returns its first
+/// token pair.
+async fn start_session(
+    req: &HttpRequest,
+    db: &DatabaseConnection,
+    keys: &JwtKeys,
+    user: user::Model,
+) -> Result<HttpResponse, ActixError> {
+    let user_agent = req
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.chars().take(255).collect());
+
+    let (session, refresh_token) = session::create(db, user.id, user_agent).await?;
+    let token = issue_access_token(keys, &user, session.id)?;
+
+    let response = LoginResponse {
+        token,
+        refresh_token,
+        expires_in: session::access_token_ttl().num_seconds(),
+        user: UserResponse {
+            id: user.id,
+            email: user.email,
+            name: user.full_name,
+        },
+    };
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Trades a refresh token for a new access token and a new refresh token.
+/// The old refresh token stops working.
+pub async fn refresh(
+    db: web::Data<DatabaseConnection>,
+    keys: web::Data<JwtKeys>,
+    body: web::Json<RefreshRequest>,
+) -> Result<HttpResponse, ActixError> {
+    let (session, user, refresh_token) = session::rotate(db.get_ref(), &body.refresh_token).await?;
+    let token = issue_access_token(keys.get_ref(), &user, session.id)?;
+
+    Ok(HttpResponse::Ok().json(RefreshResponse {
+        token,
+        refresh_token,
+        expires_in: session::access_token_ttl().num_seconds(),
+    }))
+}
+
+/// Public keys access tokens can be verified with, for other services.
+pub async fn jwks(keys: web::Data<JwtKeys>) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, "public, max-age=300"))
+        .json(keys.jwks())
+}
+
+/// Ends the session of a refresh token. Access tokens issued for it are
+/// rejected from the next request on.
+pub async fn logout(
+    db: web::Data<DatabaseConnection>,
+    body: web::Json<RefreshRequest>,
+) -> Result<HttpResponse, ActixError> {
+    session::revoke(db.get_ref(), &body.refresh_token).await?;
+    Ok(HttpResponse::NoContent().finish())
 }
 
 pub async fn register(
     db: web::Data<DatabaseConnection>,
+    mailer: web::Data<MailService>,
+    tokens: web::Data<AccountTokenKey>,
     user_data: web::Json<RegisterRequest>,
 ) -> Result<HttpResponse, ActixError> {
+    if user_data.password.len() < MIN_PASSWORD_LENGTH {
+        return Err(actix_web::error::ErrorBadRequest("Password is too short"));
+    }
+
     // Hash password
     let password_hash = hash(user_data.password.as_bytes(), DEFAULT_COST)
         .map_err(|_| actix_web::error::ErrorInternalServerError("Password hashing failed"))?;
 
-    // Create new user
+    // Create new user; they can log in once the address is verified
     let new_user = user::ActiveModel {
         email: Set(user_data.email.clone()),
-        password_hash: Set(password_hash),
+        password_hash: Set(Some(password_hash)),
         full_name: Set(user_data.full_name.clone()),
         is_admin: Set(false),
         is_active: Set(true),
+        email_verified: Set(false),
         created_at: Set(Utc::now().into()),
         updated_at: Set(Utc::now().into()),
         ..Default::default()
     };
 
     // Insert user into database
-    User::insert(new_user)
-        .exec(db.get_ref())
+    let user = new_user
+        .insert(db.get_ref())
         .await
         .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to create user"))?;
 
-    Ok(HttpResponse::Created().finish())
+    send_account_email(
+        mailer.get_ref().clone(),
+        &tokens,
+        &user,
+        TokenPurpose::VerifyEmail,
+    )?;
+
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "message": "Registration successful, check your email to verify your address"
+    })))
+}
+
+/// Marks the address a verification token was sent to as verified.
+pub async fn verify_email(
+    db: web::Data<DatabaseConnection>,
+    tokens: web::Data<AccountTokenKey>,
+    body: web::Json<TokenRequest>,
+) -> Result<HttpResponse, ActixError> {
+    let user = tokens
+        .verify(db.get_ref(), &body.token, TokenPurpose::VerifyEmail)
+        .await?;
+
+    let changes = user::ActiveModel {
+        email_verified: Set(true),
+        updated_at: Set(Utc::now().into()),
+        ..Default::default()
+    };
+
+    // Conditional on the state the token was checked against, so a token
+    // used twice at the same moment only succeeds once
+    let result = User::update_many()
+        .set(changes)
+        .filter(user::Column::Id.eq(user.id))
+        .filter(user::Column::Email.eq(user.email.clone()))
+        .filter(user::Column::EmailVerified.eq(false))
+        .exec(db.get_ref())
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    if result.rows_affected != 1 {
+        return Err(actix_web::error::ErrorBadRequest(
+            "Invalid or expired token",
+        ));
+    }
+
+    println!("User {} verified {}", user.id, user.email);
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "Email address verified"
+    })))
+}
+
+/// Sends a new verification link. The response is the same whether or not
+/// the address belongs to an account.
+pub async fn resend_verification(
+    db: web::Data<DatabaseConnection>,
+    mailer: web::Data<MailService>,
+    tokens: web::Data<AccountTokenKey>,
+    body: web::Json<EmailRequest>,
+) -> Result<HttpResponse, ActixError> {
+    let user = find_by_email(db.get_ref(), &body.email).await?;
+
+    if let Some(user) = user.filter(|user| user.is_active && !user.email_verified) {
+        send_account_email(
+            mailer.get_ref().clone(),
+            &tokens,
+            &user,
+            TokenPurpose::VerifyEmail,
+        )?;
+    }
+
+    Ok(HttpResponse::Accepted().json(serde_json::json!({
+        "message": "If the address belongs to an unverified account, a new link is on its way"
+    })))
+}
+
+/// Emails a password reset link. The response is the same whether or not
+/// the address belongs to an account.
+pub async fn forgot_password(
+    db: web::Data<DatabaseConnection>,
+    mailer: web::Data<MailService>,
+    tokens: web::Data<AccountTokenKey>,
+    body: web::Json<EmailRequest>,
+) -> Result<HttpResponse, ActixError> {
+    let user = find_by_email(db.get_ref(), &body.email).await?;
+
+    if let Some(user) = user.filter(|user| user.is_active) {
+        send_account_email(
+            mailer.get_ref().clone(),
+            &tokens,
+            &user,
+            TokenPurpose::PasswordReset,
+        )?;
+    }
+
+    Ok(HttpResponse::Accepted().json(serde_json::json!({
+        "message": "If the address belongs to an account, a reset link is on its way"
+    })))
+}
+
+/// Sets a new password with a reset token and logs out every session.
+pub async fn reset_password(
+    db: web::Data<DatabaseConnection>,
+    tokens: web::Data<AccountTokenKey>,
+    body: web::Json<ResetPasswordRequest>,
+) -> Result<HttpResponse, ActixError> {
+    if body.password.len() < MIN_PASSWORD_LENGTH {
+        return Err(actix_web::error::ErrorBadRequest("Password is too short"));
+    }
+
+    let user = tokens
+        .verify(db.get_ref(), &body.token, TokenPurpose::PasswordReset)
+        .await?;
+
+    let password_hash = hash(body.password.as_bytes(), DEFAULT_COST)
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Password hashing failed"))?;
+
+    // Following the emailed link also proves the address is real
+    let changes = user::ActiveModel {
+        password_hash: Set(Some(password_hash)),
+        email_verified: Set(true),
+        updated_at: Set(Utc::now().into()),
+        ..Default::default()
+    };
+
+    let result = User::update_many()
+        .set(changes)
+        .filter(user::Column::Id.eq(user.id))
+        .filter(match &user.password_hash {
+            Some(password_hash) => user::Column::PasswordHash.eq(password_hash.clone()),
+            None => user::Column::PasswordHash.is_null(),
+        })
+        .exec(db.get_ref())
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    if result.rows_affected != 1 {
+        return Err(actix_web::error::ErrorBadRequest(
+            "Invalid or expired token",
+        ));
+    }
+
+    // Whoever can read the mailbox may log in again straight away
+    login_throttle::clear(db.get_ref(), ThrottleKey::Email(&user.email)).await?;
+
+    let revoked = session::revoke_all_for_user(db.get_ref(), user.id).await?;
+    println!(
+        "Password reset for user {}, {} sessions revoked",
+        user.id, revoked
+    );
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "Password updated, please log in again"
+    })))
+}
+
+async fn find_by_email(
+    db: &DatabaseConnection,
+    email: &str,
+) -> Result<Option<user::Model>, ActixError> {
+    User::find()
+        .filter(user::Column::Email.eq(email))
+        .one(db)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))
+}
+
+/// Issues a token for `purpose` and mails it in the background, so response
+/// times do not reveal whether an account exists.
+pub(crate) fn send_account_email(
+    mailer: MailService,
+    tokens: &AccountTokenKey,
+    user: &user::Model,
+    purpose: TokenPurpose,
+) -> Result<(), ActixError> {
+    // Email changes are confirmed from the new address
+    let email = match purpose {
+        TokenPurpose::ChangeEmail => user.pending_email.clone().unwrap_or_default(),
+        TokenPurpose::VerifyEmail | TokenPurpose::PasswordReset | TokenPurpose::ConfirmChange => {
+            user.email.clone()
+        }
+        TokenPurpose::TwoFactorLogin => {
+            return Err(actix_web::error::ErrorInternalServerError(
+                "Login challenges are not mailed",
+            ))
+        }
+    };
+    let token = tokens.issue(user, purpose)?;
+    let name = user.full_name.clone();
+
+    actix_web::rt::spawn(async move {
+        let result = match purpose {
+            TokenPurpose::VerifyEmail => {
+                mailer.send_verification_email(&email, &name, &token).await
+            }
+            TokenPurpose::PasswordReset => mailer.send_password_reset(&email, &name, &token).await,
+            TokenPurpose::ChangeEmail => mailer.send_email_change(&email, &name, &token).await,
+            TokenPurpose::ConfirmChange => {
+                mailer.send_change_confirmation(&email, &name, &token).await
+            }
+            // Refused above
+            TokenPurpose::TwoFactorLogin => return,
+        };
+        if let Err(e) = result {
+            println!("Failed to send {:?} email to {}: {}", purpose, email, e);
+        }
+    });
+
+    Ok(())
 }
@@ -0,0 +1,160 @@
+use crate::{
+    error::AppError,
+    handlers::document::{list_page, DocumentListQuery},
+    middleware::auth::AuthenticatedUser,
+    models::collection::{self, Entity as Collection},
+    services::document_filter::DocumentFilter,
+};
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder,
+    Set,
+};
+use serde::Deserialize;
+
+const MAX_NAME_LENGTH: usize = 255;
+
+#[derive(Deserialize)]
+pub struct CreateCollectionRequest {
+    pub name: String,
+    #[serde(default)]
+    pub filter: DocumentFilter,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateCollectionRequest {
+    pub name: Option<String>,
+    pub filter: Option<DocumentFilter>,
+}
+
+fn validate_name(name: &str) -> Result<String, AppError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(AppError::BadRequest(format!(
+            "Collection name must be between 1 and {} characters",
+            MAX_NAME_LENGTH
+        )));
+    }
+    Ok(name.to_string())
+}
+
+fn filter_json(filter: &DocumentFilter) -> Result<serde_json::Value, AppError> {
+    filter.validate()?;
+    serde_json::to_value(filter).map_err(|e| AppError::InternalServerError(e.to_string()))
+}
+
+fn name_conflict(e: DbErr) -> AppError {
+    if e.to_string().contains("collections_user_id_name_key") {
+        AppError::Conflict("A collection with this name already exists".into())
+    } else {
+        e.into()
+    }
+}
+
+async fn find_for_user(
+    db: &DatabaseConnection,
+    user_id: i32,
+    collection_id: i32,
+) -> Result<collection::Model, AppError> {
+    Collection::find_by_id(collection_id)
+        .filter(collection::Column::UserId.eq(user_id))
+        .one(db)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Collection not found".into()))
+}
+
+pub async fn list_collections(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, AppError> {
+    let collections = Collection::find()
+        .filter(collection::Column::UserId.eq(user.id))
+        .order_by_asc(collection::Column::Name)
+        .all(db.get_ref())
+        .await?;
+
+    Ok(HttpResponse::Ok().json(collections))
+}
+
+pub async fn create_collection(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    body: web::Json<CreateCollectionRequest>,
+) -> Result<HttpResponse, AppError> {
+    let collection = collection::ActiveModel {
+        user_id: Set(user.id),
+        name: Set(validate_name(&body.name)?),
+        filter: Set(filter_json(&body.filter)?),
+        created_at: Set(Utc::now().into()),
+        updated_at: Set(Utc::now().into()),
+        ..Default::default()
+    }
+    .insert(db.get_ref())
+    .await
+    .map_err(name_conflict)?;
+
+    Ok(HttpResponse::Created().json(collection))
+}
+
+pub async fn get_collection(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    let collection = find_for_user(db.get_ref(), user.id, path.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(collection))
+}
+
+/// Renames a collection or replaces its filter.
+pub async fn update_collection(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    path: web::Path<i32>,
+    body: web::Json<UpdateCollectionRequest>,
+) -> Result<HttpResponse, AppError> {
+    let collection = find_for_user(db.get_ref(), user.id, path.into_inner()).await?;
+    let mut changes: collection::ActiveModel = collection.into();
+
+    if let Some(name) = &body.name {
+        changes.name = Set(validate_name(name)?);
+    }
+    if let Some(filter) = &body.filter {
+        changes.filter = Set(filter_json(filter)?);
+    }
+
+    // updated_at is bumped by the collections trigger
+    let collection = changes.update(db.get_ref()).await.map_err(name_conflict)?;
+    Ok(HttpResponse::Ok().json(collection))
+}
+
+/// Deletes the collection only; its documents are untouched.
+pub async fn delete_collection(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    let collection = find_for_user(db.get_ref(), user.id, path.into_inner()).await?;
+    Collection::delete_by_id(collection.id)
+        .exec(db.get_ref())
+        .await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// The documents currently matching the collection's filter. Takes the same
+/// paging, sorting and filter parameters as the document list, which narrow
+/// the collection further.
+pub async fn list_collection_documents(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    path: web::Path<i32>,
+    query: web::Query<DocumentListQuery>,
+) -> Result<HttpResponse, AppError> {
+    let collection = find_for_user(db.get_ref(), user.id, path.into_inner()).await?;
+    let filter: DocumentFilter = serde_json::from_value(collection.filter)
+        .map_err(|e| AppError::InternalServerError(format!("Invalid collection filter: {}", e)))?;
+
+    let page = list_page(db.get_ref(), user.id, &query, Some(&filter)).await?;
+    Ok(HttpResponse::Ok().json(page))
+}
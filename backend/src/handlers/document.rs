@@ -1,116 +1,252 @@
 use crate::{
+    error::AppError,
+    handlers::folder::nullable,
     middleware::auth::AuthenticatedUser,
-    models::{document, subscription},
-    services::storage::StorageService,
+    models::document::{self, DocumentResponse, TextStatus},
+    services::{
+        document_filter::DocumentFilter,
+        duplicate::{self, DuplicateAction},
+        file_type::{self, FileKind},
+        folder as folders,
+        pdf_processor::{Pdf, PdfError, PdfInfo, RangeReader},
+        plan, preview,
+        storage::{StorageError, StorageService},
+        tag as tags, text_extraction,
+    },
+};
+use actix_multipart::{Multipart, MultipartError};
+use actix_web::{
+    http::{
+        header::{
+            ContentRange, ContentRangeSpec, ETag, EntityTag, Header, HttpDate, IfRange,
+            LastModified, Range,
+        },
+        StatusCode,
+    },
+    web, Error, HttpRequest, HttpResponse,
+};
+use bytes::Bytes;
+use futures_util::{
+    future::ready,
+    stream::{self, StreamExt},
+    TryStreamExt,
 };
-use actix_multipart::Multipart;
-use actix_web::{web, Error, HttpResponse};
-use futures_util::TryStreamExt;
 use sanitize_filename::sanitize;
-use sea_orm::{ActiveValue::Set, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
-use std::path::Path;
+use sea_orm::{
+    ActiveModelTrait, ActiveValue::Set, ColumnTrait, DatabaseConnection, EntityTrait, Order,
+    PaginatorTrait, QueryFilter, QueryOrder,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Error as IoError;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::{fs, io::AsyncWriteExt, runtime::Handle};
+use tokio_util::io::ReaderStream;
 use uuid::Uuid;
 
+#[derive(Deserialize)]
+pub struct UploadQuery {
+    pub folder_id: Option<i32>,                // Top level when omitted
+    pub on_duplicate: Option<DuplicateAction>, // keep when omitted
+}
+
+#[derive(Serialize)]
+struct UploadResponse {
+    #[serde(flatten)]
+    document: DocumentResponse,
+    duplicate_of: Option<i32>, // The user's oldest other document with the same content
+}
+
 pub async fn upload_document(
     db: web::Data<DatabaseConnection>,
     storage: web::Data<StorageService>,
+    query: web::Query<UploadQuery>,
     mut payload: Multipart,
     user: AuthenticatedUser,
 ) -> Result<HttpResponse, Error> {
-    while let Some(mut field) = payload.try_next().await? {
+    if let Some(folder_id) = query.folder_id {
+        folders::find_for_user(db.get_ref(), user.id, folder_id).await?;
+    }
+
+    if let Some(field) = payload.try_next().await? {
         let content_disposition = field.content_disposition();
 
         let filename = content_disposition
             .and_then(|cd| cd.get_filename())
-            .map(|f| sanitize(f))
+            .map(sanitize)
             .ok_or_else(|| actix_web::error::ErrorBadRequest("No filename provided"))?;
 
         // Get the file extension
         let extension = Path::new(&filename)
             .extension()
             .and_then(|ext| ext.to_str())
-            .unwrap_or("");
-
-        let content_type = field
-            .content_type()
-            .map(|t| t.to_string())
-            .unwrap_or_else(|| {
-                // Set proper MIME type based on file extension
-                match extension.to_lowercase().as_str() {
-                    "pdf" => "application/pdf".to_string(),
-                    "doc" => "application/msword".to_string(),
-                    "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document".to_string(),
-                    "txt" => "text/plain".to_string(),
-                    _ => "application/octet-stream".to_string(),
+            .unwrap_or("")
+            .to_string();
+        let declared_type = field.content_type().map(|t| t.essence_str().to_string());
+        // Small files end while they are being sniffed, and a field must not
+        // be polled again after its end
+        let mut field = field.fuse();
+
+        // Read enough of the file to recognise its type before storing anything
+        let mut head: Vec<Bytes> = Vec::new();
+        let mut head_len = 0;
+        let mut complete = false;
+        while head_len < file_type::SNIFF_LEN {
+            match field.try_next().await? {
+                Some(chunk) => {
+                    head_len += chunk.len();
+                    head.push(chunk);
+                }
+                None => {
+                    complete = true;
+                    break;
                 }
-            });
+            }
+        }
+        let kind = file_type::check_upload(
+            &head.concat(),
+            complete,
+            &extension,
+            declared_type.as_deref(),
+        )?;
+        let content_type = kind.mime_type().to_string();
 
         // Include the file extension in the S3 key
         let s3_key = format!("{}/{}.{}", user.id, Uuid::new_v4(), extension);
 
-        // Read the file data into a buffer
-        let mut size: i64 = 0;
-        let mut buffer = Vec::new();
-        while let Some(chunk) = field.try_next().await? {
-            size += chunk.len() as i64;
-            buffer.extend_from_slice(&chunk);
-        }
+        // Resolve the user's storage limit from their plan
+        let user_storage_limit = plan::storage_limit_for_user(db.get_ref(), user.id).await?;
+        // Calculate current storage usage, counting files shared by linked documents once
+        let current_storage_usage = plan::storage_used_by(db.get_ref(), &[user.id])
+            .await?
+            .get(&user.id)
+            .copied()
+            .unwrap_or(0);
 
-        // Fetch user's subscription to get storage limit
-        let subscription = subscription::Entity::find()
-            .filter(subscription::Column::UserId.eq(user.id))
-            .one(db.get_ref())
-            .await
-            .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
+        let remaining_quota = user_storage_limit - current_storage_usage;
 
-        let user_storage_limit = subscription.map(|s| s.storage_limit_bytes).unwrap_or(0);
-        // Calculate current storage usage by summing file sizes of user's documents
-        let current_storage_usage_result = document::Entity::find()
-            .filter(document::Column::UserId.eq(user.id))
-            .all(db.get_ref())
-            .await;
+        let mut hasher = Sha256::new();
+        let body = stream::iter(head.into_iter().map(Ok::<_, MultipartError>))
+            .chain(&mut field)
+            .inspect_ok(|chunk| hasher.update(chunk));
 
-        let current_storage_usage = match current_storage_usage_result {
-            Ok(documents) => documents.iter().map(|doc| doc.file_size).sum(),
-            Err(_) => 0, // Default to 0 if there's an error fetching documents
+        // A file that may be refused or linked to a stored copy waits on disk
+        // until its hash is known, so that neither is stored or counted
+        // against the quota. Any copy had to fit within the storage limit.
+        let on_duplicate = query.on_duplicate.unwrap_or_default();
+        let (size, spilled) = if on_duplicate == DuplicateAction::Keep {
+            // Stream the field to S3, enforcing the quota as bytes arrive
+            let size = storage
+                .upload_stream(&s3_key, &content_type, body, remaining_quota)
+                .await
+                .map_err(upload_error)?;
+            (size, None)
+        } else {
+            let (spilled, size) = SpilledUpload::write(body, user_storage_limit).await?;
+            (size, Some(spilled))
         };
 
-        // Upload to S3
-        storage
-            .upload_file(
-                &s3_key,
-                &content_type,
-                std::io::Cursor::new(buffer.clone()),
-                user_storage_limit,
-                current_storage_usage,
-            )
-            .await
-            .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
+        // A request carries one file; anything after it is refused rather than
+        // dropped. The next field is only handed out once this one is gone.
+        drop(field);
+        if payload.try_next().await?.is_some() {
+            if spilled.is_none() {
+                discard_upload(&storage, &s3_key).await;
+            }
+            return Err(actix_web::error::ErrorBadRequest(
+                "Only one file can be uploaded per request",
+            ));
+        }
+
+        let content_hash = hex::encode(hasher.finalize());
+        let original = duplicate::find_original(db.get_ref(), user.id, &content_hash).await?;
+        if let (Some(original), DuplicateAction::Reject) = (&original, on_duplicate) {
+            let tags = tags::names_by_document(db.get_ref(), &[original.id])
+                .await?
+                .remove(&original.id)
+                .unwrap_or_default();
+            return Ok(HttpResponse::Conflict().json(serde_json::json!({
+                "error": format!("This file is already stored as \"{}\"", original.filename),
+                "document": DocumentResponse::from(original.clone()).with_tags(tags),
+            })));
+        }
+
+        let text_status = if text_extraction::supports(&content_type) {
+            TextStatus::Pending
+        } else {
+            TextStatus::Unsupported
+        };
 
         // Save to database
-        let document = document::ActiveModel {
+        let mut document = document::ActiveModel {
             user_id: Set(user.id),
+            folder_id: Set(query.folder_id),
             filename: Set(filename),
             file_size: Set(size),
-            mime_type: Set(content_type),
-            s3_key: Set(s3_key),
+            mime_type: Set(content_type.clone()),
+            content_hash: Set(Some(content_hash)),
+            text_status: Set(text_status),
             ..Default::default()
         };
 
-        let result = document::Entity::insert(document)
-            .exec(db.get_ref())
-            .await
-            .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
+        // The new document uses the stored copy instead, unless that has
+        // been deleted in the meantime
+        let mut linked = None;
+        if let (Some(original), DuplicateAction::Link) = (&original, on_duplicate) {
+            document.s3_key = Set(original.s3_key.clone());
+            if kind == FileKind::Pdf {
+                describe_pdf(&mut document, &storage, &original.s3_key, size).await?;
+            }
+            linked = duplicate::link(db.get_ref(), document.clone()).await?;
+        }
+
+        let document_id = match linked {
+            Some(document_id) => document_id,
+            None => {
+                if let Some(spilled) = &spilled {
+                    if size > remaining_quota {
+                        return Err(upload_error(StorageError::QuotaExceeded));
+                    }
+                    spilled
+                        .upload(&storage, &s3_key, &content_type, remaining_quota)
+                        .await?;
+                }
+
+                // Damaged PDFs are only noticed once they are stored, so they are removed again
+                document.s3_key = Set(s3_key.clone());
+                if kind == FileKind::Pdf {
+                    if let Err(e) = describe_pdf(&mut document, &storage, &s3_key, size).await {
+                        discard_upload(&storage, &s3_key).await;
+                        return Err(e.into());
+                    }
+                }
+
+                document::Entity::insert(document)
+                    .exec(db.get_ref())
+                    .await
+                    .map_err(actix_web::error::ErrorInternalServerError)?
+                    .last_insert_id
+            }
+        };
 
-        let document = document::Entity::find_by_id(result.last_insert_id)
+        let document = document::Entity::find_by_id(document_id)
             .one(db.get_ref())
             .await
-            .map_err(|e| actix_web::error::ErrorInternalServerError(e))?
+            .map_err(actix_web::error::ErrorInternalServerError)?
             .ok_or_else(|| {
                 actix_web::error::ErrorInternalServerError("Failed to fetch created document")
             })?;
 
-        return Ok(HttpResponse::Ok().json(document));
+        if document.text_status == TextStatus::Pending {
+            text_extraction::spawn(db.get_ref().clone(), storage.get_ref().clone(), document.id);
+        }
+
+        return Ok(HttpResponse::Ok().json(UploadResponse {
+            document: DocumentResponse::from(document),
+            duplicate_of: original.map(|original| original.id),
+        }));
     }
 
     Ok(HttpResponse::BadRequest().json(serde_json::json!({
@@ -118,7 +254,169 @@ pub async fn upload_document(
     })))
 }
 
+fn upload_error(e: StorageError) -> Error {
+    match e {
+        StorageError::QuotaExceeded => actix_web::error::ErrorPayloadTooLarge(e),
+        _ => actix_web::error::ErrorInternalServerError(e),
+    }
+}
+
+/// An upload held in a temporary file until it is known whether it needs
+/// storing. The file is removed when this is dropped.
+struct SpilledUpload {
+    path: PathBuf,
+}
+
+impl SpilledUpload {
+    /// Writes `body` to a temporary file and returns its size. Fails with
+    /// `413 Payload Too Large` past `limit` bytes.
+    async fn write<S>(body: S, limit: i64) -> Result<(Self, i64), Error>
+    where
+        S: futures_util::Stream<Item = Result<Bytes, MultipartError>>,
+    {
+        let spilled = SpilledUpload {
+            path: std::env::temp_dir().join(format!("shelf-upload-{}", Uuid::new_v4())),
+        };
+        let mut file = fs::File::create(&spilled.path)
+            .await
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+
+        let mut body = std::pin::pin!(body);
+        let mut size: i64 = 0;
+        while let Some(chunk) = body.try_next().await? {
+            size += chunk.len() as i64;
+            if size > limit {
+                return Err(upload_error(StorageError::QuotaExceeded));
+            }
+            file.write_all(&chunk)
+                .await
+                .map_err(actix_web::error::ErrorInternalServerError)?;
+        }
+        file.flush()
+            .await
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+        Ok((spilled, size))
+    }
+
+    /// Streams the file to storage under `key`.
+    async fn upload(
+        &self,
+        storage: &StorageService,
+        key: &str,
+        content_type: &str,
+        remaining_quota: i64,
+    ) -> Result<(), Error> {
+        let file = fs::File::open(&self.path)
+            .await
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+        storage
+            .upload_stream(key, content_type, ReaderStream::new(file), remaining_quota)
+            .await
+            .map_err(upload_error)?;
+        Ok(())
+    }
+}
+
+impl Drop for SpilledUpload {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            println!("Failed to remove {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Removes an upload from storage that will not become a document.
+async fn discard_upload(storage: &StorageService, s3_key: &str) {
+    if let Err(e) = storage.delete_file(s3_key).await {
+        println!("Failed to delete discarded upload {}: {}", s3_key, e);
+    }
+}
+
+/// Reads byte ranges of a stored file from a blocking thread.
+struct StoredFile {
+    storage: StorageService,
+    key: String,
+    runtime: Handle,
+}
+
+impl RangeReader for StoredFile {
+    fn read(&self, offset: u64, len: usize) -> Result<Vec<u8>, PdfError> {
+        let read = async {
+            let last = offset + len as u64 - 1;
+            let mut body = self
+                .storage
+                .download_file(&self.key, Some((offset, last)))
+                .await?;
+            let mut data = Vec::with_capacity(len);
+            while let Some(chunk) = body.next().await {
+                data.extend_from_slice(&chunk?);
+            }
+            Ok::<_, StorageError>(data)
+        };
+        match self.runtime.block_on(read) {
+            Ok(data) if data.len() == len => Ok(data),
+            Ok(data) => Err(PdfError::Read(format!(
+                "expected {} bytes at offset {}, got {}",
+                len,
+                offset,
+                data.len()
+            ))),
+            Err(e) => Err(PdfError::Read(e.to_string())),
+        }
+    }
+}
+
+/// Checks that a stored PDF has a readable cross-reference table and
+/// trailer, and reads its page count and metadata. Only the parts of the
+/// file needed for that are downloaded.
+async fn inspect_pdf(
+    storage: &StorageService,
+    key: &str,
+    size: u64,
+) -> Result<(Option<i32>, PdfInfo), AppError> {
+    let file = StoredFile {
+        storage: storage.clone(),
+        key: key.to_string(),
+        runtime: Handle::current(),
+    };
+    // Parsing is CPU-bound, so it stays off the async workers
+    web::block(move || {
+        let pdf = Pdf::load_ranged(file, size)?;
+        pdf.catalog()?;
+        let page_count = pdf.pages().ok().map(|pages| pages.len() as i32);
+        Ok((page_count, pdf.info()))
+    })
+    .await
+    .map_err(|e| AppError::InternalServerError(e.to_string()))?
+    .map_err(|e: PdfError| match e {
+        PdfError::Read(_) => AppError::InternalServerError(e.to_string()),
+        e => AppError::BadRequest(format!("The PDF is damaged: {}", e)),
+    })
+}
+
+/// Fills in the page count, PDF details, title and author of a document
+/// from its stored file.
+async fn describe_pdf(
+    document: &mut document::ActiveModel,
+    storage: &StorageService,
+    key: &str,
+    size: i64,
+) -> Result<(), AppError> {
+    let (page_count, info) = inspect_pdf(storage, key, size as u64).await?;
+    // The embedded title and author are a starting point; both stay editable
+    let truncate = |text: &Option<String>| {
+        text.as_ref()
+            .map(|text| text.chars().take(MAX_TITLE_LENGTH).collect::<String>())
+    };
+    document.title = Set(truncate(&info.title));
+    document.author = Set(truncate(&info.author));
+    document.page_count = Set(page_count);
+    document.pdf_info = Set(serde_json::to_value(&info).ok());
+    Ok(())
+}
+
 pub async fn download_document(
+    req: HttpRequest,
     db: web::Data<DatabaseConnection>,
     storage: web::Data<StorageService>,
     path: web::Path<i32>,
@@ -130,14 +428,9 @@ pub async fn download_document(
         .filter(document::Column::UserId.eq(user.id))
         .one(db.get_ref())
         .await
-        .map_err(|e| actix_web::error::ErrorInternalServerError(e))?
+        .map_err(actix_web::error::ErrorInternalServerError)?
         .ok_or_else(|| actix_web::error::ErrorNotFound("Document not found"))?;
 
-    let stream = storage
-        .download_file(&document.s3_key)
-        .await
-        .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
-
     // Set proper content type for PDFs and serve inline for viewing
     let content_type = if document.mime_type.contains("pdf") {
         "application/pdf"
@@ -151,26 +444,448 @@ pub async fn download_document(
         format!("attachment; filename=\"{}\"", document.filename)
     };
 
-    Ok(HttpResponse::Ok()
+    // Stored files are never overwritten, so the tag follows the key the file
+    // is stored under rather than the metadata, which changes on every edit
+    let stored_name = Path::new(&document.s3_key)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or(&document.s3_key);
+    let etag = EntityTag::new_strong(stored_name.to_string());
+    // HTTP dates have whole seconds, so If-Range dates could never match otherwise
+    let last_modified = HttpDate::from(
+        SystemTime::UNIX_EPOCH + Duration::from_secs(document.created_at.timestamp() as u64),
+    );
+    let total_size = document.file_size as u64;
+
+    let mut response = HttpResponse::Ok();
+    response
         .append_header(("Content-Disposition", content_disposition))
-        .append_header(("Content-Type", content_type))
-        .append_header(("Content-Length", document.file_size.to_string()))
         .append_header(("Cache-Control", "no-cache"))
         .append_header(("Accept-Ranges", "bytes"))
-        .streaming(stream))
+        .insert_header(ETag(etag.clone()))
+        .insert_header(LastModified(last_modified));
+
+    let ranges = match requested_ranges(&req, &etag, last_modified, total_size) {
+        RequestedRanges::Full => {
+            let stream = storage
+                .download_file(&document.s3_key, None)
+                .await
+                .map_err(actix_web::error::ErrorInternalServerError)?;
+
+            return Ok(response
+                .append_header(("Content-Type", content_type))
+                .no_chunking(total_size)
+                .streaming(stream));
+        }
+        RequestedRanges::Unsatisfiable => {
+            return Ok(HttpResponse::RangeNotSatisfiable()
+                .insert_header(ContentRange(ContentRangeSpec::Bytes {
+                    range: None,
+                    instance_length: Some(total_size),
+                }))
+                .finish());
+        }
+        RequestedRanges::Partial(ranges) => ranges,
+    };
+
+    response.status(StatusCode::PARTIAL_CONTENT);
+
+    if let [(first, last)] = ranges[..] {
+        let stream = storage
+            .download_file(&document.s3_key, Some((first, last)))
+            .await
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+
+        return Ok(response
+            .append_header(("Content-Type", content_type))
+            .insert_header(ContentRange(ContentRangeSpec::Bytes {
+                range: Some((first, last)),
+                instance_length: Some(total_size),
+            }))
+            .no_chunking(last - first + 1)
+            .streaming(stream));
+    }
+
+    // Several ranges are sent as a multipart/byteranges body, fetching each
+    // range from storage only when the previous part has been written.
+    let boundary = Uuid::new_v4().simple().to_string();
+    let mut content_length = 0;
+    let mut parts = Vec::with_capacity(ranges.len());
+    for (first, last) in ranges {
+        let part_header = format!(
+            "--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+            boundary, content_type, first, last, total_size
+        );
+        content_length += part_header.len() as u64 + (last - first + 1) + 2;
+
+        let storage = storage.clone();
+        let key = document.s3_key.clone();
+        let body =
+            stream::once(async move { storage.download_file(&key, Some((first, last))).await })
+                .map(|result| match result {
+                    Ok(stream) => stream,
+                    Err(e) => Box::pin(stream::once(ready(Err(IoError::other(e.to_string()))))),
+                })
+                .flatten();
+
+        parts.push(
+            stream::once(ready(Ok(Bytes::from(part_header))))
+                .chain(body)
+                .chain(stream::once(ready(Ok(Bytes::from_static(b"\r\n"))))),
+        );
+    }
+    let closing = format!("--{}--\r\n", boundary);
+    content_length += closing.len() as u64;
+
+    let body = stream::iter(parts)
+        .flatten()
+        .chain(stream::once(ready(Ok(Bytes::from(closing)))));
+
+    Ok(response
+        .append_header((
+            "Content-Type",
+            format!("multipart/byteranges; boundary={}", boundary),
+        ))
+        .no_chunking(content_length)
+        .streaming(body))
+}
+
+/// Upper bound on the number of ranges served in one multipart response.
+/// Requests asking for more are answered with the full document instead.
+const MAX_RANGES: usize = 32;
+
+#[derive(Debug, PartialEq)]
+enum RequestedRanges {
+    Full,
+    Unsatisfiable,
+    Partial(Vec<(u64, u64)>),
+}
+
+/// Resolves the `Range` and `If-Range` headers of a download request against
+/// the current representation of the document.
+fn requested_ranges(
+    req: &HttpRequest,
+    etag: &EntityTag,
+    last_modified: HttpDate,
+    total_size: u64,
+) -> RequestedRanges {
+    // A missing or malformed Range header is ignored and the whole
+    // document is served.
+    let specs = match Range::parse(req) {
+        Ok(Range::Bytes(specs)) => specs,
+        _ => return RequestedRanges::Full,
+    };
+
+    // If-Range only lets the range through when the client's copy is still
+    // current; weak entity tags never match.
+    if let Ok(if_range) = IfRange::parse(req) {
+        let current = match if_range {
+            IfRange::EntityTag(tag) => tag.strong_eq(etag),
+            IfRange::Date(date) => date == last_modified,
+        };
+        if !current {
+            return RequestedRanges::Full;
+        }
+    }
+
+    if specs.len() > MAX_RANGES {
+        return RequestedRanges::Full;
+    }
+
+    let mut ranges: Vec<(u64, u64)> = specs
+        .iter()
+        .filter_map(|spec| spec.to_satisfiable_range(total_size))
+        .collect();
+
+    // Overlapping and adjacent ranges are merged, in file order, so no byte
+    // is sent twice
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (first, last) in ranges {
+        match merged.last_mut() {
+            Some((_, end)) if first <= *end + 1 => *end = last.max(*end),
+            _ => merged.push((first, last)),
+        }
+    }
+
+    if merged.is_empty() {
+        RequestedRanges::Unsatisfiable
+    } else {
+        RequestedRanges::Partial(merged)
+    }
+}
+
+const DEFAULT_PAGE_SIZE: u64 = 20;
+const MAX_PAGE_SIZE: u64 = 100;
+
+#[derive(Deserialize)]
+pub struct DocumentListQuery {
+    pub page: Option<u64>, // 1-based
+    pub per_page: Option<u64>,
+    pub sort: Option<String>,  // "name", "size" or "created_at" (default)
+    pub order: Option<String>, // "asc" or "desc" (default)
+    pub tags: Option<String>,  // Comma-separated; documents must carry all of them
+    pub mime_type: Option<String>,
+    pub min_size: Option<i64>,
+    pub max_size: Option<i64>,
+    pub created_from: Option<String>,
+    pub created_to: Option<String>,
+    pub folder_id: Option<String>, // A folder id, or "root" for the top level
+    #[serde(default)]
+    pub recursive: bool, // Include the folder's subfolders
+}
+
+impl DocumentListQuery {
+    fn filter(&self) -> DocumentFilter {
+        DocumentFilter {
+            tags: self
+                .tags
+                .as_deref()
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|tag| !tag.is_empty())
+                .map(String::from)
+                .collect(),
+            mime_type: self.mime_type.clone(),
+            min_size: self.min_size,
+            max_size: self.max_size,
+            created_from: self.created_from.clone(),
+            created_to: self.created_to.clone(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct DocumentListResponse {
+    pub documents: Vec<DocumentResponse>,
+    pub total: u64,
+    pub page: u64,
+    pub per_page: u64,
+}
+
+/// One page of the user's documents matching the query, and `base` when
+/// listing a smart collection.
+pub(crate) async fn list_page(
+    db: &DatabaseConnection,
+    user_id: i32,
+    query: &DocumentListQuery,
+    base: Option<&DocumentFilter>,
+) -> Result<DocumentListResponse, AppError> {
+    let per_page = query
+        .per_page
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let page = query.page.unwrap_or(1).max(1);
+
+    let sort_column = match query.sort.as_deref().unwrap_or("created_at") {
+        "name" => document::Column::Filename,
+        "size" => document::Column::FileSize,
+        "created_at" => document::Column::CreatedAt,
+        other => {
+            return Err(AppError::BadRequest(format!(
+                "Unknown sort field: {}",
+                other
+            )))
+        }
+    };
+    let order = match query.order.as_deref().unwrap_or("desc") {
+        "asc" => Order::Asc,
+        "desc" => Order::Desc,
+        other => {
+            return Err(AppError::BadRequest(format!(
+                "Unknown sort order: {}",
+                other
+            )))
+        }
+    };
+
+    // The id breaks ties, so pages do not overlap when sort values repeat
+    let mut select = document::Entity::find()
+        .order_by(sort_column, order.clone())
+        .order_by(document::Column::Id, order);
+
+    if let Some(base) = base {
+        select = base.apply(db, user_id, select).await?;
+    }
+    select = query.filter().apply(db, user_id, select).await?;
+
+    match query.folder_id.as_deref() {
+        None => {}
+        // Everything is below the top level
+        Some("root") if query.recursive => {}
+        Some("root") => select = select.filter(document::Column::FolderId.is_null()),
+        Some(folder_id) => {
+            let folder_id: i32 = folder_id.parse().map_err(|_| {
+                AppError::BadRequest("folder_id must be a folder id or \"root\"".into())
+            })?;
+            folders::find_for_user(db, user_id, folder_id).await?;
+
+            let folder_ids = if query.recursive {
+                folders::subtree_ids(db, user_id, folder_id).await?
+            } else {
+                vec![folder_id]
+            };
+            select = select.filter(document::Column::FolderId.is_in(folder_ids));
+        }
+    }
+
+    let paginator = select.paginate(db, per_page);
+    let total = paginator.num_items().await?;
+    let documents = paginator.fetch_page(page - 1).await?;
+
+    let ids: Vec<i32> = documents.iter().map(|document| document.id).collect();
+    let mut tags = tags::names_by_document(db, &ids).await?;
+
+    Ok(DocumentListResponse {
+        documents: documents
+            .into_iter()
+            .map(|document| {
+                let tags = tags.remove(&document.id).unwrap_or_default();
+                DocumentResponse::from(document).with_tags(tags)
+            })
+            .collect(),
+        total,
+        page,
+        per_page,
+    })
 }
 
 pub async fn list_documents(
     db: web::Data<DatabaseConnection>,
+    query: web::Query<DocumentListQuery>,
     user: AuthenticatedUser,
 ) -> Result<HttpResponse, Error> {
-    let documents = document::Entity::find()
+    let page = list_page(db.get_ref(), user.id, &query, None).await?;
+    Ok(HttpResponse::Ok().json(page))
+}
+
+/// The user's documents grouped by identical content, for cleaning up copies.
+pub async fn list_duplicates(
+    db: web::Data<DatabaseConnection>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    let groups = duplicate::groups(db.get_ref(), user.id).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "reclaimable_bytes": groups.iter().map(|group| group.reclaimable_bytes).sum::<i64>(),
+        "groups": groups,
+    })))
+}
+
+const MAX_FILENAME_LENGTH: usize = 255;
+const MAX_TITLE_LENGTH: usize = 255;
+const MAX_DESCRIPTION_LENGTH: usize = 10_000;
+const MAX_METADATA_ENTRIES: usize = 50;
+const MAX_METADATA_KEY_LENGTH: usize = 64;
+const MAX_METADATA_VALUE_LENGTH: usize = 1024;
+
+/// Fields left out are kept; an empty string clears a text field.
+#[derive(Deserialize)]
+pub struct UpdateDocumentRequest {
+    pub filename: Option<String>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub author: Option<String>,
+    pub metadata: Option<HashMap<String, String>>, // Replaces all custom metadata
+    #[serde(default, deserialize_with = "nullable")]
+    pub folder_id: Option<Option<i32>>, // null moves the document to the top level
+}
+
+/// Trims an optional text field, turning an empty value into `None`.
+fn optional_text(name: &str, value: &str, max_length: usize) -> Result<Option<String>, Error> {
+    let value = value.trim();
+    if value.chars().count() > max_length {
+        return Err(actix_web::error::ErrorBadRequest(format!(
+            "{} must be at most {} characters",
+            name, max_length
+        )));
+    }
+    Ok(Some(value.to_string()).filter(|value| !value.is_empty()))
+}
+
+/// Renames a document, moves it to another folder or updates its
+/// descriptive metadata. The stored file is left untouched.
+pub async fn update_document(
+    db: web::Data<DatabaseConnection>,
+    path: web::Path<i32>,
+    body: web::Json<UpdateDocumentRequest>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let document_id = path.into_inner();
+
+    let document = document::Entity::find_by_id(document_id)
         .filter(document::Column::UserId.eq(user.id))
-        .all(db.get_ref())
+        .one(db.get_ref())
         .await
-        .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
+        .map_err(actix_web::error::ErrorInternalServerError)?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Document not found"))?;
+
+    let mut changes: document::ActiveModel = document.into();
+
+    if let Some(filename) = &body.filename {
+        // Sanitized the same way as on upload
+        let filename = sanitize(filename.trim());
+        if filename.is_empty() || filename.len() > MAX_FILENAME_LENGTH {
+            return Err(actix_web::error::ErrorBadRequest(format!(
+                "Filename must be between 1 and {} characters",
+                MAX_FILENAME_LENGTH
+            )));
+        }
+        changes.filename = Set(filename);
+    }
+    if let Some(title) = &body.title {
+        changes.title = Set(optional_text("Title", title, MAX_TITLE_LENGTH)?);
+    }
+    if let Some(description) = &body.description {
+        changes.description = Set(optional_text(
+            "Description",
+            description,
+            MAX_DESCRIPTION_LENGTH,
+        )?);
+    }
+    if let Some(author) = &body.author {
+        changes.author = Set(optional_text("Author", author, MAX_TITLE_LENGTH)?);
+    }
+    if let Some(metadata) = &body.metadata {
+        if metadata.len() > MAX_METADATA_ENTRIES {
+            return Err(actix_web::error::ErrorBadRequest(format!(
+                "At most {} metadata entries are allowed",
+                MAX_METADATA_ENTRIES
+            )));
+        }
+        let invalid = metadata.iter().any(|(key, value)| {
+            key.trim().is_empty()
+                || key.chars().count() > MAX_METADATA_KEY_LENGTH
+                || value.chars().count() > MAX_METADATA_VALUE_LENGTH
+        });
+        if invalid {
+            return Err(actix_web::error::ErrorBadRequest(format!(
+                "Metadata keys must be 1 to {} characters and values at most {}",
+                MAX_METADATA_KEY_LENGTH, MAX_METADATA_VALUE_LENGTH
+            )));
+        }
+        changes.metadata = Set(serde_json::json!(metadata));
+    }
 
-    Ok(HttpResponse::Ok().json(documents))
+    if let Some(folder_id) = body.folder_id {
+        if let Some(folder_id) = folder_id {
+            folders::find_for_user(db.get_ref(), user.id, folder_id).await?;
+        }
+        changes.folder_id = Set(folder_id);
+    }
+
+    // updated_at is bumped by the documents trigger
+    let document = changes
+        .update(db.get_ref())
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let tags = tags::names_by_document(db.get_ref(), &[document.id])
+        .await?
+        .remove(&document.id)
+        .unwrap_or_default();
+
+    Ok(HttpResponse::Ok().json(DocumentResponse::from(document).with_tags(tags)))
 }
 
 pub async fn delete_document(
@@ -185,22 +900,493 @@ pub async fn delete_document(
         .filter(document::Column::UserId.eq(user.id))
         .one(db.get_ref())
         .await
-        .map_err(|e| actix_web::error::ErrorInternalServerError(e))?
+        .map_err(actix_web::error::ErrorInternalServerError)?
         .ok_or_else(|| actix_web::error::ErrorNotFound("Document not found"))?;
 
-    // Delete from S3
-    storage
-        .delete_file(&document.s3_key)
-        .await
-        .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
-
-    // Delete from database
-    document::Entity::delete_by_id(document_id)
-        .exec(db.get_ref())
-        .await
-        .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
+    // Delete from the database, then from S3 unless other documents are
+    // linked to the same file. The document is gone either way.
+    if duplicate::delete(db.get_ref(), &document).await? {
+        if let Err(e) = storage.delete_file(&document.s3_key).await {
+            println!("Failed to delete stored file {}: {}", document.s3_key, e);
+        }
+        preview::remove_cached(storage.get_ref(), &document.s3_key).await;
+    }
 
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "message": "Document deleted successfully"
     })))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::storage::MemoryBackend;
+    use crate::test_support::TestDb;
+    use actix_web::{
+        body::to_bytes,
+        error::PayloadError,
+        http::header::{HeaderMap, HeaderValue, CONTENT_TYPE},
+        test::TestRequest,
+    };
+    use serde_json::Value;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    const SIZE: u64 = 1000;
+
+    fn etag() -> EntityTag {
+        EntityTag::new_strong("stored".to_string())
+    }
+
+    fn last_modified() -> HttpDate {
+        HttpDate::from(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+    }
+
+    fn ranges(headers: &[(&str, &str)]) -> RequestedRanges {
+        let mut req = TestRequest::default();
+        for &header in headers {
+            req = req.insert_header(header);
+        }
+        requested_ranges(&req.to_http_request(), &etag(), last_modified(), SIZE)
+    }
+
+    fn range(range: &str) -> RequestedRanges {
+        ranges(&[("Range", range)])
+    }
+
+    #[test]
+    fn single_ranges_are_clamped_to_the_file() {
+        for (header, expected) in [
+            ("bytes=0-99", (0, 99)),
+            ("bytes=990-2000", (990, 999)),
+            ("bytes=900-", (900, 999)),
+            ("bytes=-100", (900, 999)),
+            ("bytes=-5000", (0, 999)),
+        ] {
+            assert_eq!(
+                range(header),
+                RequestedRanges::Partial(vec![expected]),
+                "{}",
+                header
+            );
+        }
+    }
+
+    #[test]
+    fn ranges_past_the_end_are_unsatisfiable() {
+        for header in [
+            "bytes=1000-",
+            "bytes=1000-1099",
+            "bytes=1000-1099,2000-",
+            "bytes=-0",
+        ] {
+            assert_eq!(range(header), RequestedRanges::Unsatisfiable, "{}", header);
+        }
+        // Only the satisfiable ones are served
+        assert_eq!(
+            range("bytes=0-9,5000-5009"),
+            RequestedRanges::Partial(vec![(0, 9)])
+        );
+    }
+
+    #[test]
+    fn malformed_ranges_get_the_full_file() {
+        for header in ["bytes=abc", "bytes=10-5", "items=0-9", "bytes="] {
+            assert_eq!(range(header), RequestedRanges::Full, "{}", header);
+        }
+        assert_eq!(ranges(&[]), RequestedRanges::Full);
+    }
+
+    #[test]
+    fn overlapping_ranges_are_merged_in_file_order() {
+        assert_eq!(
+            range("bytes=500-599,0-99"),
+            RequestedRanges::Partial(vec![(0, 99), (500, 599)])
+        );
+        assert_eq!(
+            range("bytes=0-99,50-149,150-199,-100"),
+            RequestedRanges::Partial(vec![(0, 199), (900, 999)])
+        );
+        assert_eq!(
+            range("bytes=0-,0-,100-200"),
+            RequestedRanges::Partial(vec![(0, 999)])
+        );
+    }
+
+    #[test]
+    fn too_many_ranges_get_the_full_file() {
+        let specs = |count: u64| {
+            let specs: Vec<String> = (0..count)
+                .map(|i| format!("{}-{}", i * 10, i * 10 + 4))
+                .collect();
+            format!("bytes={}", specs.join(","))
+        };
+        let RequestedRanges::Partial(served) = range(&specs(MAX_RANGES as u64)) else {
+            panic!("expected {} ranges", MAX_RANGES);
+        };
+        assert_eq!(served.len(), MAX_RANGES);
+        assert_eq!(range(&specs(MAX_RANGES as u64 + 1)), RequestedRanges::Full);
+    }
+
+    #[test]
+    fn if_range_needs_the_current_validator() {
+        let date = last_modified().to_string();
+        let older = HttpDate::from(SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000));
+        let partial = RequestedRanges::Partial(vec![(0, 99)]);
+        for (if_range, expected) in [
+            ("\"stored\"".to_string(), &partial),
+            ("\"replaced\"".to_string(), &RequestedRanges::Full),
+            ("W/\"stored\"".to_string(), &RequestedRanges::Full),
+            (date, &partial),
+            (older.to_string(), &RequestedRanges::Full),
+        ] {
+            let requested = ranges(&[("Range", "bytes=0-99"), ("If-Range", &if_range)]);
+            assert_eq!(&requested, expected, "{}", if_range);
+        }
+        // Without a range there is nothing to make conditional
+        assert_eq!(ranges(&[("If-Range", "\"stored\"")]), RequestedRanges::Full);
+    }
+
+    fn user(id: i32) -> AuthenticatedUser {
+        AuthenticatedUser {
+            id,
+            email: "uploads@example.com".to_string(),
+            session_id: None,
+        }
+    }
+
+    /// Uploads `contents` as notes.txt and returns the status and body.
+    async fn upload(
+        db: &DatabaseConnection,
+        storage: &StorageService,
+        user_id: i32,
+        on_duplicate: DuplicateAction,
+        contents: &str,
+    ) -> (StatusCode, Value) {
+        let body = format!(
+            "--boundary\r\n\
+             Content-Disposition: form-data; name=\"file\"; filename=\"notes.txt\"\r\n\
+             Content-Type: text/plain\r\n\r\n{}\r\n--boundary--\r\n",
+            contents
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("multipart/form-data; boundary=boundary"),
+        );
+        let payload = Multipart::new(
+            &headers,
+            stream::once(ready(Ok::<_, PayloadError>(Bytes::from(body)))),
+        );
+        let query = UploadQuery {
+            folder_id: None,
+            on_duplicate: Some(on_duplicate),
+        };
+        let response = upload_document(
+            web::Data::new(db.clone()),
+            web::Data::new(storage.clone()),
+            web::Query(query),
+            payload,
+            user(user_id),
+        )
+        .await
+        .unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    async fn delete(db: &DatabaseConnection, storage: &StorageService, user_id: i32, id: i64) {
+        delete_document(
+            web::Data::new(db.clone()),
+            web::Data::new(storage.clone()),
+            web::Path::from(id as i32),
+            user(user_id),
+        )
+        .await
+        .unwrap();
+    }
+
+    #[actix_web::test]
+    async fn duplicate_uploads_are_rejected_or_linked() {
+        let Some(db) = TestDb::new().await else {
+            return;
+        };
+        let storage = StorageService::new(Arc::new(MemoryBackend::new()));
+        let user_id = db.user("uploads@example.com").await;
+        let stored = || async {
+            let files = storage.list_files(&format!("{}/", user_id)).await.unwrap();
+            files.len()
+        };
+
+        let (status, first) = upload(&db, &storage, user_id, DuplicateAction::Keep, "notes").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(first["duplicate_of"], Value::Null);
+        let first_id = first["id"].as_i64().unwrap();
+
+        let (status, rejected) =
+            upload(&db, &storage, user_id, DuplicateAction::Reject, "notes").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(rejected["document"]["id"], first_id);
+        // Different content is not a duplicate
+        let (status, other) =
+            upload(&db, &storage, user_id, DuplicateAction::Reject, "other").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(stored().await, 2);
+
+        let (status, linked) = upload(&db, &storage, user_id, DuplicateAction::Link, "notes").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(linked["duplicate_of"], first_id);
+        assert_eq!(stored().await, 2);
+        let keys: HashSet<String> = document::Entity::find()
+            .filter(
+                document::Column::Id
+                    .is_in([first_id as i32, linked["id"].as_i64().unwrap() as i32]),
+            )
+            .all(&*db)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|document| document.s3_key)
+            .collect();
+        assert_eq!(keys.len(), 1);
+        // The shared file counts once against the quota
+        let used = plan::storage_used_by(&*db, &[user_id]).await.unwrap();
+        assert_eq!(used.get(&user_id), Some(&10));
+
+        // The file stays until the last document using it is deleted
+        delete(&db, &storage, user_id, first_id).await;
+        assert_eq!(stored().await, 2);
+        delete(&db, &storage, user_id, linked["id"].as_i64().unwrap()).await;
+        assert_eq!(stored().await, 1);
+        delete(&db, &storage, user_id, other["id"].as_i64().unwrap()).await;
+        assert_eq!(stored().await, 0);
+    }
+
+    /// Inserts a document uploaded on `day` of January 2024.
+    async fn stored(db: &TestDb, user_id: i32, filename: &str, file_size: i64, day: u32) -> i32 {
+        let uploaded =
+            chrono::DateTime::parse_from_rfc3339(&format!("2024-01-{:02}T12:00:00Z", day)).unwrap();
+        let mime_type = if filename.ends_with(".pdf") {
+            "application/pdf"
+        } else {
+            "text/plain"
+        };
+        let document = document::ActiveModel {
+            user_id: Set(user_id),
+            filename: Set(filename.to_string()),
+            file_size: Set(file_size),
+            mime_type: Set(mime_type.to_string()),
+            s3_key: Set(format!("{}/{}", user_id, Uuid::new_v4())),
+            created_at: Set(uploaded),
+            updated_at: Set(uploaded),
+            ..Default::default()
+        };
+        document::Entity::insert(document)
+            .exec(&**db)
+            .await
+            .unwrap()
+            .last_insert_id
+    }
+
+    /// Lists the user's documents with a query string, returning the page or
+    /// the error status.
+    async fn list(db: &TestDb, user_id: i32, query: &str) -> Result<Value, StatusCode> {
+        let query = web::Query::<DocumentListQuery>::from_query(query).unwrap();
+        let response = list_documents(web::Data::new(db.connection()), query, user(user_id))
+            .await
+            .map_err(|e| e.error_response().status())?;
+        let body = to_bytes(response.into_body()).await.unwrap();
+        Ok(serde_json::from_slice(&body).unwrap())
+    }
+
+    fn field(page: &Value, name: &str) -> Vec<Value> {
+        page["documents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|document| document[name].clone())
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn documents_are_listed_in_pages() {
+        let Some(db) = TestDb::new().await else {
+            return;
+        };
+        let user_id = db.user("list@example.com").await;
+        let other_user = db.user("other@example.com").await;
+        let c = stored(&db, user_id, "c.pdf", 300, 1).await;
+        let a = stored(&db, user_id, "a.txt", 100, 2).await;
+        let e = stored(&db, user_id, "e.pdf", 200, 3).await;
+        let b = stored(&db, user_id, "b.pdf", 200, 4).await;
+        let d = stored(&db, user_id, "d.txt", 400, 5).await;
+        stored(&db, other_user, "f.pdf", 100, 6).await;
+
+        // Newest first by default
+        let page = list(&db, user_id, "").await.unwrap();
+        assert_eq!(page["total"], 5);
+        assert_eq!(page["page"], 1);
+        assert_eq!(page["per_page"], DEFAULT_PAGE_SIZE);
+        assert_eq!(field(&page, "id"), [d, b, e, a, c]);
+        // Mapped through DocumentResponse, so the storage key stays private
+        assert_eq!(page["documents"][0].get("s3_key"), None);
+
+        let page = list(&db, user_id, "per_page=2&page=2").await.unwrap();
+        assert_eq!(page["total"], 5);
+        assert_eq!(
+            (page["page"].clone(), page["per_page"].clone()),
+            (2.into(), 2.into())
+        );
+        assert_eq!(field(&page, "id"), [e, a]);
+        let page = list(&db, user_id, "per_page=2&page=4").await.unwrap();
+        assert_eq!(page["total"], 5);
+        assert_eq!(field(&page, "id"), Vec::<Value>::new());
+        let page = list(&db, user_id, "per_page=1000&page=0").await.unwrap();
+        assert_eq!(
+            (page["page"].clone(), page["per_page"].clone()),
+            (1.into(), MAX_PAGE_SIZE.into())
+        );
+
+        let page = list(&db, user_id, "sort=name&order=asc").await.unwrap();
+        assert_eq!(
+            field(&page, "filename"),
+            ["a.txt", "b.pdf", "c.pdf", "d.txt", "e.pdf"]
+        );
+        let page = list(&db, user_id, "sort=size&order=desc").await.unwrap();
+        assert_eq!(field(&page, "file_size"), [400, 300, 200, 200, 100]);
+
+        // Equal sizes are ordered by id, so pages neither repeat nor skip them
+        let mut seen = Vec::new();
+        for page in 1..=5 {
+            let page = list(
+                &db,
+                user_id,
+                &format!("sort=size&order=asc&per_page=1&page={}", page),
+            )
+            .await
+            .unwrap();
+            seen.extend(field(&page, "id"));
+        }
+        assert_eq!(seen, [a, e, b, c, d]);
+
+        let page = list(&db, user_id, "mime_type=text/plain").await.unwrap();
+        assert_eq!(
+            (page["total"].clone(), field(&page, "id")),
+            (2.into(), vec![d.into(), a.into()])
+        );
+        let page = list(
+            &db,
+            user_id,
+            "min_size=200&max_size=300&sort=name&order=asc",
+        )
+        .await
+        .unwrap();
+        assert_eq!(field(&page, "filename"), ["b.pdf", "c.pdf", "e.pdf"]);
+        let page = list(
+            &db,
+            user_id,
+            "created_from=2024-01-02&created_to=2024-01-04",
+        )
+        .await
+        .unwrap();
+        assert_eq!(field(&page, "id"), [b, e, a]);
+
+        for query in ["sort=owner", "order=up", "created_from=yesterday"] {
+            assert_eq!(
+                list(&db, user_id, query).await,
+                Err(StatusCode::BAD_REQUEST),
+                "{}",
+                query
+            );
+        }
+    }
+
+    /// Patches a document, returning the updated document or the error status.
+    async fn update(
+        db: &TestDb,
+        user_id: i32,
+        id: i32,
+        changes: Value,
+    ) -> Result<Value, StatusCode> {
+        let response = update_document(
+            web::Data::new(db.connection()),
+            web::Path::from(id),
+            web::Json(serde_json::from_value(changes).unwrap()),
+            user(user_id),
+        )
+        .await
+        .map_err(|e| e.error_response().status())?;
+        let body = to_bytes(response.into_body()).await.unwrap();
+        Ok(serde_json::from_slice(&body).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn documents_can_be_renamed_and_described() {
+        let Some(db) = TestDb::new().await else {
+            return;
+        };
+        let user_id = db.user("edit@example.com").await;
+        let other_user = db.user("other@example.com").await;
+        let id = stored(&db, user_id, "scan.pdf", 100, 1).await;
+
+        let updated = update(
+            &db,
+            user_id,
+            id,
+            serde_json::json!({
+                "filename": " reports/2024: Q1?.pdf ",
+                "title": "  Annual report ",
+                "author": "Ann",
+                "description": "",
+                "metadata": { "year": "2024" },
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(updated["filename"], sanitize("reports/2024: Q1?.pdf"));
+        assert!(!updated["filename"].as_str().unwrap().contains('/'));
+        assert_eq!(updated["title"], "Annual report");
+        assert_eq!(updated["author"], "Ann");
+        assert_eq!(updated["description"], Value::Null);
+        assert_eq!(updated["metadata"], serde_json::json!({ "year": "2024" }));
+        assert_eq!(updated.get("s3_key"), None);
+        // Bumped by the trigger
+        assert!(updated["updated_at"].as_str().unwrap() > "2024-01-02");
+
+        // Fields left out are kept, and an empty string clears one
+        let updated = update(&db, user_id, id, serde_json::json!({ "author": "" }))
+            .await
+            .unwrap();
+        assert_eq!(updated["author"], Value::Null);
+        assert_eq!(updated["title"], "Annual report");
+        assert_eq!(updated["metadata"], serde_json::json!({ "year": "2024" }));
+
+        let page = list(&db, user_id, "").await.unwrap();
+        assert_eq!(field(&page, "title"), ["Annual report"]);
+        assert_eq!(field(&page, "filename"), [updated["filename"].clone()]);
+
+        let long = "x".repeat(MAX_TITLE_LENGTH + 1);
+        for changes in [
+            serde_json::json!({ "filename": "  " }),
+            serde_json::json!({ "filename": "///" }),
+            serde_json::json!({ "title": long }),
+            serde_json::json!({ "metadata": { " ": "blank key" } }),
+        ] {
+            assert_eq!(
+                update(&db, user_id, id, changes.clone()).await,
+                Err(StatusCode::BAD_REQUEST),
+                "{}",
+                changes
+            );
+        }
+        // Nothing was changed by the rejected requests
+        let page = list(&db, user_id, "").await.unwrap();
+        assert_eq!(page["documents"][0]["title"], "Annual report");
+
+        assert_eq!(
+            update(&db, other_user, id, serde_json::json!({ "title": "Mine" })).await,
+            Err(StatusCode::NOT_FOUND)
+        );
+    }
+}
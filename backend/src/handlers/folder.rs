@@ -0,0 +1,280 @@
+use crate::{
+    error::AppError,
+    middleware::auth::AuthenticatedUser,
+    models::{
+        document::{self, Entity as Document},
+        folder::{self, Entity as Folder},
+    },
+    services::folder as folders,
+};
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, PaginatorTrait,
+    QueryFilter, QueryOrder, Set, TransactionTrait,
+};
+use serde::{Deserialize, Deserializer};
+
+#[derive(Deserialize)]
+pub struct CreateFolderRequest {
+    pub name: String,
+    pub parent_id: Option<i32>, // Top level when omitted
+}
+
+#[derive(Deserialize)]
+pub struct UpdateFolderRequest {
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub parent_id: Option<Option<i32>>, // null moves the folder to the top level
+}
+
+/// Tells a field set to `null` (`Some(None)`) apart from a missing one
+/// (`None`), for fields where `null` means "clear".
+pub(crate) fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+fn name_conflict(e: DbErr) -> AppError {
+    if e.to_string().contains("idx_folders_sibling_name") {
+        AppError::Conflict("A folder with this name already exists here".into())
+    } else {
+        e.into()
+    }
+}
+
+/// Every folder of the user, as a flat list ordered by name. Clients build
+/// the tree from `parent_id`.
+pub async fn list_folders(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, AppError> {
+    let folders = Folder::find()
+        .filter(folder::Column::UserId.eq(user.id))
+        .order_by_asc(folder::Column::Name)
+        .order_by_asc(folder::Column::Id)
+        .all(db.get_ref())
+        .await?;
+
+    Ok(HttpResponse::Ok().json(folders))
+}
+
+pub async fn create_folder(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    body: web::Json<CreateFolderRequest>,
+) -> Result<HttpResponse, AppError> {
+    let name = folders::validate_name(&body.name)?;
+    if let Some(parent_id) = body.parent_id {
+        folders::find_for_user(db.get_ref(), user.id, parent_id).await?;
+    }
+
+    let folder = folder::ActiveModel {
+        user_id: Set(user.id),
+        parent_id: Set(body.parent_id),
+        name: Set(name),
+        created_at: Set(Utc::now().into()),
+        updated_at: Set(Utc::now().into()),
+        ..Default::default()
+    }
+    .insert(db.get_ref())
+    .await
+    .map_err(name_conflict)?;
+
+    Ok(HttpResponse::Created().json(folder))
+}
+
+pub async fn get_folder(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    let folder = folders::find_for_user(db.get_ref(), user.id, path.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(folder))
+}
+
+/// Renames a folder or moves it under another parent, along with everything
+/// inside it.
+pub async fn update_folder(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    path: web::Path<i32>,
+    body: web::Json<UpdateFolderRequest>,
+) -> Result<HttpResponse, AppError> {
+    let transaction = db.begin().await?;
+    // Two moves checked side by side could each pass and still form a cycle
+    // together, so the user's folder tree stays locked until this one is done
+    folders::lock_for_user(&transaction, user.id).await?;
+
+    let folder = folders::find_for_user(&transaction, user.id, path.into_inner()).await?;
+    let folder_id = folder.id;
+    let mut changes: folder::ActiveModel = folder.into();
+
+    if let Some(name) = &body.name {
+        changes.name = Set(folders::validate_name(name)?);
+    }
+
+    if let Some(parent_id) = body.parent_id {
+        if let Some(parent_id) = parent_id {
+            folders::find_for_user(&transaction, user.id, parent_id).await?;
+
+            // A folder cannot end up inside itself
+            let subtree = folders::subtree_ids(&transaction, user.id, folder_id).await?;
+            if subtree.contains(&parent_id) {
+                return Err(AppError::BadRequest(
+                    "A folder cannot be moved into itself or one of its subfolders".into(),
+                ));
+            }
+        }
+        changes.parent_id = Set(parent_id);
+    }
+
+    // updated_at is bumped by the folders trigger
+    let folder = changes.update(&transaction).await.map_err(name_conflict)?;
+    transaction.commit().await?;
+    Ok(HttpResponse::Ok().json(folder))
+}
+
+/// Deletes an empty folder. Folders that still hold documents or
+/// subfolders are refused, so nothing is ever removed by accident.
+pub async fn delete_folder(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    let folder = folders::find_for_user(db.get_ref(), user.id, path.into_inner()).await?;
+
+    let subfolders = Folder::find()
+        .filter(folder::Column::ParentId.eq(folder.id))
+        .count(db.get_ref())
+        .await?;
+    let documents = Document::find()
+        .filter(document::Column::FolderId.eq(folder.id))
+        .count(db.get_ref())
+        .await?;
+    if subfolders > 0 || documents > 0 {
+        return Err(AppError::Conflict(format!(
+            "Folder is not empty ({} documents, {} subfolders)",
+            documents, subfolders
+        )));
+    }
+
+    // A document moved in meanwhile makes the foreign key refuse the delete
+    Folder::delete_by_id(folder.id)
+        .exec(db.get_ref())
+        .await
+        .map_err(|_| AppError::Conflict("Folder is not empty".into()))?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestDb;
+    use actix_web::http::StatusCode;
+
+    fn user(id: i32) -> AuthenticatedUser {
+        AuthenticatedUser {
+            id,
+            email: "folders@example.com".to_string(),
+            session_id: None,
+        }
+    }
+
+    async fn create(db: &DatabaseConnection, user_id: i32, name: &str) -> i32 {
+        let body = CreateFolderRequest {
+            name: name.to_string(),
+            parent_id: None,
+        };
+        create_folder(user(user_id), web::Data::new(db.clone()), web::Json(body))
+            .await
+            .unwrap();
+        Folder::find()
+            .filter(folder::Column::Name.eq(name))
+            .one(db)
+            .await
+            .unwrap()
+            .unwrap()
+            .id
+    }
+
+    async fn move_into(
+        db: &DatabaseConnection,
+        user_id: i32,
+        folder_id: i32,
+        parent_id: i32,
+    ) -> Result<HttpResponse, AppError> {
+        let body = UpdateFolderRequest {
+            name: None,
+            parent_id: Some(Some(parent_id)),
+        };
+        update_folder(
+            user(user_id),
+            web::Data::new(db.clone()),
+            web::Path::from(folder_id),
+            web::Json(body),
+        )
+        .await
+    }
+
+    #[actix_web::test]
+    async fn folder_cannot_move_below_itself() {
+        let Some(db) = TestDb::new().await else {
+            return;
+        };
+        let user_id = db.user("folders@example.com").await;
+        let parent = create(&db, user_id, "parent").await;
+        let child = create(&db, user_id, "child").await;
+        move_into(&db, user_id, child, parent).await.unwrap();
+
+        for target in [parent, child] {
+            let result = move_into(&db, user_id, parent, target).await;
+            assert!(matches!(result, Err(AppError::BadRequest(_))));
+        }
+    }
+
+    #[actix_web::test]
+    async fn crossed_moves_cannot_form_a_cycle() {
+        let Some(db) = TestDb::new().await else {
+            return;
+        };
+        let user_id = db.user("folders@example.com").await;
+        let a = create(&db, user_id, "a").await;
+        let b = create(&db, user_id, "b").await;
+
+        let (a_into_b, b_into_a) =
+            futures_util::join!(move_into(&db, user_id, a, b), move_into(&db, user_id, b, a));
+
+        let statuses = [a_into_b, b_into_a].map(|result| match result {
+            Ok(response) => response.status(),
+            Err(e) => actix_web::ResponseError::error_response(&e).status(),
+        });
+        assert!(statuses.contains(&StatusCode::OK));
+        assert!(statuses.contains(&StatusCode::BAD_REQUEST));
+
+        let tops = Folder::find()
+            .filter(folder::Column::ParentId.is_null())
+            .count(&*db)
+            .await
+            .unwrap();
+        assert_eq!(tops, 1);
+    }
+
+    #[actix_web::test]
+    async fn moving_into_another_users_folder_is_not_found() {
+        let Some(db) = TestDb::new().await else {
+            return;
+        };
+        let user_id = db.user("folders@example.com").await;
+        let other_id = db.user("other@example.com").await;
+        let mine = create(&db, user_id, "mine").await;
+        let theirs = create(&db, other_id, "theirs").await;
+
+        let result = move_into(&db, user_id, mine, theirs).await;
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+    }
+}
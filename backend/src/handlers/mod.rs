@@ -1,4 +1,15 @@
+pub mod account;
+pub mod admin;
 pub mod auth;
+pub mod collection;
 pub mod document;
+pub mod folder;
+pub mod oidc;
 pub mod payment;
+pub mod plan;
+pub mod preview;
+pub mod search;
 pub mod subscription;
+pub mod tag;
+pub mod token;
+pub mod two_factor;
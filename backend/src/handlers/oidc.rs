@@ -0,0 +1,246 @@
+use crate::{
+    error::AppError,
+    handlers::auth::{complete_login, send_account_email},
+    middleware::auth::AuthenticatedUser,
+    models::{
+        user::Entity as User,
+        user_identity::{self, Entity as UserIdentity},
+    },
+    services::{
+        account_token::{AccountTokenKey, TokenPurpose},
+        jwt::JwtKeys,
+        mailer::MailService,
+        oidc::{self, OidcOutcome, OidcService},
+    },
+};
+use actix_web::{
+    cookie::{time::Duration as CookieDuration, Cookie, SameSite},
+    web, Error as ActixError, HttpRequest, HttpResponse,
+};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+
+/// Ties a round trip to the browser that started it. Without it, anyone could
+/// have a victim's browser relay the attacker's own `state` and `code` to the
+/// callback and so log the victim into the attacker's account.
+const STATE_COOKIE: &str = "shelf_oidc_state";
+/// Only the callback needs the cookie.
+const STATE_COOKIE_PATH: &str = "/api/auth/oidc";
+
+#[derive(Deserialize)]
+pub struct CallbackRequest {
+    pub state: String,
+    pub code: String,
+}
+
+#[derive(Serialize)]
+pub struct AuthorizationResponse {
+    pub authorization_url: String,
+}
+
+#[derive(Serialize)]
+pub struct IdentityResponse {
+    pub provider: String,
+    pub email: Option<String>,
+    pub created_at: String,
+    pub last_login_at: Option<String>,
+}
+
+fn state_cookie(oidc: &OidcService, state: &str) -> Cookie<'static> {
+    Cookie::build(STATE_COOKIE, oidc::state_binding(state))
+        .path(STATE_COOKIE_PATH)
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .secure(oidc.uses_https())
+        .max_age(CookieDuration::minutes(oidc::STATE_TTL_MINUTES))
+        .finish()
+}
+
+/// Refuses a callback whose `state` was not handed out to this browser.
+fn check_state_cookie(req: &HttpRequest, state: &str) -> Result<(), AppError> {
+    match req.cookie(STATE_COOKIE) {
+        Some(cookie) if cookie.value() == oidc::state_binding(state) => Ok(()),
+        _ => Err(AppError::BadRequest(
+            "This login was not started in this browser".into(),
+        )),
+    }
+}
+
+fn authorization_response(oidc: &OidcService, authorization: oidc::Authorization) -> HttpResponse {
+    HttpResponse::Ok()
+        .cookie(state_cookie(oidc, &authorization.state))
+        .json(AuthorizationResponse {
+            authorization_url: authorization.url,
+        })
+}
+
+pub async fn list_providers(oidc: web::Data<OidcService>) -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({
+        "providers": oidc.provider_names()
+    }))
+}
+
+/// Starts a social login; the frontend sends the browser to the returned URL.
+pub async fn authorize(
+    oidc: web::Data<OidcService>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let authorization = oidc.authorize(&path.into_inner(), None).await?;
+    Ok(authorization_response(&oidc, authorization))
+}
+
+/// Receives `state` and `code` from the provider redirect (relayed by the
+/// frontend) and logs the user in, or finishes linking the provider. The
+/// browser must hold the cookie set when the round trip started.
+pub async fn callback(
+    req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    keys: web::Data<JwtKeys>,
+    oidc: web::Data<OidcService>,
+    mailer: web::Data<MailService>,
+    tokens: web::Data<AccountTokenKey>,
+    body: web::Json<CallbackRequest>,
+) -> Result<HttpResponse, ActixError> {
+    // Checked first, so a forged callback does not use up the real state
+    check_state_cookie(&req, &body.state)?;
+
+    let mut response = match oidc.complete(&body.state, &body.code).await? {
+        OidcOutcome::Login { user, created } => {
+            // The provider did not vouch for the address; confirm it ourselves
+            if created && !user.email_verified {
+                send_account_email(
+                    mailer.get_ref().clone(),
+                    &tokens,
+                    &user,
+                    TokenPurpose::VerifyEmail,
+                )?;
+            }
+            complete_login(&req, db.get_ref(), keys.get_ref(), tokens.get_ref(), user).await?
+        }
+        OidcOutcome::Linked => HttpResponse::Ok().json(serde_json::json!({
+            "linked": true
+        })),
+    };
+
+    let cookie = Cookie::build(STATE_COOKIE, "")
+        .path(STATE_COOKIE_PATH)
+        .finish();
+    response.add_removal_cookie(&cookie)?;
+    Ok(response)
+}
+
+pub async fn list_identities(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, AppError> {
+    let identities = UserIdentity::find()
+        .filter(user_identity::Column::UserId.eq(user.id))
+        .all(db.get_ref())
+        .await?;
+
+    let response: Vec<IdentityResponse> = identities
+        .into_iter()
+        .map(|identity| IdentityResponse {
+            provider: identity.provider,
+            email: identity.email,
+            created_at: identity.created_at.to_rfc3339(),
+            last_login_at: identity.last_login_at.map(|at| at.to_rfc3339()),
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Starts linking a provider to the logged-in user.
+pub async fn link(
+    user: AuthenticatedUser,
+    oidc: web::Data<OidcService>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let authorization = oidc.authorize(&path.into_inner(), Some(user.id)).await?;
+    Ok(authorization_response(&oidc, authorization))
+}
+
+/// Removes a linked provider, unless it is the only way left to log in.
+pub async fn unlink(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let provider = path.into_inner();
+
+    let account = User::find_by_id(user.id)
+        .one(db.get_ref())
+        .await?
+        .ok_or(AppError::NotFound("User not found".into()))?;
+
+    let identity_count = UserIdentity::find()
+        .filter(user_identity::Column::UserId.eq(user.id))
+        .count(db.get_ref())
+        .await?;
+
+    if account.password_hash.is_none() && identity_count <= 1 {
+        return Err(AppError::BadRequest(
+            "Set a password before removing your only login provider".into(),
+        ));
+    }
+
+    let result = UserIdentity::delete_many()
+        .filter(user_identity::Column::UserId.eq(user.id))
+        .filter(user_identity::Column::Provider.eq(provider.clone()))
+        .exec(db.get_ref())
+        .await?;
+
+    if result.rows_affected == 0 {
+        return Err(AppError::NotFound(format!(
+            "No linked {} account",
+            provider
+        )));
+    }
+
+    println!("User {} unlinked their {} account", user.id, provider);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn state_cookie_is_http_only_and_lax() {
+        let oidc = OidcService::from_env(DatabaseConnection::Disconnected).unwrap();
+        let cookie = state_cookie(&oidc, "state");
+
+        assert_eq!(cookie.value(), oidc::state_binding("state"));
+        assert_eq!(cookie.http_only(), Some(true));
+        assert_eq!(cookie.same_site(), Some(SameSite::Lax));
+        assert_eq!(cookie.path(), Some(STATE_COOKIE_PATH));
+        assert_eq!(
+            cookie.max_age(),
+            Some(CookieDuration::minutes(oidc::STATE_TTL_MINUTES))
+        );
+    }
+
+    #[test]
+    fn callback_needs_the_cookie_of_its_state() {
+        let cookie = Cookie::new(STATE_COOKIE, oidc::state_binding("state"));
+        let req = TestRequest::default().cookie(cookie).to_http_request();
+        assert!(check_state_cookie(&req, "state").is_ok());
+        assert!(check_state_cookie(&req, "attacker-state").is_err());
+    }
+
+    #[test]
+    fn callback_without_cookie_is_refused() {
+        let req = TestRequest::default().to_http_request();
+        assert!(matches!(
+            check_state_cookie(&req, "state"),
+            Err(AppError::BadRequest(_))
+        ));
+
+        // The raw state is not accepted in place of its hash
+        let cookie = Cookie::new(STATE_COOKIE, "state");
+        let req = TestRequest::default().cookie(cookie).to_http_request();
+        assert!(check_state_cookie(&req, "state").is_err());
+    }
+}
@@ -1,26 +1,53 @@
 use crate::{
     error::AppError,
-    middleware::auth::AuthenticatedUser,
-    models::subscription::{self, Entity as Subscription},
-    services::payment::PaymentService,
+    middleware::auth::{AuthenticatedUser, RequireAdmin},
+    models::{
+        payment::{Model as PaymentModel, PaymentProvider, PaymentStatus},
+        plan_change::PlanChangeSource,
+    },
+    services::{
+        payment::PaymentService,
+        plan::{self, PlanChange},
+    },
 };
 use actix_web::{web, HttpResponse};
-use rust_decimal::prelude::ToPrimitive;
-use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, TransactionTrait};
+use sea_orm::{prelude::Decimal, DatabaseConnection};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Deserialize)]
 pub struct PaymentRequest {
-    pub amount: String,
-    pub phone_number: String,
+    #[serde(default = "default_provider")]
+    pub provider: PaymentProvider,
+    pub plan: String, // Plan code from the catalog, e.g. 'premium'
+    pub phone_number: Option<String>,
     pub payer_message: String,
     pub payee_note: String,
 }
 
+fn default_provider() -> PaymentProvider {
+    PaymentProvider::MtnMomo
+}
+
 #[derive(Debug, Serialize)]
 pub struct PaymentResponse {
     pub reference_id: String,
     pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub approval_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefundRequest {
+    pub amount: Option<Decimal>, // Everything not refunded yet when omitted
+}
+
+#[derive(Debug, Serialize)]
+pub struct RefundResponse {
+    pub reference_id: String,
+    pub status: String,
+    pub amount: Decimal,
+    pub refunded_amount: Decimal,
+    pub currency: String,
 }
 
 pub async fn request_payment(
@@ -28,19 +55,22 @@ pub async fn request_payment(
     user: AuthenticatedUser,
     payment_data: web::Json<PaymentRequest>,
 ) -> Result<HttpResponse, AppError> {
-    let payment = payment_service
+    let payment_data = payment_data.into_inner();
+    let (payment, approval_url) = payment_service
         .request_payment(
             user.id,
-            payment_data.amount.clone(),
-            payment_data.phone_number.clone(),
-            payment_data.payer_message.clone(),
-            payment_data.payee_note.clone(),
+            payment_data.provider,
+            &payment_data.plan,
+            payment_data.phone_number,
+            payment_data.payer_message,
+            payment_data.payee_note,
         )
         .await?;
 
     Ok(HttpResponse::Ok().json(PaymentResponse {
         reference_id: payment.reference_id,
         status: format!("{:?}", payment.status),
+        approval_url,
     }))
 }
 
@@ -51,118 +81,259 @@ pub async fn check_payment_status(
     db: web::Data<DatabaseConnection>,
 ) -> Result<HttpResponse, AppError> {
     let reference_id = path.into_inner();
-    let payment = payment_service
-        .check_payment_status(reference_id.clone())
+    let (payment, transitioned) = payment_service
+        .check_payment_status(reference_id.clone(), user.id)
         .await?;
 
     println!("Payment status: {:?}", payment.status);
     println!("Payment amount (Decimal): {}", payment.amount);
 
-    // If payment is successful, update subscription
-    if format!("{:?}", payment.status) == "Successful" {
-        println!("Payment successful, updating subscription");
-        // Use Decimal to i64 conversion for amount
-        let amount: i64 = payment.amount.to_i64().unwrap_or(0);
-        println!("Parsed amount as i64: {}", amount);
-
-        let plan = if amount >= 1000 {
-            "enterprise"
-        } else if amount >= 500 {
-            "premium"
-        } else {
-            "basic"
-        };
-        println!("Determined plan: {}", plan);
+    // Upgrade only when this poll confirmed the payment, so polling again
+    // (or a webhook racing the poll) cannot apply it twice
+    if transitioned && payment.status == PaymentStatus::Successful {
+        apply_plan_upgrade(db.get_ref(), &payment).await?;
+    } else {
+        println!("No subscription change, status: {:?}", payment.status);
+    }
 
-        // First check current subscription
-        let current_sub = Subscription::find()
-            .filter(subscription::Column::UserId.eq(user.id))
-            .one(db.get_ref())
-            .await
-            .map_err(|e| {
-                println!("Error fetching current subscription: {}", e);
-                AppError::InternalServerError(format!("Failed to fetch subscription: {}", e))
-            })?;
-
-        if let Some(current_sub) = current_sub {
-            println!("Current subscription plan: {}", current_sub.plan);
-            println!("Current subscription status: {}", current_sub.status);
-        }
+    Ok(HttpResponse::Ok().json(PaymentResponse {
+        reference_id: payment.reference_id,
+        status: format!("{:?}", payment.status),
+        approval_url: None,
+    }))
+}
 
-        // Update subscription with transaction
-        let transaction = db.get_ref().begin().await.map_err(|e| {
-            println!("Error starting transaction: {}", e);
-            AppError::InternalServerError(format!("Failed to start transaction: {}", e))
-        })?;
-
-        let update_result = Subscription::update_many()
-            .col_expr(subscription::Column::Plan, plan.into())
-            .col_expr(
-                subscription::Column::StorageLimitBytes,
-                get_storage_limit_for_plan(plan).into(),
-            )
-            .col_expr(subscription::Column::Status, "active".into())
-            .filter(subscription::Column::UserId.eq(user.id))
-            .exec(&transaction)
-            .await;
-
-        match update_result {
-            Ok(_) => {
-                println!("Subscription updated successfully to plan: {}", plan);
-                // Commit the transaction
-                transaction.commit().await.map_err(|e| {
-                    println!("Error committing transaction: {}", e);
-                    AppError::InternalServerError(format!("Failed to commit transaction: {}", e))
-                })?;
-
-                // Verify the update
-                let updated_sub = Subscription::find()
-                    .filter(subscription::Column::UserId.eq(user.id))
-                    .one(db.get_ref())
-                    .await
-                    .map_err(|e| {
-                        println!("Error verifying subscription update: {}", e);
-                        AppError::InternalServerError(format!(
-                            "Failed to verify subscription: {}",
-                            e
-                        ))
-                    })?;
-
-                if let Some(sub) = updated_sub {
-                    println!(
-                        "Verified updated subscription - Plan: {}, Status: {}",
-                        sub.plan, sub.status
-                    );
-                }
-            }
-            Err(e) => {
-                println!("Failed to update subscription: {}", e);
-                // Rollback the transaction
-                transaction.rollback().await.map_err(|e| {
-                    println!("Error rolling back transaction: {}", e);
-                    AppError::InternalServerError(format!("Failed to rollback transaction: {}", e))
-                })?;
-                return Err(AppError::InternalServerError(format!(
-                    "Failed to update subscription: {}",
-                    e
-                )));
-            }
-        }
-    } else {
-        println!("Payment not successful, status: {:?}", payment.status);
+/// Refunds a payment, in full or in part. Admin only.
+pub async fn refund_payment(
+    payment_service: web::Data<PaymentService>,
+    admin: RequireAdmin,
+    path: web::Path<String>,
+    refund: web::Json<RefundRequest>,
+) -> Result<HttpResponse, AppError> {
+    let payment = payment_service
+        .refund_payment(path.into_inner(), refund.amount)
+        .await?;
+
+    println!(
+        "Payment {} refunded by admin {}, {} of {} refunded",
+        payment.reference_id, admin.0.id, payment.refunded_amount, payment.amount
+    );
+
+    Ok(HttpResponse::Ok().json(RefundResponse {
+        reference_id: payment.reference_id,
+        status: format!("{:?}", payment.status),
+        amount: payment.amount,
+        refunded_amount: payment.refunded_amount,
+        currency: payment.currency,
+    }))
+}
+
+/// MTN MoMo request-to-pay callback (`MTN_CALLBACK_URL`).
+pub async fn payment_callback(
+    payment_service: web::Data<PaymentService>,
+    db: web::Data<DatabaseConnection>,
+    body: web::Bytes,
+) -> Result<HttpResponse, AppError> {
+    handle_webhook(payment_service, db, PaymentProvider::MtnMomo, body).await
+}
+
+/// PayPal webhook for `CHECKOUT.ORDER.*` and `PAYMENT.CAPTURE.*` events.
+pub async fn paypal_webhook(
+    payment_service: web::Data<PaymentService>,
+    db: web::Data<DatabaseConnection>,
+    body: web::Bytes,
+) -> Result<HttpResponse, AppError> {
+    handle_webhook(payment_service, db, PaymentProvider::PayPal, body).await
+}
+
+async fn handle_webhook(
+    payment_service: web::Data<PaymentService>,
+    db: web::Data<DatabaseConnection>,
+    provider: PaymentProvider,
+    body: web::Bytes,
+) -> Result<HttpResponse, AppError> {
+    println!(
+        "Received {:?} webhook: {}",
+        provider,
+        String::from_utf8_lossy(&body)
+    );
+
+    let (payment, transitioned) = payment_service.handle_webhook(provider, &body).await?;
+
+    // Only the delivery that moved the payment out of Pending upgrades the
+    // plan, so provider retries do not apply it twice.
+    if transitioned && payment.status == PaymentStatus::Successful {
+        apply_plan_upgrade(db.get_ref(), &payment).await?;
     }
 
     Ok(HttpResponse::Ok().json(PaymentResponse {
         reference_id: payment.reference_id,
         status: format!("{:?}", payment.status),
+        approval_url: None,
     }))
 }
 
-fn get_storage_limit_for_plan(plan: &str) -> i64 {
-    match plan {
-        "basic" => 1_073_741_824,       // 1 GB
-        "premium" => 5_368_709_120,     // 5 GB
-        "enterprise" => 10_737_418_240, // 10 GB
-        _ => 0,                         // Default to 0 if plan is invalid
+/// Moves the paying user onto the plan paid for by a successful payment.
+async fn apply_plan_upgrade(
+    db: &DatabaseConnection,
+    payment: &PaymentModel,
+) -> Result<(), AppError> {
+    println!("Payment successful, updating subscription");
+    let plan = plan::find_by_id(db, payment.plan_id).await?;
+
+    plan::change_plan(
+        db,
+        payment.user_id,
+        &plan,
+        PlanChange {
+            changed_by: payment.user_id,
+            source: PlanChangeSource::Payment,
+            payment_id: Some(payment.id),
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::{
+            payment::{self, Entity as Payment},
+            plan_change::{self, Entity as PlanChangeEntity},
+        },
+        services::payment::{
+            GatewayStatus, InitiatedPayment, PaymentGateway, PaymentInitiation, RefundResult,
+            WebhookEvent,
+        },
+        test_support::TestDb,
+    };
+    use actix_web::{test, App};
+    use async_trait::async_trait;
+    use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, PaginatorTrait, QueryFilter, Set};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    /// MoMo stand-in whose status checks report every payment as successful.
+    struct ApprovingGateway;
+
+    #[async_trait(?Send)]
+    impl PaymentGateway for ApprovingGateway {
+        fn currency(&self) -> &str {
+            "EUR"
+        }
+
+        async fn initiate(&self, _: &PaymentInitiation) -> Result<InitiatedPayment, AppError> {
+            unimplemented!()
+        }
+
+        async fn query_status(&self, _: &PaymentModel) -> Result<GatewayStatus, AppError> {
+            Ok(GatewayStatus {
+                status: PaymentStatus::Successful,
+                provider_status: "SUCCESSFUL".to_string(),
+                details: json!({}),
+            })
+        }
+
+        async fn refund(&self, _: &PaymentModel, _: Decimal) -> Result<RefundResult, AppError> {
+            unimplemented!()
+        }
+
+        fn parse_webhook(&self, body: &[u8]) -> Result<WebhookEvent, AppError> {
+            let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+            Ok(WebhookEvent {
+                provider_reference_id: body["externalId"].as_str().unwrap().to_string(),
+                provider_status: body["status"].as_str().unwrap().to_string(),
+            })
+        }
+    }
+
+    #[actix_web::test]
+    async fn callback_records_the_payment_and_upgrades_once() {
+        let Some(db) = TestDb::new().await else {
+            return;
+        };
+        let user_id = db.user("payer@example.com").await;
+        let premium = plan::find_by_code(&*db, "premium").await.unwrap();
+        let pending = payment::ActiveModel {
+            user_id: Set(user_id),
+            plan_id: Set(premium.id),
+            reference_id: Set("momo-1_1700000000000".to_string()),
+            provider_reference_id: Set("momo-1".to_string()),
+            amount: Set(Decimal::new(500, 0)),
+            currency: Set("EUR".to_string()),
+            provider: Set(PaymentProvider::MtnMomo),
+            status: Set(PaymentStatus::Pending),
+            ..Default::default()
+        }
+        .insert(&*db)
+        .await
+        .unwrap();
+
+        let service =
+            PaymentService::from_gateways(db.connection(), Some(Arc::new(ApprovingGateway)), None);
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(service))
+                .app_data(web::Data::new(db.connection()))
+                .route("/api/payments/callback", web::post().to(payment_callback)),
+        )
+        .await;
+
+        for _ in 0..2 {
+            let request = test::TestRequest::post()
+                .uri("/api/payments/callback")
+                .set_json(json!({"externalId": "momo-1", "status": "SUCCESSFUL"}))
+                .to_request();
+            let response: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+            assert_eq!(response["status"], "Successful");
+        }
+
+        let recorded = Payment::find_by_id(pending.id)
+            .one(&*db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(recorded.status, PaymentStatus::Successful);
+        assert_eq!(
+            recorded.provider_response.unwrap()["callback"]["status"],
+            "SUCCESSFUL"
+        );
+        assert_eq!(
+            plan::plan_for_user(&*db, user_id).await.unwrap().id,
+            premium.id
+        );
+        // The repeated delivery did not apply the upgrade a second time
+        let changes = PlanChangeEntity::find()
+            .filter(plan_change::Column::PaymentId.eq(pending.id))
+            .count(&*db)
+            .await
+            .unwrap();
+        assert_eq!(changes, 1);
+    }
+
+    #[actix_web::test]
+    async fn callback_for_an_unknown_payment_is_not_found() {
+        let Some(db) = TestDb::new().await else {
+            return;
+        };
+        let service =
+            PaymentService::from_gateways(db.connection(), Some(Arc::new(ApprovingGateway)), None);
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(service))
+                .app_data(web::Data::new(db.connection()))
+                .route("/api/payments/callback", web::post().to(payment_callback)),
+        )
+        .await;
+
+        let request = test::TestRequest::post()
+            .uri("/api/payments/callback")
+            .set_json(json!({"externalId": "unknown", "status": "SUCCESSFUL"}))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), actix_web::http::StatusCode::NOT_FOUND);
     }
 }
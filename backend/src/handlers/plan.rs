@@ -0,0 +1,56 @@
+use crate::{
+    error::AppError,
+    models::{
+        plan::{self, BillingPeriod, Entity as Plan},
+        plan_price::Entity as PlanPrice,
+    },
+};
+use actix_web::{web, HttpResponse};
+use sea_orm::prelude::{Decimal, Json};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct PlanResponse {
+    pub code: String,
+    pub name: String,
+    pub storage_limit_bytes: i64,
+    pub billing_period: BillingPeriod,
+    pub features: Json,
+    pub prices: Vec<PriceResponse>,
+}
+
+#[derive(Serialize)]
+pub struct PriceResponse {
+    pub currency: String,
+    pub amount: Decimal,
+}
+
+pub async fn list_plans(db: web::Data<DatabaseConnection>) -> Result<HttpResponse, AppError> {
+    let plans = Plan::find()
+        .filter(plan::Column::IsActive.eq(true))
+        .order_by_asc(plan::Column::StorageLimitBytes)
+        .find_with_related(PlanPrice)
+        .all(db.get_ref())
+        .await?;
+
+    let response: Vec<PlanResponse> = plans
+        .into_iter()
+        .map(|(plan, prices)| PlanResponse {
+            code: plan.code,
+            name: plan.name,
+            storage_limit_bytes: plan.storage_limit_bytes,
+            billing_period: plan.billing_period,
+            features: plan.features,
+            prices: prices
+                .into_iter()
+                .map(|price| PriceResponse {
+                    currency: price.currency,
+                    amount: price.amount,
+                })
+                .collect(),
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(response))
+}
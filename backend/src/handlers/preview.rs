@@ -0,0 +1,75 @@
+use crate::{
+    error::AppError,
+    middleware::auth::AuthenticatedUser,
+    models::document::{self, Entity as Document},
+    services::{
+        preview::{self, PreviewFormat, PreviewSize},
+        storage::StorageService,
+    },
+};
+use actix_web::{http::header::CACHE_CONTROL, web, HttpResponse};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct PreviewQuery {
+    pub size: Option<PreviewSize>,
+    pub format: Option<PreviewFormat>, // png when omitted
+}
+
+async fn find_for_user(
+    db: &DatabaseConnection,
+    user_id: i32,
+    document_id: i32,
+) -> Result<document::Model, AppError> {
+    Document::find_by_id(document_id)
+        .filter(document::Column::UserId.eq(user_id))
+        .one(db)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Document not found".into()))
+}
+
+async fn image_response(
+    storage: &StorageService,
+    document: &document::Model,
+    page: u32,
+    size: PreviewSize,
+    format: PreviewFormat,
+) -> Result<HttpResponse, AppError> {
+    let image = preview::page_image(storage, document, page, size, format).await?;
+
+    // A render never changes, so browsers may keep it for a day
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header((CACHE_CONTROL, "private, max-age=86400"))
+        .body(image))
+}
+
+/// The first page of a PDF document as an image, medium-sized by default.
+pub async fn document_thumbnail(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    storage: web::Data<StorageService>,
+    path: web::Path<i32>,
+    query: web::Query<PreviewQuery>,
+) -> Result<HttpResponse, AppError> {
+    let document = find_for_user(db.get_ref(), user.id, path.into_inner()).await?;
+    let size = query.size.unwrap_or(PreviewSize::Medium);
+    let format = query.format.unwrap_or_default();
+    image_response(&storage, &document, 1, size, format).await
+}
+
+/// Any page of a PDF document as an image, large by default.
+pub async fn page_preview(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    storage: web::Data<StorageService>,
+    path: web::Path<(i32, u32)>,
+    query: web::Query<PreviewQuery>,
+) -> Result<HttpResponse, AppError> {
+    let (document_id, page) = path.into_inner();
+    let document = find_for_user(db.get_ref(), user.id, document_id).await?;
+    let size = query.size.unwrap_or(PreviewSize::Large);
+    let format = query.format.unwrap_or_default();
+    image_response(&storage, &document, page, size, format).await
+}
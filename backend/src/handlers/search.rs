@@ -0,0 +1,149 @@
+use crate::{error::AppError, middleware::auth::AuthenticatedUser};
+use actix_web::{web, HttpResponse};
+use sea_orm::{DatabaseConnection, DbBackend, FromQueryResult, Statement};
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_PAGE_SIZE: u64 = 20;
+const MAX_PAGE_SIZE: u64 = 100;
+const MAX_QUERY_LENGTH: usize = 256;
+
+// ts_headline wraps matches in these. Stored text never contains control
+// characters, so they survive HTML escaping and then become <mark> tags.
+const MATCH_START: char = '\u{1}';
+const MATCH_END: char = '\u{2}';
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    pub q: Option<String>,
+    pub page: Option<u64>, // 1-based
+    pub per_page: Option<u64>,
+}
+
+#[derive(Serialize, FromQueryResult)]
+pub struct SearchHit {
+    pub document_id: i32,
+    pub filename: String,
+    pub title: Option<String>,
+    pub page_number: i32,
+    pub rank: f32,
+    pub snippet: String, // HTML-escaped, matches wrapped in <mark>
+}
+
+#[derive(Serialize)]
+pub struct SearchResponse {
+    pub hits: Vec<SearchHit>,
+    pub total: u64,
+    pub page: u64,
+    pub per_page: u64,
+}
+
+#[derive(FromQueryResult)]
+struct HitCount {
+    total: i64,
+}
+
+const COUNT_SQL: &str = r#"
+SELECT COUNT(*) AS total
+FROM document_pages p
+JOIN documents d ON d.id = p.document_id
+WHERE d.user_id = $1 AND p.content_tsv @@ websearch_to_tsquery('english', $2)
+"#;
+
+// Snippets are only built for the page of hits being returned
+const SEARCH_SQL: &str = r#"
+WITH query AS (SELECT websearch_to_tsquery('english', $2) AS q),
+hits AS (
+    SELECT p.document_id, p.page_number, ts_rank_cd(p.content_tsv, query.q) AS rank
+    FROM document_pages p
+    JOIN documents d ON d.id = p.document_id
+    CROSS JOIN query
+    WHERE d.user_id = $1 AND p.content_tsv @@ query.q
+    ORDER BY rank DESC, p.document_id, p.page_number
+    LIMIT $3 OFFSET $4
+)
+SELECT hits.document_id, d.filename, d.title, hits.page_number, hits.rank,
+       ts_headline('english', p.content, query.q, $5) AS snippet
+FROM hits
+JOIN document_pages p USING (document_id, page_number)
+JOIN documents d ON d.id = hits.document_id
+CROSS JOIN query
+ORDER BY hits.rank DESC, hits.document_id, hits.page_number
+"#;
+
+/// Escapes the snippet for HTML and turns the match markers into tags.
+fn render_snippet(snippet: &str) -> String {
+    let mut html = String::with_capacity(snippet.len());
+    for c in snippet.chars() {
+        match c {
+            MATCH_START => html.push_str("<mark>"),
+            MATCH_END => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            '\n' => html.push(' '),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
+/// Full-text search over the extracted text of the user's documents. Each
+/// hit is one page, best matches first. `q` takes web search syntax:
+/// quoted phrases, `or`, and `-` to exclude a word.
+pub async fn search_documents(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    query: web::Query<SearchQuery>,
+) -> Result<HttpResponse, AppError> {
+    let q = query.q.as_deref().unwrap_or_default().trim();
+    if q.is_empty() || q.chars().count() > MAX_QUERY_LENGTH {
+        return Err(AppError::BadRequest(format!(
+            "q must be between 1 and {} characters",
+            MAX_QUERY_LENGTH
+        )));
+    }
+    let per_page = query
+        .per_page
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let page = query.page.unwrap_or(1).max(1);
+
+    let total = HitCount::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        COUNT_SQL,
+        [user.id.into(), q.into()],
+    ))
+    .one(db.get_ref())
+    .await?
+    .map_or(0, |count| count.total as u64);
+
+    let headline_options = format!(
+        "StartSel={}, StopSel={}, MaxFragments=2, MinWords=8, MaxWords=25, FragmentDelimiter=\" … \"",
+        MATCH_START, MATCH_END
+    );
+    let mut hits = SearchHit::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        SEARCH_SQL,
+        [
+            user.id.into(),
+            q.into(),
+            (per_page as i64).into(),
+            (((page - 1) * per_page) as i64).into(),
+            headline_options.into(),
+        ],
+    ))
+    .all(db.get_ref())
+    .await?;
+    for hit in &mut hits {
+        hit.snippet = render_snippet(&hit.snippet);
+    }
+
+    Ok(HttpResponse::Ok().json(SearchResponse {
+        hits,
+        total,
+        page,
+        per_page,
+    }))
+}
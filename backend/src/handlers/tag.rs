@@ -0,0 +1,243 @@
+use crate::{
+    error::AppError,
+    middleware::auth::AuthenticatedUser,
+    models::{
+        document::{self, Entity as Document},
+        document_tag::{self, Entity as DocumentTag},
+        tag::{self, Entity as Tag},
+    },
+    services::tag as tags,
+};
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use sea_orm::{
+    sea_query::OnConflict, ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait,
+    PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Set,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+const MAX_BULK_DOCUMENTS: usize = 500;
+
+#[derive(Deserialize)]
+pub struct TagRequest {
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+pub struct BulkTagRequest {
+    pub document_ids: Vec<i32>,
+    #[serde(default)]
+    pub add: Vec<String>,
+    #[serde(default)]
+    pub remove: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct TagResponse {
+    #[serde(flatten)]
+    pub tag: tag::Model,
+    pub document_count: i64,
+}
+
+#[derive(Serialize)]
+pub struct DocumentTagsResponse {
+    pub id: i32,
+    pub tags: Vec<String>,
+}
+
+fn name_conflict(e: DbErr) -> AppError {
+    if e.to_string().contains("tags_user_id_name_key") {
+        AppError::Conflict("A tag with this name already exists".into())
+    } else {
+        e.into()
+    }
+}
+
+async fn find_for_user(
+    db: &DatabaseConnection,
+    user_id: i32,
+    tag_id: i32,
+) -> Result<tag::Model, AppError> {
+    Tag::find_by_id(tag_id)
+        .filter(tag::Column::UserId.eq(user_id))
+        .one(db)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Tag not found".into()))
+}
+
+/// Every tag of the user, ordered by name, with how many documents carry it.
+pub async fn list_tags(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, AppError> {
+    let user_tags = Tag::find()
+        .filter(tag::Column::UserId.eq(user.id))
+        .order_by_asc(tag::Column::Name)
+        .all(db.get_ref())
+        .await?;
+
+    let tag_ids: Vec<i32> = user_tags.iter().map(|tag| tag.id).collect();
+    let counts: HashMap<i32, i64> = DocumentTag::find()
+        .select_only()
+        .column(document_tag::Column::TagId)
+        .column_as(document_tag::Column::DocumentId.count(), "document_count")
+        .filter(document_tag::Column::TagId.is_in(tag_ids))
+        .group_by(document_tag::Column::TagId)
+        .into_tuple::<(i32, i64)>()
+        .all(db.get_ref())
+        .await?
+        .into_iter()
+        .collect();
+
+    let response: Vec<TagResponse> = user_tags
+        .into_iter()
+        .map(|tag| TagResponse {
+            document_count: counts.get(&tag.id).copied().unwrap_or(0),
+            tag,
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+pub async fn create_tag(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    body: web::Json<TagRequest>,
+) -> Result<HttpResponse, AppError> {
+    let tag = tag::ActiveModel {
+        user_id: Set(user.id),
+        name: Set(tags::validate_name(&body.name)?),
+        created_at: Set(Utc::now().into()),
+        ..Default::default()
+    }
+    .insert(db.get_ref())
+    .await
+    .map_err(name_conflict)?;
+
+    Ok(HttpResponse::Created().json(tag))
+}
+
+/// Renames a tag on every document that carries it.
+pub async fn rename_tag(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    path: web::Path<i32>,
+    body: web::Json<TagRequest>,
+) -> Result<HttpResponse, AppError> {
+    let tag = find_for_user(db.get_ref(), user.id, path.into_inner()).await?;
+
+    let mut changes: tag::ActiveModel = tag.into();
+    changes.name = Set(tags::validate_name(&body.name)?);
+    let tag = changes.update(db.get_ref()).await.map_err(name_conflict)?;
+
+    Ok(HttpResponse::Ok().json(tag))
+}
+
+/// Deletes a tag and takes it off every document. The documents stay.
+pub async fn delete_tag(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    let tag = find_for_user(db.get_ref(), user.id, path.into_inner()).await?;
+    Tag::delete_by_id(tag.id).exec(db.get_ref()).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Adds and removes tags on several documents at once. Tags in `add` that
+/// do not exist yet are created; removing a tag a document does not carry
+/// is not an error.
+pub async fn bulk_tag_documents(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    body: web::Json<BulkTagRequest>,
+) -> Result<HttpResponse, AppError> {
+    let document_ids: BTreeSet<i32> = body.document_ids.iter().copied().collect();
+    if document_ids.is_empty() || document_ids.len() > MAX_BULK_DOCUMENTS {
+        return Err(AppError::BadRequest(format!(
+            "document_ids must list between 1 and {} documents",
+            MAX_BULK_DOCUMENTS
+        )));
+    }
+    let document_ids: Vec<i32> = document_ids.into_iter().collect();
+
+    let add = body
+        .add
+        .iter()
+        .map(|name| tags::validate_name(name))
+        .collect::<Result<BTreeSet<_>, _>>()?;
+    let remove = body
+        .remove
+        .iter()
+        .map(|name| tags::validate_name(name))
+        .collect::<Result<BTreeSet<_>, _>>()?;
+    if let Some(name) = add.intersection(&remove).next() {
+        return Err(AppError::BadRequest(format!(
+            "Tag \"{}\" cannot be both added and removed",
+            name
+        )));
+    }
+
+    let owned = Document::find()
+        .filter(document::Column::UserId.eq(user.id))
+        .filter(document::Column::Id.is_in(document_ids.clone()))
+        .count(db.get_ref())
+        .await?;
+    if owned != document_ids.len() as u64 {
+        return Err(AppError::NotFound("Document not found".into()));
+    }
+
+    let add: Vec<String> = add.into_iter().collect();
+    let added = tags::find_or_create(db.get_ref(), user.id, &add).await?;
+    if !added.is_empty() {
+        let links = document_ids.iter().flat_map(|&document_id| {
+            added.iter().map(move |tag| document_tag::ActiveModel {
+                document_id: Set(document_id),
+                tag_id: Set(tag.id),
+            })
+        });
+        DocumentTag::insert_many(links)
+            .on_conflict(
+                OnConflict::columns([
+                    document_tag::Column::DocumentId,
+                    document_tag::Column::TagId,
+                ])
+                .do_nothing()
+                .to_owned(),
+            )
+            .do_nothing()
+            .exec(db.get_ref())
+            .await?;
+    }
+
+    if !remove.is_empty() {
+        let removed: Vec<i32> = Tag::find()
+            .filter(tag::Column::UserId.eq(user.id))
+            .filter(tag::Column::Name.is_in(remove))
+            .all(db.get_ref())
+            .await?
+            .into_iter()
+            .map(|tag| tag.id)
+            .collect();
+
+        DocumentTag::delete_many()
+            .filter(document_tag::Column::DocumentId.is_in(document_ids.clone()))
+            .filter(document_tag::Column::TagId.is_in(removed))
+            .exec(db.get_ref())
+            .await?;
+    }
+
+    let mut names = tags::names_by_document(db.get_ref(), &document_ids).await?;
+    let response: Vec<DocumentTagsResponse> = document_ids
+        .into_iter()
+        .map(|id| DocumentTagsResponse {
+            id,
+            tags: names.remove(&id).unwrap_or_default(),
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(response))
+}
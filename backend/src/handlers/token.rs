@@ -0,0 +1,130 @@
+use crate::{
+    error::AppError,
+    middleware::auth::AuthenticatedUser,
+    models::personal_access_token,
+    services::personal_access_token::{self as pat, Scope},
+};
+use actix_web::{web, HttpResponse};
+use chrono::Duration;
+use sea_orm::DatabaseConnection;
+use serde::{Deserialize, Serialize};
+
+const MAX_NAME_LENGTH: usize = 100;
+const MAX_EXPIRY_DAYS: i64 = 365;
+
+#[derive(Deserialize)]
+pub struct CreateTokenRequest {
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub expires_in_days: Option<i64>, // Never expires when omitted
+}
+
+#[derive(Serialize)]
+pub struct TokenResponse {
+    pub id: i32,
+    pub name: String,
+    pub token_prefix: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<String>,
+    pub last_used_at: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Serialize)]
+pub struct CreatedTokenResponse {
+    #[serde(flatten)]
+    pub details: TokenResponse,
+    pub token: String, // Only ever returned here
+}
+
+impl From<personal_access_token::Model> for TokenResponse {
+    fn from(model: personal_access_token::Model) -> Self {
+        TokenResponse {
+            id: model.id,
+            name: model.name,
+            token_prefix: model.token_prefix,
+            scopes: model.scopes.split_whitespace().map(String::from).collect(),
+            expires_at: model.expires_at.map(|at| at.to_rfc3339()),
+            last_used_at: model.last_used_at.map(|at| at.to_rfc3339()),
+            created_at: model.created_at.to_rfc3339(),
+        }
+    }
+}
+
+pub async fn create_token(
+    user: AuthenticatedUser,
+    data: web::Json<CreateTokenRequest>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, AppError> {
+    let name = data.name.trim();
+    if name.is_empty() || name.len() > MAX_NAME_LENGTH {
+        return Err(AppError::BadRequest(format!(
+            "Token name must be between 1 and {} characters",
+            MAX_NAME_LENGTH
+        )));
+    }
+
+    if data.scopes.is_empty() {
+        return Err(AppError::BadRequest(
+            "At least one scope is required".into(),
+        ));
+    }
+    let scopes = data
+        .scopes
+        .iter()
+        .map(|scope| scope.parse())
+        .collect::<Result<Vec<Scope>, _>>()?;
+
+    let expires_in = match data.expires_in_days {
+        Some(days) if !(1..=MAX_EXPIRY_DAYS).contains(&days) => {
+            return Err(AppError::BadRequest(format!(
+                "expires_in_days must be between 1 and {}",
+                MAX_EXPIRY_DAYS
+            )))
+        }
+        Some(days) => Some(Duration::days(days)),
+        None => None,
+    };
+
+    let (model, token) = pat::create(db.get_ref(), user.id, name, &scopes, expires_in).await?;
+    println!(
+        "Personal access token {} created for user {}",
+        model.id, user.id
+    );
+
+    Ok(HttpResponse::Created().json(CreatedTokenResponse {
+        details: model.into(),
+        token,
+    }))
+}
+
+pub async fn list_tokens(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, AppError> {
+    let tokens: Vec<TokenResponse> = pat::list(db.get_ref(), user.id)
+        .await?
+        .into_iter()
+        .map(Into::into)
+        .collect();
+
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+pub async fn revoke_token(
+    user: AuthenticatedUser,
+    path: web::Path<i32>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, AppError> {
+    let token_id = path.into_inner();
+
+    if !pat::revoke(db.get_ref(), user.id, token_id).await? {
+        return Err(AppError::NotFound("Token not found".into()));
+    }
+    println!(
+        "Personal access token {} of user {} revoked",
+        token_id, user.id
+    );
+
+    Ok(HttpResponse::NoContent().finish())
+}
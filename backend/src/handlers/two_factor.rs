@@ -0,0 +1,218 @@
+use crate::{
+    error::AppError,
+    middleware::auth::AuthenticatedUser,
+    models::{
+        recovery_code::{self, Entity as RecoveryCode},
+        user::{self, Entity as User},
+    },
+    services::{
+        login_throttle::{self, ThrottleKey},
+        two_factor,
+    },
+};
+use actix_web::{web, HttpResponse};
+use bcrypt::verify;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter, Set};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+
+#[derive(Serialize)]
+pub struct TwoFactorStatusResponse {
+    pub enabled: bool,
+    pub recovery_codes_remaining: u64,
+}
+
+#[derive(Serialize)]
+pub struct TwoFactorSetupResponse {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Deserialize)]
+pub struct CodeRequest {
+    pub code: String,
+}
+
+#[derive(Deserialize)]
+pub struct DisableTwoFactorRequest {
+    #[serde(default)]
+    pub password: String, // Ignored for accounts without a password
+    pub code: String, // TOTP code or recovery code
+}
+
+#[derive(Serialize)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+async fn load_user(db: &DatabaseConnection, user_id: i32) -> Result<user::Model, AppError> {
+    User::find_by_id(user_id)
+        .one(db)
+        .await?
+        .ok_or(AppError::NotFound("User not found".into()))
+}
+
+/// Runs `verify` under the attempt limit of the login challenge, so codes
+/// cannot be guessed here instead. A locked account is turned away before
+/// anything is checked; the attempt counts as a failure unless the code
+/// turns out right, which clears them.
+pub(crate) async fn throttled(
+    db: &DatabaseConnection,
+    user_id: i32,
+    verify: impl Future<Output = Result<bool, AppError>>,
+) -> Result<bool, AppError> {
+    let key = ThrottleKey::TwoFactor(user_id);
+    login_throttle::begin_attempt(db, key).await?;
+
+    if !verify.await? {
+        return Ok(false);
+    }
+
+    login_throttle::record_success(db, key).await?;
+    Ok(true)
+}
+
+pub async fn get_status(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, AppError> {
+    let account = load_user(db.get_ref(), user.id).await?;
+
+    let recovery_codes_remaining = RecoveryCode::find()
+        .filter(recovery_code::Column::UserId.eq(user.id))
+        .filter(recovery_code::Column::UsedAt.is_null())
+        .count(db.get_ref())
+        .await?;
+
+    Ok(HttpResponse::Ok().json(TwoFactorStatusResponse {
+        enabled: account.totp_enabled,
+        recovery_codes_remaining,
+    }))
+}
+
+/// Starts enrolment with a fresh secret. Nothing changes at login until the
+/// secret is confirmed with `enable`; calling this again replaces it.
+pub async fn setup(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, AppError> {
+    let account = load_user(db.get_ref(), user.id).await?;
+    if account.totp_enabled {
+        return Err(AppError::BadRequest(
+            "Two-factor authentication is already enabled".into(),
+        ));
+    }
+
+    let secret = two_factor::generate_secret();
+    let changes = user::ActiveModel {
+        totp_secret: Set(Some(secret.clone())),
+        totp_last_step: Set(None),
+        ..Default::default()
+    };
+    User::update_many()
+        .set(changes)
+        .filter(user::Column::Id.eq(user.id))
+        .filter(user::Column::TotpEnabled.eq(false))
+        .exec(db.get_ref())
+        .await?;
+
+    Ok(HttpResponse::Ok().json(TwoFactorSetupResponse {
+        otpauth_uri: two_factor::provisioning_uri(&account.email, &secret),
+        secret,
+    }))
+}
+
+/// Confirms enrolment with a first code from the authenticator and returns
+/// the recovery codes.
+pub async fn enable(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    body: web::Json<CodeRequest>,
+) -> Result<HttpResponse, AppError> {
+    let account = load_user(db.get_ref(), user.id).await?;
+    if account.totp_enabled {
+        return Err(AppError::BadRequest(
+            "Two-factor authentication is already enabled".into(),
+        ));
+    }
+    if account.totp_secret.is_none() {
+        return Err(AppError::BadRequest("Start the setup first".into()));
+    }
+
+    let verified = two_factor::verify_totp(db.get_ref(), &account, &body.code);
+    if !throttled(db.get_ref(), account.id, verified).await? {
+        return Err(AppError::BadRequest("Invalid code".into()));
+    }
+
+    let changes = user::ActiveModel {
+        totp_enabled: Set(true),
+        ..Default::default()
+    };
+    User::update_many()
+        .set(changes)
+        .filter(user::Column::Id.eq(user.id))
+        .exec(db.get_ref())
+        .await?;
+
+    let recovery_codes = two_factor::regenerate_recovery_codes(db.get_ref(), user.id).await?;
+    println!("Two-factor authentication enabled for user {}", user.id);
+
+    Ok(HttpResponse::Ok().json(RecoveryCodesResponse { recovery_codes }))
+}
+
+/// Turns two-factor authentication off. Needs the password and a current
+/// code, so a hijacked session alone cannot do it.
+pub async fn disable(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    body: web::Json<DisableTwoFactorRequest>,
+) -> Result<HttpResponse, AppError> {
+    let account = load_user(db.get_ref(), user.id).await?;
+    if !account.totp_enabled {
+        return Err(AppError::BadRequest(
+            "Two-factor authentication is not enabled".into(),
+        ));
+    }
+
+    let verified = async {
+        // Accounts created through social login have no password to check
+        let password_ok = match &account.password_hash {
+            Some(password_hash) => verify(&body.password, password_hash).map_err(|_| {
+                AppError::InternalServerError("Password verification failed".into())
+            })?,
+            None => true,
+        };
+        Ok(password_ok
+            && two_factor::verify_second_factor(db.get_ref(), &account, &body.code).await?)
+    };
+    if !throttled(db.get_ref(), account.id, verified).await? {
+        return Err(AppError::BadRequest("Invalid password or code".into()));
+    }
+
+    two_factor::disable(db.get_ref(), user.id).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Replaces the recovery codes; the old ones stop working.
+pub async fn regenerate_recovery_codes(
+    user: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    body: web::Json<CodeRequest>,
+) -> Result<HttpResponse, AppError> {
+    let account = load_user(db.get_ref(), user.id).await?;
+    if !account.totp_enabled {
+        return Err(AppError::BadRequest(
+            "Two-factor authentication is not enabled".into(),
+        ));
+    }
+
+    let verified = two_factor::verify_totp(db.get_ref(), &account, &body.code);
+    if !throttled(db.get_ref(), account.id, verified).await? {
+        return Err(AppError::BadRequest("Invalid code".into()));
+    }
+
+    let recovery_codes = two_factor::regenerate_recovery_codes(db.get_ref(), user.id).await?;
+
+    Ok(HttpResponse::Ok().json(RecoveryCodesResponse { recovery_codes }))
+}
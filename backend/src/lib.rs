@@ -4,3 +4,6 @@ pub mod handlers;
 pub mod middleware;
 pub mod models;
 pub mod services;
+
+#[cfg(test)]
+pub(crate) mod test_support;
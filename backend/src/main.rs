@@ -1,18 +1,19 @@
-use crate::services::storage::StorageService;
 use actix_cors::Cors;
 use actix_web::{web, App, HttpServer, HttpResponse, Responder};
 use actix_web_httpauth::middleware::HttpAuthentication;
-use handlers::payment::{check_payment_status, request_payment};
-use handlers::subscription::{update_subscription, get_subscription};
+use handlers::payment::{check_payment_status, payment_callback, paypal_webhook, request_payment};
+use handlers::subscription::get_subscription;
+use pdf_shelf::services::mailer::{LogBackend, MailBackend, MailService, SmtpBackend, SmtpTls};
+use pdf_shelf::services::storage::{
+    LocalBackend, MemoryBackend, S3Backend, StorageBackend, StorageService,
+};
+use pdf_shelf::{config, handlers, middleware, services};
+use services::account_token::AccountTokenKey;
+use services::jwt::JwtKeys;
+use services::oidc::OidcService;
 use services::payment::PaymentService;
 use std::env;
-
-mod config;
-mod error;
-mod handlers;
-mod middleware;
-mod models;
-mod services;
+use std::sync::Arc;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -28,27 +29,99 @@ async fn main() -> std::io::Result<()> {
         .expect("Failed to initialize database");
 
     // Initialize storage service
-    let storage = StorageService::new(
-        env::var("MINIO_ENDPOINT").expect("MINIO_ENDPOINT must be set"),
-        env::var("MINIO_BUCKET").expect("MINIO_BUCKET must be set"),
-        env::var("AWS_REGION").expect("AWS_REGION must be set"),
-    )
-    .await
-    .expect("Failed to initialize storage service");
+    let storage_backend: Arc<dyn StorageBackend> = match env::var("STORAGE_BACKEND")
+        .unwrap_or_else(|_| "s3".to_string())
+        .as_str()
+    {
+        "s3" => Arc::new(
+            S3Backend::new(
+                env::var("MINIO_ENDPOINT").expect("MINIO_ENDPOINT must be set"),
+                env::var("MINIO_BUCKET").expect("MINIO_BUCKET must be set"),
+                env::var("AWS_REGION").expect("AWS_REGION must be set"),
+            )
+            .await
+            .expect("Failed to initialize storage service"),
+        ),
+        "local" => Arc::new(
+            LocalBackend::new(
+                env::var("STORAGE_LOCAL_ROOT").unwrap_or_else(|_| "./data".to_string()),
+            )
+            .await
+            .expect("Failed to initialize storage service"),
+        ),
+        "memory" => Arc::new(MemoryBackend::new()),
+        other => panic!("Unknown STORAGE_BACKEND: {}", other),
+    };
+    let storage = StorageService::new(storage_backend);
+
+    // Initialize mailer
+    let mail_backend: Arc<dyn MailBackend> = match env::var("MAILER")
+        .unwrap_or_else(|_| "log".to_string())
+        .as_str()
+    {
+        "smtp" => {
+            let tls: SmtpTls = env::var("SMTP_TLS")
+                .unwrap_or_else(|_| "starttls".to_string())
+                .parse()
+                .expect("Invalid SMTP_TLS");
+            let credentials = match (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+                (Ok(username), Ok(password)) if !username.is_empty() => Some((username, password)),
+                _ => None,
+            };
+            Arc::new(
+                SmtpBackend::new(
+                    env::var("SMTP_HOST").expect("SMTP_HOST must be set"),
+                    env::var("SMTP_PORT")
+                        .unwrap_or_else(|_| "587".to_string())
+                        .parse()
+                        .expect("Invalid SMTP_PORT"),
+                    tls,
+                    credentials,
+                )
+                .expect("Failed to initialize mailer"),
+            )
+        }
+        "log" => Arc::new(
+            LogBackend::new(env::var("MAIL_DIR").ok().map(Into::into))
+                .await
+                .expect("Failed to initialize mailer"),
+        ),
+        other => panic!("Unknown MAILER: {}", other),
+    };
+    let mailer = MailService::new(
+        mail_backend,
+        env::var("MAIL_FROM").unwrap_or_else(|_| "Shelf <no-reply@localhost>".to_string()),
+        env::var("APP_URL").unwrap_or_else(|_| "http://localhost".to_string()),
+    );
+
+    // Load access token signing keys
+    let jwt_keys = JwtKeys::from_env().expect("Failed to load JWT keys");
+    let account_token_key = AccountTokenKey::from_env().expect("Failed to load account token key");
+
+    // Initialize social login providers
+    let oidc_service =
+        OidcService::from_env(pool.clone()).expect("Failed to initialize social login providers");
 
     // Initialize payment service
     let payment_service = PaymentService::new(pool.clone())
         .await
         .expect("Failed to initialize payment service");
 
+    // Finish text extraction interrupted by the last shutdown
+    services::text_extraction::resume_pending(pool.clone(), storage.clone());
+    // Documents from before uploads were hashed
+    services::duplicate::hash_existing(pool.clone(), storage.clone());
+
     println!("Starting server at http://0.0.0.0:8080");
 
     HttpServer::new(move || {
         let auth = HttpAuthentication::bearer(middleware::auth::validator);
+        let two_factor_auth = HttpAuthentication::bearer(middleware::auth::validator);
+        let identities_auth = HttpAuthentication::bearer(middleware::auth::validator);
 
         // Configure CORS
         let cors = Cors::permissive()
-            .allowed_methods(vec!["GET", "POST", "PUT", "DELETE", "OPTIONS"])
+            .allowed_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"])
             .allowed_headers(vec!["Authorization", "Content-Type", "Cache-Control"])
             .supports_credentials();
 
@@ -57,13 +130,90 @@ async fn main() -> std::io::Result<()> {
             .app_data(web::Data::new(pool.clone()))
             .app_data(web::Data::new(storage.clone()))
             .app_data(web::Data::new(payment_service.clone()))
+            .app_data(web::Data::new(mailer.clone()))
+            .app_data(web::Data::new(oidc_service.clone()))
+            .app_data(web::Data::new(jwt_keys.clone()))
+            .app_data(web::Data::new(account_token_key.clone()))
             .route("/health", web::get().to(health_check)) //  health check route for render serivce
+            .route(
+                "/.well-known/jwks.json",
+                web::get().to(handlers::auth::jwks),
+            )
             .service(
                 web::scope("/api")
                     .service(
                         web::scope("/auth")
                             .route("/login", web::post().to(handlers::auth::login))
-                            .route("/register", web::post().to(handlers::auth::register)),
+                            .route(
+                                "/login/2fa",
+                                web::post().to(handlers::auth::login_two_factor),
+                            )
+                            .route("/register", web::post().to(handlers::auth::register))
+                            .route("/refresh", web::post().to(handlers::auth::refresh))
+                            .route("/logout", web::post().to(handlers::auth::logout))
+                            .route(
+                                "/verify-email",
+                                web::post().to(handlers::auth::verify_email),
+                            )
+                            .route(
+                                "/resend-verification",
+                                web::post().to(handlers::auth::resend_verification),
+                            )
+                            .route(
+                                "/forgot-password",
+                                web::post().to(handlers::auth::forgot_password),
+                            )
+                            .route(
+                                "/reset-password",
+                                web::post().to(handlers::auth::reset_password),
+                            )
+                            .route(
+                                "/confirm-email",
+                                web::post().to(handlers::account::confirm_email_change),
+                            )
+                            .route(
+                                "/oidc/providers",
+                                web::get().to(handlers::oidc::list_providers),
+                            )
+                            .route("/oidc/callback", web::post().to(handlers::oidc::callback))
+                            .route(
+                                "/oidc/{provider}/authorize",
+                                web::post().to(handlers::oidc::authorize),
+                            )
+                            // Linked social logins of the current user
+                            .service(
+                                web::scope("/identities")
+                                    .wrap(identities_auth)
+                                    .route("", web::get().to(handlers::oidc::list_identities))
+                                    .route("/{provider}", web::post().to(handlers::oidc::link))
+                                    .route("/{provider}", web::delete().to(handlers::oidc::unlink)),
+                            )
+                            // Enrolment needs a logged-in user
+                            .service(
+                                web::scope("/2fa")
+                                    .wrap(two_factor_auth)
+                                    .route("", web::get().to(handlers::two_factor::get_status))
+                                    .route("/setup", web::post().to(handlers::two_factor::setup))
+                                    .route("/enable", web::post().to(handlers::two_factor::enable))
+                                    .route(
+                                        "/disable",
+                                        web::post().to(handlers::two_factor::disable),
+                                    )
+                                    .route(
+                                        "/recovery-codes",
+                                        web::post()
+                                            .to(handlers::two_factor::regenerate_recovery_codes),
+                                    ),
+                            ),
+                    )
+                    .route("/plans", web::get().to(handlers::plan::list_plans))
+                    // Payment providers call these without a bearer token
+                    .service(
+                        web::resource("/payments/callback").route(web::post().to(payment_callback)),
+                    )
+                    .service(
+                        web::resource("/payments/paypal/webhook")
+                            .route(web::post().to(paypal_webhook)),
                     )
                     .service(
                         web::scope("")
@@ -72,13 +222,86 @@ async fn main() -> std::io::Result<()> {
                                 web::scope("/documents")
                                     .route("", web::post().to(handlers::document::upload_document))
                                     .route("", web::get().to(handlers::document::list_documents))
+                                    .route(
+                                        "/search",
+                                        web::get().to(handlers::search::search_documents),
+                                    )
+                                    .route(
+                                        "/tags",
+                                        web::post().to(handlers::tag::bulk_tag_documents),
+                                    )
+                                    .route(
+                                        "/duplicates",
+                                        web::get().to(handlers::document::list_duplicates),
+                                    )
                                     .route(
                                         "/{id}",
                                         web::get().to(handlers::document::download_document),
                                     )
+                                    .route(
+                                        "/{id}",
+                                        web::patch().to(handlers::document::update_document),
+                                    )
                                     .route(
                                         "/{id}",
                                         web::delete().to(handlers::document::delete_document),
+                                    )
+                                    .route(
+                                        "/{id}/thumbnail",
+                                        web::get().to(handlers::preview::document_thumbnail),
+                                    )
+                                    .route(
+                                        "/{id}/pages/{page}/preview",
+                                        web::get().to(handlers::preview::page_preview),
+                                    ),
+                            )
+                            .service(
+                                web::scope("/folders")
+                                    .route("", web::get().to(handlers::folder::list_folders))
+                                    .route("", web::post().to(handlers::folder::create_folder))
+                                    .route("/{id}", web::get().to(handlers::folder::get_folder))
+                                    .route(
+                                        "/{id}",
+                                        web::patch().to(handlers::folder::update_folder),
+                                    )
+                                    .route(
+                                        "/{id}",
+                                        web::delete().to(handlers::folder::delete_folder),
+                                    ),
+                            )
+                            .service(
+                                web::scope("/tags")
+                                    .route("", web::get().to(handlers::tag::list_tags))
+                                    .route("", web::post().to(handlers::tag::create_tag))
+                                    .route("/{id}", web::patch().to(handlers::tag::rename_tag))
+                                    .route("/{id}", web::delete().to(handlers::tag::delete_tag)),
+                            )
+                            .service(
+                                web::scope("/collections")
+                                    .route(
+                                        "",
+                                        web::get().to(handlers::collection::list_collections),
+                                    )
+                                    .route(
+                                        "",
+                                        web::post().to(handlers::collection::create_collection),
+                                    )
+                                    .route(
+                                        "/{id}",
+                                        web::get().to(handlers::collection::get_collection),
+                                    )
+                                    .route(
+                                        "/{id}",
+                                        web::patch().to(handlers::collection::update_collection),
+                                    )
+                                    .route(
+                                        "/{id}",
+                                        web::delete().to(handlers::collection::delete_collection),
+                                    )
+                                    .route(
+                                        "/{id}/documents",
+                                        web::get()
+                                            .to(handlers::collection::list_collection_documents),
                                     ),
                             )
                             .service(
@@ -93,8 +316,72 @@ async fn main() -> std::io::Result<()> {
                                     ),
                             )
                             .service(
-                                web::resource("/subscription/update")
-                                    .route(web::post().to(update_subscription)),
+                                web::scope("/admin")
+                                    .route("/users", web::get().to(handlers::admin::list_users))
+                                    .route("/users/{id}", web::get().to(handlers::admin::get_user))
+                                    .route(
+                                        "/users/{id}/suspend",
+                                        web::post().to(handlers::admin::suspend_user),
+                                    )
+                                    .route(
+                                        "/users/{id}/reactivate",
+                                        web::post().to(handlers::admin::reactivate_user),
+                                    )
+                                    .route(
+                                        "/users/{id}/plan",
+                                        web::put().to(handlers::admin::force_plan),
+                                    )
+                                    .route(
+                                        "/users/{id}/storage",
+                                        web::get().to(handlers::admin::storage_breakdown),
+                                    )
+                                    .route(
+                                        "/users/{id}/unlock",
+                                        web::post().to(handlers::admin::unlock_user),
+                                    )
+                                    .route(
+                                        "/payments/{reference_id}/refund",
+                                        web::post().to(handlers::payment::refund_payment),
+                                    ),
+                            )
+                            .service(
+                                web::scope("/me")
+                                    .route("", web::get().to(handlers::account::get_me))
+                                    .route("", web::patch().to(handlers::account::update_me))
+                                    .route("", web::delete().to(handlers::account::delete_me))
+                                    .route(
+                                        "/confirmations",
+                                        web::post().to(handlers::account::request_confirmation),
+                                    )
+                                    .route(
+                                        "/password",
+                                        web::put().to(handlers::account::change_password),
+                                    )
+                                    .route(
+                                        "/email",
+                                        web::post().to(handlers::account::request_email_change),
+                                    )
+                                    .route(
+                                        "/exports",
+                                        web::post().to(handlers::account::create_export),
+                                    )
+                                    .route(
+                                        "/exports/{id}",
+                                        web::get().to(handlers::account::get_export),
+                                    )
+                                    .route(
+                                        "/exports/{id}/download",
+                                        web::get().to(handlers::account::download_export),
+                                    ),
+                            )
+                            .service(
+                                web::scope("/tokens")
+                                    .route("", web::post().to(handlers::token::create_token))
+                                    .route("", web::get().to(handlers::token::list_tokens))
+                                    .route(
+                                        "/{id}",
+                                        web::delete().to(handlers::token::revoke_token),
+                                    ),
                             )
                             .service(
                                 web::resource("/subscription")
@@ -1,24 +1,28 @@
+use crate::{
+    error::AppError,
+    models::user::Entity as User,
+    services::{
+        jwt::JwtKeys,
+        personal_access_token::{self, Scope},
+        session,
+    },
+};
 use actix_web::{
     dev::{Payload, ServiceRequest},
-    error::ErrorUnauthorized,
-    Error, FromRequest, HttpMessage, HttpRequest,
+    error::{ErrorInternalServerError, ErrorUnauthorized},
+    http::Method,
+    web, Error, FromRequest, HttpMessage, HttpRequest,
 };
 use actix_web_httpauth::extractors::bearer::BearerAuth;
-use futures_util::future::{ready, Ready};
-use jsonwebtoken::{decode, DecodingKey, Validation};
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use sea_orm::{DatabaseConnection, EntityTrait};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AuthenticatedUser {
     pub id: i32,
     pub email: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Claims {
-    sub: String,   // User ID as a string in the JWT
-    email: String, // Add email to the claims
-    exp: usize,
+    pub session_id: Option<i32>, // None for personal access tokens
 }
 
 pub async fn validator(
@@ -26,30 +30,90 @@ pub async fn validator(
     credentials: BearerAuth,
 ) -> Result<ServiceRequest, (Error, ServiceRequest)> {
     // Skip authentication for OPTIONS requests (CORS preflight)
-    if req.method() == actix_web::http::Method::OPTIONS {
+    if req.method() == Method::OPTIONS {
         return Ok(req);
     }
     
     let token = credentials.token();
-    let jwt_secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-
-    let token_data = match decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(jwt_secret.as_bytes()),
-        &Validation::default(),
-    ) {
-        Ok(data) => data,
+    if token.starts_with(personal_access_token::TOKEN_PREFIX) {
+        return validate_personal_access_token(req, token).await;
+    }
+
+    let keys = match req.app_data::<web::Data<JwtKeys>>() {
+        Some(keys) => keys.clone(),
+        None => return Err((ErrorInternalServerError("Token keys not configured"), req)),
+    };
+    let claims = match keys.verify_access_token(token) {
+        Ok(claims) => claims,
         Err(_) => return Err((ErrorUnauthorized("Invalid token"), req)),
     };
 
-    let id = match token_data.claims.sub.parse::<i32>() {
+    let id = match claims.sub.parse::<i32>() {
         Ok(id) => id,
         Err(_) => return Err((ErrorUnauthorized("Invalid user ID in token"), req)),
     };
 
+    // A valid signature is not enough: the session may have been revoked
+    // (logout, stolen refresh token) or the account disabled since
+    let db = match req.app_data::<web::Data<DatabaseConnection>>() {
+        Some(db) => db.clone(),
+        None => return Err((ErrorInternalServerError("Database not configured"), req)),
+    };
+    match session::is_active(db.get_ref(), claims.sid, id).await {
+        Ok(true) => {}
+        Ok(false) => return Err((ErrorUnauthorized("Session expired or revoked"), req)),
+        Err(e) => return Err((e.into(), req)),
+    }
+
     let user = AuthenticatedUser {
         id,
-        email: token_data.claims.email.clone(),
+        email: claims.email,
+        session_id: Some(claims.sid),
+    };
+    req.extensions_mut().insert(user);
+    Ok(req)
+}
+
+/// Personal access tokens only reach the endpoints their scopes cover; the
+/// handlers behind them see an ordinary `AuthenticatedUser`.
+async fn validate_personal_access_token(
+    req: ServiceRequest,
+    token: &str,
+) -> Result<ServiceRequest, (Error, ServiceRequest)> {
+    let read_only = matches!(*req.method(), Method::GET | Method::HEAD);
+    let required = match Scope::required_for(req.path(), read_only) {
+        Some(scope) => scope,
+        None => {
+            return Err((
+                AppError::Forbidden(
+                    "This endpoint cannot be used with a personal access token".into(),
+                )
+                .into(),
+                req,
+            ))
+        }
+    };
+
+    let db = match req.app_data::<web::Data<DatabaseConnection>>() {
+        Some(db) => db.clone(),
+        None => return Err((ErrorInternalServerError("Database not configured"), req)),
+    };
+    let (account, scopes) = match personal_access_token::authenticate(db.get_ref(), token).await {
+        Ok(found) => found,
+        Err(e) => return Err((e.into(), req)),
+    };
+
+    if !scopes.contains(&required) {
+        return Err((
+            AppError::Forbidden(format!("Token is missing the {} scope", required)).into(),
+            req,
+        ));
+    }
+
+    let user = AuthenticatedUser {
+        id: account.id,
+        email: account.email,
+        session_id: None,
     };
     req.extensions_mut().insert(user);
     Ok(req)
@@ -68,3 +132,34 @@ impl FromRequest for AuthenticatedUser {
         }
     }
 }
+
+/// Extractor for handlers restricted to administrators. The flag is read from
+/// `users.is_admin` on every request, so revoking it takes effect at once.
+#[derive(Debug, Clone)]
+pub struct RequireAdmin(pub AuthenticatedUser);
+
+impl FromRequest for RequireAdmin {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let user = AuthenticatedUser::from_request(req, payload).into_inner();
+        let db = req.app_data::<web::Data<DatabaseConnection>>().cloned();
+
+        Box::pin(async move {
+            let user = user?;
+            let db =
+                db.ok_or_else(|| AppError::InternalServerError("Database not configured".into()))?;
+
+            let account = User::find_by_id(user.id)
+                .one(db.get_ref())
+                .await
+                .map_err(AppError::from)?;
+
+            match account {
+                Some(account) if account.is_admin && account.is_active => Ok(RequireAdmin(user)),
+                _ => Err(AppError::Forbidden("Administrator access required".into()).into()),
+            }
+        })
+    }
+}
@@ -1,16 +1,40 @@
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
 
+/// Progress of reading a document's text for search.
+#[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "text_status")]
+#[serde(rename_all = "lowercase")]
+pub enum TextStatus {
+    #[sea_orm(string_value = "pending")]
+    Pending,
+    #[sea_orm(string_value = "completed")]
+    Completed,
+    #[sea_orm(string_value = "failed")]
+    Failed,
+    #[sea_orm(string_value = "unsupported")]
+    Unsupported, // Not a PDF or text file, too large, or encrypted
+}
+
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
 #[sea_orm(table_name = "documents")]
 pub struct Model {
     #[sea_orm(primary_key)]
     pub id: i32,
     pub user_id: i32,
+    pub folder_id: Option<i32>, // None for documents at the top level
     pub filename: String,
     pub file_size: i64,
     pub mime_type: String,
-    pub s3_key: String,
+    pub s3_key: String, // Shared by documents linked to the same file
+    pub content_hash: Option<String>, // Hex-encoded SHA-256 of the file
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub author: Option<String>,
+    pub metadata: Json, // Custom key/value pairs, all strings
+    pub text_status: TextStatus,
+    pub page_count: Option<i32>,
+    pub pdf_info: Option<Json>, // Version, encryption and embedded metadata of PDFs
     pub created_at: DateTimeWithTimeZone,
     pub updated_at: DateTimeWithTimeZone,
 }
@@ -23,6 +47,12 @@ pub enum Relation {
         to = "super::user::Column::Id"
     )]
     User,
+    #[sea_orm(
+        belongs_to = "super::folder::Entity",
+        from = "Column::FolderId",
+        to = "super::folder::Column::Id"
+    )]
+    Folder,
 }
 
 impl Related<super::user::Entity> for Entity {
@@ -31,13 +61,60 @@ impl Related<super::user::Entity> for Entity {
     }
 }
 
+impl Related<super::folder::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Folder.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DocumentResponse {
     pub id: i32,
+    pub folder_id: Option<i32>,
     pub filename: String,
     pub file_size: i64,
     pub mime_type: String,
+    pub content_hash: Option<String>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub author: Option<String>,
+    pub metadata: Json,
+    pub tags: Vec<String>,
+    pub text_status: TextStatus,
+    pub page_count: Option<i32>,
+    pub pdf_info: Option<Json>,
     pub created_at: DateTimeWithTimeZone,
-}
\ No newline at end of file
+    pub updated_at: DateTimeWithTimeZone,
+}
+
+impl DocumentResponse {
+    pub fn with_tags(mut self, tags: Vec<String>) -> Self {
+        self.tags = tags;
+        self
+    }
+}
+
+impl From<Model> for DocumentResponse {
+    fn from(model: Model) -> Self {
+        DocumentResponse {
+            id: model.id,
+            folder_id: model.folder_id,
+            filename: model.filename,
+            file_size: model.file_size,
+            mime_type: model.mime_type,
+            content_hash: model.content_hash,
+            title: model.title,
+            description: model.description,
+            author: model.author,
+            metadata: model.metadata,
+            tags: Vec::new(),
+            text_status: model.text_status,
+            page_count: model.page_count,
+            pdf_info: model.pdf_info,
+            created_at: model.created_at,
+            updated_at: model.updated_at,
+        }
+    }
+}
@@ -0,0 +1,20 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Recent failed logins for one key (an email address or a client IP).
+/// Rows whose last failure is older than the lockout window are stale and
+/// start over on the next failure.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "login_throttles")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub key: String, // e.g. "email:jane@example.com" or "ip:203.0.113.7"
+    pub failures: i32,
+    pub last_failed_at: DateTimeWithTimeZone,
+    pub locked_until: Option<DateTimeWithTimeZone>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
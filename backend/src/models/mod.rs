@@ -1,5 +1,20 @@
+pub mod account_export;
+pub mod login_throttle;
+pub mod oidc_state;
 pub mod pdf;
+pub mod personal_access_token;
+pub mod plan;
+pub mod plan_change;
+pub mod plan_price;
+pub mod recovery_code;
+pub mod session;
 pub mod subscription;
 pub mod user;
+pub mod user_identity;
 pub mod document;
-pub mod payment;
\ No newline at end of file
+pub mod payment;
+pub mod folder;
+pub mod tag;
+pub mod document_tag;
+pub mod collection;
+pub mod document_page;
@@ -0,0 +1,23 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// An authorization request in flight, keyed by its `state` parameter.
+/// Deleted when the provider redirects back.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "oidc_states")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub state: String,
+    pub provider: String,
+    pub code_verifier: String, // PKCE verifier, only its hash is sent to the provider
+    pub nonce: String,
+    pub user_id: Option<i32>, // Set when a logged-in user is linking the provider
+    pub expires_at: DateTimeWithTimeZone,
+    pub created_at: DateTimeWithTimeZone,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
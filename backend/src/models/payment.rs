@@ -12,14 +12,18 @@ pub enum PaymentStatus {
     Failed,
     #[sea_orm(string_value = "cancelled")]
     Cancelled,
+    #[sea_orm(string_value = "refunded")]
+    Refunded,
 }
 
 #[derive(Debug, Clone, PartialEq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
 #[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "payment_provider")]
 pub enum PaymentProvider {
     #[sea_orm(string_value = "mtn_momo")]
+    #[serde(rename = "mtn_momo")]
     MtnMomo,
     #[sea_orm(string_value = "paypal")]
+    #[serde(rename = "paypal")]
     PayPal,
 }
 
@@ -29,11 +33,13 @@ pub struct Model {
     #[sea_orm(primary_key)]
     pub id: i32,
     pub user_id: i32,
+    pub plan_id: i32, // Plan being paid for
     pub reference_id: String,
-    pub mtn_reference_id: String,
+    pub provider_reference_id: String, // Order/transaction id assigned by the provider
     pub amount: Decimal,
+    pub refunded_amount: Decimal, // Total of all refunds so far
     pub currency: String,
-    pub phone_number: String,
+    pub phone_number: Option<String>, // Only used by MTN MoMo
     pub provider: PaymentProvider,
     pub status: PaymentStatus,
     pub provider_response: Option<Json>,
@@ -51,6 +57,12 @@ pub enum Relation {
         to = "super::user::Column::Id"
     )]
     User,
+    #[sea_orm(
+        belongs_to = "super::plan::Entity",
+        from = "Column::PlanId",
+        to = "super::plan::Column::Id"
+    )]
+    Plan,
 }
 
 impl Related<super::user::Entity> for Entity {
@@ -59,4 +71,10 @@ impl Related<super::user::Entity> for Entity {
     }
 }
 
+impl Related<super::plan::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Plan.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,43 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "billing_period")]
+#[serde(rename_all = "lowercase")]
+pub enum BillingPeriod {
+    #[sea_orm(string_value = "none")]
+    None,
+    #[sea_orm(string_value = "monthly")]
+    Monthly,
+    #[sea_orm(string_value = "yearly")]
+    Yearly,
+}
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "plans")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub code: String, // e.g., 'none', 'basic', 'premium', 'enterprise'
+    pub name: String,
+    pub storage_limit_bytes: i64,
+    pub billing_period: BillingPeriod,
+    pub features: Json, // Feature entitlements, e.g. {"priority_support": true}
+    pub is_active: bool,
+    pub created_at: DateTimeWithTimeZone,
+    pub updated_at: DateTimeWithTimeZone,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(has_many = "super::plan_price::Entity")]
+    PlanPrice,
+}
+
+impl Related<super::plan_price::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::PlanPrice.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,45 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "plan_change_source")]
+#[serde(rename_all = "lowercase")]
+pub enum PlanChangeSource {
+    #[sea_orm(string_value = "payment")]
+    Payment,
+    #[sea_orm(string_value = "admin")]
+    Admin,
+}
+
+/// Audit trail of every change to a user's plan.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "plan_changes")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub user_id: i32,
+    pub previous_plan_id: Option<i32>, // None when the subscription was created
+    pub new_plan_id: i32,
+    pub changed_by: Option<i32>, // The admin, or the paying user; None once that account is deleted
+    pub source: PlanChangeSource,
+    pub payment_id: Option<i32>,
+    pub created_at: DateTimeWithTimeZone,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -10,8 +10,7 @@ pub struct Model {
     pub stripe_customer_id: String,
     pub stripe_subscription_id: String,
     pub status: String,
-    pub plan: String,             // e.g., 'basic', 'premium', 'enterprise'
-    pub storage_limit_bytes: i64, // Storage limit in bytes based on plan
+    pub plan_id: i32, // Storage limit and entitlements come from the plan
     pub current_period_end: DateTimeWithTimeZone,
     #[sea_orm(column_type = "TimestampWithTimeZone")]
     pub created_at: DateTimeWithTimeZone,
@@ -27,6 +26,18 @@ pub enum Relation {
         to = "super::user::Column::Id"
     )]
     User,
+    #[sea_orm(
+        belongs_to = "super::plan::Entity",
+        from = "Column::PlanId",
+        to = "super::plan::Column::Id"
+    )]
+    Plan,
+}
+
+impl Related<super::plan::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Plan.def()
+    }
 }
 
 impl ActiveModelBehavior for ActiveModel {}
@@ -7,10 +7,16 @@ pub struct Model {
     #[sea_orm(primary_key)]
     pub id: i32,
     pub email: String,
-    pub password_hash: String,
+    pub password_hash: Option<String>, // None for accounts created through social login
     pub full_name: String,
     pub is_admin: bool,
     pub is_active: bool,
+    pub email_verified: bool,
+    pub pending_email: Option<String>, // New address awaiting confirmation
+    #[serde(skip_serializing)]
+    pub totp_secret: Option<String>, // Base32, set on enrolment and kept once confirmed
+    pub totp_enabled: bool,
+    pub totp_last_step: Option<i64>, // Last accepted time step, so a code works only once
     pub created_at: DateTimeWithTimeZone,
     pub updated_at: DateTimeWithTimeZone,
 }
@@ -0,0 +1,572 @@
+use crate::{
+    error::AppError,
+    models::{
+        account_export::{self, Entity as AccountExport, ExportStatus},
+        collection::{self, Entity as Collection},
+        document::{self, Entity as Document},
+        document_tag::{self, Entity as DocumentTag},
+        folder::{self, Entity as Folder},
+        payment::{self, Entity as Payment},
+        plan_change::{self, Entity as PlanChange},
+        subscription::{self, Entity as Subscription},
+        tag::{self, Entity as Tag},
+        user::Entity as User,
+        user_identity::{self, Entity as UserIdentity},
+    },
+    services::storage::StorageService,
+};
+use bytes::{BufMut, Bytes, BytesMut};
+use chrono::{Datelike, Duration, Timelike, Utc};
+use futures_util::{
+    future::ready,
+    stream::{self, Stream, StreamExt},
+};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, Set,
+};
+use std::cell::RefCell;
+use std::io::Error as IoError;
+use std::rc::Rc;
+use uuid::Uuid;
+
+/// How long a finished export can be downloaded.
+const EXPORT_TTL_DAYS: i64 = 7;
+
+/// An export still pending or running after this long was interrupted (e.g.
+/// by a restart) and no longer blocks a new one.
+const STALE_AFTER_MINUTES: i64 = 60;
+
+/// Queues an export of everything stored for `user_id` and builds it in the
+/// background. Earlier exports of the user are removed, so there is at most
+/// one archive per account in storage.
+pub async fn start(
+    db: &DatabaseConnection,
+    storage: &StorageService,
+    user_id: i32,
+) -> Result<account_export::Model, AppError> {
+    let previous = AccountExport::find()
+        .filter(account_export::Column::UserId.eq(user_id))
+        .all(db)
+        .await?;
+
+    let stale_before = Utc::now() - Duration::minutes(STALE_AFTER_MINUTES);
+    if previous.iter().any(|export| {
+        matches!(export.status, ExportStatus::Pending | ExportStatus::Running)
+            && export.created_at > stale_before
+    }) {
+        return Err(AppError::Conflict(
+            "An export is already in progress".into(),
+        ));
+    }
+
+    for export in previous {
+        remove(db, storage, &export).await?;
+    }
+
+    let export = account_export::ActiveModel {
+        user_id: Set(user_id),
+        status: Set(ExportStatus::Pending),
+        created_at: Set(Utc::now().into()),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+
+    let (db, storage, export_id) = (db.clone(), storage.clone(), export.id);
+    actix_web::rt::spawn(async move {
+        if let Err(e) = run(&db, &storage, export_id, user_id).await {
+            println!("Export {} of user {} failed: {}", export_id, user_id, e);
+            let failed = account_export::ActiveModel {
+                status: Set(ExportStatus::Failed),
+                error_message: Set(Some(e.to_string())),
+                completed_at: Set(Some(Utc::now().into())),
+                ..Default::default()
+            };
+            let _ = AccountExport::update_many()
+                .set(failed)
+                .filter(account_export::Column::Id.eq(export_id))
+                .exec(&db)
+                .await;
+        }
+    });
+
+    Ok(export)
+}
+
+/// Deletes an export and its archive.
+async fn remove(
+    db: &DatabaseConnection,
+    storage: &StorageService,
+    export: &account_export::Model,
+) -> Result<(), AppError> {
+    if let Some(key) = &export.storage_key {
+        storage
+            .delete_file(key)
+            .await
+            .map_err(|e| AppError::InternalServerError(e.to_string()))?;
+    }
+    AccountExport::delete_by_id(export.id).exec(db).await?;
+    Ok(())
+}
+
+async fn run(
+    db: &DatabaseConnection,
+    storage: &StorageService,
+    export_id: i32,
+    user_id: i32,
+) -> Result<(), AppError> {
+    let running = account_export::ActiveModel {
+        status: Set(ExportStatus::Running),
+        ..Default::default()
+    };
+    AccountExport::update_many()
+        .set(running)
+        .filter(account_export::Column::Id.eq(export_id))
+        .exec(db)
+        .await?;
+
+    let account = account_json(db, user_id).await?;
+    let documents = Document::find()
+        .filter(document::Column::UserId.eq(user_id))
+        .order_by_asc(document::Column::Id)
+        .all(db)
+        .await?;
+
+    // The archive is written as it is uploaded: each document is fetched
+    // from storage only once the previous one has been copied.
+    let zip = Rc::new(RefCell::new(ZipWriter::default()));
+    let mut entries = vec![file_entry(
+        zip.clone(),
+        "account.json".to_string(),
+        stream::once(ready(Ok(Bytes::from(account)))).boxed_local(),
+    )];
+    for document in documents {
+        let name = format!(
+            "documents/{}-{}",
+            document.id,
+            document.filename.replace(['/', '\\'], "_")
+        );
+        let (storage, key) = (storage.clone(), document.s3_key);
+        let body = stream::once(async move { storage.download_file(&key, None).await })
+            .map(|result| match result {
+                Ok(stream) => stream,
+                Err(e) => Box::pin(stream::once(ready(Err(IoError::other(e.to_string()))))),
+            })
+            .flatten()
+            .boxed_local();
+        entries.push(file_entry(zip.clone(), name, body));
+    }
+    let trailer = stream::once(async move { Ok(zip.borrow_mut().finish()) });
+    let archive = stream::iter(entries).flatten().chain(trailer);
+
+    let key = format!("{}/exports/{}.zip", user_id, Uuid::new_v4());
+    let size = storage
+        .upload_stream(&key, "application/zip", archive, i64::MAX)
+        .await
+        .map_err(|e| AppError::InternalServerError(e.to_string()))?;
+
+    let now = Utc::now();
+    let completed = account_export::ActiveModel {
+        status: Set(ExportStatus::Completed),
+        storage_key: Set(Some(key.clone())),
+        file_size: Set(Some(size)),
+        completed_at: Set(Some(now.into())),
+        expires_at: Set(Some((now + Duration::days(EXPORT_TTL_DAYS)).into())),
+        ..Default::default()
+    };
+    let result = AccountExport::update_many()
+        .set(completed)
+        .filter(account_export::Column::Id.eq(export_id))
+        .exec(db)
+        .await?;
+
+    // The account (or the export) was deleted while the archive was built
+    if result.rows_affected == 0 {
+        let _ = storage.delete_file(&key).await;
+        return Ok(());
+    }
+
+    println!(
+        "Export {} of user {} completed, {} bytes",
+        export_id, user_id, size
+    );
+    Ok(())
+}
+
+/// Profile, linked logins, subscription and payment history, folders, tags,
+/// collections and document metadata, as pretty-printed JSON.
+async fn account_json(db: &DatabaseConnection, user_id: i32) -> Result<Vec<u8>, AppError> {
+    let user = User::find_by_id(user_id)
+        .one(db)
+        .await?
+        .ok_or(AppError::NotFound("User not found".into()))?;
+
+    let identities = UserIdentity::find()
+        .filter(user_identity::Column::UserId.eq(user_id))
+        .all(db)
+        .await?;
+    let subscriptions = Subscription::find()
+        .filter(subscription::Column::UserId.eq(user_id))
+        .order_by_asc(subscription::Column::CreatedAt)
+        .all(db)
+        .await?;
+    let plan_changes = PlanChange::find()
+        .filter(plan_change::Column::UserId.eq(user_id))
+        .order_by_asc(plan_change::Column::CreatedAt)
+        .all(db)
+        .await?;
+    let payments = Payment::find()
+        .filter(payment::Column::UserId.eq(user_id))
+        .order_by_asc(payment::Column::CreatedAt)
+        .all(db)
+        .await?;
+    let folders = Folder::find()
+        .filter(folder::Column::UserId.eq(user_id))
+        .order_by_asc(folder::Column::Id)
+        .all(db)
+        .await?;
+    let documents = Document::find()
+        .filter(document::Column::UserId.eq(user_id))
+        .order_by_asc(document::Column::Id)
+        .all(db)
+        .await?;
+    let tags = Tag::find()
+        .filter(tag::Column::UserId.eq(user_id))
+        .order_by_asc(tag::Column::Id)
+        .all(db)
+        .await?;
+    let document_tags = DocumentTag::find()
+        .filter(document_tag::Column::TagId.is_in(tags.iter().map(|tag| tag.id)))
+        .order_by_asc(document_tag::Column::DocumentId)
+        .order_by_asc(document_tag::Column::TagId)
+        .all(db)
+        .await?;
+    let collections = Collection::find()
+        .filter(collection::Column::UserId.eq(user_id))
+        .order_by_asc(collection::Column::Id)
+        .all(db)
+        .await?;
+
+    let account = serde_json::json!({
+        "exported_at": Utc::now().to_rfc3339(),
+        "profile": {
+            "id": user.id,
+            "email": user.email,
+            "full_name": user.full_name,
+            "email_verified": user.email_verified,
+            "two_factor_enabled": user.totp_enabled,
+            "created_at": user.created_at.to_rfc3339(),
+            "updated_at": user.updated_at.to_rfc3339(),
+        },
+        "identities": identities,
+        "subscriptions": subscriptions,
+        "plan_changes": plan_changes,
+        "payments": payments,
+        "folders": folders,
+        "documents": documents,
+        "tags": tags,
+        "document_tags": document_tags,
+        "collections": collections,
+    });
+
+    serde_json::to_vec_pretty(&account)
+        .map_err(|e| AppError::InternalServerError(format!("Failed to encode export: {}", e)))
+}
+
+type EntryBody = stream::LocalBoxStream<'static, Result<Bytes, IoError>>;
+
+/// One archive member: local header, contents, then the data descriptor
+/// with the checksum and size, which are only known once the contents have
+/// passed through.
+fn file_entry(
+    zip: Rc<RefCell<ZipWriter>>,
+    name: String,
+    body: EntryBody,
+) -> impl Stream<Item = Result<Bytes, IoError>> {
+    let (start, update, end) = (zip.clone(), zip.clone(), zip);
+
+    stream::once(async move { Ok(start.borrow_mut().start_entry(name)) })
+        .chain(body.map(move |chunk| {
+            if let Ok(chunk) = &chunk {
+                update.borrow_mut().update(chunk);
+            }
+            chunk
+        }))
+        .chain(stream::once(
+            async move { Ok(end.borrow_mut().finish_entry()) },
+        ))
+}
+
+const ZIP_VERSION: u16 = 45; // ZIP64
+const ZIP_FLAGS: u16 = 0x0808; // Data descriptor follows, UTF-8 names
+
+struct CentralEntry {
+    name: String,
+    crc: u32,
+    size: u64,
+    offset: u64,
+}
+
+/// Minimal streaming ZIP writer: stored (uncompressed) entries with ZIP64
+/// sizes throughout, so neither the number nor the size of documents is
+/// limited. PDFs barely compress, so deflate would cost CPU for little gain.
+struct ZipWriter {
+    offset: u64,
+    entries: Vec<CentralEntry>,
+    current: Option<(String, u64, crc32fast::Hasher, u64)>,
+    dos_time: u16,
+    dos_date: u16,
+}
+
+impl Default for ZipWriter {
+    fn default() -> Self {
+        let now = Utc::now();
+        Self {
+            offset: 0,
+            entries: Vec::new(),
+            current: None,
+            dos_time: ((now.hour() << 11) | (now.minute() << 5) | (now.second() / 2)) as u16,
+            dos_date: (((now.year() - 1980) as u32) << 9 | (now.month() << 5) | now.day()) as u16,
+        }
+    }
+}
+
+impl ZipWriter {
+    fn start_entry(&mut self, name: String) -> Bytes {
+        let mut header = BytesMut::with_capacity(30 + name.len() + 20);
+        header.put_u32_le(0x0403_4b50);
+        header.put_u16_le(ZIP_VERSION);
+        header.put_u16_le(ZIP_FLAGS);
+        header.put_u16_le(0); // Stored
+        header.put_u16_le(self.dos_time);
+        header.put_u16_le(self.dos_date);
+        header.put_u32_le(0); // CRC, sizes: in the data descriptor
+        header.put_u32_le(u32::MAX);
+        header.put_u32_le(u32::MAX);
+        header.put_u16_le(name.len() as u16);
+        header.put_u16_le(20);
+        header.put_slice(name.as_bytes());
+        header.put_u16_le(0x0001); // ZIP64 extra field
+        header.put_u16_le(16);
+        header.put_u64_le(0);
+        header.put_u64_le(0);
+
+        self.current = Some((name, self.offset, crc32fast::Hasher::new(), 0));
+        self.offset += header.len() as u64;
+        header.freeze()
+    }
+
+    fn update(&mut self, chunk: &[u8]) {
+        if let Some((_, _, hasher, size)) = &mut self.current {
+            hasher.update(chunk);
+            *size += chunk.len() as u64;
+        }
+        self.offset += chunk.len() as u64;
+    }
+
+    fn finish_entry(&mut self) -> Bytes {
+        let (name, offset, hasher, size) = match self.current.take() {
+            Some(current) => current,
+            None => return Bytes::new(),
+        };
+        let crc = hasher.finalize();
+
+        let mut descriptor = BytesMut::with_capacity(24);
+        descriptor.put_u32_le(0x0807_4b50);
+        descriptor.put_u32_le(crc);
+        descriptor.put_u64_le(size);
+        descriptor.put_u64_le(size);
+
+        self.entries.push(CentralEntry {
+            name,
+            crc,
+            size,
+            offset,
+        });
+        self.offset += descriptor.len() as u64;
+        descriptor.freeze()
+    }
+
+    /// Central directory and the end records that point to it.
+    fn finish(&mut self) -> Bytes {
+        let mut out = BytesMut::new();
+        let directory_offset = self.offset;
+
+        for entry in &self.entries {
+            out.put_u32_le(0x0201_4b50);
+            out.put_u16_le(ZIP_VERSION);
+            out.put_u16_le(ZIP_VERSION);
+            out.put_u16_le(ZIP_FLAGS);
+            out.put_u16_le(0);
+            out.put_u16_le(self.dos_time);
+            out.put_u16_le(self.dos_date);
+            out.put_u32_le(entry.crc);
+            out.put_u32_le(u32::MAX);
+            out.put_u32_le(u32::MAX);
+            out.put_u16_le(entry.name.len() as u16);
+            out.put_u16_le(28);
+            out.put_u16_le(0); // Comment
+            out.put_u16_le(0); // Disk
+            out.put_u16_le(0); // Internal attributes
+            out.put_u32_le(0); // External attributes
+            out.put_u32_le(u32::MAX);
+            out.put_slice(entry.name.as_bytes());
+            out.put_u16_le(0x0001);
+            out.put_u16_le(24);
+            out.put_u64_le(entry.size);
+            out.put_u64_le(entry.size);
+            out.put_u64_le(entry.offset);
+        }
+
+        let directory_size = out.len() as u64;
+        let end_offset = directory_offset + directory_size;
+        let count = self.entries.len() as u64;
+
+        // ZIP64 end of central directory record and its locator
+        out.put_u32_le(0x0606_4b50);
+        out.put_u64_le(44);
+        out.put_u16_le(ZIP_VERSION);
+        out.put_u16_le(ZIP_VERSION);
+        out.put_u32_le(0);
+        out.put_u32_le(0);
+        out.put_u64_le(count);
+        out.put_u64_le(count);
+        out.put_u64_le(directory_size);
+        out.put_u64_le(directory_offset);
+
+        out.put_u32_le(0x0706_4b50);
+        out.put_u32_le(0);
+        out.put_u64_le(end_offset);
+        out.put_u32_le(1);
+
+        // Classic end record, with every field deferring to the ZIP64 one
+        out.put_u32_le(0x0605_4b50);
+        out.put_u16_le(u16::MAX);
+        out.put_u16_le(u16::MAX);
+        out.put_u16_le(u16::MAX);
+        out.put_u16_le(u16::MAX);
+        out.put_u32_le(u32::MAX);
+        out.put_u32_le(u32::MAX);
+        out.put_u16_le(0);
+
+        self.offset += out.len() as u64;
+        out.freeze()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+    use zip::ZipArchive;
+
+    /// Builds an archive the way `run` does, from in-memory files.
+    async fn archive(files: Vec<(String, Vec<u8>)>) -> Vec<u8> {
+        let zip = Rc::new(RefCell::new(ZipWriter::default()));
+        let entries: Vec<_> = files
+            .into_iter()
+            .map(|(name, contents)| {
+                // Several chunks per file, as storage delivers them
+                let chunks: Vec<_> = contents
+                    .chunks(7)
+                    .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+                    .collect();
+                file_entry(zip.clone(), name, stream::iter(chunks).boxed_local())
+            })
+            .collect();
+        let trailer = stream::once(async move { Ok(zip.borrow_mut().finish()) });
+        let parts: Vec<Result<Bytes, IoError>> = stream::iter(entries)
+            .flatten()
+            .chain(trailer)
+            .collect()
+            .await;
+        parts.into_iter().flat_map(|part| part.unwrap()).collect()
+    }
+
+    fn read(archive: &mut ZipArchive<impl Read + Seek>, name: &str) -> Vec<u8> {
+        let mut file = archive.by_name(name).unwrap();
+        let mut contents = Vec::new();
+        // Reading to the end also checks the CRC
+        file.read_to_end(&mut contents).unwrap();
+        assert_eq!(file.size(), contents.len() as u64);
+        contents
+    }
+
+    #[actix_web::test]
+    async fn entries_round_trip_through_a_zip_reader() {
+        let pdf: Vec<u8> = (0..10_000u32).map(|i| (i * 31 % 251) as u8).collect();
+        let files = vec![
+            ("account.json".to_string(), b"{\"profile\": {}}".to_vec()),
+            ("documents/1-report.pdf".to_string(), pdf.clone()),
+            ("documents/2-empty.pdf".to_string(), Vec::new()),
+            (
+                "documents/3-Übersicht.pdf".to_string(),
+                b"%PDF-1.7".to_vec(),
+            ),
+        ];
+        let bytes = archive(files).await;
+
+        let mut archive = ZipArchive::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(archive.len(), 4);
+        assert_eq!(read(&mut archive, "account.json"), b"{\"profile\": {}}");
+        assert_eq!(read(&mut archive, "documents/1-report.pdf"), pdf);
+        assert_eq!(read(&mut archive, "documents/2-empty.pdf"), b"");
+        assert_eq!(read(&mut archive, "documents/3-Übersicht.pdf"), b"%PDF-1.7");
+    }
+
+    #[actix_web::test]
+    async fn entry_count_is_not_limited_to_16_bits() {
+        let files = (0..70_000)
+            .map(|i| (format!("documents/{}.pdf", i), i.to_string().into_bytes()))
+            .collect();
+        let bytes = archive(files).await;
+
+        let mut archive = ZipArchive::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(archive.len(), 70_000);
+        assert_eq!(read(&mut archive, "documents/69999.pdf"), b"69999");
+    }
+
+    #[test]
+    fn entries_past_4_gib_are_found() {
+        // Sizes and offsets above u32::MAX only fit the ZIP64 fields. The
+        // large entry is a hole in a sparse file, so this needs no disk space.
+        const LARGE: u64 = (1 << 32) + 5;
+        let mut file = tempfile::tempfile().unwrap();
+        let mut zip = ZipWriter::default();
+
+        file.write_all(&zip.start_entry("documents/1-large.pdf".to_string()))
+            .unwrap();
+        file.seek(SeekFrom::Current(LARGE as i64)).unwrap();
+        // The checksum of LARGE zero bytes, built by doubling
+        let mut zeros = crc32fast::Hasher::new();
+        zeros.update(&[0]);
+        let mut crc = crc32fast::Hasher::new();
+        for bit in 0..=32 {
+            if LARGE & (1 << bit) != 0 {
+                crc.combine(&zeros);
+            }
+            let double = zeros.clone();
+            zeros.combine(&double);
+        }
+        if let Some((_, _, hasher, size)) = &mut zip.current {
+            *hasher = crc;
+            *size = LARGE;
+        }
+        zip.offset += LARGE;
+        file.write_all(&zip.finish_entry()).unwrap();
+
+        file.write_all(&zip.start_entry("account.json".to_string()))
+            .unwrap();
+        zip.update(b"{}");
+        file.write_all(b"{}").unwrap();
+        file.write_all(&zip.finish_entry()).unwrap();
+        file.write_all(&zip.finish()).unwrap();
+
+        let mut archive = ZipArchive::new(file).unwrap();
+        assert_eq!(
+            archive.by_name("documents/1-large.pdf").unwrap().size(),
+            LARGE
+        );
+        assert_eq!(read(&mut archive, "account.json"), b"{}");
+    }
+}
@@ -0,0 +1,227 @@
+use crate::{
+    error::AppError,
+    models::user::{self, Entity as User},
+};
+use chrono::{Duration, Utc};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use sea_orm::{DatabaseConnection, EntityTrait};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// What a token may be used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenPurpose {
+    VerifyEmail,
+    PasswordReset,
+    /// Confirms the new address of an email change. Sent to that address.
+    ChangeEmail,
+    /// Handed out by `login` when the password was right but a second factor
+    /// is still needed. Never emailed.
+    TwoFactorLogin,
+    /// Stands in for the password on sensitive changes to accounts that have
+    /// none. Sent to the account's address.
+    ConfirmChange,
+}
+
+impl TokenPurpose {
+    fn ttl(self) -> Duration {
+        match self {
+            TokenPurpose::VerifyEmail => Duration::hours(24),
+            TokenPurpose::PasswordReset => Duration::hours(1),
+            TokenPurpose::ChangeEmail => Duration::hours(24),
+            TokenPurpose::TwoFactorLogin => Duration::minutes(5),
+            TokenPurpose::ConfirmChange => Duration::minutes(15),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AccountTokenClaims {
+    sub: String,
+    purpose: TokenPurpose,
+    fp: String, // Fingerprint of the account state the token was issued for
+    exp: usize,
+}
+
+/// Digest of the part of the account a token acts on. Using the token changes
+/// that state (the address becomes verified, the password hash changes), so
+/// the fingerprint no longer matches and the token cannot be used twice. A
+/// reset token also dies when the password is changed some other way, a
+/// login challenge when a TOTP code is accepted, and an email change link
+/// when another change is requested. A change confirmation covers the
+/// address, the pending address and the password, which every change it
+/// confirms alters.
+fn fingerprint(user: &user::Model, purpose: TokenPurpose) -> String {
+    let state = match purpose {
+        TokenPurpose::VerifyEmail => format!("verify:{}:{}", user.email, user.email_verified),
+        TokenPurpose::PasswordReset => format!("reset:{:?}", user.password_hash),
+        TokenPurpose::ChangeEmail => format!("change:{}:{:?}", user.email, user.pending_email),
+        TokenPurpose::TwoFactorLogin => {
+            format!("2fa:{:?}:{:?}", user.password_hash, user.totp_last_step)
+        }
+        TokenPurpose::ConfirmChange => format!(
+            "confirm:{}:{:?}:{:?}",
+            user.email, user.pending_email, user.password_hash
+        ),
+    };
+    hex::encode(Sha256::digest(state.as_bytes()))
+}
+
+/// Key account tokens are signed with, taken from `JWT_SECRET`. Loaded once
+/// at startup, so a missing secret stops the server instead of failing
+/// requests later.
+#[derive(Clone)]
+pub struct AccountTokenKey {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+}
+
+impl AccountTokenKey {
+    pub fn from_env() -> Result<Self, AppError> {
+        match std::env::var("JWT_SECRET") {
+            Ok(secret) if !secret.is_empty() => Ok(Self::new(secret.as_bytes())),
+            _ => Err(AppError::InternalServerError(
+                "JWT_SECRET must be set".into(),
+            )),
+        }
+    }
+
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+        }
+    }
+
+    /// Signs a single-use token for `purpose` on `user`.
+    pub fn issue(&self, user: &user::Model, purpose: TokenPurpose) -> Result<String, AppError> {
+        let claims = AccountTokenClaims {
+            sub: user.id.to_string(),
+            purpose,
+            fp: fingerprint(user, purpose),
+            exp: (Utc::now() + purpose.ttl()).timestamp() as usize,
+        };
+
+        encode(&Header::default(), &claims, &self.encoding)
+            .map_err(|e| AppError::InternalServerError(format!("Token generation failed: {}", e)))
+    }
+
+    /// Checks a token and returns the user it was issued for. Expired,
+    /// tampered, already used and wrong-purpose tokens are all rejected the
+    /// same way.
+    pub async fn verify(
+        &self,
+        db: &DatabaseConnection,
+        token: &str,
+        purpose: TokenPurpose,
+    ) -> Result<user::Model, AppError> {
+        let invalid = || AppError::BadRequest("Invalid or expired token".into());
+
+        let claims = decode::<AccountTokenClaims>(token, &self.decoding, &Validation::default())
+            .map_err(|_| invalid())?
+            .claims;
+
+        if claims.purpose != purpose {
+            return Err(invalid());
+        }
+
+        let user_id: i32 = claims.sub.parse().map_err(|_| invalid())?;
+        let user = User::find_by_id(user_id)
+            .one(db)
+            .await?
+            .ok_or_else(invalid)?;
+
+        if fingerprint(&user, purpose) != claims.fp {
+            return Err(invalid());
+        }
+
+        Ok(user)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user() -> user::Model {
+        let now = Utc::now().fixed_offset();
+        user::Model {
+            id: 7,
+            email: "jane@example.com".to_string(),
+            password_hash: Some("$2b$12$hash".to_string()),
+            full_name: "Jane".to_string(),
+            is_admin: false,
+            is_active: true,
+            email_verified: false,
+            pending_email: None,
+            totp_secret: None,
+            totp_enabled: false,
+            totp_last_step: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    // These tokens are turned away before the account is looked up, so no
+    // database is needed
+    async fn rejected(key: &AccountTokenKey, token: &str, purpose: TokenPurpose) -> bool {
+        matches!(
+            key.verify(&DatabaseConnection::Disconnected, token, purpose)
+                .await,
+            Err(AppError::BadRequest(_))
+        )
+    }
+
+    #[tokio::test]
+    async fn token_signed_with_another_key_is_rejected() {
+        let token = AccountTokenKey::new(b"other secret")
+            .issue(&user(), TokenPurpose::VerifyEmail)
+            .unwrap();
+
+        let key = AccountTokenKey::new(b"secret");
+        assert!(rejected(&key, &token, TokenPurpose::VerifyEmail).await);
+    }
+
+    #[tokio::test]
+    async fn token_for_another_purpose_is_rejected() {
+        let key = AccountTokenKey::new(b"secret");
+        let token = key.issue(&user(), TokenPurpose::VerifyEmail).unwrap();
+
+        assert!(rejected(&key, &token, TokenPurpose::PasswordReset).await);
+        assert!(rejected(&key, &token, TokenPurpose::TwoFactorLogin).await);
+    }
+
+    #[tokio::test]
+    async fn malformed_token_is_rejected() {
+        let key = AccountTokenKey::new(b"secret");
+        assert!(rejected(&key, "", TokenPurpose::VerifyEmail).await);
+        assert!(rejected(&key, "not.a.token", TokenPurpose::VerifyEmail).await);
+    }
+
+    #[test]
+    fn fingerprint_changes_with_the_state_a_token_acts_on() {
+        let mut account = user();
+        let before = fingerprint(&account, TokenPurpose::VerifyEmail);
+        account.email_verified = true;
+        assert_ne!(before, fingerprint(&account, TokenPurpose::VerifyEmail));
+
+        let before = fingerprint(&account, TokenPurpose::PasswordReset);
+        account.password_hash = Some("$2b$12$other".to_string());
+        assert_ne!(before, fingerprint(&account, TokenPurpose::PasswordReset));
+    }
+
+    #[test]
+    fn change_confirmation_is_spent_by_the_change() {
+        let mut account = user();
+        account.password_hash = None;
+
+        let before = fingerprint(&account, TokenPurpose::ConfirmChange);
+        account.pending_email = Some("new@example.com".to_string());
+        assert_ne!(before, fingerprint(&account, TokenPurpose::ConfirmChange));
+
+        let before = fingerprint(&account, TokenPurpose::ConfirmChange);
+        account.password_hash = Some("$2b$12$first".to_string());
+        assert_ne!(before, fingerprint(&account, TokenPurpose::ConfirmChange));
+    }
+}
@@ -0,0 +1,343 @@
+use crate::{
+    error::AppError,
+    models::{
+        document::{self, Entity as Document},
+        document_tag,
+        tag::{self, Entity as Tag},
+    },
+};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveTime, TimeZone, Utc};
+use sea_orm::{
+    sea_query::Query, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Select,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// Conditions on a user's documents. Used for the filters of the document
+/// list and stored as the definition of smart collections.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DocumentFilter {
+    /// Documents carrying all of these tags.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Exact type, or a family such as "image/*".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    /// Size range in bytes, inclusive.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_size: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<i64>,
+    /// Upload date range, inclusive: RFC 3339 timestamps or YYYY-MM-DD dates.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_from: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_to: Option<String>,
+}
+
+impl DocumentFilter {
+    /// Rejects malformed values, so a bad filter is refused when a
+    /// collection is saved rather than every time it is opened.
+    pub fn validate(&self) -> Result<(), AppError> {
+        if let Some(from) = &self.created_from {
+            parse_date_bound("created_from", from, false)?;
+        }
+        if let Some(to) = &self.created_to {
+            parse_date_bound("created_to", to, true)?;
+        }
+        Ok(())
+    }
+
+    /// Narrows `select` to the documents of `user_id` matching the filter.
+    pub async fn apply(
+        &self,
+        db: &DatabaseConnection,
+        user_id: i32,
+        mut select: Select<Document>,
+    ) -> Result<Select<Document>, AppError> {
+        select = select.filter(document::Column::UserId.eq(user_id));
+
+        // Names are compared as tags are stored: trimmed, and each only once
+        let names: BTreeSet<&str> = self
+            .tags
+            .iter()
+            .map(|name| name.trim())
+            .filter(|name| !name.is_empty())
+            .collect();
+        if !names.is_empty() {
+            let tags = Tag::find()
+                .filter(tag::Column::UserId.eq(user_id))
+                .filter(tag::Column::Name.is_in(names.iter().copied()))
+                .all(db)
+                .await?;
+
+            // A tag the user does not have matches no documents
+            if tags.len() < names.len() {
+                return Ok(select.filter(document::Column::Id.is_in(Vec::<i32>::new())));
+            }
+            for tag in tags {
+                select = select.filter(
+                    document::Column::Id.in_subquery(
+                        Query::select()
+                            .column(document_tag::Column::DocumentId)
+                            .from(document_tag::Entity)
+                            .and_where(document_tag::Column::TagId.eq(tag.id))
+                            .to_owned(),
+                    ),
+                );
+            }
+        }
+
+        match self.mime_type.as_deref().map(str::trim) {
+            None | Some("") => {}
+            Some(family) if family.ends_with("/*") => {
+                let prefix = family.trim_end_matches('*');
+                select = select.filter(document::Column::MimeType.starts_with(prefix));
+            }
+            Some(mime_type) => select = select.filter(document::Column::MimeType.eq(mime_type)),
+        }
+
+        if let Some(min_size) = self.min_size {
+            select = select.filter(document::Column::FileSize.gte(min_size));
+        }
+        if let Some(max_size) = self.max_size {
+            select = select.filter(document::Column::FileSize.lte(max_size));
+        }
+        if let Some(from) = &self.created_from {
+            let from = parse_date_bound("created_from", from, false)?;
+            select = select.filter(document::Column::CreatedAt.gte(from));
+        }
+        if let Some(to) = &self.created_to {
+            let to = parse_date_bound("created_to", to, true)?;
+            select = select.filter(document::Column::CreatedAt.lte(to));
+        }
+
+        Ok(select)
+    }
+}
+
+/// Parses a `created_from` / `created_to` bound. A bare date stands for the
+/// whole day, so `end_of_day` picks its last instant for upper bounds.
+fn parse_date_bound(
+    name: &str,
+    value: &str,
+    end_of_day: bool,
+) -> Result<DateTime<FixedOffset>, AppError> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Ok(timestamp);
+    }
+
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| {
+        AppError::BadRequest(format!(
+            "{} must be an RFC 3339 timestamp or a YYYY-MM-DD date",
+            name
+        ))
+    })?;
+    let time = if end_of_day {
+        NaiveTime::from_hms_micro_opt(23, 59, 59, 999_999)
+    } else {
+        NaiveTime::from_hms_opt(0, 0, 0)
+    }
+    .unwrap_or_default();
+
+    Ok(Utc.from_utc_datetime(&date.and_time(time)).fixed_offset())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::tag as tags;
+    use crate::test_support::TestDb;
+    use sea_orm::{ActiveValue::Set, QueryOrder};
+
+    /// Inserts a document uploaded at `created_at` with these tags.
+    async fn document(
+        db: &TestDb,
+        user_id: i32,
+        mime_type: &str,
+        created_at: &str,
+        tag_names: &[&str],
+    ) -> i32 {
+        let document = document::ActiveModel {
+            user_id: Set(user_id),
+            filename: Set("file".to_string()),
+            file_size: Set(100),
+            mime_type: Set(mime_type.to_string()),
+            s3_key: Set(uuid::Uuid::new_v4().to_string()),
+            created_at: Set(DateTime::parse_from_rfc3339(created_at).unwrap()),
+            ..Default::default()
+        };
+        let document_id = Document::insert(document)
+            .exec(&**db)
+            .await
+            .unwrap()
+            .last_insert_id;
+
+        let names: Vec<String> = tag_names.iter().map(|name| name.to_string()).collect();
+        for tag in tags::find_or_create(db, user_id, &names).await.unwrap() {
+            let link = document_tag::ActiveModel {
+                document_id: Set(document_id),
+                tag_id: Set(tag.id),
+            };
+            document_tag::Entity::insert(link)
+                .exec(&**db)
+                .await
+                .unwrap();
+        }
+        document_id
+    }
+
+    async fn matching(db: &TestDb, user_id: i32, filter: DocumentFilter) -> Vec<i32> {
+        filter
+            .apply(db, user_id, Document::find())
+            .await
+            .unwrap()
+            .order_by_asc(document::Column::Id)
+            .all(&**db)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|document| document.id)
+            .collect()
+    }
+
+    fn tagged(names: &[&str]) -> DocumentFilter {
+        DocumentFilter {
+            tags: names.iter().map(|name| name.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[actix_web::test]
+    async fn documents_must_carry_every_tag() {
+        let Some(db) = TestDb::new().await else {
+            return;
+        };
+        let user_id = db.user("tags@example.com").await;
+        let other_user = db.user("other@example.com").await;
+        let now = "2024-01-01T00:00:00Z";
+        let both = document(&db, user_id, "application/pdf", now, &["work", "urgent"]).await;
+        let work = document(&db, user_id, "application/pdf", now, &["work"]).await;
+        document(&db, user_id, "application/pdf", now, &[]).await;
+        document(&db, other_user, "application/pdf", now, &["work", "urgent"]).await;
+
+        assert_eq!(
+            matching(&db, user_id, tagged(&["work"])).await,
+            vec![both, work]
+        );
+        assert_eq!(
+            matching(&db, user_id, tagged(&["work", "urgent"])).await,
+            vec![both]
+        );
+        // Repeated and padded names are the same tag
+        assert_eq!(
+            matching(
+                &db,
+                user_id,
+                tagged(&["urgent", " work", "work ", "urgent"])
+            )
+            .await,
+            vec![both]
+        );
+        assert_eq!(
+            matching(&db, user_id, tagged(&["work", "missing"])).await,
+            Vec::<i32>::new()
+        );
+        assert_eq!(
+            matching(&db, user_id, tagged(&["Work"])).await,
+            Vec::<i32>::new()
+        );
+        assert_eq!(matching(&db, user_id, tagged(&[" ", ""])).await.len(), 3);
+    }
+
+    #[actix_web::test]
+    async fn mime_types_match_exactly_or_by_family() {
+        let Some(db) = TestDb::new().await else {
+            return;
+        };
+        let user_id = db.user("types@example.com").await;
+        let now = "2024-01-01T00:00:00Z";
+        let png = document(&db, user_id, "image/png", now, &[]).await;
+        let jpeg = document(&db, user_id, "image/jpeg", now, &[]).await;
+        let pdf = document(&db, user_id, "application/pdf", now, &[]).await;
+        document(&db, user_id, "imagex/png", now, &[]).await;
+
+        let typed = |mime_type: &str| DocumentFilter {
+            mime_type: Some(mime_type.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            matching(&db, user_id, typed("image/*")).await,
+            vec![png, jpeg]
+        );
+        assert_eq!(
+            matching(&db, user_id, typed(" image/* ")).await,
+            vec![png, jpeg]
+        );
+        assert_eq!(
+            matching(&db, user_id, typed("application/pdf")).await,
+            vec![pdf]
+        );
+        assert_eq!(
+            matching(&db, user_id, typed("image")).await,
+            Vec::<i32>::new()
+        );
+        assert_eq!(matching(&db, user_id, typed("")).await.len(), 4);
+    }
+
+    #[actix_web::test]
+    async fn date_bounds_are_inclusive() {
+        let Some(db) = TestDb::new().await else {
+            return;
+        };
+        let user_id = db.user("dates@example.com").await;
+        let before = document(&db, user_id, "text/plain", "2024-01-01T23:59:59Z", &[]).await;
+        let start = document(&db, user_id, "text/plain", "2024-01-02T00:00:00Z", &[]).await;
+        let end = document(&db, user_id, "text/plain", "2024-01-02T23:59:59.999Z", &[]).await;
+        let after = document(&db, user_id, "text/plain", "2024-01-03T00:00:00Z", &[]).await;
+
+        let between = |from: Option<&str>, to: Option<&str>| DocumentFilter {
+            created_from: from.map(String::from),
+            created_to: to.map(String::from),
+            ..Default::default()
+        };
+        // A date covers the whole day
+        assert_eq!(
+            matching(
+                &db,
+                user_id,
+                between(Some("2024-01-02"), Some("2024-01-02"))
+            )
+            .await,
+            vec![start, end]
+        );
+        assert_eq!(
+            matching(&db, user_id, between(Some("2024-01-02"), None)).await,
+            vec![start, end, after]
+        );
+        assert_eq!(
+            matching(&db, user_id, between(None, Some("2024-01-01"))).await,
+            vec![before]
+        );
+        // Timestamps are exact, in any offset
+        assert_eq!(
+            matching(
+                &db,
+                user_id,
+                between(
+                    Some("2024-01-02T01:00:00+01:00"),
+                    Some("2024-01-02T00:00:00Z")
+                )
+            )
+            .await,
+            vec![start]
+        );
+
+        for bad in ["2024-13-01", "yesterday", "2024-01-02 00:00:00"] {
+            let filter = between(Some(bad), None);
+            assert!(filter.validate().is_err(), "{}", bad);
+            assert!(filter.apply(&db, user_id, Document::find()).await.is_err());
+        }
+    }
+}
@@ -0,0 +1,352 @@
+//! Documents with the same content, recognised by the SHA-256 hash taken of
+//! each file while it uploads.
+
+use crate::{
+    error::AppError,
+    models::document::{self, DocumentResponse, Entity as Document},
+    services::{storage::StorageService, tag as tags},
+};
+use futures_util::TryStreamExt;
+use sea_orm::{
+    sea_query::Expr, ActiveValue, ColumnTrait, DatabaseConnection, EntityTrait, PaginatorTrait,
+    QueryFilter, QueryOrder, QuerySelect, TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+
+/// What an upload does when the user already has a document with the same
+/// content.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateAction {
+    /// Store the new file as well
+    #[default]
+    Keep,
+    /// Refuse the upload
+    Reject,
+    /// Create the document without storing the file again, sharing the
+    /// existing one
+    Link,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DuplicateGroup {
+    pub content_hash: String,
+    pub file_size: i64,
+    /// Freed by deleting all but one of the stored copies. Linked documents
+    /// share a copy, so they free nothing.
+    pub reclaimable_bytes: i64,
+    pub documents: Vec<DocumentResponse>, // Oldest first
+}
+
+/// The user's oldest document with this content.
+pub async fn find_original(
+    db: &DatabaseConnection,
+    user_id: i32,
+    content_hash: &str,
+) -> Result<Option<document::Model>, AppError> {
+    Ok(Document::find()
+        .filter(document::Column::UserId.eq(user_id))
+        .filter(document::Column::ContentHash.eq(content_hash))
+        .order_by_asc(document::Column::Id)
+        .one(db)
+        .await?)
+}
+
+/// Creates `document` sharing the stored file its `s3_key` names, as long
+/// as another document still uses that file. Returns the new document's id,
+/// or `None` when the file has gone.
+///
+/// The documents using the file stay locked until the new one is in, so a
+/// concurrent [`delete`] either sees it or runs first and leaves no row to
+/// link to.
+pub async fn link(
+    db: &DatabaseConnection,
+    document: document::ActiveModel,
+) -> Result<Option<i32>, AppError> {
+    let ActiveValue::Set(s3_key) = &document.s3_key else {
+        return Err(AppError::InternalServerError(
+            "No stored file to link to".into(),
+        ));
+    };
+    let transaction = db.begin().await?;
+    let sharing = Document::find()
+        .filter(document::Column::S3Key.eq(s3_key.as_str()))
+        .lock_exclusive()
+        .all(&transaction)
+        .await?;
+    if sharing.is_empty() {
+        return Ok(None);
+    }
+    let result = Document::insert(document).exec(&transaction).await?;
+    transaction.commit().await?;
+    Ok(Some(result.last_insert_id))
+}
+
+/// Deletes a document and reports whether its stored file is now unused,
+/// and can be deleted as well.
+pub async fn delete(db: &DatabaseConnection, document: &document::Model) -> Result<bool, AppError> {
+    let transaction = db.begin().await?;
+    // Waits for any link to this file in progress
+    Document::find()
+        .filter(document::Column::S3Key.eq(&document.s3_key))
+        .lock_exclusive()
+        .all(&transaction)
+        .await?;
+    Document::delete_by_id(document.id)
+        .exec(&transaction)
+        .await?;
+    // Counted afresh, to include documents linked while waiting for the lock
+    let others = Document::find()
+        .filter(document::Column::S3Key.eq(&document.s3_key))
+        .count(&transaction)
+        .await?;
+    transaction.commit().await?;
+    Ok(others == 0)
+}
+
+/// The user's documents that share their content with another, grouped by
+/// content, most reclaimable space first.
+pub async fn groups(
+    db: &DatabaseConnection,
+    user_id: i32,
+) -> Result<Vec<DuplicateGroup>, AppError> {
+    let hashes: Vec<String> = Document::find()
+        .select_only()
+        .column(document::Column::ContentHash)
+        .filter(document::Column::UserId.eq(user_id))
+        .filter(document::Column::ContentHash.is_not_null())
+        .group_by(document::Column::ContentHash)
+        .having(Expr::expr(document::Column::Id.count()).gt(1))
+        .into_tuple()
+        .all(db)
+        .await?;
+    if hashes.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let documents = Document::find()
+        .filter(document::Column::UserId.eq(user_id))
+        .filter(document::Column::ContentHash.is_in(hashes))
+        .order_by_asc(document::Column::Id)
+        .all(db)
+        .await?;
+    let ids: Vec<i32> = documents.iter().map(|document| document.id).collect();
+    let mut tag_names = tags::names_by_document(db, &ids).await?;
+
+    let mut by_hash: HashMap<String, Vec<document::Model>> = HashMap::new();
+    for document in documents {
+        let hash = document.content_hash.clone().unwrap_or_default();
+        by_hash.entry(hash).or_default().push(document);
+    }
+
+    let mut groups: Vec<DuplicateGroup> = by_hash
+        .into_iter()
+        .map(|(content_hash, documents)| {
+            let file_size = documents[0].file_size;
+            let copies = documents
+                .iter()
+                .map(|document| document.s3_key.as_str())
+                .collect::<HashSet<_>>()
+                .len() as i64;
+            DuplicateGroup {
+                content_hash,
+                file_size,
+                reclaimable_bytes: (copies - 1) * file_size,
+                documents: documents
+                    .into_iter()
+                    .map(|document| {
+                        let tags = tag_names.remove(&document.id).unwrap_or_default();
+                        DocumentResponse::from(document).with_tags(tags)
+                    })
+                    .collect(),
+            }
+        })
+        .collect();
+    groups.sort_by(|a, b| {
+        b.reclaimable_bytes
+            .cmp(&a.reclaimable_bytes)
+            .then_with(|| a.documents[0].id.cmp(&b.documents[0].id))
+    });
+    Ok(groups)
+}
+
+/// Hashes documents uploaded before uploads were hashed, so that they are
+/// found as duplicates too. They are done one at a time, in the background.
+pub fn hash_existing(db: DatabaseConnection, storage: StorageService) {
+    actix_web::rt::spawn(async move {
+        match hash_unhashed(&db, &storage).await {
+            Ok(0) => {}
+            Ok(hashed) => println!("Hashed {} existing documents", hashed),
+            Err(e) => println!("Failed to hash existing documents: {}", e),
+        }
+    });
+}
+
+/// Returns how many documents were hashed. Documents whose file cannot be
+/// read are left without a hash.
+async fn hash_unhashed(
+    db: &DatabaseConnection,
+    storage: &StorageService,
+) -> Result<usize, AppError> {
+    let unhashed: Vec<(i32, String)> = Document::find()
+        .select_only()
+        .column(document::Column::Id)
+        .column(document::Column::S3Key)
+        .filter(document::Column::ContentHash.is_null())
+        .order_by_asc(document::Column::Id)
+        .into_tuple()
+        .all(db)
+        .await?;
+
+    let mut hashed = 0;
+    for (document_id, s3_key) in unhashed {
+        let content_hash = match hash_file(storage, &s3_key).await {
+            Ok(content_hash) => content_hash,
+            Err(e) => {
+                println!("Cannot hash document {}: {}", document_id, e);
+                continue;
+            }
+        };
+        Document::update_many()
+            .col_expr(document::Column::ContentHash, Expr::value(content_hash))
+            .filter(document::Column::Id.eq(document_id))
+            .filter(document::Column::ContentHash.is_null())
+            .exec(db)
+            .await?;
+        hashed += 1;
+    }
+    Ok(hashed)
+}
+
+async fn hash_file(storage: &StorageService, key: &str) -> Result<String, std::io::Error> {
+    let mut hasher = Sha256::new();
+    storage
+        .download_file(key, None)
+        .await
+        .map_err(|e| std::io::Error::other(e.to_string()))?
+        .try_for_each(|chunk| {
+            hasher.update(&chunk);
+            futures_util::future::ready(Ok(()))
+        })
+        .await?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::storage::MemoryBackend;
+    use crate::test_support::TestDb;
+    use bytes::Bytes;
+    use futures_util::stream;
+    use std::convert::Infallible;
+    use std::sync::Arc;
+
+    async fn store(storage: &StorageService, key: &str, contents: &'static [u8]) {
+        let body = stream::once(async move { Ok::<_, Infallible>(Bytes::from_static(contents)) });
+        storage
+            .upload_stream(key, "application/pdf", body, i64::MAX)
+            .await
+            .unwrap();
+    }
+
+    fn document(user_id: i32, s3_key: &str, content_hash: Option<&str>) -> document::ActiveModel {
+        document::ActiveModel {
+            user_id: ActiveValue::Set(user_id),
+            filename: ActiveValue::Set("report.pdf".to_string()),
+            file_size: ActiveValue::Set(3),
+            mime_type: ActiveValue::Set("application/pdf".to_string()),
+            s3_key: ActiveValue::Set(s3_key.to_string()),
+            content_hash: ActiveValue::Set(content_hash.map(String::from)),
+            ..Default::default()
+        }
+    }
+
+    async fn insert(db: &TestDb, user_id: i32, s3_key: &str, content_hash: Option<&str>) -> i32 {
+        Document::insert(document(user_id, s3_key, content_hash))
+            .exec(&**db)
+            .await
+            .unwrap()
+            .last_insert_id
+    }
+
+    async fn find(db: &TestDb, document_id: i32) -> document::Model {
+        Document::find_by_id(document_id)
+            .one(&**db)
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    async fn content_hash(db: &TestDb, document_id: i32) -> Option<String> {
+        find(db, document_id).await.content_hash
+    }
+
+    #[actix_web::test]
+    async fn shared_file_is_unused_once_its_last_document_is_deleted() {
+        let Some(db) = TestDb::new().await else {
+            return;
+        };
+        let user_id = db.user("reader@example.com").await;
+        let first = insert(&db, user_id, "1/shared.pdf", Some("same")).await;
+        let linked = link(&db, document(user_id, "1/shared.pdf", Some("same")))
+            .await
+            .unwrap()
+            .unwrap();
+        let copy = insert(&db, user_id, "1/copy.pdf", Some("same")).await;
+
+        let original = find_original(&db, user_id, "same").await.unwrap().unwrap();
+        assert_eq!(original.id, first);
+        assert!(find_original(&db, user_id + 1, "same")
+            .await
+            .unwrap()
+            .is_none());
+
+        // Two stored copies, so deleting one of them would free its size
+        let groups = groups(&db, user_id).await.unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].reclaimable_bytes, 3);
+        let ids: Vec<i32> = groups[0].documents.iter().map(|d| d.id).collect();
+        assert_eq!(ids, vec![first, linked, copy]);
+
+        assert!(!delete(&db, &find(&db, first).await).await.unwrap());
+        assert!(delete(&db, &find(&db, linked).await).await.unwrap());
+        assert!(delete(&db, &find(&db, copy).await).await.unwrap());
+
+        // Nothing is left to link to
+        let relinked = link(&db, document(user_id, "1/shared.pdf", Some("same")))
+            .await
+            .unwrap();
+        assert_eq!(relinked, None);
+        assert_eq!(Document::find().count(&*db).await.unwrap(), 0);
+    }
+
+    #[actix_web::test]
+    async fn existing_documents_are_hashed_once() {
+        let Some(db) = TestDb::new().await else {
+            return;
+        };
+        let storage = StorageService::new(Arc::new(MemoryBackend::new()));
+        let user_id = db.user("reader@example.com").await;
+        store(&storage, "1/old.pdf", b"abc").await;
+        let old = insert(&db, user_id, "1/old.pdf", None).await;
+        let lost = insert(&db, user_id, "1/lost.pdf", None).await;
+        let hashed = insert(&db, user_id, "1/new.pdf", Some("kept")).await;
+
+        assert_eq!(hash_unhashed(&db, &storage).await.unwrap(), 1);
+        assert_eq!(
+            content_hash(&db, old).await.as_deref(),
+            Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        );
+        assert_eq!(content_hash(&db, lost).await, None);
+        assert_eq!(content_hash(&db, hashed).await.as_deref(), Some("kept"));
+
+        // Now found as a duplicate of the same content uploaded again
+        let original = find_original(&db, user_id, &content_hash(&db, old).await.unwrap())
+            .await
+            .unwrap();
+        assert_eq!(original.map(|document| document.id), Some(old));
+    }
+}
@@ -0,0 +1,404 @@
+//! Recognises uploaded files by their content rather than by the name or
+//! type the client claims for them.
+
+use crate::error::AppError;
+
+/// Bytes read from the start of an upload before its type is decided.
+pub const SNIFF_LEN: usize = 64 * 1024;
+
+const DOCX_MIME_TYPE: &str =
+    "application/vnd.openxmlformats-officedocument.wordprocessingml.document";
+
+/// The kinds of file that can be uploaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    Pdf,
+    Docx,
+    Epub,
+    Text,
+}
+
+impl FileKind {
+    const ALL: [FileKind; 4] = [
+        FileKind::Pdf,
+        FileKind::Docx,
+        FileKind::Epub,
+        FileKind::Text,
+    ];
+
+    /// Name used in `UPLOAD_ALLOWED_TYPES`.
+    fn name(self) -> &'static str {
+        match self {
+            FileKind::Pdf => "pdf",
+            FileKind::Docx => "docx",
+            FileKind::Epub => "epub",
+            FileKind::Text => "txt",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            FileKind::Pdf => "a PDF document",
+            FileKind::Docx => "a Word document",
+            FileKind::Epub => "an EPUB book",
+            FileKind::Text => "plain text",
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            FileKind::Pdf => "application/pdf",
+            FileKind::Docx => DOCX_MIME_TYPE,
+            FileKind::Epub => "application/epub+zip",
+            FileKind::Text => "text/plain",
+        }
+    }
+
+    fn extensions(self) -> &'static [&'static str] {
+        match self {
+            FileKind::Pdf => &["pdf"],
+            FileKind::Docx => &["docx"],
+            FileKind::Epub => &["epub"],
+            FileKind::Text => &["txt", "text", "md", "markdown", "csv", "log"],
+        }
+    }
+
+    /// Whether a client-declared content type agrees with this kind.
+    /// Browsers label files they do not know as octet streams, and plain
+    /// text under a variety of `text/` types.
+    fn accepts_declared(self, declared: &str) -> bool {
+        match declared {
+            "" | "application/octet-stream" => true,
+            "application/x-pdf" => self == FileKind::Pdf,
+            "text/html" => false,
+            declared if declared.starts_with("text/") => self == FileKind::Text,
+            declared => declared == self.mime_type(),
+        }
+    }
+}
+
+/// Kinds accepted for upload, from the comma-separated `UPLOAD_ALLOWED_TYPES`
+/// (default `pdf,docx,txt,epub`, also used when it is empty). Unknown names
+/// are ignored.
+pub fn allowed_kinds() -> Vec<FileKind> {
+    let names = match std::env::var("UPLOAD_ALLOWED_TYPES") {
+        Ok(names) if !names.trim().is_empty() => names,
+        _ => return FileKind::ALL.to_vec(),
+    };
+    names
+        .split(',')
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| {
+            let kind = FileKind::ALL.into_iter().find(|kind| kind.name() == name);
+            if kind.is_none() {
+                println!(
+                    "Ignoring unknown upload type in UPLOAD_ALLOWED_TYPES: {}",
+                    name
+                );
+            }
+            kind
+        })
+        .collect()
+}
+
+/// Decides what an upload really is from its first bytes, `complete` when
+/// they are the whole file. Files of other kinds are described instead, to
+/// explain the rejection.
+pub fn sniff(head: &[u8], complete: bool) -> Result<FileKind, &'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (
+            b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1",
+            "a legacy Office document",
+        ),
+        (b"\x89PNG\r\n\x1a\n", "a PNG image"),
+        (b"\xff\xd8\xff", "a JPEG image"),
+        (b"GIF87a", "a GIF image"),
+        (b"GIF89a", "a GIF image"),
+        (b"\x7fELF", "an executable"),
+        (b"MZ\x90\x00", "an executable"),
+        (b"\xcf\xfa\xed\xfe", "an executable"),
+        (b"\xca\xfe\xba\xbe", "an executable"),
+        (b"\x1f\x8b", "a compressed archive"),
+        (b"Rar!\x1a\x07", "a compressed archive"),
+        (b"7z\xbc\xaf\x27\x1c", "a compressed archive"),
+    ];
+
+    // Checked first so a PDF header hidden in an executable is not enough
+    if let Some((_, description)) = SIGNATURES
+        .iter()
+        .find(|(signature, _)| head.starts_with(signature))
+    {
+        return Err(description);
+    }
+    // PDF readers accept the header anywhere in the first kilobyte
+    let window = &head[..head.len().min(1024)];
+    if window.windows(5).any(|bytes| bytes == b"%PDF-") {
+        return Ok(FileKind::Pdf);
+    }
+    if head.starts_with(b"PK\x03\x04") {
+        return sniff_zip(head);
+    }
+    sniff_text(head, complete)
+}
+
+/// Tells EPUB books and Word documents apart from other ZIP archives by the
+/// entries near the start.
+fn sniff_zip(head: &[u8]) -> Result<FileKind, &'static str> {
+    let entries = zip_entries(head);
+
+    // An EPUB must start with an uncompressed `mimetype` entry
+    if entries.first() == Some(&&b"mimetype"[..])
+        && head
+            .windows(20)
+            .take(128)
+            .any(|bytes| bytes == b"application/epub+zip")
+    {
+        return Ok(FileKind::Epub);
+    }
+    let has = |prefix: &[u8]| entries.iter().any(|name| name.starts_with(prefix));
+    if has(b"word/") {
+        Ok(FileKind::Docx)
+    } else if has(b"xl/") {
+        Err("an Excel workbook")
+    } else if has(b"ppt/") {
+        Err("a PowerPoint presentation")
+    } else {
+        Err("a ZIP archive")
+    }
+}
+
+/// Names of the ZIP entries whose local headers lie within `head`.
+fn zip_entries(head: &[u8]) -> Vec<&[u8]> {
+    let u16_at = |at: usize| usize::from(u16::from_le_bytes([head[at], head[at + 1]]));
+    let mut entries = Vec::new();
+    let mut at = 0;
+    while head.get(at..at + 4) == Some(b"PK\x03\x04") && at + 30 <= head.len() {
+        let flags = u16_at(at + 6);
+        let compressed_size =
+            u32::from_le_bytes([head[at + 18], head[at + 19], head[at + 20], head[at + 21]]);
+        let name_start = at + 30;
+        let name_end = name_start + u16_at(at + 26);
+        let Some(name) = head.get(name_start..name_end) else {
+            break;
+        };
+        entries.push(name);
+
+        let data_start = name_end + u16_at(at + 28);
+        if flags & 0x08 == 0 {
+            at = data_start + compressed_size as usize;
+            continue;
+        }
+        // The size follows the data, so look for the next header instead
+        match head
+            .get(data_start..)
+            .and_then(|rest| rest.windows(4).position(|bytes| bytes == b"PK\x03\x04"))
+        {
+            Some(offset) => at = data_start + offset,
+            None => break,
+        }
+    }
+    entries
+}
+
+/// Plain text is UTF-8 without control characters other than whitespace.
+fn sniff_text(head: &[u8], complete: bool) -> Result<FileKind, &'static str> {
+    let head = head.strip_prefix(b"\xef\xbb\xbf").unwrap_or(head);
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        // The sniffed window may end in the middle of a character
+        Err(e) if !complete && e.error_len().is_none() => {
+            std::str::from_utf8(&head[..e.valid_up_to()]).unwrap_or_default()
+        }
+        Err(_) => return Err("an unrecognised binary file"),
+    };
+    if text
+        .chars()
+        .any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c'))
+    {
+        return Err("an unrecognised binary file");
+    }
+
+    // Markup a browser would run is not plain text
+    let start = text.trim_start().to_lowercase();
+    if ["<!doctype html", "<html", "<script", "<svg"]
+        .iter()
+        .any(|prefix| start.starts_with(prefix))
+    {
+        return Err("an HTML page");
+    }
+    Ok(FileKind::Text)
+}
+
+/// Checks an upload before it is stored: its content must be of an allowed
+/// kind, and the file name and declared content type must agree with it.
+pub fn check_upload(
+    head: &[u8],
+    complete: bool,
+    extension: &str,
+    declared: Option<&str>,
+) -> Result<FileKind, AppError> {
+    let allowed = allowed_kinds();
+    let kind = sniff(head, complete).map_err(|description| {
+        AppError::BadRequest(format!(
+            "The file is {}. Accepted types: {}",
+            description,
+            allowed
+                .iter()
+                .map(|kind| kind.name())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    })?;
+    if !allowed.contains(&kind) {
+        return Err(AppError::BadRequest(format!(
+            "Uploading {} files is not allowed",
+            kind.name()
+        )));
+    }
+
+    let extension = extension.to_lowercase();
+    if !kind.extensions().contains(&extension.as_str()) {
+        return Err(AppError::BadRequest(format!(
+            "The file is {} but its name does not end in .{}",
+            kind.description(),
+            kind.extensions()[0]
+        )));
+    }
+    if let Some(declared) = declared {
+        if !kind.accepts_declared(&declared.to_lowercase()) {
+            return Err(AppError::BadRequest(format!(
+                "The file is {} but was sent as {}",
+                kind.description(),
+                declared
+            )));
+        }
+    }
+    Ok(kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ZIP local headers for stored entries with the given names and data.
+    fn zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for (name, data) in entries {
+            bytes.extend_from_slice(b"PK\x03\x04\x14\x00\x00\x00\x00\x00\x00\x00\x00\x00");
+            bytes.extend_from_slice(&[0; 4]);
+            bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(&[0; 2]);
+            bytes.extend_from_slice(name.as_bytes());
+            bytes.extend_from_slice(data);
+        }
+        bytes
+    }
+
+    fn rejection(result: Result<FileKind, AppError>) -> String {
+        match result {
+            Err(AppError::BadRequest(message)) => message,
+            other => panic!(
+                "expected a bad request, got {:?}",
+                other.map_err(|e| e.to_string())
+            ),
+        }
+    }
+
+    #[test]
+    fn executables_named_pdf_are_rejected() {
+        let mut exe = b"MZ\x90\x00\x03\x00\x00\x00".to_vec();
+        exe.resize(512, 0);
+        let message = rejection(check_upload(&exe, true, "pdf", Some("application/pdf")));
+        assert!(
+            message.starts_with("The file is an executable."),
+            "{}",
+            message
+        );
+
+        // A PDF header inside the first kilobyte does not make it a PDF
+        exe.extend_from_slice(b"%PDF-1.7\n");
+        assert_eq!(sniff(&exe, true), Err("an executable"));
+
+        let mut elf = b"\x7fELF\x02\x01\x01".to_vec();
+        elf.extend_from_slice(b"%PDF-1.4\n");
+        assert_eq!(sniff(&elf, true), Err("an executable"));
+
+        // A real PDF may have junk before its header
+        assert_eq!(sniff(b"\n\n%PDF-1.4\n", true), Ok(FileKind::Pdf));
+        let message = rejection(check_upload(b"%PDF-1.4\n", true, "exe", None));
+        assert!(message.contains("does not end in .pdf"), "{}", message);
+    }
+
+    #[test]
+    fn office_archives_are_told_apart() {
+        let docx = zip(&[
+            ("[Content_Types].xml", b"<Types/>"),
+            ("_rels/.rels", b"<Relationships/>"),
+            ("word/document.xml", b"<w:document/>"),
+        ]);
+        assert_eq!(sniff(&docx, false), Ok(FileKind::Docx));
+        assert_eq!(
+            check_upload(&docx, false, "DOCX", Some("application/octet-stream")).ok(),
+            Some(FileKind::Docx)
+        );
+        assert_eq!(
+            check_upload(&docx, false, "docx", Some(DOCX_MIME_TYPE)).ok(),
+            Some(FileKind::Docx)
+        );
+        let message = rejection(check_upload(&docx, false, "pdf", Some("application/pdf")));
+        assert!(message.contains("a Word document"), "{}", message);
+        let message = rejection(check_upload(&docx, false, "docx", Some("application/zip")));
+        assert!(message.contains("sent as application/zip"), "{}", message);
+
+        let xlsx = zip(&[
+            ("[Content_Types].xml", b"<Types/>"),
+            ("xl/workbook.xml", b""),
+        ]);
+        assert_eq!(sniff(&xlsx, false), Err("an Excel workbook"));
+        let pptx = zip(&[("[Content_Types].xml", b"<Types/>"), ("ppt/slides/", b"")]);
+        assert_eq!(sniff(&pptx, false), Err("a PowerPoint presentation"));
+        let plain = zip(&[("notes.txt", b"hello")]);
+        assert_eq!(sniff(&plain, true), Err("a ZIP archive"));
+
+        let epub = zip(&[
+            ("mimetype", b"application/epub+zip"),
+            ("META-INF/container.xml", b"<container/>"),
+        ]);
+        assert_eq!(sniff(&epub, false), Ok(FileKind::Epub));
+        // The mimetype entry only counts as the first one
+        let late = zip(&[("word/", b""), ("mimetype", b"application/epub+zip")]);
+        assert_eq!(sniff(&late, false), Ok(FileKind::Docx));
+    }
+
+    #[test]
+    fn truncated_headers_do_not_panic() {
+        let docx = zip(&[
+            ("[Content_Types].xml", b"<Types/>"),
+            ("word/document.xml", b"<w:document/>"),
+        ]);
+        for len in 0..docx.len() {
+            let _ = sniff(&docx[..len], false);
+        }
+        // Cut inside the first header, or inside the second entry's name
+        assert_eq!(sniff(&docx[..20], false), Err("a ZIP archive"));
+        let second = docx.len() - "word/document.xml".len() - b"<w:document/>".len();
+        assert_eq!(sniff(&docx[..second + 3], false), Err("a ZIP archive"));
+
+        // Sizes pointing past the end stop the walk
+        let mut huge = docx.clone();
+        huge[18..22].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(sniff(&huge, false), Err("a ZIP archive"));
+
+        assert_eq!(sniff(b"%PDF", true), Ok(FileKind::Text));
+        assert_eq!(sniff(b"", true), Ok(FileKind::Text));
+
+        // The sniffed window may end in the middle of a character
+        let text = "caf\u{e9}".as_bytes();
+        let cut = &text[..text.len() - 1];
+        assert_eq!(sniff(cut, false), Ok(FileKind::Text));
+        assert_eq!(sniff(cut, true), Err("an unrecognised binary file"));
+    }
+}
@@ -0,0 +1,158 @@
+use crate::{
+    error::AppError,
+    models::folder::{self, Entity as Folder},
+};
+use sea_orm::{ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter, QuerySelect};
+use std::collections::{HashMap, HashSet};
+
+const MAX_NAME_LENGTH: usize = 255;
+
+/// A folder of `user_id`. Other users' folders are reported as missing.
+pub async fn find_for_user<C: ConnectionTrait>(
+    db: &C,
+    user_id: i32,
+    folder_id: i32,
+) -> Result<folder::Model, AppError> {
+    Folder::find_by_id(folder_id)
+        .filter(folder::Column::UserId.eq(user_id))
+        .one(db)
+        .await?
+        .ok_or(AppError::NotFound("Folder not found".into()))
+}
+
+/// Locks every folder of `user_id` until the transaction `db` ends, so
+/// checks on the folder tree cannot race another change to it.
+pub async fn lock_for_user<C: ConnectionTrait>(db: &C, user_id: i32) -> Result<(), AppError> {
+    Folder::find()
+        .filter(folder::Column::UserId.eq(user_id))
+        .lock_exclusive()
+        .all(db)
+        .await?;
+    Ok(())
+}
+
+/// Ids of `root` and every folder below it. Each folder is listed once,
+/// even if the tree has been corrupted into a cycle.
+pub async fn subtree_ids<C: ConnectionTrait>(
+    db: &C,
+    user_id: i32,
+    root: i32,
+) -> Result<Vec<i32>, AppError> {
+    let folders = Folder::find()
+        .filter(folder::Column::UserId.eq(user_id))
+        .all(db)
+        .await?;
+
+    let mut children: HashMap<i32, Vec<i32>> = HashMap::new();
+    for folder in &folders {
+        if let Some(parent_id) = folder.parent_id {
+            children.entry(parent_id).or_default().push(folder.id);
+        }
+    }
+
+    let mut ids = vec![root];
+    let mut seen = HashSet::from([root]);
+    let mut next = 0;
+    while next < ids.len() {
+        if let Some(below) = children.get(&ids[next]) {
+            ids.extend(below.iter().filter(|id| seen.insert(**id)));
+        }
+        next += 1;
+    }
+
+    Ok(ids)
+}
+
+/// Trims a folder name and checks it can be shown as one path segment.
+pub fn validate_name(name: &str) -> Result<String, AppError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(AppError::BadRequest(format!(
+            "Folder name must be between 1 and {} characters",
+            MAX_NAME_LENGTH
+        )));
+    }
+    if name.contains(['/', '\\']) || name.chars().any(char::is_control) {
+        return Err(AppError::BadRequest(
+            "Folder name cannot contain slashes or control characters".into(),
+        ));
+    }
+    Ok(name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestDb;
+    use sea_orm::{ActiveModelTrait, Set};
+
+    async fn folder(db: &TestDb, user_id: i32, parent_id: Option<i32>, name: &str) -> i32 {
+        let now = chrono::Utc::now().fixed_offset();
+        folder::ActiveModel {
+            user_id: Set(user_id),
+            parent_id: Set(parent_id),
+            name: Set(name.to_string()),
+            created_at: Set(now),
+            updated_at: Set(now),
+            ..Default::default()
+        }
+        .insert(&**db)
+        .await
+        .unwrap()
+        .id
+    }
+
+    #[test]
+    fn names_are_trimmed_single_segments() {
+        assert_eq!(validate_name("  Reports ").unwrap(), "Reports");
+        assert!(validate_name("   ").is_err());
+        assert!(validate_name("a/b").is_err());
+        assert!(validate_name("a\\b").is_err());
+        assert!(validate_name("a\nb").is_err());
+        assert!(validate_name(&"x".repeat(MAX_NAME_LENGTH + 1)).is_err());
+    }
+
+    #[actix_web::test]
+    async fn subtree_lists_every_descendant_once() {
+        let Some(db) = TestDb::new().await else {
+            return;
+        };
+        let user_id = db.user("folders@example.com").await;
+        let other_id = db.user("other@example.com").await;
+        let root = folder(&db, user_id, None, "root").await;
+        let child = folder(&db, user_id, Some(root), "child").await;
+        let grandchild = folder(&db, user_id, Some(child), "grandchild").await;
+        let sibling = folder(&db, user_id, None, "sibling").await;
+        folder(&db, other_id, None, "foreign").await;
+
+        let mut ids = subtree_ids(&*db, user_id, root).await.unwrap();
+        ids.sort();
+        assert_eq!(ids, [root, child, grandchild]);
+        assert_eq!(
+            subtree_ids(&*db, user_id, sibling).await.unwrap(),
+            [sibling]
+        );
+    }
+
+    #[actix_web::test]
+    async fn subtree_of_a_cycle_ends() {
+        let Some(db) = TestDb::new().await else {
+            return;
+        };
+        let user_id = db.user("folders@example.com").await;
+        let a = folder(&db, user_id, None, "a").await;
+        let b = folder(&db, user_id, Some(a), "b").await;
+        folder::ActiveModel {
+            id: Set(a),
+            parent_id: Set(Some(b)),
+            ..Default::default()
+        }
+        .update(&*db)
+        .await
+        .unwrap();
+
+        let mut ids = subtree_ids(&*db, user_id, a).await.unwrap();
+        ids.sort();
+        assert_eq!(ids, [a, b]);
+    }
+}
@@ -0,0 +1,351 @@
+use crate::{
+    error::AppError,
+    models::login_throttle::{self, Entity as LoginThrottle},
+};
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use sea_orm::{
+    sea_query::{Expr, OnConflict},
+    ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QuerySelect, Set, TransactionTrait,
+};
+
+/// Longest wait between two attempts before the lockout kicks in.
+const MAX_DELAY_SECONDS: i64 = 60;
+
+/// What failed attempts are counted against.
+#[derive(Debug, Clone, Copy)]
+pub enum ThrottleKey<'a> {
+    /// Password attempts for an email address. Keyed by address rather than
+    /// account so unknown addresses behave exactly like known ones.
+    Email(&'a str),
+    /// Password attempts from one client, across all addresses.
+    Ip(&'a str),
+    /// Second-factor codes entered for an account.
+    TwoFactor(i32),
+}
+
+impl ThrottleKey<'_> {
+    fn as_key(&self) -> String {
+        match self {
+            ThrottleKey::Email(email) => format!("email:{}", email.trim().to_lowercase()),
+            ThrottleKey::Ip(ip) => format!("ip:{}", ip),
+            ThrottleKey::TwoFactor(user_id) => format!("2fa:{}", user_id),
+        }
+    }
+
+    /// Failures allowed without any delay, and the count that locks the key.
+    fn limits(&self) -> (i32, i32) {
+        match self {
+            ThrottleKey::Email(_) | ThrottleKey::TwoFactor(_) => {
+                (3, env_limit("LOGIN_MAX_FAILURES", 10))
+            }
+            ThrottleKey::Ip(_) => (20, env_limit("LOGIN_IP_MAX_FAILURES", 100)),
+        }
+    }
+}
+
+fn env_limit(name: &str, default: i32) -> i32 {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// How long a key stays locked, from `LOGIN_LOCKOUT_MINUTES` (default 15).
+/// Failures older than this are forgotten.
+fn lockout_duration() -> Duration {
+    Duration::minutes(env_limit("LOGIN_LOCKOUT_MINUTES", 15) as i64)
+}
+
+/// Wait required after `failures` attempts: none for the first few, then
+/// doubling from one second up to `MAX_DELAY_SECONDS`.
+fn delay_after(failures: i32, free_attempts: i32) -> Duration {
+    if failures < free_attempts {
+        return Duration::zero();
+    }
+    let exponent = (failures - free_attempts).min(6) as u32;
+    Duration::seconds((1i64 << exponent).min(MAX_DELAY_SECONDS))
+}
+
+fn too_many_attempts(ready_at: DateTime<FixedOffset>) -> AppError {
+    let wait = (ready_at.with_timezone(&Utc) - Utc::now())
+        .num_seconds()
+        .max(1);
+    let message = if wait >= 60 {
+        format!(
+            "Too many failed attempts, try again in {} minutes",
+            (wait + 59) / 60
+        )
+    } else {
+        format!("Too many failed attempts, try again in {} seconds", wait)
+    };
+    AppError::TooManyRequests(message)
+}
+
+/// Failure count of `throttle` once one more attempt is counted, or the
+/// error to turn the attempt away with if the key is locked or still inside
+/// its delay.
+fn admit(
+    throttle: &login_throttle::Model,
+    free_attempts: i32,
+    now: DateTime<Utc>,
+) -> Result<i32, AppError> {
+    // Failures from before the window (or before an expired lock) start over
+    if throttle.last_failed_at < now - lockout_duration() {
+        return Ok(1);
+    }
+
+    if let Some(locked_until) = throttle.locked_until.filter(|until| *until > now) {
+        return Err(too_many_attempts(locked_until));
+    }
+
+    // Parallel attempts can take the row lock out of order, so a free attempt
+    // may find one stamped a moment after its own `now`
+    let delay = delay_after(throttle.failures, free_attempts);
+    let ready_at = throttle.last_failed_at + delay;
+    if delay > Duration::zero() && ready_at > now {
+        return Err(too_many_attempts(ready_at));
+    }
+
+    Ok(throttle.failures + 1)
+}
+
+/// Admits an attempt and counts it as failed before anything is verified,
+/// locking the key once it reaches its limit. Attempts on the same key are
+/// serialised by a row lock, so parallel requests each see the ones before
+/// them and cannot slip through the delay together. Callers report a
+/// successful attempt with `record_success`.
+///
+/// Rejected attempts are not counted. Called before any password hashing,
+/// so throttled attempts cost nothing.
+pub async fn begin_attempt(db: &DatabaseConnection, key: ThrottleKey<'_>) -> Result<(), AppError> {
+    let id = key.as_key();
+    let now = Utc::now();
+    let transaction = db.begin().await?;
+
+    // The row has to exist before it can be locked
+    let first = login_throttle::ActiveModel {
+        key: Set(id.clone()),
+        failures: Set(0),
+        last_failed_at: Set(now.into()),
+        locked_until: Set(None),
+    };
+    LoginThrottle::insert(first)
+        .on_conflict(
+            OnConflict::column(login_throttle::Column::Key)
+                .do_nothing()
+                .to_owned(),
+        )
+        .exec_without_returning(&transaction)
+        .await?;
+    let throttle = LoginThrottle::find_by_id(id.clone())
+        .lock_exclusive()
+        .one(&transaction)
+        .await?
+        .ok_or_else(|| AppError::InternalServerError("Login throttle row missing".into()))?;
+
+    let (free_attempts, max_failures) = key.limits();
+    let failures = match admit(&throttle, free_attempts, now) {
+        Ok(failures) => failures,
+        Err(e) => {
+            transaction.rollback().await?;
+            return Err(e);
+        }
+    };
+
+    let locked = failures >= max_failures;
+    let attempt = login_throttle::ActiveModel {
+        key: Set(id.clone()),
+        failures: Set(failures),
+        last_failed_at: Set(now.into()),
+        locked_until: Set(locked.then(|| (now + lockout_duration()).into())),
+    };
+    LoginThrottle::update(attempt).exec(&transaction).await?;
+    transaction.commit().await?;
+
+    if locked {
+        println!("Locked {} after {} failed attempts", id, failures);
+    }
+    Ok(())
+}
+
+/// Takes back the failure `begin_attempt` counted for an attempt that
+/// succeeded. Per-client counts only lose that attempt, since other
+/// addresses may be guessed from the same client; the other keys are
+/// cleared.
+pub async fn record_success(db: &DatabaseConnection, key: ThrottleKey<'_>) -> Result<(), AppError> {
+    match key {
+        ThrottleKey::Ip(_) => {
+            LoginThrottle::update_many()
+                .col_expr(
+                    login_throttle::Column::Failures,
+                    Expr::col(login_throttle::Column::Failures).sub(1),
+                )
+                .filter(login_throttle::Column::Key.eq(key.as_key()))
+                .filter(login_throttle::Column::Failures.gt(0))
+                .exec(db)
+                .await?;
+        }
+        ThrottleKey::Email(_) | ThrottleKey::TwoFactor(_) => {
+            clear(db, key).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Forgets the failures of a key, after a successful login or an unlock.
+pub async fn clear(db: &DatabaseConnection, key: ThrottleKey<'_>) -> Result<bool, AppError> {
+    let result = LoginThrottle::delete_by_id(key.as_key()).exec(db).await?;
+    Ok(result.rows_affected > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestDb;
+
+    fn throttle(
+        failures: i32,
+        last_failed_at: DateTime<Utc>,
+        locked_until: Option<DateTime<Utc>>,
+    ) -> login_throttle::Model {
+        login_throttle::Model {
+            key: "email:jane@example.com".to_string(),
+            failures,
+            last_failed_at: last_failed_at.into(),
+            locked_until: locked_until.map(Into::into),
+        }
+    }
+
+    #[test]
+    fn delay_doubles_after_the_free_attempts_up_to_a_minute() {
+        let delays: Vec<i64> = (0..12)
+            .map(|failures| delay_after(failures, 3).num_seconds())
+            .collect();
+        assert_eq!(delays, [0, 0, 0, 1, 2, 4, 8, 16, 32, 60, 60, 60]);
+    }
+
+    #[test]
+    fn attempts_wait_for_the_delay() {
+        let now = Utc::now();
+        assert_eq!(admit(&throttle(2, now, None), 3, now).unwrap(), 3);
+        let stamped_later = throttle(2, now + Duration::milliseconds(5), None);
+        assert_eq!(admit(&stamped_later, 3, now).unwrap(), 3);
+
+        let waiting = throttle(5, now - Duration::seconds(3), None);
+        assert!(matches!(
+            admit(&waiting, 3, now),
+            Err(AppError::TooManyRequests(_))
+        ));
+        let waited = throttle(5, now - Duration::seconds(4), None);
+        assert_eq!(admit(&waited, 3, now).unwrap(), 6);
+    }
+
+    #[test]
+    fn locked_keys_are_refused_until_the_lock_ends() {
+        let now = Utc::now();
+        let locked = throttle(
+            10,
+            now - Duration::minutes(5),
+            Some(now + Duration::minutes(10)),
+        );
+        assert!(matches!(
+            admit(&locked, 3, now),
+            Err(AppError::TooManyRequests(_))
+        ));
+
+        // Once the window has passed the count starts over
+        let lapsed = throttle(
+            10,
+            now - lockout_duration() - Duration::seconds(1),
+            Some(now - Duration::seconds(1)),
+        );
+        assert_eq!(admit(&lapsed, 3, now).unwrap(), 1);
+    }
+
+    #[actix_web::test]
+    async fn parallel_attempts_cannot_skip_the_delay() {
+        let Some(db) = TestDb::new().await else {
+            return;
+        };
+        let key = ThrottleKey::Email("jane@example.com");
+
+        let attempts =
+            futures_util::future::join_all((0..10).map(|_| begin_attempt(&db, key))).await;
+
+        // The free attempts go through; the next one has to wait a second
+        let admitted = attempts.iter().filter(|attempt| attempt.is_ok()).count();
+        assert_eq!(admitted, 3);
+        assert!(attempts
+            .iter()
+            .filter_map(|attempt| attempt.as_ref().err())
+            .all(|e| matches!(e, AppError::TooManyRequests(_))));
+        let row = LoginThrottle::find_by_id(key.as_key())
+            .one(&*db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(row.failures, 3);
+    }
+
+    #[actix_web::test]
+    async fn reaching_the_limit_locks_the_key() {
+        let Some(db) = TestDb::new().await else {
+            return;
+        };
+        let key = ThrottleKey::Email("jane@example.com");
+        let (_, max_failures) = key.limits();
+        let earlier = Utc::now() - Duration::seconds(MAX_DELAY_SECONDS);
+        LoginThrottle::insert(login_throttle::ActiveModel {
+            key: Set(key.as_key()),
+            failures: Set(max_failures - 1),
+            last_failed_at: Set(earlier.into()),
+            locked_until: Set(None),
+        })
+        .exec(&*db)
+        .await
+        .unwrap();
+
+        begin_attempt(&db, key).await.unwrap();
+        let row = LoginThrottle::find_by_id(key.as_key())
+            .one(&*db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(row.failures, max_failures);
+        assert!(row.locked_until.unwrap() > Utc::now() + lockout_duration() - Duration::minutes(1));
+
+        // Even a right password is not checked while locked
+        assert!(matches!(
+            begin_attempt(&db, key).await,
+            Err(AppError::TooManyRequests(_))
+        ));
+    }
+
+    #[actix_web::test]
+    async fn success_clears_the_account_but_only_its_attempt_for_the_client() {
+        let Some(db) = TestDb::new().await else {
+            return;
+        };
+        let email = ThrottleKey::Email("jane@example.com");
+        let ip = ThrottleKey::Ip("203.0.113.7");
+        for _ in 0..2 {
+            begin_attempt(&db, email).await.unwrap();
+            begin_attempt(&db, ip).await.unwrap();
+        }
+
+        record_success(&db, email).await.unwrap();
+        record_success(&db, ip).await.unwrap();
+
+        assert!(LoginThrottle::find_by_id(email.as_key())
+            .one(&*db)
+            .await
+            .unwrap()
+            .is_none());
+        let client = LoginThrottle::find_by_id(ip.as_key())
+            .one(&*db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(client.failures, 1);
+    }
+}
@@ -0,0 +1,46 @@
+use super::{EmailMessage, MailBackend, MailError};
+use async_trait::async_trait;
+use std::path::PathBuf;
+use tokio::fs;
+use uuid::Uuid;
+
+/// Development and test sink: prints each message, and also writes it as an
+/// `.eml` file when a directory is configured.
+pub struct LogBackend {
+    dir: Option<PathBuf>,
+}
+
+impl LogBackend {
+    pub async fn new(dir: Option<PathBuf>) -> Result<Self, MailError> {
+        if let Some(dir) = &dir {
+            println!("Writing outgoing mail to: {}", dir.display());
+            fs::create_dir_all(dir).await?;
+        }
+
+        Ok(Self { dir })
+    }
+}
+
+#[async_trait]
+impl MailBackend for LogBackend {
+    async fn send(&self, message: &EmailMessage) -> Result<(), MailError> {
+        let rendered = message.to_rfc5322();
+
+        match &self.dir {
+            Some(dir) => {
+                let file_name = format!(
+                    "{}-{}.eml",
+                    chrono::Utc::now().format("%Y%m%dT%H%M%S"),
+                    Uuid::new_v4()
+                );
+                fs::write(dir.join(file_name), rendered).await?;
+            }
+            None => println!(
+                "---- outgoing mail ----\n{}-----------------------",
+                rendered
+            ),
+        }
+
+        Ok(())
+    }
+}
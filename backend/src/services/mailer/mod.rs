@@ -0,0 +1,191 @@
+use async_trait::async_trait;
+use derive_more::Display;
+use std::error::Error;
+use std::io::Error as IoError;
+use std::sync::Arc;
+
+pub mod log;
+pub mod smtp;
+
+pub use log::LogBackend;
+pub use smtp::{SmtpBackend, SmtpTls};
+
+#[derive(Debug, Display)]
+pub enum MailError {
+    #[display(fmt = "Invalid email address: {}", _0)]
+    InvalidAddress(String),
+
+    #[display(fmt = "Mail server rejected the message: {}", _0)]
+    Rejected(String),
+
+    #[display(fmt = "Mail transport error: {}", _0)]
+    Transport(String),
+}
+
+impl Error for MailError {}
+
+impl From<IoError> for MailError {
+    fn from(err: IoError) -> Self {
+        MailError::Transport(err.to_string())
+    }
+}
+
+/// A plain-text email, ready to be handed to a backend.
+#[derive(Debug, Clone)]
+pub struct EmailMessage {
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl EmailMessage {
+    /// Renders the message as RFC 5322 text with CRLF line endings.
+    pub fn to_rfc5322(&self) -> String {
+        let mut message = String::new();
+        message.push_str(&format!("From: {}\r\n", self.from));
+        message.push_str(&format!("To: {}\r\n", self.to));
+        message.push_str(&format!("Subject: {}\r\n", self.subject));
+        message.push_str(&format!("Date: {}\r\n", chrono::Utc::now().to_rfc2822()));
+        message.push_str(&format!("Message-ID: <{}@shelf>\r\n", uuid::Uuid::new_v4()));
+        message.push_str("MIME-Version: 1.0\r\n");
+        message.push_str("Content-Type: text/plain; charset=utf-8\r\n");
+        message.push_str("Content-Transfer-Encoding: 8bit\r\n");
+        message.push_str("\r\n");
+        for line in self.body.lines() {
+            message.push_str(line);
+            message.push_str("\r\n");
+        }
+        message
+    }
+}
+
+/// Delivers outgoing mail.
+#[async_trait]
+pub trait MailBackend: Send + Sync {
+    async fn send(&self, message: &EmailMessage) -> Result<(), MailError>;
+}
+
+/// Writes the account emails and hands them to the configured backend.
+#[derive(Clone)]
+pub struct MailService {
+    backend: Arc<dyn MailBackend>,
+    from: String,
+    app_url: String,
+}
+
+impl MailService {
+    /// `app_url` is the frontend base URL that links in emails point to.
+    pub fn new(backend: Arc<dyn MailBackend>, from: String, app_url: String) -> Self {
+        Self {
+            backend,
+            from,
+            app_url: app_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    pub async fn send(&self, to: &str, subject: &str, body: String) -> Result<(), MailError> {
+        // Header values must stay on one line
+        if to.contains(['\r', '\n']) || !to.contains('@') {
+            return Err(MailError::InvalidAddress(to.to_string()));
+        }
+
+        let message = EmailMessage {
+            from: self.from.clone(),
+            to: to.to_string(),
+            subject: subject.replace(['\r', '\n'], " "),
+            body,
+        };
+
+        self.backend.send(&message).await?;
+        println!("Sent \"{}\" email to {}", message.subject, message.to);
+        Ok(())
+    }
+
+    pub async fn send_verification_email(
+        &self,
+        to: &str,
+        name: &str,
+        token: &str,
+    ) -> Result<(), MailError> {
+        let link = format!("{}/verify-email?token={}", self.app_url, token);
+        let body = format!(
+            "Hello {},\n\n\
+             Please confirm your email address for Shelf by opening this link:\n\n\
+             {}\n\n\
+             The link expires in 24 hours. If you did not create an account, you can ignore this email.\n",
+            name, link
+        );
+        self.send(to, "Verify your Shelf email address", body).await
+    }
+
+    pub async fn send_password_reset(
+        &self,
+        to: &str,
+        name: &str,
+        token: &str,
+    ) -> Result<(), MailError> {
+        let link = format!("{}/reset-password?token={}", self.app_url, token);
+        let body = format!(
+            "Hello {},\n\n\
+             Someone asked to reset the password of your Shelf account. To choose a new password, open this link:\n\n\
+             {}\n\n\
+             The link expires in 1 hour and can only be used once. If you did not ask for this, you can ignore this email.\n",
+            name, link
+        );
+        self.send(to, "Reset your Shelf password", body).await
+    }
+
+    pub async fn send_email_change(
+        &self,
+        to: &str,
+        name: &str,
+        token: &str,
+    ) -> Result<(), MailError> {
+        let link = format!("{}/confirm-email?token={}", self.app_url, token);
+        let body = format!(
+            "Hello {},\n\n\
+             Someone asked to use this address for their Shelf account. To confirm the change, open this link:\n\n\
+             {}\n\n\
+             The link expires in 24 hours. Until then, the account keeps its current address. If you did not ask for this, you can ignore this email.\n",
+            name, link
+        );
+        self.send(to, "Confirm your new Shelf email address", body)
+            .await
+    }
+
+    pub async fn send_change_confirmation(
+        &self,
+        to: &str,
+        name: &str,
+        token: &str,
+    ) -> Result<(), MailError> {
+        let link = format!("{}/confirm-change?token={}", self.app_url, token);
+        let body = format!(
+            "Hello {},\n\n\
+             Someone asked to make a sensitive change to your Shelf account, such as changing its email address or password, or deleting it. To confirm, open this link:\n\n\
+             {}\n\n\
+             The link expires in 15 minutes. If this was not you, do not open it, and sign out of Shelf on every device.\n",
+            name, link
+        );
+        self.send(to, "Confirm the change to your Shelf account", body)
+            .await
+    }
+
+    /// Tells the current address that a change to `new_email` was requested.
+    pub async fn send_email_change_notice(
+        &self,
+        to: &str,
+        name: &str,
+        new_email: &str,
+    ) -> Result<(), MailError> {
+        let body = format!(
+            "Hello {},\n\n\
+             Someone asked to change the email address of your Shelf account to {}. The change takes effect once it is confirmed from the new address.\n\n\
+             If this was not you, change your password right away.\n",
+            name, new_email
+        );
+        self.send(to, "Your Shelf email address is about to change", body)
+            .await
+    }
+}
@@ -0,0 +1,409 @@
+use super::{EmailMessage, MailBackend, MailError};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufStream};
+use tokio::net::TcpStream;
+use tokio_native_tls::{native_tls, TlsConnector};
+
+const HELLO_NAME: &str = "shelf";
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How the connection to the SMTP server is secured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpTls {
+    /// Plain text, for local stand-ins such as MailHog or Mailpit.
+    None,
+    /// Upgrade a plain connection with `STARTTLS` (usually port 587).
+    StartTls,
+    /// TLS from the first byte (usually port 465).
+    Implicit,
+}
+
+impl FromStr for SmtpTls {
+    type Err = MailError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "none" => Ok(SmtpTls::None),
+            "starttls" => Ok(SmtpTls::StartTls),
+            "tls" => Ok(SmtpTls::Implicit),
+            other => Err(MailError::Transport(format!(
+                "Unknown SMTP_TLS mode: {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// Minimal SMTP client: one connection per message, optional `AUTH PLAIN`.
+pub struct SmtpBackend {
+    host: String,
+    port: u16,
+    tls: SmtpTls,
+    credentials: Option<(String, String)>,
+    connector: TlsConnector,
+}
+
+impl SmtpBackend {
+    pub fn new(
+        host: String,
+        port: u16,
+        tls: SmtpTls,
+        credentials: Option<(String, String)>,
+    ) -> Result<Self, MailError> {
+        println!("Initializing SMTP mailer for {}:{} ({:?})", host, port, tls);
+
+        let connector = native_tls::TlsConnector::new()
+            .map_err(|e| MailError::Transport(format!("TLS setup failed: {}", e)))?;
+
+        Ok(Self {
+            host,
+            port,
+            tls,
+            credentials,
+            connector: TlsConnector::from(connector),
+        })
+    }
+
+    async fn send_inner(&self, message: &EmailMessage) -> Result<(), MailError> {
+        let tcp = TcpStream::connect((self.host.as_str(), self.port)).await?;
+
+        match self.tls {
+            SmtpTls::None => {
+                let mut stream = BufStream::new(tcp);
+                read_reply(&mut stream, 220).await?;
+                self.deliver(&mut stream, message).await
+            }
+            SmtpTls::Implicit => {
+                let tls =
+                    self.connector.connect(&self.host, tcp).await.map_err(|e| {
+                        MailError::Transport(format!("TLS handshake failed: {}", e))
+                    })?;
+                let mut stream = BufStream::new(tls);
+                read_reply(&mut stream, 220).await?;
+                self.deliver(&mut stream, message).await
+            }
+            SmtpTls::StartTls => {
+                let mut stream = BufStream::new(tcp);
+                read_reply(&mut stream, 220).await?;
+                command(&mut stream, &format!("EHLO {}", HELLO_NAME), 250).await?;
+                command(&mut stream, "STARTTLS", 220).await?;
+
+                let tls = self
+                    .connector
+                    .connect(&self.host, stream.into_inner())
+                    .await
+                    .map_err(|e| MailError::Transport(format!("TLS handshake failed: {}", e)))?;
+                self.deliver(&mut BufStream::new(tls), message).await
+            }
+        }
+    }
+
+    /// Runs the mail transaction once the greeting (and any TLS upgrade) is done.
+    async fn deliver<S>(
+        &self,
+        stream: &mut BufStream<S>,
+        message: &EmailMessage,
+    ) -> Result<(), MailError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        command(stream, &format!("EHLO {}", HELLO_NAME), 250).await?;
+
+        if let Some((username, password)) = &self.credentials {
+            let token = STANDARD.encode(format!("\0{}\0{}", username, password));
+            command(stream, &format!("AUTH PLAIN {}", token), 235).await?;
+        }
+
+        command(
+            stream,
+            &format!("MAIL FROM:<{}>", mailbox(&message.from)),
+            250,
+        )
+        .await?;
+        command(stream, &format!("RCPT TO:<{}>", mailbox(&message.to)), 250).await?;
+        command(stream, "DATA", 354).await?;
+
+        // Lines starting with a dot are escaped by doubling it (RFC 5321 4.5.2)
+        let mut data = String::new();
+        for line in message.to_rfc5322().split("\r\n") {
+            if line.starts_with('.') {
+                data.push('.');
+            }
+            data.push_str(line);
+            data.push_str("\r\n");
+        }
+        // The message ends in CRLF, so `split` yields an empty last line; drop it
+        data.truncate(data.len() - 2);
+        data.push_str(".\r\n");
+
+        stream.write_all(data.as_bytes()).await?;
+        stream.flush().await?;
+        read_reply(stream, 250).await?;
+
+        // The message is accepted at this point, a failing QUIT does not matter
+        let _ = command(stream, "QUIT", 221).await;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MailBackend for SmtpBackend {
+    async fn send(&self, message: &EmailMessage) -> Result<(), MailError> {
+        tokio::time::timeout(SEND_TIMEOUT, self.send_inner(message))
+            .await
+            .map_err(|_| MailError::Transport("SMTP server timed out".into()))?
+    }
+}
+
+/// Bare address of a header value such as `Shelf <no-reply@example.com>`.
+fn mailbox(value: &str) -> &str {
+    match (value.rfind('<'), value.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &value[start + 1..end],
+        _ => value.trim(),
+    }
+}
+
+async fn command<S>(stream: &mut BufStream<S>, line: &str, expected: u16) -> Result<(), MailError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    stream.write_all(line.as_bytes()).await?;
+    stream.write_all(b"\r\n").await?;
+    stream.flush().await?;
+    read_reply(stream, expected).await
+}
+
+/// Reads a (possibly multi-line) reply and checks that its code is in the
+/// same class as `expected`, e.g. 251 is fine where 250 is expected.
+async fn read_reply<S>(stream: &mut BufStream<S>, expected: u16) -> Result<(), MailError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    let mut reply = String::new();
+
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await? == 0 {
+            return Err(MailError::Transport(
+                "Connection closed by SMTP server".into(),
+            ));
+        }
+        reply.push_str(&line);
+
+        // The last line of a reply has a space after the code, others a dash
+        if line.len() < 4 || line.as_bytes()[3] != b'-' {
+            break;
+        }
+    }
+
+    let code: u16 = reply
+        .get(..3)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| MailError::Transport(format!("Malformed SMTP reply: {}", reply.trim())))?;
+
+    if code / 100 != expected / 100 {
+        return Err(MailError::Rejected(reply.trim().to_string()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::mailer::MailService;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    /// A local SMTP stand-in that accepts one connection. It answers every
+    /// command with `reply` (or 250) and returns what the client sent: the
+    /// command lines and the message data.
+    async fn stand_in(
+        reply: fn(&str) -> Option<&'static str>,
+    ) -> (u16, JoinHandle<(Vec<String>, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut stream = BufStream::new(socket);
+            let (mut commands, mut data) = (Vec::new(), String::new());
+            stream.write_all(b"220 stand-in ready\r\n").await.unwrap();
+            stream.flush().await.unwrap();
+
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if stream.read_line(&mut line).await.unwrap() == 0 {
+                    break;
+                }
+                if in_data {
+                    if line == ".\r\n" {
+                        in_data = false;
+                        stream.write_all(b"250 queued\r\n").await.unwrap();
+                        stream.flush().await.unwrap();
+                    } else {
+                        data.push_str(&line);
+                    }
+                    continue;
+                }
+
+                let line = line.trim_end().to_string();
+                let answer = reply(&line).unwrap_or(match line.as_str() {
+                    l if l.starts_with("EHLO") => "250-stand-in\r\n250-AUTH PLAIN\r\n250 8BITMIME",
+                    "DATA" => "354 go ahead",
+                    "QUIT" => "221 bye",
+                    _ => "250 ok",
+                });
+                in_data = answer.starts_with("354");
+                commands.push(line.clone());
+                stream.write_all(answer.as_bytes()).await.unwrap();
+                stream.write_all(b"\r\n").await.unwrap();
+                stream.flush().await.unwrap();
+                if line == "QUIT" || answer.starts_with('5') {
+                    break;
+                }
+            }
+            (commands, data)
+        });
+
+        (port, server)
+    }
+
+    fn backend(port: u16, credentials: Option<(String, String)>) -> SmtpBackend {
+        SmtpBackend::new("127.0.0.1".to_string(), port, SmtpTls::None, credentials).unwrap()
+    }
+
+    fn message(body: &str) -> EmailMessage {
+        EmailMessage {
+            from: "Shelf <no-reply@shelf.test>".to_string(),
+            to: "jane@example.com".to_string(),
+            subject: "Hello".to_string(),
+            body: body.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn delivers_message_with_authentication() {
+        let (port, server) = stand_in(|_| None).await;
+        let credentials = Some(("user".to_string(), "secret".to_string()));
+
+        backend(port, credentials)
+            .send(&message("First line\nSecond line"))
+            .await
+            .unwrap();
+
+        let (commands, data) = server.await.unwrap();
+        assert_eq!(
+            commands,
+            [
+                "EHLO shelf".to_string(),
+                format!("AUTH PLAIN {}", STANDARD.encode("\0user\0secret")),
+                "MAIL FROM:<no-reply@shelf.test>".to_string(),
+                "RCPT TO:<jane@example.com>".to_string(),
+                "DATA".to_string(),
+                "QUIT".to_string(),
+            ]
+        );
+        assert!(data.contains("From: Shelf <no-reply@shelf.test>\r\n"));
+        assert!(data.contains("To: jane@example.com\r\n"));
+        assert!(data.contains("Subject: Hello\r\n"));
+        assert!(data.ends_with("\r\n\r\nFirst line\r\nSecond line\r\n"));
+    }
+
+    #[tokio::test]
+    async fn escapes_lines_starting_with_a_dot() {
+        let (port, server) = stand_in(|_| None).await;
+
+        backend(port, None)
+            .send(&message("before\n.\n.hidden"))
+            .await
+            .unwrap();
+
+        let (commands, data) = server.await.unwrap();
+        assert!(!commands.iter().any(|command| command.starts_with("AUTH")));
+        // A lone dot would otherwise end the message early
+        assert!(data.ends_with("\r\nbefore\r\n..\r\n..hidden\r\n"));
+    }
+
+    #[tokio::test]
+    async fn refused_recipient_is_rejected() {
+        let (port, server) =
+            stand_in(|line| line.starts_with("RCPT").then_some("550 5.1.1 no such user")).await;
+
+        let result = backend(port, None).send(&message("Hi")).await;
+        assert!(matches!(result, Err(MailError::Rejected(reply)) if reply.starts_with("550")));
+
+        let (commands, data) = server.await.unwrap();
+        assert!(!commands.contains(&"DATA".to_string()));
+        assert!(data.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_authentication_is_rejected() {
+        let (port, _server) = stand_in(|line| {
+            line.starts_with("AUTH")
+                .then_some("535 5.7.8 bad credentials")
+        })
+        .await;
+        let credentials = Some(("user".to_string(), "wrong".to_string()));
+
+        let result = backend(port, credentials).send(&message("Hi")).await;
+        assert!(matches!(result, Err(MailError::Rejected(_))));
+    }
+
+    #[tokio::test]
+    async fn closed_connection_is_a_transport_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            drop(socket);
+        });
+
+        let result = backend(port, None).send(&message("Hi")).await;
+        assert!(matches!(result, Err(MailError::Transport(_))));
+    }
+
+    #[tokio::test]
+    async fn mail_service_sends_links_through_smtp() {
+        let (port, server) = stand_in(|_| None).await;
+        let mailer = MailService::new(
+            Arc::new(backend(port, None)),
+            "Shelf <no-reply@shelf.test>".to_string(),
+            "https://shelf.test/".to_string(),
+        );
+
+        mailer
+            .send_password_reset("jane@example.com", "Jane", "abc.def")
+            .await
+            .unwrap();
+
+        let (_, data) = server.await.unwrap();
+        assert!(data.contains("Subject: Reset your Shelf password\r\n"));
+        assert!(data.contains("https://shelf.test/reset-password?token=abc.def\r\n"));
+    }
+
+    #[tokio::test]
+    async fn mail_service_refuses_header_injection() {
+        // Nothing listens here; the address must be refused before connecting
+        let mailer = MailService::new(
+            Arc::new(backend(1, None)),
+            "Shelf <no-reply@shelf.test>".to_string(),
+            "https://shelf.test".to_string(),
+        );
+
+        let result = mailer
+            .send(
+                "jane@example.com\r\nBcc: all@example.com",
+                "Hi",
+                "Hi".to_string(),
+            )
+            .await;
+        assert!(matches!(result, Err(MailError::InvalidAddress(_))));
+    }
+}
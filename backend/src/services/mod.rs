@@ -1,2 +1,20 @@
-pub mod storage;
+pub mod account_export;
+pub mod account_token;
+pub mod document_filter;
+pub mod duplicate;
+pub mod file_type;
+pub mod folder;
+pub mod jwt;
+pub mod login_throttle;
+pub mod mailer;
+pub mod oidc;
 pub mod payment;
+pub mod pdf_processor;
+pub mod personal_access_token;
+pub mod plan;
+pub mod preview;
+pub mod session;
+pub mod storage;
+pub mod tag;
+pub mod text_extraction;
+pub mod two_factor;
@@ -0,0 +1,876 @@
+use crate::{
+    error::AppError,
+    models::{
+        oidc_state::{self, Entity as OidcState},
+        user::{self, Entity as User},
+        user_identity::{self, Entity as UserIdentity},
+    },
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{Duration, Utc};
+use jsonwebtoken::{decode, decode_header, DecodingKey, Validation};
+use rand::RngCore;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set,
+    TransactionTrait,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::env;
+use std::sync::Arc;
+use tokio::sync::OnceCell;
+
+/// How long the user has to finish signing in at the provider.
+pub const STATE_TTL_MINUTES: i64 = 10;
+
+/// Endpoints of a provider, configured directly or discovered from its issuer.
+#[derive(Debug, Clone, Deserialize)]
+struct ProviderEndpoints {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: Option<String>,
+}
+
+/// One configured login provider, read from `OIDC_<NAME>_*` variables.
+struct OidcProvider {
+    name: String,
+    client_id: String,
+    client_secret: String,
+    scopes: String,
+    /// Set for OpenID Connect providers; enables discovery and ID token checks.
+    issuer: Option<String>,
+    /// Field of the userinfo response holding the stable user id. Plain
+    /// OAuth2 providers such as GitHub use `id` instead of `sub`.
+    subject_claim: String,
+    configured_endpoints: Option<ProviderEndpoints>,
+    discovered_endpoints: OnceCell<ProviderEndpoints>,
+}
+
+/// The user as described by the provider.
+#[derive(Debug)]
+pub struct ExternalIdentity {
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub name: Option<String>,
+}
+
+/// A started round trip: where to send the browser, and the `state` the
+/// provider will hand back.
+pub struct Authorization {
+    pub url: String,
+    pub state: String,
+}
+
+/// What a completed provider redirect amounts to.
+pub enum OidcOutcome {
+    /// Sign the user in.
+    Login { user: user::Model, created: bool },
+    /// The provider was linked to an already logged-in user.
+    Linked,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    id_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    nonce: Option<String>,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    name: Option<String>,
+}
+
+/// Authorization code flow with PKCE against any number of OpenID Connect
+/// (or OIDC-like OAuth2) providers.
+#[derive(Clone)]
+pub struct OidcService {
+    providers: Arc<BTreeMap<String, Arc<OidcProvider>>>,
+    redirect_url: String,
+    http: reqwest::Client,
+    db: DatabaseConnection,
+}
+
+impl OidcService {
+    /// Reads the providers listed in `OIDC_PROVIDERS`. Discovery happens on
+    /// first use, so a provider being down does not stop the server.
+    pub fn from_env(db: DatabaseConnection) -> Result<Self, AppError> {
+        let mut providers = BTreeMap::new();
+
+        let names = env::var("OIDC_PROVIDERS").unwrap_or_default();
+        for name in names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            let provider = OidcProvider::from_env(name)?;
+            println!("Social login provider enabled: {}", name);
+            providers.insert(name.to_string(), Arc::new(provider));
+        }
+
+        let redirect_url = env::var("OIDC_REDIRECT_URL").unwrap_or_else(|_| {
+            let app_url = env::var("APP_URL").unwrap_or_else(|_| "http://localhost".to_string());
+            format!("{}/oauth/callback", app_url.trim_end_matches('/'))
+        });
+
+        let http = reqwest::Client::builder()
+            .user_agent("shelf")
+            .build()
+            .map_err(|e| AppError::InternalServerError(format!("HTTP client error: {}", e)))?;
+
+        Ok(Self {
+            providers: Arc::new(providers),
+            redirect_url,
+            http,
+            db,
+        })
+    }
+
+    /// Whether the frontend is served over HTTPS, so cookies can be `Secure`.
+    pub fn uses_https(&self) -> bool {
+        self.redirect_url.starts_with("https://")
+    }
+
+    pub fn provider_names(&self) -> Vec<String> {
+        self.providers.keys().cloned().collect()
+    }
+
+    fn provider(&self, name: &str) -> Result<&Arc<OidcProvider>, AppError> {
+        self.providers
+            .get(name)
+            .ok_or_else(|| AppError::NotFound(format!("Unknown login provider: {}", name)))
+    }
+
+    /// Starts a login (or, with `link_user_id`, a linking) round trip.
+    pub async fn authorize(
+        &self,
+        provider_name: &str,
+        link_user_id: Option<i32>,
+    ) -> Result<Authorization, AppError> {
+        let provider = self.provider(provider_name)?;
+        let endpoints = provider.endpoints(&self.http).await?;
+
+        let state = random_token();
+        let nonce = random_token();
+        let code_verifier = random_token();
+        let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+
+        let now = Utc::now();
+        oidc_state::ActiveModel {
+            state: Set(state.clone()),
+            provider: Set(provider.name.clone()),
+            code_verifier: Set(code_verifier),
+            nonce: Set(nonce.clone()),
+            user_id: Set(link_user_id),
+            expires_at: Set((now + Duration::minutes(STATE_TTL_MINUTES)).into()),
+            created_at: Set(now.into()),
+            ..Default::default()
+        }
+        .insert(&self.db)
+        .await?;
+
+        let mut url = reqwest::Url::parse(&endpoints.authorization_endpoint).map_err(|e| {
+            AppError::InternalServerError(format!("Invalid authorization endpoint: {}", e))
+        })?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &provider.client_id)
+            .append_pair("redirect_uri", &self.redirect_url)
+            .append_pair("scope", &provider.scopes)
+            .append_pair("state", &state)
+            .append_pair("nonce", &nonce)
+            .append_pair("code_challenge", &code_challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        Ok(Authorization {
+            url: url.to_string(),
+            state,
+        })
+    }
+
+    /// Finishes the round trip the provider redirected back from.
+    pub async fn complete(&self, state: &str, code: &str) -> Result<OidcOutcome, AppError> {
+        let pending = self.take_state(state).await?;
+        let provider = self.provider(&pending.provider)?;
+        let identity = self.fetch_identity(provider, code, &pending).await?;
+
+        println!(
+            "{} identity {} returned (linking: {:?})",
+            provider.name, identity.subject, pending.user_id
+        );
+        self.resolve_user(&provider.name, identity, pending.user_id)
+            .await
+    }
+
+    /// Looks up and deletes a pending state, so each redirect is handled once.
+    async fn take_state(&self, state: &str) -> Result<oidc_state::Model, AppError> {
+        let invalid = || AppError::BadRequest("Invalid or expired login request".into());
+
+        let pending = OidcState::find()
+            .filter(oidc_state::Column::State.eq(state))
+            .one(&self.db)
+            .await?
+            .ok_or_else(invalid)?;
+
+        let deleted = OidcState::delete_many()
+            .filter(oidc_state::Column::Id.eq(pending.id))
+            .exec(&self.db)
+            .await?;
+
+        // Clean up requests that were abandoned at the provider
+        OidcState::delete_many()
+            .filter(oidc_state::Column::ExpiresAt.lt(Utc::now()))
+            .exec(&self.db)
+            .await?;
+
+        if deleted.rows_affected != 1 || pending.expires_at < Utc::now() {
+            return Err(invalid());
+        }
+
+        Ok(pending)
+    }
+
+    async fn fetch_identity(
+        &self,
+        provider: &OidcProvider,
+        code: &str,
+        pending: &oidc_state::Model,
+    ) -> Result<ExternalIdentity, AppError> {
+        let endpoints = provider.endpoints(&self.http).await?;
+
+        let response = self
+            .http
+            .post(&endpoints.token_endpoint)
+            .header(reqwest::header::ACCEPT, "application/json")
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", self.redirect_url.as_str()),
+                ("client_id", provider.client_id.as_str()),
+                ("client_secret", provider.client_secret.as_str()),
+                ("code_verifier", pending.code_verifier.as_str()),
+            ])
+            .send()
+            .await
+            .map_err(|e| provider_error(provider, e))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            println!(
+                "{} token exchange failed: {} {}",
+                provider.name, status, body
+            );
+            return Err(AppError::Unauthorized(
+                "Login at the provider failed".into(),
+            ));
+        }
+
+        let tokens: TokenResponse = response
+            .json()
+            .await
+            .map_err(|e| provider_error(provider, e))?;
+
+        match (&tokens.id_token, &provider.issuer) {
+            (Some(id_token), Some(issuer)) => {
+                self.identity_from_id_token(provider, issuer, id_token, &pending.nonce)
+            }
+            _ => {
+                self.identity_from_userinfo(provider, &endpoints, &tokens.access_token)
+                    .await
+            }
+        }
+    }
+
+    /// Reads the ID token. It came straight from the token endpoint over TLS,
+    /// which OpenID Connect Core 3.1.3.7 accepts in place of checking the
+    /// signature; issuer, audience, expiry and nonce are still enforced.
+    fn identity_from_id_token(
+        &self,
+        provider: &OidcProvider,
+        issuer: &str,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<ExternalIdentity, AppError> {
+        let header = decode_header(id_token).map_err(|e| provider_error(provider, e))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.insecure_disable_signature_validation();
+        validation.set_audience(&[&provider.client_id]);
+        validation.set_issuer(&[issuer]);
+
+        let claims = decode::<IdTokenClaims>(id_token, &DecodingKey::from_secret(&[]), &validation)
+            .map_err(|e| provider_error(provider, e))?
+            .claims;
+
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(AppError::Unauthorized("ID token nonce mismatch".into()));
+        }
+
+        Ok(ExternalIdentity {
+            subject: claims.sub,
+            email: claims.email,
+            email_verified: claims.email_verified,
+            name: claims.name,
+        })
+    }
+
+    async fn identity_from_userinfo(
+        &self,
+        provider: &OidcProvider,
+        endpoints: &ProviderEndpoints,
+        access_token: &str,
+    ) -> Result<ExternalIdentity, AppError> {
+        let userinfo_endpoint = endpoints.userinfo_endpoint.as_ref().ok_or_else(|| {
+            AppError::InternalServerError(format!(
+                "Provider {} returned no ID token and has no userinfo endpoint",
+                provider.name
+            ))
+        })?;
+
+        let info: Value = self
+            .http
+            .get(userinfo_endpoint)
+            .bearer_auth(access_token)
+            .header(reqwest::header::ACCEPT, "application/json")
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| provider_error(provider, e))?
+            .json()
+            .await
+            .map_err(|e| provider_error(provider, e))?;
+
+        // Ids may be numbers (GitHub) or strings
+        let subject = match &info[&provider.subject_claim] {
+            Value::String(subject) => subject.clone(),
+            Value::Number(subject) => subject.to_string(),
+            _ => {
+                return Err(AppError::Unauthorized(format!(
+                    "Provider response has no '{}'",
+                    provider.subject_claim
+                )))
+            }
+        };
+
+        Ok(ExternalIdentity {
+            subject,
+            email: info["email"].as_str().map(str::to_string),
+            email_verified: info["email_verified"].as_bool().unwrap_or(false),
+            name: info["name"]
+                .as_str()
+                .or_else(|| info["login"].as_str())
+                .map(str::to_string),
+        })
+    }
+
+    /// Maps an external identity to a local user.
+    ///
+    /// Known identities log in their user. Unknown ones are linked when a
+    /// logged-in user started the flow, or when both the provider and Shelf
+    /// have verified the same email address; otherwise a new user is created.
+    async fn resolve_user(
+        &self,
+        provider: &str,
+        identity: ExternalIdentity,
+        link_user_id: Option<i32>,
+    ) -> Result<OidcOutcome, AppError> {
+        let existing = UserIdentity::find()
+            .filter(user_identity::Column::Provider.eq(provider))
+            .filter(user_identity::Column::Subject.eq(identity.subject.clone()))
+            .find_also_related(User)
+            .one(&self.db)
+            .await?;
+
+        match (existing, link_user_id) {
+            (Some((linked, Some(user))), None) => {
+                let changes = user_identity::ActiveModel {
+                    last_login_at: Set(Some(Utc::now().into())),
+                    email: Set(identity.email),
+                    ..Default::default()
+                };
+                UserIdentity::update_many()
+                    .set(changes)
+                    .filter(user_identity::Column::Id.eq(linked.id))
+                    .exec(&self.db)
+                    .await?;
+
+                Ok(OidcOutcome::Login {
+                    user,
+                    created: false,
+                })
+            }
+            (Some((linked, _)), Some(user_id)) => {
+                if linked.user_id == user_id {
+                    Ok(OidcOutcome::Linked)
+                } else {
+                    Err(AppError::Conflict(format!(
+                        "This {} account is already linked to another user",
+                        provider
+                    )))
+                }
+            }
+            (Some((_, None)), None) => Err(AppError::InternalServerError(
+                "Identity without a user".into(),
+            )),
+            (None, Some(user_id)) => {
+                let already_linked = UserIdentity::find()
+                    .filter(user_identity::Column::UserId.eq(user_id))
+                    .filter(user_identity::Column::Provider.eq(provider))
+                    .one(&self.db)
+                    .await?;
+                if already_linked.is_some() {
+                    return Err(AppError::Conflict(format!(
+                        "A different {} account is already linked",
+                        provider
+                    )));
+                }
+
+                self.link(&self.db, user_id, provider, &identity).await?;
+                println!("User {} linked their {} account", user_id, provider);
+                Ok(OidcOutcome::Linked)
+            }
+            (None, None) => {
+                let email = identity.email.clone().ok_or_else(|| {
+                    AppError::BadRequest(format!(
+                        "Your {} account did not share an email address",
+                        provider
+                    ))
+                })?;
+
+                let existing_user = User::find()
+                    .filter(user::Column::Email.eq(email.clone()))
+                    .one(&self.db)
+                    .await?;
+
+                match existing_user {
+                    Some(user) if identity.email_verified && user.email_verified => {
+                        self.link(&self.db, user.id, provider, &identity).await?;
+                        println!("User {} linked their {} account by email", user.id, provider);
+                        Ok(OidcOutcome::Login {
+                            user,
+                            created: false,
+                        })
+                    }
+                    Some(_) => Err(AppError::Conflict(format!(
+                        "An account with this email already exists. Sign in with your password and link {} from your account",
+                        provider
+                    ))),
+                    None => {
+                        let transaction = self.db.begin().await?;
+
+                        let now = Utc::now();
+                        let user = user::ActiveModel {
+                            email: Set(email.clone()),
+                            password_hash: Set(None),
+                            full_name: Set(identity
+                                .name
+                                .clone()
+                                .unwrap_or_else(|| email.split('@').next().unwrap_or("").to_string())),
+                            is_admin: Set(false),
+                            is_active: Set(true),
+                            email_verified: Set(identity.email_verified),
+                            totp_secret: Set(None),
+                            totp_enabled: Set(false),
+                            totp_last_step: Set(None),
+                            created_at: Set(now.into()),
+                            updated_at: Set(now.into()),
+                            ..Default::default()
+                        }
+                        .insert(&transaction)
+                        .await?;
+
+                        self.link(&transaction, user.id, provider, &identity).await?;
+                        transaction.commit().await?;
+
+                        println!("User {} created through {} login", user.id, provider);
+                        Ok(OidcOutcome::Login {
+                            user,
+                            created: true,
+                        })
+                    }
+                }
+            }
+        }
+    }
+
+    async fn link<C: sea_orm::ConnectionTrait>(
+        &self,
+        db: &C,
+        user_id: i32,
+        provider: &str,
+        identity: &ExternalIdentity,
+    ) -> Result<(), AppError> {
+        let now = Utc::now();
+        user_identity::ActiveModel {
+            user_id: Set(user_id),
+            provider: Set(provider.to_string()),
+            subject: Set(identity.subject.clone()),
+            email: Set(identity.email.clone()),
+            created_at: Set(now.into()),
+            last_login_at: Set(Some(now.into())),
+            ..Default::default()
+        }
+        .insert(db)
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl OidcProvider {
+    fn from_env(name: &str) -> Result<Self, AppError> {
+        let prefix = format!("OIDC_{}_", name.to_uppercase());
+        let var = |key: &str| env::var(format!("{}{}", prefix, key)).ok();
+        let required = |key: &str| {
+            var(key).ok_or_else(|| {
+                AppError::InternalServerError(format!("{}{} must be set", prefix, key))
+            })
+        };
+
+        let issuer = var("ISSUER").map(|issuer| issuer.trim_end_matches('/').to_string());
+
+        let configured_endpoints = match (var("AUTHORIZATION_URL"), var("TOKEN_URL")) {
+            (Some(authorization_endpoint), Some(token_endpoint)) => Some(ProviderEndpoints {
+                authorization_endpoint,
+                token_endpoint,
+                userinfo_endpoint: var("USERINFO_URL"),
+            }),
+            _ if issuer.is_some() => None,
+            _ => {
+                return Err(AppError::InternalServerError(format!(
+                    "Set {0}ISSUER, or {0}AUTHORIZATION_URL and {0}TOKEN_URL",
+                    prefix
+                )))
+            }
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            client_id: required("CLIENT_ID")?,
+            client_secret: required("CLIENT_SECRET")?,
+            scopes: var("SCOPES").unwrap_or_else(|| "openid email profile".to_string()),
+            issuer,
+            subject_claim: var("SUBJECT_CLAIM").unwrap_or_else(|| "sub".to_string()),
+            configured_endpoints,
+            discovered_endpoints: OnceCell::new(),
+        })
+    }
+
+    async fn endpoints(&self, http: &reqwest::Client) -> Result<ProviderEndpoints, AppError> {
+        if let Some(endpoints) = &self.configured_endpoints {
+            return Ok(endpoints.clone());
+        }
+
+        let issuer = self.issuer.as_ref().expect("checked in from_env");
+        let endpoints = self
+            .discovered_endpoints
+            .get_or_try_init(|| async {
+                let url = format!("{}/.well-known/openid-configuration", issuer);
+                println!("Discovering {} endpoints from {}", self.name, url);
+                http.get(&url)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(|e| provider_error(self, e))?
+                    .json::<ProviderEndpoints>()
+                    .await
+                    .map_err(|e| provider_error(self, e))
+            })
+            .await?;
+
+        Ok(endpoints.clone())
+    }
+}
+
+/// What the browser keeps of a `state` to prove it started the round trip.
+/// Only the hash is stored, so the cookie alone cannot complete a login.
+pub fn state_binding(state: &str) -> String {
+    hex::encode(Sha256::digest(state.as_bytes()))
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn provider_error(provider: &OidcProvider, err: impl std::fmt::Display) -> AppError {
+    println!("{} login error: {}", provider.name, err);
+    AppError::InternalServerError(format!("Could not talk to {}", provider.name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    const CLIENT_ID: &str = "shelf";
+    const REDIRECT_URL: &str = "https://shelf.test/oauth/callback";
+
+    /// What the mock issuer answers and what it was sent.
+    #[derive(Default)]
+    struct Issuer {
+        /// Claims of the ID token, `None` for a plain OAuth2 provider
+        id_token_claims: Option<Value>,
+        userinfo: Value,
+        token_requests: Vec<HashMap<String, String>>,
+        discovery_requests: usize,
+    }
+
+    type Shared = Arc<Mutex<Issuer>>;
+
+    /// Starts a mock OpenID Connect issuer on a free local port and returns
+    /// its URL. Only code `good-code` is accepted at the token endpoint.
+    fn mock_issuer(issuer: Shared) -> String {
+        let server = HttpServer::new(move || {
+            App::new()
+                    .app_data(web::Data::new(issuer.clone()))
+                    .route(
+                        "/.well-known/openid-configuration",
+                        web::get().to(
+                            |issuer: web::Data<Shared>, req: actix_web::HttpRequest| async move {
+                                issuer.lock().unwrap().discovery_requests += 1;
+                                let base = format!("http://{}", req.connection_info().host());
+                                HttpResponse::Ok().json(json!({
+                                    "issuer": base,
+                                    "authorization_endpoint": format!("{}/authorize", base),
+                                    "token_endpoint": format!("{}/token", base),
+                                    "userinfo_endpoint": format!("{}/userinfo", base)
+                                }))
+                            },
+                        ),
+                    )
+                    .route(
+                        "/token",
+                        web::post().to(
+                            |issuer: web::Data<Shared>,
+                             form: web::Form<HashMap<String, String>>| async move {
+                                let mut issuer = issuer.lock().unwrap();
+                                let form = form.into_inner();
+                                let accepted =
+                                    form.get("code").map(String::as_str) == Some("good-code");
+                                issuer.token_requests.push(form);
+                                if !accepted {
+                                    return HttpResponse::BadRequest()
+                                        .json(json!({"error": "invalid_grant"}));
+                                }
+
+                                let id_token = issuer.id_token_claims.as_ref().map(|claims| {
+                                    encode(
+                                        &Header::default(),
+                                        claims,
+                                        &EncodingKey::from_secret(b"issuer"),
+                                    )
+                                    .unwrap()
+                                });
+                                HttpResponse::Ok().json(json!({
+                                    "access_token": "access",
+                                    "token_type": "Bearer",
+                                    "id_token": id_token
+                                }))
+                            },
+                        ),
+                    )
+                    .route(
+                        "/userinfo",
+                        web::get().to(
+                            |issuer: web::Data<Shared>, req: actix_web::HttpRequest| async move {
+                                let authorized = req
+                                    .headers()
+                                    .get("Authorization")
+                                    .is_some_and(|value| value == "Bearer access");
+                                if !authorized {
+                                    return HttpResponse::Unauthorized().finish();
+                                }
+                                HttpResponse::Ok().json(issuer.lock().unwrap().userinfo.clone())
+                            },
+                        ),
+                    )
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let address = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+        format!("http://{}", address)
+    }
+
+    /// A service with one provider, `mock`. Nothing here reaches the
+    /// database, so it stays disconnected.
+    fn service(provider: OidcProvider) -> OidcService {
+        let mut providers = BTreeMap::new();
+        providers.insert(provider.name.clone(), Arc::new(provider));
+        OidcService {
+            providers: Arc::new(providers),
+            redirect_url: REDIRECT_URL.to_string(),
+            http: reqwest::Client::new(),
+            db: DatabaseConnection::Disconnected,
+        }
+    }
+
+    fn oidc_provider(issuer: &str) -> OidcProvider {
+        OidcProvider {
+            name: "mock".to_string(),
+            client_id: CLIENT_ID.to_string(),
+            client_secret: "client-secret".to_string(),
+            scopes: "openid email profile".to_string(),
+            issuer: Some(issuer.to_string()),
+            subject_claim: "sub".to_string(),
+            configured_endpoints: None,
+            discovered_endpoints: OnceCell::new(),
+        }
+    }
+
+    fn pending(nonce: &str) -> oidc_state::Model {
+        let now = Utc::now().fixed_offset();
+        oidc_state::Model {
+            id: 1,
+            state: "state".to_string(),
+            provider: "mock".to_string(),
+            code_verifier: "verifier".to_string(),
+            nonce: nonce.to_string(),
+            user_id: None,
+            expires_at: now + Duration::minutes(STATE_TTL_MINUTES),
+            created_at: now,
+        }
+    }
+
+    fn claims(issuer: &str, audience: &str, nonce: &str) -> Value {
+        json!({
+            "iss": issuer,
+            "aud": audience,
+            "sub": "user-42",
+            "exp": (Utc::now() + Duration::minutes(5)).timestamp(),
+            "nonce": nonce,
+            "email": "jane@example.com",
+            "email_verified": true,
+            "name": "Jane"
+        })
+    }
+
+    async fn identity(
+        issuer: &Shared,
+        base: &str,
+        code: &str,
+        nonce: &str,
+    ) -> Result<ExternalIdentity, AppError> {
+        let oidc = service(oidc_provider(base));
+        let provider = oidc.provider("mock")?.clone();
+        let result = oidc.fetch_identity(&provider, code, &pending(nonce)).await;
+        // Endpoints are discovered once and then reused
+        let _ = provider.endpoints(&oidc.http).await;
+        assert_eq!(issuer.lock().unwrap().discovery_requests, 1);
+        result
+    }
+
+    #[actix_web::test]
+    async fn code_exchange_reads_the_id_token() {
+        let issuer = Shared::default();
+        let base = mock_issuer(issuer.clone());
+        issuer.lock().unwrap().id_token_claims = Some(claims(&base, CLIENT_ID, "nonce-1"));
+
+        let identity = identity(&issuer, &base, "good-code", "nonce-1")
+            .await
+            .unwrap();
+        assert_eq!(identity.subject, "user-42");
+        assert_eq!(identity.email.as_deref(), Some("jane@example.com"));
+        assert!(identity.email_verified);
+        assert_eq!(identity.name.as_deref(), Some("Jane"));
+
+        let issuer = issuer.lock().unwrap();
+        let form = &issuer.token_requests[0];
+        assert_eq!(form["grant_type"], "authorization_code");
+        assert_eq!(form["code"], "good-code");
+        assert_eq!(form["code_verifier"], "verifier");
+        assert_eq!(form["redirect_uri"], REDIRECT_URL);
+        assert_eq!(form["client_id"], CLIENT_ID);
+    }
+
+    #[actix_web::test]
+    async fn id_token_for_another_nonce_is_refused() {
+        let issuer = Shared::default();
+        let base = mock_issuer(issuer.clone());
+        issuer.lock().unwrap().id_token_claims = Some(claims(&base, CLIENT_ID, "nonce-1"));
+
+        let result = identity(&issuer, &base, "good-code", "nonce-2").await;
+        assert!(matches!(result, Err(AppError::Unauthorized(_))));
+    }
+
+    #[actix_web::test]
+    async fn id_token_for_another_client_or_issuer_is_refused() {
+        let issuer = Shared::default();
+        let base = mock_issuer(issuer.clone());
+
+        issuer.lock().unwrap().id_token_claims = Some(claims(&base, "other-client", "nonce-1"));
+        assert!(identity(&issuer, &base, "good-code", "nonce-1")
+            .await
+            .is_err());
+
+        let issuer = Shared::default();
+        let base = mock_issuer(issuer.clone());
+        issuer.lock().unwrap().id_token_claims =
+            Some(claims("https://evil.test", CLIENT_ID, "nonce-1"));
+        assert!(identity(&issuer, &base, "good-code", "nonce-1")
+            .await
+            .is_err());
+    }
+
+    #[actix_web::test]
+    async fn rejected_code_is_unauthorized() {
+        let issuer = Shared::default();
+        let base = mock_issuer(issuer.clone());
+        issuer.lock().unwrap().id_token_claims = Some(claims(&base, CLIENT_ID, "nonce-1"));
+
+        let result = identity(&issuer, &base, "stolen-code", "nonce-1").await;
+        assert!(matches!(result, Err(AppError::Unauthorized(_))));
+    }
+
+    #[actix_web::test]
+    async fn oauth2_provider_is_read_from_userinfo() {
+        let issuer = Shared::default();
+        let base = mock_issuer(issuer.clone());
+        issuer.lock().unwrap().userinfo =
+            json!({"id": 1234, "login": "octocat", "email": "octo@example.com"});
+
+        // Configured endpoints and numeric ids, as with GitHub
+        let oidc = service(OidcProvider {
+            issuer: None,
+            subject_claim: "id".to_string(),
+            configured_endpoints: Some(ProviderEndpoints {
+                authorization_endpoint: format!("{}/authorize", base),
+                token_endpoint: format!("{}/token", base),
+                userinfo_endpoint: Some(format!("{}/userinfo", base)),
+            }),
+            ..oidc_provider(&base)
+        });
+        let provider = oidc.provider("mock").unwrap().clone();
+
+        let identity = oidc
+            .fetch_identity(&provider, "good-code", &pending("nonce-1"))
+            .await
+            .unwrap();
+        assert_eq!(identity.subject, "1234");
+        assert_eq!(identity.name.as_deref(), Some("octocat"));
+        assert!(!identity.email_verified);
+        assert_eq!(issuer.lock().unwrap().discovery_requests, 0);
+    }
+
+    #[test]
+    fn state_binding_is_a_hash_of_the_state() {
+        assert_eq!(state_binding("abc"), state_binding("abc"));
+        assert_ne!(state_binding("abc"), state_binding("abd"));
+        assert_ne!(state_binding("abc"), "abc");
+    }
+}
@@ -0,0 +1,648 @@
+use crate::{
+    error::AppError,
+    models::payment::{
+        self, Entity as Payment, Model as PaymentModel, PaymentProvider, PaymentStatus,
+    },
+    services::plan,
+};
+use async_trait::async_trait;
+use chrono;
+use sea_orm::prelude::Decimal;
+use sea_orm::{
+    sea_query::Expr, ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    Set,
+};
+use serde_json::{json, Value};
+use std::env;
+use std::sync::Arc;
+
+pub mod momo;
+pub mod paypal;
+
+pub use momo::MomoGateway;
+pub use paypal::PayPalGateway;
+
+/// What the user asked to pay, independent of the provider.
+pub struct PaymentInitiation {
+    pub amount: Decimal,
+    pub phone_number: Option<String>,
+    pub payer_message: String,
+    pub payee_note: String,
+}
+
+pub struct InitiatedPayment {
+    /// Id the provider uses for the payment (MoMo `X-Reference-Id`, PayPal order id)
+    pub provider_reference_id: String,
+    pub provider_status: String,
+    /// Page the payer must visit to approve the payment, if the provider has one
+    pub approval_url: Option<String>,
+}
+
+pub struct GatewayStatus {
+    pub status: PaymentStatus,
+    pub provider_status: String,
+    /// Provider specific data kept in `provider_response.details`
+    pub details: Value,
+}
+
+pub struct RefundResult {
+    pub provider_refund_id: String,
+    pub provider_status: String,
+}
+
+/// Notification pushed by a provider. Webhooks are unauthenticated, so the
+/// event only says which payment changed; the status is always re-read with
+/// `query_status` before it is recorded.
+pub struct WebhookEvent {
+    pub provider_reference_id: String,
+    pub provider_status: String,
+}
+
+#[async_trait(?Send)]
+pub trait PaymentGateway: Send + Sync {
+    /// Currency payments are requested in.
+    fn currency(&self) -> &str;
+
+    async fn initiate(&self, request: &PaymentInitiation) -> Result<InitiatedPayment, AppError>;
+
+    async fn query_status(&self, payment: &PaymentModel) -> Result<GatewayStatus, AppError>;
+
+    /// Refunds `amount` of the payment.
+    async fn refund(
+        &self,
+        payment: &PaymentModel,
+        amount: Decimal,
+    ) -> Result<RefundResult, AppError>;
+
+    fn parse_webhook(&self, body: &[u8]) -> Result<WebhookEvent, AppError>;
+}
+
+/// A provider setting from the environment. Empty values count as unset, as
+/// docker-compose passes variables missing from `.env` as empty strings.
+fn setting(var: &str) -> Option<String> {
+    env::var(var).ok().filter(|value| !value.is_empty())
+}
+
+/// The ISO 4217 currency a provider charges in, read from `var` (EUR when it
+/// is unset). A bad code fails at startup rather than on the first payment.
+fn currency_from_env(var: &str) -> Result<String, AppError> {
+    let currency = setting(var).unwrap_or_else(|| "EUR".to_string());
+    if currency.len() != 3 || !currency.bytes().all(|b| b.is_ascii_uppercase()) {
+        return Err(AppError::BadRequest(format!(
+            "{} must be a three-letter currency code such as EUR, not {:?}",
+            var, currency
+        )));
+    }
+    Ok(currency)
+}
+
+#[derive(Clone)]
+pub struct PaymentService {
+    momo: Option<Arc<dyn PaymentGateway>>,
+    paypal: Option<Arc<dyn PaymentGateway>>,
+    db: DatabaseConnection,
+}
+
+impl PaymentService {
+    /// Enables every provider whose configuration is present in the
+    /// environment: MTN MoMo when `MTN_URL` is set and PayPal when
+    /// `PAYPAL_CLIENT_ID` is set.
+    pub async fn new(db: DatabaseConnection) -> Result<Self, AppError> {
+        let momo: Option<Arc<dyn PaymentGateway>> = match setting("MTN_URL") {
+            Some(mtn_url) => Some(Arc::new(MomoGateway::new(mtn_url).await?)),
+            None => None,
+        };
+
+        let paypal: Option<Arc<dyn PaymentGateway>> = match setting("PAYPAL_CLIENT_ID") {
+            Some(client_id) => Some(Arc::new(PayPalGateway::new(client_id)?)),
+            None => None,
+        };
+
+        if momo.is_none() && paypal.is_none() {
+            return Err(AppError::BadRequest(
+                "No payment provider configured (set MTN_URL or PAYPAL_CLIENT_ID)".into(),
+            ));
+        }
+
+        Ok(Self::from_gateways(db, momo, paypal))
+    }
+
+    /// Service over gateways that are already set up.
+    pub fn from_gateways(
+        db: DatabaseConnection,
+        momo: Option<Arc<dyn PaymentGateway>>,
+        paypal: Option<Arc<dyn PaymentGateway>>,
+    ) -> Self {
+        Self { momo, paypal, db }
+    }
+
+    fn gateway(&self, provider: &PaymentProvider) -> Result<&dyn PaymentGateway, AppError> {
+        let gateway = match provider {
+            PaymentProvider::MtnMomo => self.momo.as_deref(),
+            PaymentProvider::PayPal => self.paypal.as_deref(),
+        };
+
+        gateway.ok_or_else(|| {
+            AppError::BadRequest(format!("Payment provider {:?} is not configured", provider))
+        })
+    }
+
+    /// Starts a payment for `plan_code` with `provider` and records it as
+    /// pending. The amount is the catalog price of the plan in the
+    /// provider's currency. Returns the stored payment and, for redirect
+    /// based providers, the URL the payer has to visit.
+    pub async fn request_payment(
+        &self,
+        user_id: i32,
+        provider: PaymentProvider,
+        plan_code: &str,
+        phone_number: Option<String>,
+        payer_message: String,
+        payee_note: String,
+    ) -> Result<(PaymentModel, Option<String>), AppError> {
+        let gateway = self.gateway(&provider)?;
+
+        let plan = plan::find_by_code(&self.db, plan_code).await?;
+        let amount_decimal = plan::price_for(&self.db, &plan, gateway.currency()).await?;
+        if amount_decimal <= Decimal::ZERO {
+            return Err(AppError::BadRequest("Amount must be greater than 0".into()));
+        }
+
+        let initiated = gateway
+            .initiate(&PaymentInitiation {
+                amount: amount_decimal,
+                phone_number: phone_number.clone(),
+                payer_message: payer_message.clone(),
+                payee_note: payee_note.clone(),
+            })
+            .await?;
+
+        println!(
+            "Received reference_id from {:?}: {}",
+            provider, initiated.provider_reference_id
+        );
+        let unique_ref = format!(
+            "{}_{}",
+            initiated.provider_reference_id,
+            chrono::Utc::now().timestamp_millis()
+        );
+        println!("Generated unique reference: {}", unique_ref);
+
+        let existing_payment = Payment::find()
+            .filter(payment::Column::ReferenceId.eq(unique_ref.clone()))
+            .one(&self.db)
+            .await?;
+
+        if existing_payment.is_some() {
+            println!("Warning: Duplicate reference_id detected: {}", unique_ref);
+            return Err(AppError::BadRequest(
+                "Payment request already processed".into(),
+            ));
+        }
+
+        let payment = payment::ActiveModel {
+            user_id: Set(user_id),
+            plan_id: Set(plan.id),
+            reference_id: Set(unique_ref.clone()),
+            provider_reference_id: Set(initiated.provider_reference_id.clone()),
+            amount: Set(amount_decimal),
+            currency: Set(gateway.currency().to_string()),
+            phone_number: Set(phone_number),
+            provider: Set(provider),
+            status: Set(PaymentStatus::Pending),
+            provider_response: Set(Some(json!({
+                "reference_id": unique_ref,
+                "provider_reference_id": initiated.provider_reference_id,
+                "status": initiated.provider_status,
+                "approval_url": initiated.approval_url
+            }))),
+            error_message: Set(None),
+            metadata: Set(Some(json!({
+                "payer_message": payer_message,
+                "payee_note": payee_note
+            }))),
+            ..Default::default()
+        };
+
+        Ok((payment.insert(&self.db).await?, initiated.approval_url))
+    }
+
+    /// Polls the provider for a payment owned by `user_id`. Returns the
+    /// payment and whether this call moved it out of `Pending`.
+    pub async fn check_payment_status(
+        &self,
+        reference_id: String,
+        user_id: i32,
+    ) -> Result<(PaymentModel, bool), AppError> {
+        let payment = Payment::find()
+            .filter(payment::Column::ReferenceId.eq(reference_id.clone()))
+            .filter(payment::Column::UserId.eq(user_id))
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::NotFound("Payment not found".into()))?;
+
+        // Final states never change again; no need to ask the provider
+        if payment.status != PaymentStatus::Pending {
+            return Ok((payment, false));
+        }
+
+        let gateway = self.gateway(&payment.provider)?;
+
+        match gateway.query_status(&payment).await {
+            Ok(status) => {
+                println!(
+                    "{:?} status response: {}",
+                    payment.provider, status.provider_status
+                );
+                self.record_status(payment, status, None).await
+            }
+            Err(e) => {
+                // Not hearing from the provider says nothing about the payment
+                // itself, so it stays pending for a later check or callback
+                println!("Status check error for {}: {}", payment.reference_id, e);
+                Err(AppError::InternalServerError(format!(
+                    "Status check failed: {}",
+                    e
+                )))
+            }
+        }
+    }
+
+    /// Applies a webhook/callback pushed by `provider`.
+    ///
+    /// The endpoints receiving these are unauthenticated, so the reported
+    /// status is confirmed with the provider before it is recorded. Payments
+    /// that already left `Pending` are returned unchanged, which makes
+    /// repeated deliveries harmless. The returned flag is `true` only for
+    /// the call that actually moved the payment out of `Pending`.
+    pub async fn handle_webhook(
+        &self,
+        provider: PaymentProvider,
+        body: &[u8],
+    ) -> Result<(PaymentModel, bool), AppError> {
+        let gateway = self.gateway(&provider)?;
+        let event = gateway.parse_webhook(body)?;
+        let raw_body: Value = serde_json::from_slice(body).unwrap_or(Value::Null);
+
+        let payment = Payment::find()
+            .filter(payment::Column::Provider.eq(provider))
+            .filter(payment::Column::ProviderReferenceId.eq(event.provider_reference_id.clone()))
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::NotFound("Payment not found".into()))?;
+
+        if payment.status != PaymentStatus::Pending {
+            println!(
+                "Ignoring webhook for payment {} already in status {:?}",
+                payment.reference_id, payment.status
+            );
+            return Ok((payment, false));
+        }
+
+        println!(
+            "Webhook reports {} for payment {}, verifying with provider",
+            event.provider_status, payment.reference_id
+        );
+        let verified = gateway.query_status(&payment).await?;
+        self.record_status(payment, verified, Some(raw_body)).await
+    }
+
+    /// Stores the status reported by the provider. The payment only moves if
+    /// nobody else (a concurrent webhook or status poll) has moved it out of
+    /// `Pending` in the meantime; the flag tells whether this call did.
+    async fn record_status(
+        &self,
+        payment: PaymentModel,
+        status: GatewayStatus,
+        callback: Option<Value>,
+    ) -> Result<(PaymentModel, bool), AppError> {
+        let error_message = match status.status {
+            PaymentStatus::Failed | PaymentStatus::Cancelled => {
+                Some(format!("Payment {}", status.provider_status.to_lowercase()))
+            }
+            _ => None,
+        };
+
+        let mut provider_response = json!({
+            "reference_id": payment.reference_id,
+            "provider_reference_id": payment.provider_reference_id,
+            "status": status.provider_status,
+            "details": status.details
+        });
+        if let Some(callback) = callback {
+            provider_response["callback"] = callback;
+        }
+
+        let changes = payment::ActiveModel {
+            status: Set(status.status),
+            provider_response: Set(Some(provider_response)),
+            error_message: Set(error_message),
+            ..Default::default()
+        };
+
+        let result = Payment::update_many()
+            .set(changes)
+            .filter(payment::Column::Id.eq(payment.id))
+            .filter(payment::Column::Status.eq(PaymentStatus::Pending))
+            .exec(&self.db)
+            .await?;
+
+        let updated_payment = Payment::find_by_id(payment.id)
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::NotFound("Payment not found".into()))?;
+
+        let transitioned =
+            result.rows_affected == 1 && updated_payment.status != PaymentStatus::Pending;
+        Ok((updated_payment, transitioned))
+    }
+
+    /// Refunds a successful payment through its provider, either for
+    /// `amount` or for whatever has not been refunded yet. Refunds add up in
+    /// `refunded_amount`; once it reaches the amount paid the payment is
+    /// marked as `Refunded`.
+    pub async fn refund_payment(
+        &self,
+        reference_id: String,
+        amount: Option<Decimal>,
+    ) -> Result<PaymentModel, AppError> {
+        let payment = Payment::find()
+            .filter(payment::Column::ReferenceId.eq(reference_id))
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::NotFound("Payment not found".into()))?;
+
+        let amount = refund_amount(&payment, amount)?;
+        let gateway = self.gateway(&payment.provider)?;
+
+        // The amount is taken off the balance before the provider is asked,
+        // so refunds racing each other cannot add up to more than was paid
+        let reserved = Payment::update_many()
+            .col_expr(
+                payment::Column::RefundedAmount,
+                Expr::col(payment::Column::RefundedAmount).add(amount),
+            )
+            .filter(payment::Column::Id.eq(payment.id))
+            .filter(payment::Column::Status.eq(PaymentStatus::Successful))
+            .filter(
+                Expr::col(payment::Column::RefundedAmount)
+                    .lte(Expr::col(payment::Column::Amount).sub(amount)),
+            )
+            .exec(&self.db)
+            .await?;
+        if reserved.rows_affected == 0 {
+            return Err(AppError::BadRequest(
+                "Refund exceeds the remaining balance".into(),
+            ));
+        }
+
+        let refund = match gateway.refund(&payment, amount).await {
+            Ok(refund) => refund,
+            Err(e) => {
+                Payment::update_many()
+                    .col_expr(
+                        payment::Column::RefundedAmount,
+                        Expr::col(payment::Column::RefundedAmount).sub(amount),
+                    )
+                    .filter(payment::Column::Id.eq(payment.id))
+                    .exec(&self.db)
+                    .await?;
+                return Err(e);
+            }
+        };
+        println!(
+            "Refund {} of {} for payment {}: {}",
+            refund.provider_refund_id, amount, payment.reference_id, refund.provider_status
+        );
+
+        let payment = Payment::find_by_id(payment.id)
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::NotFound("Payment not found".into()))?;
+
+        let mut provider_response = payment.provider_response.clone().unwrap_or(json!({}));
+        if !provider_response["refunds"].is_array() {
+            provider_response["refunds"] = json!([]);
+        }
+        if let Some(refunds) = provider_response["refunds"].as_array_mut() {
+            refunds.push(json!({
+                "provider_refund_id": refund.provider_refund_id,
+                "status": refund.provider_status,
+                "amount": amount.to_string()
+            }));
+        }
+
+        let fully_refunded = payment.refunded_amount >= payment.amount;
+        let mut updated_payment: payment::ActiveModel = payment.into();
+        if fully_refunded {
+            updated_payment.status = Set(PaymentStatus::Refunded);
+        }
+        updated_payment.provider_response = Set(Some(provider_response));
+
+        Ok(updated_payment.update(&self.db).await?)
+    }
+}
+
+/// The amount a refund of `requested` takes off `payment`; without an amount,
+/// everything not refunded yet.
+fn refund_amount(payment: &PaymentModel, requested: Option<Decimal>) -> Result<Decimal, AppError> {
+    if payment.status != PaymentStatus::Successful {
+        return Err(AppError::BadRequest(
+            "Only successful payments can be refunded".into(),
+        ));
+    }
+
+    let remaining = payment.amount - payment.refunded_amount;
+    match requested {
+        Some(amount) if amount <= Decimal::ZERO => {
+            Err(AppError::BadRequest("Invalid refund amount".into()))
+        }
+        Some(amount) if amount > remaining => Err(AppError::BadRequest(format!(
+            "Refund exceeds the remaining balance of {}",
+            remaining
+        ))),
+        Some(amount) => Ok(amount),
+        None if remaining > Decimal::ZERO => Ok(remaining),
+        None => Err(AppError::BadRequest(
+            "Payment is already fully refunded".into(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{services::plan, test_support::TestDb};
+
+    fn payment(status: PaymentStatus, amount: i64, refunded: i64) -> PaymentModel {
+        let now = chrono::Utc::now().fixed_offset();
+        PaymentModel {
+            id: 1,
+            user_id: 1,
+            plan_id: 2,
+            reference_id: "ref".to_string(),
+            provider_reference_id: "order".to_string(),
+            amount: Decimal::new(amount, 2),
+            refunded_amount: Decimal::new(refunded, 2),
+            currency: "EUR".to_string(),
+            phone_number: None,
+            provider: PaymentProvider::PayPal,
+            status,
+            provider_response: None,
+            error_message: None,
+            metadata: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    #[test]
+    fn currencies_must_be_iso_codes() {
+        env::remove_var("TEST_UNSET_CURRENCY");
+        assert_eq!(currency_from_env("TEST_UNSET_CURRENCY").unwrap(), "EUR");
+        env::set_var("TEST_EMPTY_CURRENCY", "");
+        assert_eq!(currency_from_env("TEST_EMPTY_CURRENCY").unwrap(), "EUR");
+        env::set_var("TEST_XAF_CURRENCY", "XAF");
+        assert_eq!(currency_from_env("TEST_XAF_CURRENCY").unwrap(), "XAF");
+        for (i, currency) in ["eur", "EURO", "E1R", "€UR", " EUR"]
+            .into_iter()
+            .enumerate()
+        {
+            let var = format!("TEST_BAD_CURRENCY_{}", i);
+            env::set_var(&var, currency);
+            assert!(currency_from_env(&var).is_err(), "{:?}", currency);
+        }
+    }
+
+    #[test]
+    fn refund_defaults_to_the_remaining_balance() {
+        let payment = payment(PaymentStatus::Successful, 1000, 250);
+        assert_eq!(refund_amount(&payment, None).unwrap(), Decimal::new(750, 2));
+    }
+
+    #[test]
+    fn partial_refunds_stay_within_the_remaining_balance() {
+        let payment = payment(PaymentStatus::Successful, 1000, 250);
+        assert_eq!(
+            refund_amount(&payment, Some(Decimal::new(750, 2))).unwrap(),
+            Decimal::new(750, 2)
+        );
+        assert!(refund_amount(&payment, Some(Decimal::new(751, 2))).is_err());
+        assert!(refund_amount(&payment, Some(Decimal::ZERO)).is_err());
+        assert!(refund_amount(&payment, Some(Decimal::new(-100, 2))).is_err());
+    }
+
+    #[test]
+    fn nothing_left_to_refund() {
+        let payment = payment(PaymentStatus::Successful, 1000, 1000);
+        assert!(refund_amount(&payment, None).is_err());
+        assert!(refund_amount(&payment, Some(Decimal::new(1, 2))).is_err());
+    }
+
+    #[test]
+    fn only_successful_payments_are_refunded() {
+        for status in [
+            PaymentStatus::Pending,
+            PaymentStatus::Failed,
+            PaymentStatus::Cancelled,
+            PaymentStatus::Refunded,
+        ] {
+            assert!(refund_amount(&payment(status, 1000, 0), None).is_err());
+        }
+    }
+
+    /// PayPal stand-in that only refunds, or fails every refund.
+    struct RefundingGateway {
+        failing: bool,
+    }
+
+    #[async_trait(?Send)]
+    impl PaymentGateway for RefundingGateway {
+        fn currency(&self) -> &str {
+            "EUR"
+        }
+
+        async fn initiate(&self, _: &PaymentInitiation) -> Result<InitiatedPayment, AppError> {
+            unimplemented!()
+        }
+
+        async fn query_status(&self, _: &PaymentModel) -> Result<GatewayStatus, AppError> {
+            unimplemented!()
+        }
+
+        async fn refund(
+            &self,
+            _: &PaymentModel,
+            amount: Decimal,
+        ) -> Result<RefundResult, AppError> {
+            if self.failing {
+                return Err(AppError::InternalServerError("Refund failed".into()));
+            }
+            Ok(RefundResult {
+                provider_refund_id: format!("refund-{}", amount),
+                provider_status: "COMPLETED".to_string(),
+            })
+        }
+
+        fn parse_webhook(&self, _: &[u8]) -> Result<WebhookEvent, AppError> {
+            unimplemented!()
+        }
+    }
+
+    #[actix_web::test]
+    async fn refunds_add_up_until_the_payment_is_refunded() {
+        let Some(db) = TestDb::new().await else {
+            return;
+        };
+        let user_id = db.user("payer@example.com").await;
+        let premium = plan::find_by_code(&*db, "premium").await.unwrap();
+        payment::ActiveModel {
+            user_id: Set(user_id),
+            plan_id: Set(premium.id),
+            reference_id: Set("ref".to_string()),
+            provider_reference_id: Set("order".to_string()),
+            amount: Set(Decimal::new(1000, 2)),
+            currency: Set("EUR".to_string()),
+            provider: Set(PaymentProvider::PayPal),
+            status: Set(PaymentStatus::Successful),
+            ..Default::default()
+        }
+        .insert(&*db)
+        .await
+        .unwrap();
+        let service = |failing| {
+            PaymentService::from_gateways(
+                db.connection(),
+                None,
+                Some(Arc::new(RefundingGateway { failing })),
+            )
+        };
+
+        // A refund the provider refuses leaves the balance alone
+        assert!(service(true)
+            .refund_payment("ref".to_string(), Some(Decimal::new(400, 2)))
+            .await
+            .is_err());
+        let refunded = service(false)
+            .refund_payment("ref".to_string(), Some(Decimal::new(400, 2)))
+            .await
+            .unwrap();
+        assert_eq!(refunded.refunded_amount, Decimal::new(400, 2));
+        assert_eq!(refunded.status, PaymentStatus::Successful);
+
+        let refunded = service(false)
+            .refund_payment("ref".to_string(), None)
+            .await
+            .unwrap();
+        assert_eq!(refunded.refunded_amount, Decimal::new(1000, 2));
+        assert_eq!(refunded.status, PaymentStatus::Refunded);
+        let refunds = &refunded.provider_response.unwrap()["refunds"];
+        assert_eq!(refunds.as_array().map(Vec::len), Some(2));
+        assert_eq!(refunds[1]["amount"], "6.00");
+
+        assert!(service(false)
+            .refund_payment("ref".to_string(), Some(Decimal::new(1, 2)))
+            .await
+            .is_err());
+    }
+}
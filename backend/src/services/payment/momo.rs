@@ -0,0 +1,417 @@
+use super::{
+    GatewayStatus, InitiatedPayment, PaymentGateway, PaymentInitiation, RefundResult, WebhookEvent,
+};
+use crate::{
+    error::AppError,
+    models::payment::{Model as PaymentModel, PaymentStatus},
+};
+use async_trait::async_trait;
+use mtnmomo::{Environment, Momo, RefundRequest};
+use reqwest::{Client, Url};
+use sea_orm::prelude::Decimal;
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+
+/// MTN Mobile Money collections (request-to-pay).
+pub struct MomoGateway {
+    momo: Momo,
+    client: Client,
+    collection_primary_key: String,
+    collection_secondary_key: String,
+    /// Refunds go through the disbursement product, which has its own keys
+    disbursement_keys: Option<(String, String)>,
+    currency: String,
+    /// Where MoMo reports the outcome of a request-to-pay (`MTN_CALLBACK_URL`).
+    /// Without it payments are only settled by status checks.
+    callback_url: Option<Url>,
+}
+
+/// Body of the request-to-pay callback sent by MTN MoMo. `external_id` is the
+/// `X-Reference-Id` the payment was requested with.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MomoCallback {
+    external_id: String,
+    status: String,
+}
+
+impl MomoGateway {
+    pub async fn new(mtn_url: String) -> Result<Self, AppError> {
+        let currency = super::currency_from_env("MTN_CURRENCY")?;
+        let primary_key = super::setting("MTN_COLLECTION_PRIMARY_KEY")
+            .ok_or_else(|| AppError::BadRequest("MTN_COLLECTION_PRIMARY_KEY must be set".into()))?;
+        let secondary_key = super::setting("MTN_COLLECTION_SECONDARY_KEY").ok_or_else(|| {
+            AppError::BadRequest("MTN_COLLECTION_SECONDARY_KEY must be set".into())
+        })?;
+        let disbursement_keys = super::setting("MTN_DISBURSEMENT_PRIMARY_KEY")
+            .zip(super::setting("MTN_DISBURSEMENT_SECONDARY_KEY"));
+
+        let callback_url =
+            match super::setting("MTN_CALLBACK_URL") {
+                Some(url) => Some(Url::parse(&url).map_err(|e| {
+                    AppError::BadRequest(format!("Invalid MTN_CALLBACK_URL: {}", e))
+                })?),
+                None => None,
+            };
+
+        let client = Client::new();
+        let (api_user, api_key) =
+            provision_api_user(&client, &mtn_url, &primary_key, callback_url.as_ref())
+                .await
+                .map_err(|e| {
+                    AppError::BadRequest(format!("Failed to initialize MTN MoMo: {}", e))
+                })?;
+        let momo = Momo::new(mtn_url, api_user, Environment::Sandbox, Some(api_key)).await;
+
+        Ok(Self {
+            momo,
+            client,
+            collection_primary_key: primary_key,
+            collection_secondary_key: secondary_key,
+            disbursement_keys,
+            currency,
+            callback_url,
+        })
+    }
+
+    /// Collection access token for the provisioned API user.
+    async fn access_token(&self) -> Result<String, reqwest::Error> {
+        #[derive(Deserialize)]
+        struct Token {
+            access_token: String,
+        }
+
+        let token: Token = self
+            .client
+            .post(format!("{}/collection/token/", self.momo.url))
+            .basic_auth(&self.momo.api_user, Some(&self.momo.api_key))
+            .header("Ocp-Apim-Subscription-Key", &self.collection_primary_key)
+            .header("Content-Length", "0")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(token.access_token)
+    }
+
+    /// Sends a request-to-pay with `reference_id` as both its `X-Reference-Id`
+    /// and external id. The `mtnmomo` client cannot set `X-Callback-Url`.
+    async fn request_to_pay(
+        &self,
+        reference_id: &str,
+        request: &PaymentInitiation,
+        phone_number: String,
+    ) -> Result<(), String> {
+        let token = self.access_token().await.map_err(|e| e.to_string())?;
+        let mut http_request = self
+            .client
+            .post(format!("{}/collection/v1_0/requesttopay", self.momo.url))
+            .bearer_auth(token)
+            .header("X-Reference-Id", reference_id)
+            .header("X-Target-Environment", self.momo.environment.to_string())
+            .header("Ocp-Apim-Subscription-Key", &self.collection_primary_key)
+            .json(&json!({
+                "amount": request.amount.to_string(),
+                "currency": self.currency,
+                "externalId": reference_id,
+                "payer": {"partyIdType": "MSISDN", "partyId": phone_number},
+                "payerMessage": request.payer_message,
+                "payeeNote": request.payee_note,
+            }));
+        if let Some(callback_url) = &self.callback_url {
+            http_request = http_request.header("X-Callback-Url", callback_url.as_str());
+        }
+
+        let response = http_request.send().await.map_err(|e| e.to_string())?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            let status = response.status();
+            Err(format!(
+                "{}: {}",
+                status,
+                response.text().await.unwrap_or_default()
+            ))
+        }
+    }
+}
+
+/// Creates a sandbox API user and key. MoMo only delivers callbacks to the
+/// host the user was created with, so it is taken from the callback URL.
+async fn provision_api_user(
+    client: &Client,
+    mtn_url: &str,
+    subscription_key: &str,
+    callback_url: Option<&Url>,
+) -> Result<(String, String), reqwest::Error> {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct ApiKey {
+        api_key: String,
+    }
+
+    let api_user = Uuid::new_v4().to_string();
+    let callback_host = callback_url.and_then(Url::host_str).unwrap_or("localhost");
+    client
+        .post(format!("{}/v1_0/apiuser", mtn_url))
+        .header("X-Reference-Id", &api_user)
+        .header("Ocp-Apim-Subscription-Key", subscription_key)
+        .json(&json!({"providerCallbackHost": callback_host}))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let key: ApiKey = client
+        .post(format!("{}/v1_0/apiuser/{}/apikey", mtn_url, api_user))
+        .header("Ocp-Apim-Subscription-Key", subscription_key)
+        .header("Content-Length", "0")
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok((api_user, key.api_key))
+}
+
+#[async_trait(?Send)]
+impl PaymentGateway for MomoGateway {
+    fn currency(&self) -> &str {
+        &self.currency
+    }
+
+    async fn initiate(&self, request: &PaymentInitiation) -> Result<InitiatedPayment, AppError> {
+        let phone_number = request
+            .phone_number
+            .clone()
+            .ok_or_else(|| AppError::BadRequest("Phone number is required".into()))?;
+        if !phone_number.starts_with("237") || phone_number.len() != 12 {
+            return Err(AppError::BadRequest("Invalid phone number format".into()));
+        }
+
+        let reference_id = Uuid::new_v4().to_string();
+        match self
+            .request_to_pay(&reference_id, request, phone_number)
+            .await
+        {
+            Ok(()) => Ok(InitiatedPayment {
+                provider_reference_id: reference_id,
+                provider_status: "PENDING".to_string(),
+                approval_url: None,
+            }),
+            Err(e) => {
+                println!("MTN API call failed: {}", e);
+                Err(AppError::InternalServerError(format!(
+                    "Payment request failed: {}",
+                    e
+                )))
+            }
+        }
+    }
+
+    async fn query_status(&self, payment: &PaymentModel) -> Result<GatewayStatus, AppError> {
+        let collection = self.momo.collection(
+            self.collection_primary_key.clone(),
+            self.collection_secondary_key.clone(),
+        );
+
+        let result = collection
+            .request_to_pay_transaction_status(&payment.provider_reference_id)
+            .await
+            .map_err(|e| {
+                AppError::InternalServerError(format!("MTN status check failed: {}", e))
+            })?;
+
+        println!("MTN Status response: {:?}", result);
+
+        Ok(GatewayStatus {
+            status: match result.status.as_str() {
+                "SUCCESSFUL" => PaymentStatus::Successful,
+                "FAILED" => PaymentStatus::Failed,
+                "CANCELLED" => PaymentStatus::Cancelled,
+                _ => PaymentStatus::Pending,
+            },
+            details: json!({
+                "financial_transaction_id": result.financial_transaction_id
+            }),
+            provider_status: result.status,
+        })
+    }
+
+    async fn refund(
+        &self,
+        payment: &PaymentModel,
+        amount: Decimal,
+    ) -> Result<RefundResult, AppError> {
+        let (primary_key, secondary_key) = self
+            .disbursement_keys
+            .clone()
+            .ok_or_else(|| AppError::BadRequest("MTN MoMo refunds are not configured".into()))?;
+
+        let disbursement = self.momo.disbursement(primary_key, secondary_key);
+        let refund = RefundRequest::new(
+            amount.to_string(),
+            payment.currency.clone(),
+            "Refund".to_string(),
+            format!("Refund of {}", payment.reference_id),
+            payment.provider_reference_id.clone(),
+        );
+
+        let refund_id = disbursement
+            .refund_v1(refund, None)
+            .await
+            .map_err(|e| AppError::InternalServerError(format!("MTN refund failed: {}", e)))?;
+
+        Ok(RefundResult {
+            provider_refund_id: refund_id.as_string(),
+            provider_status: "PENDING".to_string(),
+        })
+    }
+
+    fn parse_webhook(&self, body: &[u8]) -> Result<WebhookEvent, AppError> {
+        let callback: MomoCallback = serde_json::from_slice(body)
+            .map_err(|e| AppError::BadRequest(format!("Invalid callback body: {}", e)))?;
+
+        Ok(WebhookEvent {
+            provider_reference_id: callback.external_id,
+            provider_status: callback.status,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+    use std::env;
+    use std::sync::{Arc, Mutex};
+
+    /// Requests the mock server received: path, `X-Callback-Url` and body.
+    type Received = Arc<Mutex<Vec<(String, Option<String>, serde_json::Value)>>>;
+
+    /// The `MTN_*` variables are process wide, so gateways are built one at a
+    /// time.
+    static ENV: Mutex<()> = Mutex::new(());
+
+    /// Starts an MTN MoMo stand-in on a free local port that accepts every
+    /// request-to-pay.
+    fn mock_momo(received: Received) -> String {
+        let server = HttpServer::new(move || {
+            let received = received.clone();
+            App::new().default_service(web::to(move |request: HttpRequest, body: web::Bytes| {
+                let received = received.clone();
+                async move {
+                    let path = request.path().to_string();
+                    let callback_url = request
+                        .headers()
+                        .get("X-Callback-Url")
+                        .and_then(|value| value.to_str().ok())
+                        .map(str::to_string);
+                    let body = serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null);
+                    received
+                        .lock()
+                        .unwrap()
+                        .push((path.clone(), callback_url, body));
+
+                    match path.as_str() {
+                        "/v1_0/apiuser" => HttpResponse::Created().finish(),
+                        "/collection/token/" => HttpResponse::Ok().json(json!({
+                            "access_token": "token",
+                            "token_type": "access_token",
+                            "expires_in": 3600
+                        })),
+                        "/collection/v1_0/requesttopay" => HttpResponse::Accepted().finish(),
+                        path if path.ends_with("/apikey") => {
+                            HttpResponse::Created().json(json!({"apiKey": "key"}))
+                        }
+                        _ => HttpResponse::NotFound().finish(),
+                    }
+                }
+            }))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let address = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+        format!("http://{}", address)
+    }
+
+    async fn gateway(base_url: &str, callback_url: Option<&str>) -> MomoGateway {
+        {
+            let _guard = ENV.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            env::set_var("MTN_COLLECTION_PRIMARY_KEY", "primary");
+            env::set_var("MTN_COLLECTION_SECONDARY_KEY", "secondary");
+            env::set_var("MTN_CURRENCY", "EUR");
+            match callback_url {
+                Some(url) => env::set_var("MTN_CALLBACK_URL", url),
+                None => env::remove_var("MTN_CALLBACK_URL"),
+            }
+        }
+        MomoGateway::new(base_url.to_string()).await.unwrap()
+    }
+
+    fn initiation() -> PaymentInitiation {
+        PaymentInitiation {
+            amount: Decimal::new(500, 0),
+            phone_number: Some("237670000000".to_string()),
+            payer_message: "Premium plan".to_string(),
+            payee_note: "pdf-shelf".to_string(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn request_to_pay_asks_for_a_callback() {
+        let received = Received::default();
+        let base_url = mock_momo(received.clone());
+        let gateway = gateway(
+            &base_url,
+            Some("https://shelf.example.com/api/payments/callback"),
+        )
+        .await;
+
+        let initiated = gateway.initiate(&initiation()).await.unwrap();
+
+        let received = received.lock().unwrap();
+        // The API user may only call back to the host it was created with
+        assert_eq!(received[0].0, "/v1_0/apiuser");
+        assert_eq!(
+            received[0].2,
+            json!({"providerCallbackHost": "shelf.example.com"})
+        );
+
+        let (path, callback_url, body) = received.last().unwrap();
+        assert_eq!(path, "/collection/v1_0/requesttopay");
+        assert_eq!(
+            callback_url.as_deref(),
+            Some("https://shelf.example.com/api/payments/callback")
+        );
+        // The callback names the payment by its external id
+        assert_eq!(body["externalId"], initiated.provider_reference_id.as_str());
+        assert_eq!(body["amount"], "500");
+        assert_eq!(body["currency"], "EUR");
+        assert_eq!(body["payer"]["partyId"], "237670000000");
+    }
+
+    #[actix_web::test]
+    async fn request_to_pay_without_callback_url_is_polled_only() {
+        let received = Received::default();
+        let gateway = gateway(&mock_momo(received.clone()), None).await;
+
+        gateway.initiate(&initiation()).await.unwrap();
+
+        let received = received.lock().unwrap();
+        let (path, callback_url, _) = received.last().unwrap();
+        assert_eq!(path, "/collection/v1_0/requesttopay");
+        assert_eq!(callback_url, &None);
+    }
+
+    #[test]
+    fn callback_body_names_the_payment() {
+        let body = br#"{"financialTransactionId":"1","externalId":"ref-1","amount":"500","currency":"EUR","status":"SUCCESSFUL"}"#;
+        let callback: MomoCallback = serde_json::from_slice(body).unwrap();
+        assert_eq!(callback.external_id, "ref-1");
+        assert_eq!(callback.status, "SUCCESSFUL");
+    }
+}
@@ -0,0 +1,502 @@
+use super::{
+    GatewayStatus, InitiatedPayment, PaymentGateway, PaymentInitiation, RefundResult, WebhookEvent,
+};
+use crate::{
+    error::AppError,
+    models::payment::{Model as PaymentModel, PaymentStatus},
+};
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder};
+use sea_orm::prelude::Decimal;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const PAYPAL_SANDBOX_URL: &str = "https://api-m.sandbox.paypal.com";
+
+/// PayPal Checkout through the Orders v2 API. The payer approves the order on
+/// PayPal; the order is captured the first time its status is checked after
+/// approval.
+///
+/// The API base URL comes from `PAYPAL_API_URL`, so the gateway can be pointed
+/// at a local mock server.
+pub struct PayPalGateway {
+    client: Client,
+    base_url: String,
+    client_id: String,
+    client_secret: String,
+    currency: String,
+    return_url: Option<String>,
+    cancel_url: Option<String>,
+    token: Mutex<Option<(String, Instant)>>,
+}
+
+#[derive(Deserialize)]
+struct AccessToken {
+    access_token: String,
+    expires_in: u64,
+}
+
+#[derive(Deserialize)]
+struct Order {
+    id: String,
+    status: String,
+    #[serde(default)]
+    links: Vec<Link>,
+    #[serde(default)]
+    purchase_units: Vec<PurchaseUnit>,
+}
+
+#[derive(Deserialize)]
+struct Link {
+    href: String,
+    rel: String,
+}
+
+#[derive(Deserialize)]
+struct PurchaseUnit {
+    payments: Option<PurchaseUnitPayments>,
+}
+
+#[derive(Deserialize)]
+struct PurchaseUnitPayments {
+    #[serde(default)]
+    captures: Vec<Capture>,
+}
+
+#[derive(Deserialize)]
+struct Capture {
+    id: String,
+    status: String,
+}
+
+#[derive(Deserialize)]
+struct Refund {
+    id: String,
+    status: String,
+}
+
+#[derive(Deserialize)]
+struct WebhookBody {
+    event_type: String,
+    resource: Value,
+}
+
+impl Order {
+    fn capture(&self) -> Option<&Capture> {
+        self.purchase_units
+            .iter()
+            .filter_map(|unit| unit.payments.as_ref())
+            .flat_map(|payments| payments.captures.iter())
+            .next()
+    }
+}
+
+impl PayPalGateway {
+    pub fn new(client_id: String) -> Result<Self, AppError> {
+        let client_secret = super::setting("PAYPAL_CLIENT_SECRET")
+            .ok_or_else(|| AppError::BadRequest("PAYPAL_CLIENT_SECRET must be set".into()))?;
+        let currency = super::currency_from_env("PAYPAL_CURRENCY")?;
+
+        Ok(Self {
+            client: Client::new(),
+            base_url: super::setting("PAYPAL_API_URL")
+                .unwrap_or_else(|| PAYPAL_SANDBOX_URL.to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            client_id,
+            client_secret,
+            currency,
+            return_url: super::setting("PAYPAL_RETURN_URL"),
+            cancel_url: super::setting("PAYPAL_CANCEL_URL"),
+            token: Mutex::new(None),
+        })
+    }
+
+    /// Returns a cached OAuth token, fetching a new one shortly before the
+    /// current one expires.
+    async fn access_token(&self) -> Result<String, AppError> {
+        if let Some((token, expires_at)) = self.token.lock().unwrap().as_ref() {
+            if Instant::now() < *expires_at {
+                return Ok(token.clone());
+            }
+        }
+
+        let response = self
+            .client
+            .post(format!("{}/v1/oauth2/token", self.base_url))
+            .basic_auth(&self.client_id, Some(&self.client_secret))
+            .form(&[("grant_type", "client_credentials")])
+            .send()
+            .await
+            .map_err(|e| AppError::InternalServerError(format!("PayPal auth failed: {}", e)))?;
+        let token: AccessToken = read_json(response, "PayPal auth").await?;
+
+        let expires_at = Instant::now() + Duration::from_secs(token.expires_in.saturating_sub(60));
+        *self.token.lock().unwrap() = Some((token.access_token.clone(), expires_at));
+
+        Ok(token.access_token)
+    }
+
+    async fn send<T: for<'de> Deserialize<'de>>(
+        &self,
+        request: RequestBuilder,
+        action: &str,
+    ) -> Result<T, AppError> {
+        let token = self.access_token().await?;
+        let response = request
+            .bearer_auth(token)
+            .send()
+            .await
+            .map_err(|e| AppError::InternalServerError(format!("{} failed: {}", action, e)))?;
+
+        read_json(response, action).await
+    }
+
+    async fn get_order(&self, order_id: &str) -> Result<Order, AppError> {
+        self.send(
+            self.client
+                .get(format!("{}/v2/checkout/orders/{}", self.base_url, order_id)),
+            "PayPal order lookup",
+        )
+        .await
+    }
+
+    /// Captures an approved order. The request id makes retries safe: PayPal
+    /// returns the original capture instead of charging twice.
+    async fn capture_order(&self, order_id: &str, request_id: &str) -> Result<Order, AppError> {
+        self.send(
+            self.client
+                .post(format!(
+                    "{}/v2/checkout/orders/{}/capture",
+                    self.base_url, order_id
+                ))
+                .header("PayPal-Request-Id", request_id)
+                .json(&json!({})),
+            "PayPal capture",
+        )
+        .await
+    }
+}
+
+async fn read_json<T: for<'de> Deserialize<'de>>(
+    response: reqwest::Response,
+    action: &str,
+) -> Result<T, AppError> {
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        println!("{} returned {}: {}", action, status, body);
+        return Err(AppError::InternalServerError(format!(
+            "{} failed with status {}",
+            action, status
+        )));
+    }
+
+    response
+        .json()
+        .await
+        .map_err(|e| AppError::InternalServerError(format!("Invalid {} response: {}", action, e)))
+}
+
+#[async_trait(?Send)]
+impl PaymentGateway for PayPalGateway {
+    fn currency(&self) -> &str {
+        &self.currency
+    }
+
+    async fn initiate(&self, request: &PaymentInitiation) -> Result<InitiatedPayment, AppError> {
+        let mut body = json!({
+            "intent": "CAPTURE",
+            "purchase_units": [{
+                "description": request.payee_note,
+                "amount": {
+                    "currency_code": self.currency,
+                    "value": format!("{:.2}", request.amount)
+                }
+            }]
+        });
+        if let (Some(return_url), Some(cancel_url)) = (&self.return_url, &self.cancel_url) {
+            body["application_context"] = json!({
+                "return_url": return_url,
+                "cancel_url": cancel_url,
+                "user_action": "PAY_NOW"
+            });
+        }
+
+        let order: Order = self
+            .send(
+                self.client
+                    .post(format!("{}/v2/checkout/orders", self.base_url))
+                    .json(&body),
+                "PayPal order creation",
+            )
+            .await?;
+
+        let approval_url = order
+            .links
+            .iter()
+            .find(|link| link.rel == "approve" || link.rel == "payer-action")
+            .map(|link| link.href.clone());
+
+        Ok(InitiatedPayment {
+            provider_reference_id: order.id,
+            provider_status: order.status,
+            approval_url,
+        })
+    }
+
+    async fn query_status(&self, payment: &PaymentModel) -> Result<GatewayStatus, AppError> {
+        let mut order = self.get_order(&payment.provider_reference_id).await?;
+        if order.status == "APPROVED" {
+            order = self
+                .capture_order(&payment.provider_reference_id, &payment.reference_id)
+                .await?;
+        }
+
+        let capture = order.capture();
+        let status = match (order.status.as_str(), capture.map(|c| c.status.as_str())) {
+            ("COMPLETED", Some("COMPLETED")) => PaymentStatus::Successful,
+            ("COMPLETED", Some("DECLINED" | "FAILED")) => PaymentStatus::Failed,
+            ("VOIDED", _) => PaymentStatus::Cancelled,
+            _ => PaymentStatus::Pending,
+        };
+
+        Ok(GatewayStatus {
+            status,
+            details: json!({
+                "order_status": order.status,
+                "capture_id": capture.map(|c| c.id.clone()),
+                "capture_status": capture.map(|c| c.status.clone())
+            }),
+            provider_status: capture
+                .map(|c| c.status.clone())
+                .unwrap_or_else(|| order.status.clone()),
+        })
+    }
+
+    async fn refund(
+        &self,
+        payment: &PaymentModel,
+        amount: Decimal,
+    ) -> Result<RefundResult, AppError> {
+        let capture_id = payment
+            .provider_response
+            .as_ref()
+            .and_then(|response| response["details"]["capture_id"].as_str())
+            .ok_or_else(|| AppError::BadRequest("Payment has no PayPal capture".into()))?;
+
+        let body = json!({
+            "amount": {
+                "currency_code": payment.currency,
+                "value": format!("{:.2}", amount)
+            }
+        });
+
+        let refund: Refund = self
+            .send(
+                self.client
+                    .post(format!(
+                        "{}/v2/payments/captures/{}/refund",
+                        self.base_url, capture_id
+                    ))
+                    .json(&body),
+                "PayPal refund",
+            )
+            .await?;
+
+        Ok(RefundResult {
+            provider_refund_id: refund.id,
+            provider_status: refund.status,
+        })
+    }
+
+    /// Accepts order events (`CHECKOUT.ORDER.*`, resource is the order) and
+    /// capture events (`PAYMENT.CAPTURE.*`, resource is the capture, linked to
+    /// its order through `supplementary_data`).
+    fn parse_webhook(&self, body: &[u8]) -> Result<WebhookEvent, AppError> {
+        let webhook: WebhookBody = serde_json::from_slice(body)
+            .map_err(|e| AppError::BadRequest(format!("Invalid webhook body: {}", e)))?;
+
+        let order_id = if webhook.event_type.starts_with("PAYMENT.CAPTURE.") {
+            webhook.resource["supplementary_data"]["related_ids"]["order_id"].as_str()
+        } else {
+            webhook.resource["id"].as_str()
+        };
+
+        let order_id = order_id.ok_or_else(|| {
+            AppError::BadRequest(format!(
+                "Webhook {} does not reference an order",
+                webhook.event_type
+            ))
+        })?;
+
+        Ok(WebhookEvent {
+            provider_reference_id: order_id.to_string(),
+            provider_status: webhook.event_type,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+    use std::env;
+    use std::sync::Arc;
+
+    /// Requests the mock server received: path, `PayPal-Request-Id` and body.
+    type Received = Arc<Mutex<Vec<(String, Option<String>, Value)>>>;
+
+    /// `PAYPAL_API_URL` is process wide, so gateways are built one at a time.
+    static ENV: Mutex<()> = Mutex::new(());
+
+    /// Starts a PayPal stand-in on a free local port. Order `ORDER-OK` is
+    /// approved and captures successfully; any other order fails with 503.
+    fn mock_paypal(received: Received) -> String {
+        let server = HttpServer::new(move || {
+            let received = received.clone();
+            App::new().default_service(web::to(
+                move |request: HttpRequest, body: web::Bytes| {
+                    let received = received.clone();
+                    async move {
+                        let path = request.path().to_string();
+                        let request_id = request
+                            .headers()
+                            .get("PayPal-Request-Id")
+                            .and_then(|value| value.to_str().ok())
+                            .map(str::to_string);
+                        let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+                        received.lock().unwrap().push((path.clone(), request_id, body));
+
+                        match path.as_str() {
+                            "/v1/oauth2/token" => HttpResponse::Ok()
+                                .json(json!({"access_token": "token", "expires_in": 3600})),
+                            "/v2/checkout/orders/ORDER-OK" => HttpResponse::Ok()
+                                .json(json!({"id": "ORDER-OK", "status": "APPROVED"})),
+                            "/v2/checkout/orders/ORDER-OK/capture" => HttpResponse::Ok().json(json!({
+                                "id": "ORDER-OK",
+                                "status": "COMPLETED",
+                                "purchase_units": [{
+                                    "payments": {"captures": [{"id": "CAPTURE-1", "status": "COMPLETED"}]}
+                                }]
+                            })),
+                            "/v2/payments/captures/CAPTURE-1/refund" => HttpResponse::Created()
+                                .json(json!({"id": "REFUND-1", "status": "COMPLETED"})),
+                            _ => HttpResponse::ServiceUnavailable().finish(),
+                        }
+                    }
+                },
+            ))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let address = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+        format!("http://{}", address)
+    }
+
+    fn gateway(base_url: &str) -> PayPalGateway {
+        let _guard = ENV.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        env::set_var("PAYPAL_API_URL", base_url);
+        env::set_var("PAYPAL_CLIENT_SECRET", "secret");
+        PayPalGateway::new("client".to_string()).unwrap()
+    }
+
+    fn payment(order_id: &str, provider_response: Option<Value>) -> PaymentModel {
+        let now = chrono::Utc::now().fixed_offset();
+        PaymentModel {
+            id: 1,
+            user_id: 1,
+            plan_id: 2,
+            reference_id: format!("{}_1700000000000", order_id),
+            provider_reference_id: order_id.to_string(),
+            amount: Decimal::new(1000, 2),
+            refunded_amount: Decimal::ZERO,
+            currency: "EUR".to_string(),
+            phone_number: None,
+            provider: crate::models::payment::PaymentProvider::PayPal,
+            status: PaymentStatus::Pending,
+            provider_response,
+            error_message: None,
+            metadata: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    #[actix_web::test]
+    async fn approved_order_is_captured_once() {
+        let received = Received::default();
+        let gateway = gateway(&mock_paypal(received.clone()));
+        let payment = payment("ORDER-OK", None);
+
+        let status = gateway.query_status(&payment).await.unwrap();
+        assert_eq!(status.status, PaymentStatus::Successful);
+        assert_eq!(status.details["capture_id"], "CAPTURE-1");
+
+        let received = received.lock().unwrap();
+        let paths: Vec<&str> = received.iter().map(|(path, _, _)| path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "/v1/oauth2/token",
+                "/v2/checkout/orders/ORDER-OK",
+                "/v2/checkout/orders/ORDER-OK/capture"
+            ]
+        );
+        // Retried captures carry the same id, so PayPal charges only once
+        assert_eq!(
+            received[2].1.as_deref(),
+            Some(payment.reference_id.as_str())
+        );
+    }
+
+    #[actix_web::test]
+    async fn provider_failure_is_an_error_not_a_status() {
+        let received = Received::default();
+        let gateway = gateway(&mock_paypal(received.clone()));
+
+        let result = gateway.query_status(&payment("ORDER-DOWN", None)).await;
+        assert!(matches!(result, Err(AppError::InternalServerError(_))));
+    }
+
+    #[actix_web::test]
+    async fn refund_sends_the_amount_for_the_capture() {
+        let received = Received::default();
+        let gateway = gateway(&mock_paypal(received.clone()));
+        let payment = payment(
+            "ORDER-OK",
+            Some(json!({"details": {"capture_id": "CAPTURE-1"}})),
+        );
+
+        let refund = gateway
+            .refund(&payment, Decimal::new(450, 2))
+            .await
+            .unwrap();
+        assert_eq!(refund.provider_refund_id, "REFUND-1");
+        assert_eq!(refund.provider_status, "COMPLETED");
+
+        let received = received.lock().unwrap();
+        let (path, _, body) = received.last().unwrap();
+        assert_eq!(path, "/v2/payments/captures/CAPTURE-1/refund");
+        assert_eq!(
+            body,
+            &json!({"amount": {"currency_code": "EUR", "value": "4.50"}})
+        );
+    }
+
+    #[actix_web::test]
+    async fn refund_without_capture_is_refused_locally() {
+        let received = Received::default();
+        let gateway = gateway(&mock_paypal(received.clone()));
+
+        let result = gateway
+            .refund(&payment("ORDER-OK", None), Decimal::ONE)
+            .await;
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+        assert!(received.lock().unwrap().is_empty());
+    }
+}
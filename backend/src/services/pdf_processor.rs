@@ -0,0 +1,2809 @@
+//! A small PDF reader: enough of the file structure to walk the page tree,
+//! read the document metadata and pull the text out of content streams. It
+//! does not render anything.
+
+use chrono::{FixedOffset, TimeZone};
+use derive_more::Display;
+use flate2::read::{DeflateDecoder, ZlibDecoder};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::io::Read;
+use std::rc::Rc;
+
+/// Limit on reference chains, page tree depth, nested forms and nested
+/// arrays, so crafted files cannot recurse forever.
+const MAX_DEPTH: usize = 32;
+/// Largest decoded stream, against decompression bombs.
+const MAX_STREAM_SIZE: u64 = 64 * 1024 * 1024;
+/// Text kept per page. Postgres refuses to index much more than this.
+const MAX_PAGE_TEXT: usize = 256 * 1024;
+/// A gap in a `TJ` array wider than this (thousandths of an em) is a space.
+const WORD_GAP: f64 = 180.0;
+/// Bytes fetched at a time from files read in parts.
+const WINDOW_LEN: usize = 256 * 1024;
+/// Windows of that size kept for later objects.
+const MAX_WINDOWS: usize = 16;
+/// End of a file read in parts searched for `startxref`. The specification
+/// puts it in the last 1024 bytes; some writers append junk after `%%EOF`.
+const TAIL_LEN: usize = 4096;
+
+#[derive(Debug, Display)]
+pub enum PdfError {
+    #[display(fmt = "Not a PDF file")]
+    NotPdf,
+
+    #[display(fmt = "PDF is encrypted")]
+    Encrypted,
+
+    #[display(fmt = "Malformed PDF: {}", _0)]
+    Malformed(String),
+
+    #[display(fmt = "Unsupported PDF feature: {}", _0)]
+    Unsupported(String),
+
+    #[display(fmt = "Cannot read the PDF: {}", _0)]
+    Read(String),
+}
+
+impl Error for PdfError {}
+
+fn malformed(message: impl Into<String>) -> PdfError {
+    PdfError::Malformed(message.into())
+}
+
+pub type Dict = HashMap<String, Object>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Object {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Real(f64),
+    String(Vec<u8>),
+    Name(String),
+    Array(Vec<Object>),
+    Dict(Dict),
+    Stream(Dict, Vec<u8>), // Dictionary and the still encoded data
+    Ref(u32, u16),
+}
+
+impl Object {
+    pub fn as_int(&self) -> Option<i64> {
+        match *self {
+            Object::Int(n) => Some(n),
+            Object::Real(n) => Some(n as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Object::Int(n) => Some(n as f64),
+            Object::Real(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn as_name(&self) -> Option<&str> {
+        match self {
+            Object::Name(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Object::String(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Object]> {
+        match self {
+            Object::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// The dictionary of a dictionary or stream.
+    pub fn as_dict(&self) -> Option<&Dict> {
+        match self {
+            Object::Dict(dict) | Object::Stream(dict, _) => Some(dict),
+            _ => None,
+        }
+    }
+}
+
+enum Token {
+    Object(Object),
+    Keyword(Vec<u8>),
+    ArrayStart,
+    ArrayEnd,
+    DictStart,
+    DictEnd,
+}
+
+fn is_whitespace(b: u8) -> bool {
+    matches!(b, 0 | b'\t' | b'\n' | 0x0c | b'\r' | b' ')
+}
+
+fn is_delimiter(b: u8) -> bool {
+    matches!(
+        b,
+        b'(' | b')' | b'<' | b'>' | b'[' | b']' | b'{' | b'}' | b'/' | b'%'
+    )
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|at| at + from)
+}
+
+fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .rposition(|window| window == needle)
+}
+
+/// Tokenizer shared by file structure, content streams and CMaps.
+struct Lexer<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Self { data, pos }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).copied()
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        self.data.get(self.pos..).unwrap_or_default()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b) = self.peek() {
+            if is_whitespace(b) {
+                self.pos += 1;
+            } else if b == b'%' {
+                while !matches!(self.peek(), None | Some(b'\n') | Some(b'\r')) {
+                    self.pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn token(&mut self) -> Result<Option<Token>, PdfError> {
+        self.skip_whitespace();
+        let Some(b) = self.peek() else {
+            return Ok(None);
+        };
+        let next = self.data.get(self.pos + 1).copied();
+
+        let token = match b {
+            b'/' => {
+                self.pos += 1;
+                Token::Object(Object::Name(self.name()))
+            }
+            b'(' => {
+                self.pos += 1;
+                Token::Object(Object::String(self.literal_string()?))
+            }
+            b'<' if next == Some(b'<') => {
+                self.pos += 2;
+                Token::DictStart
+            }
+            b'<' => {
+                self.pos += 1;
+                Token::Object(Object::String(self.hex_string()?))
+            }
+            b'>' if next == Some(b'>') => {
+                self.pos += 2;
+                Token::DictEnd
+            }
+            b'[' => {
+                self.pos += 1;
+                Token::ArrayStart
+            }
+            b']' => {
+                self.pos += 1;
+                Token::ArrayEnd
+            }
+            b'0'..=b'9' | b'+' | b'-' | b'.' => Token::Object(self.number()),
+            _ => match self.regular().as_slice() {
+                b"true" => Token::Object(Object::Bool(true)),
+                b"false" => Token::Object(Object::Bool(false)),
+                b"null" => Token::Object(Object::Null),
+                word => Token::Keyword(word.to_vec()),
+            },
+        };
+        Ok(Some(token))
+    }
+
+    /// A run of regular characters. A stray delimiter is returned on its
+    /// own, so the lexer always moves forward.
+    fn regular(&mut self) -> Vec<u8> {
+        let start = self.pos;
+        while let Some(b) = self.peek() {
+            if is_whitespace(b) || is_delimiter(b) {
+                break;
+            }
+            self.pos += 1;
+        }
+        if self.pos == start {
+            self.pos += 1;
+        }
+        self.data[start..self.pos].to_vec()
+    }
+
+    fn number(&mut self) -> Object {
+        let word = self.regular();
+        let text = String::from_utf8_lossy(&word);
+        if let Ok(n) = text.parse::<i64>() {
+            return Object::Int(n);
+        }
+        // Writers produce oddities such as "--5" or "1.2.3"; read what makes sense
+        let cleaned: String = text.trim_start_matches(['+', '-']).to_string();
+        let sign = if text.starts_with('-') { -1.0 } else { 1.0 };
+        let value = cleaned
+            .split('.')
+            .take(2)
+            .collect::<Vec<_>>()
+            .join(".")
+            .parse::<f64>()
+            .unwrap_or(0.0);
+        Object::Real(sign * value)
+    }
+
+    fn name(&mut self) -> String {
+        let raw = self.regular_or_empty();
+        let mut bytes = Vec::with_capacity(raw.len());
+        let mut i = 0;
+        while i < raw.len() {
+            if raw[i] == b'#' {
+                if let Some(b) = raw
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                {
+                    bytes.push(b);
+                    i += 3;
+                    continue;
+                }
+            }
+            bytes.push(raw[i]);
+            i += 1;
+        }
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    /// Like `regular`, but an empty name ("/" alone) is allowed.
+    fn regular_or_empty(&mut self) -> Vec<u8> {
+        let start = self.pos;
+        while let Some(b) = self.peek() {
+            if is_whitespace(b) || is_delimiter(b) {
+                break;
+            }
+            self.pos += 1;
+        }
+        self.data[start..self.pos].to_vec()
+    }
+
+    fn literal_string(&mut self) -> Result<Vec<u8>, PdfError> {
+        let mut bytes = Vec::new();
+        let mut depth = 1;
+        loop {
+            let b = self
+                .peek()
+                .ok_or_else(|| malformed("unterminated string"))?;
+            self.pos += 1;
+            match b {
+                b'(' => {
+                    depth += 1;
+                    bytes.push(b);
+                }
+                b')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(bytes);
+                    }
+                    bytes.push(b);
+                }
+                b'\\' => {
+                    let Some(escaped) = self.peek() else {
+                        continue;
+                    };
+                    self.pos += 1;
+                    match escaped {
+                        b'n' => bytes.push(b'\n'),
+                        b'r' => bytes.push(b'\r'),
+                        b't' => bytes.push(b'\t'),
+                        b'b' => bytes.push(0x08),
+                        b'f' => bytes.push(0x0c),
+                        b'0'..=b'7' => {
+                            let mut value = u32::from(escaped - b'0');
+                            for _ in 0..2 {
+                                match self.peek() {
+                                    Some(digit @ b'0'..=b'7') => {
+                                        value = value * 8 + u32::from(digit - b'0');
+                                        self.pos += 1;
+                                    }
+                                    _ => break,
+                                }
+                            }
+                            bytes.push(value as u8);
+                        }
+                        // A backslash at the end of a line continues the string
+                        b'\r' => {
+                            if self.peek() == Some(b'\n') {
+                                self.pos += 1;
+                            }
+                        }
+                        b'\n' => {}
+                        other => bytes.push(other),
+                    }
+                }
+                _ => bytes.push(b),
+            }
+        }
+    }
+
+    fn hex_string(&mut self) -> Result<Vec<u8>, PdfError> {
+        let mut digits = Vec::new();
+        loop {
+            let b = self
+                .peek()
+                .ok_or_else(|| malformed("unterminated hex string"))?;
+            self.pos += 1;
+            match b {
+                b'>' => break,
+                b if is_whitespace(b) => {}
+                b if b.is_ascii_hexdigit() => digits.push(b),
+                _ => return Err(malformed("invalid hex string")),
+            }
+        }
+        if digits.len() % 2 == 1 {
+            digits.push(b'0');
+        }
+        Ok(digits
+            .chunks(2)
+            .map(|pair| {
+                let hex = std::str::from_utf8(pair).unwrap_or("00");
+                u8::from_str_radix(hex, 16).unwrap_or(0)
+            })
+            .collect())
+    }
+
+    fn object(&mut self) -> Result<Object, PdfError> {
+        let token = self
+            .token()?
+            .ok_or_else(|| malformed("unexpected end of data"))?;
+        self.value(token, 0)
+    }
+
+    /// Completes an object from its first token: arrays, dictionaries and
+    /// `n g R` references span several tokens.
+    fn value(&mut self, token: Token, depth: usize) -> Result<Object, PdfError> {
+        if depth > MAX_DEPTH {
+            return Err(malformed("objects nested too deeply"));
+        }
+        match token {
+            Token::Object(Object::Int(num)) => {
+                let start = self.pos;
+                if let (Ok(num), Ok(Some(Token::Object(Object::Int(generation))))) =
+                    (u32::try_from(num), self.token())
+                {
+                    if let (Ok(generation), Ok(Some(Token::Keyword(keyword)))) =
+                        (u16::try_from(generation), self.token())
+                    {
+                        if keyword == b"R" {
+                            return Ok(Object::Ref(num, generation));
+                        }
+                    }
+                }
+                self.pos = start;
+                Ok(Object::Int(num))
+            }
+            Token::Object(object) => Ok(object),
+            Token::ArrayStart => {
+                let mut items = Vec::new();
+                loop {
+                    match self.token()? {
+                        Some(Token::ArrayEnd) => return Ok(Object::Array(items)),
+                        Some(token) => items.push(self.value(token, depth + 1)?),
+                        None => return Err(malformed("unterminated array")),
+                    }
+                }
+            }
+            Token::DictStart => {
+                let mut dict = Dict::new();
+                loop {
+                    match self.token()? {
+                        Some(Token::DictEnd) => return Ok(Object::Dict(dict)),
+                        Some(Token::Object(Object::Name(key))) => {
+                            let token = self
+                                .token()?
+                                .ok_or_else(|| malformed("unterminated dictionary"))?;
+                            if let Token::DictEnd = token {
+                                // A key without a value; treat it as null
+                                return Ok(Object::Dict(dict));
+                            }
+                            let value = self.value(token, depth + 1)?;
+                            dict.insert(key, value);
+                        }
+                        Some(_) => return Err(malformed("dictionary key is not a name")),
+                        None => return Err(malformed("unterminated dictionary")),
+                    }
+                }
+            }
+            Token::Keyword(keyword) => Err(malformed(format!(
+                "unexpected keyword {}",
+                String::from_utf8_lossy(&keyword)
+            ))),
+            Token::ArrayEnd | Token::DictEnd => Err(malformed("unbalanced brackets")),
+        }
+    }
+}
+
+/// Parses `n g obj ... endobj` at `offset`, including any stream data.
+fn indirect_object(data: &[u8], offset: usize) -> Result<(u32, Object), PdfError> {
+    let mut lexer = Lexer::new(data, offset);
+    let num = match lexer.token()? {
+        Some(Token::Object(Object::Int(num))) => {
+            u32::try_from(num).map_err(|_| malformed("invalid object number"))?
+        }
+        _ => return Err(malformed(format!("no object at offset {}", offset))),
+    };
+    match (lexer.token()?, lexer.token()?) {
+        (Some(Token::Object(Object::Int(_))), Some(Token::Keyword(keyword)))
+            if keyword == b"obj" => {}
+        _ => return Err(malformed(format!("no object at offset {}", offset))),
+    }
+
+    let object = lexer.object()?;
+    lexer.skip_whitespace();
+    let Object::Dict(dict) = object else {
+        return Ok((num, object));
+    };
+    if !lexer.rest().starts_with(b"stream") {
+        return Ok((num, Object::Dict(dict)));
+    }
+
+    // The keyword is followed by CRLF or LF, though some writers use a lone CR
+    let mut start = lexer.pos + b"stream".len();
+    if data.get(start) == Some(&b'\r') {
+        start += 1;
+    }
+    if data.get(start) == Some(&b'\n') {
+        start += 1;
+    }
+
+    // /Length is often an indirect object or simply wrong; trust it only
+    // when endstream follows it
+    let declared = dict
+        .get("Length")
+        .and_then(Object::as_int)
+        .and_then(|length| usize::try_from(length).ok())
+        .map(|length| start.saturating_add(length))
+        .filter(|&end| {
+            end <= data.len() && {
+                let mut after = Lexer::new(data, end);
+                after.skip_whitespace();
+                after.rest().starts_with(b"endstream")
+            }
+        });
+    let end = match declared {
+        Some(end) => end,
+        None => {
+            let mut end =
+                find(data, b"endstream", start).ok_or_else(|| malformed("unterminated stream"))?;
+            if end > start && data[end - 1] == b'\n' {
+                end -= 1;
+            }
+            if end > start && data[end - 1] == b'\r' {
+                end -= 1;
+            }
+            end
+        }
+    };
+
+    Ok((num, Object::Stream(dict, data[start..end].to_vec())))
+}
+
+/// A cross-reference section as found in the file.
+enum XrefSection {
+    /// In-use entries of an `xref` table, in order, and the trailer after it.
+    Table(Vec<(u32, usize)>, Dict),
+    /// A cross-reference stream, still encoded.
+    Stream(Dict, Vec<u8>),
+}
+
+/// Parses the cross-reference table or stream at `offset`.
+fn xref_section(data: &[u8], offset: usize) -> Result<XrefSection, PdfError> {
+    let mut lexer = Lexer::new(data, offset);
+    lexer.skip_whitespace();
+
+    if lexer.rest().starts_with(b"xref") {
+        lexer.pos += b"xref".len();
+        let int = |lexer: &mut Lexer| match lexer.token()? {
+            Some(Token::Object(Object::Int(n))) if n >= 0 => Ok(n as u64),
+            _ => Err(malformed("invalid cross-reference table")),
+        };
+        let mut entries = Vec::new();
+        loop {
+            let start = lexer.pos;
+            if let Some(Token::Keyword(keyword)) = lexer.token()? {
+                if keyword == b"trailer" {
+                    return match lexer.object()? {
+                        Object::Dict(dict) => Ok(XrefSection::Table(entries, dict)),
+                        _ => Err(malformed("invalid trailer")),
+                    };
+                }
+            }
+            lexer.pos = start;
+
+            let first = int(&mut lexer)?;
+            let count = int(&mut lexer)?;
+            for num in first..first + count {
+                let entry_offset = int(&mut lexer)?;
+                int(&mut lexer)?;
+                let in_use = match lexer.token()? {
+                    Some(Token::Keyword(kind)) if kind == b"n" => true,
+                    Some(Token::Keyword(kind)) if kind == b"f" => false,
+                    _ => return Err(malformed("invalid cross-reference entry")),
+                };
+                if in_use && entry_offset > 0 {
+                    let num = u32::try_from(num).map_err(|_| malformed("invalid object number"))?;
+                    entries.push((num, entry_offset as usize));
+                }
+            }
+        }
+    }
+
+    match indirect_object(data, lexer.pos)? {
+        (_, Object::Stream(dict, raw)) => Ok(XrefSection::Stream(dict, raw)),
+        _ => Err(malformed("cross-reference is neither a table nor a stream")),
+    }
+}
+
+enum XrefEntry {
+    Offset(usize),
+    Compressed(u32), // Number of the object stream holding it
+}
+
+/// The decoded body of an object stream.
+struct ObjectStream {
+    data: Vec<u8>,
+    offsets: HashMap<u32, usize>,
+}
+
+/// What a PDF says about itself. Descriptive fields come from XMP metadata
+/// where present, and from the document information dictionary otherwise.
+#[derive(Debug, Default, Serialize)]
+pub struct PdfInfo {
+    pub version: String,
+    pub encrypted: bool,
+    pub linearized: bool,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    pub keywords: Option<String>,
+    pub creator: Option<String>,  // Application the document was made in
+    pub producer: Option<String>, // Application that wrote the PDF
+    pub created_at: Option<String>, // RFC 3339
+    pub modified_at: Option<String>,
+}
+
+/// Reads byte ranges of a file too large to hold in memory.
+pub trait RangeReader {
+    /// `len` bytes from `offset`. The range always lies within the file.
+    fn read(&self, offset: u64, len: usize) -> Result<Vec<u8>, PdfError>;
+}
+
+enum Source<'a> {
+    Memory(&'a [u8]),
+    Ranged {
+        reader: Box<dyn RangeReader + 'a>,
+        len: usize,
+        /// Recently read windows and their offsets, oldest first.
+        windows: RefCell<Vec<(usize, Rc<Vec<u8>>)>>,
+    },
+}
+
+/// A parsed PDF file. Objects are read lazily, from borrowed data or from
+/// a [`RangeReader`].
+pub struct Pdf<'a> {
+    source: Source<'a>,
+    /// From the `%PDF-x.y` header.
+    pub version: String,
+    pub trailer: Dict,
+    /// The cross-reference data was missing or broken, and objects were
+    /// found by scanning the file instead.
+    pub repaired: bool,
+    xref: HashMap<u32, XrefEntry>,
+    object_streams: RefCell<HashMap<u32, Rc<ObjectStream>>>,
+    /// Object streams being decoded, so one that needs itself is caught.
+    opening: RefCell<HashSet<u32>>,
+}
+
+impl<'a> Pdf<'a> {
+    pub fn load(data: &'a [u8]) -> Result<Self, PdfError> {
+        Self::open(Source::Memory(data))
+    }
+
+    /// Opens a file of `len` bytes that is read a window at a time. Only
+    /// the parts needed are fetched, so its cross-references must be
+    /// intact: repairing them would mean reading the whole file.
+    pub fn load_ranged(reader: impl RangeReader + 'a, len: u64) -> Result<Self, PdfError> {
+        let len = usize::try_from(len).map_err(|_| malformed("file too large"))?;
+        Self::open(Source::Ranged {
+            reader: Box::new(reader),
+            len,
+            windows: RefCell::new(Vec::new()),
+        })
+    }
+
+    fn open(source: Source<'a>) -> Result<Self, PdfError> {
+        let mut pdf = Pdf {
+            source,
+            version: String::new(),
+            trailer: Dict::new(),
+            repaired: false,
+            xref: HashMap::new(),
+            object_streams: RefCell::new(HashMap::new()),
+            opening: RefCell::new(HashSet::new()),
+        };
+        pdf.version = header_version(&pdf.head()?).ok_or(PdfError::NotPdf)?;
+
+        if let Source::Ranged { .. } = pdf.source {
+            pdf.read_xref()?;
+            return Ok(pdf);
+        }
+        // Stale or broken cross-references are common in the wild
+        if pdf.read_xref().is_err() {
+            pdf.xref.clear();
+            pdf.trailer.clear();
+            pdf.object_streams.borrow_mut().clear();
+            pdf.scan_objects()?;
+            pdf.repaired = true;
+        }
+        Ok(pdf)
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.trailer.contains_key("Encrypt")
+    }
+
+    /// Size of the file in bytes.
+    fn len(&self) -> usize {
+        match &self.source {
+            Source::Memory(data) => data.len(),
+            Source::Ranged { len, .. } => *len,
+        }
+    }
+
+    /// `len` bytes from `offset`, or fewer at the end of the file.
+    fn read(&self, offset: usize, len: usize) -> Result<Cow<'a, [u8]>, PdfError> {
+        let end = offset.saturating_add(len).min(self.len());
+        match &self.source {
+            Source::Memory(data) => Ok(Cow::Borrowed(data.get(offset..end).unwrap_or_default())),
+            Source::Ranged { reader, .. } if offset < end => {
+                Ok(Cow::Owned(reader.read(offset as u64, end - offset)?))
+            }
+            Source::Ranged { .. } => Ok(Cow::Borrowed(&[])),
+        }
+    }
+
+    /// The first kilobyte, where the header and any linearization
+    /// dictionary are.
+    fn head(&self) -> Result<Cow<'a, [u8]>, PdfError> {
+        self.read(0, 1024)
+    }
+
+    /// Runs `parse` on the file from `offset`. Files read in parts are
+    /// parsed from a window, which grows while `parse` fails for want of data.
+    fn parse_at<T>(
+        &self,
+        offset: usize,
+        parse: impl Fn(&[u8], usize) -> Result<T, PdfError>,
+    ) -> Result<T, PdfError> {
+        let (reader, len, windows) = match &self.source {
+            Source::Memory(data) => return parse(data, offset),
+            Source::Ranged {
+                reader,
+                len,
+                windows,
+            } => (reader, *len, windows),
+        };
+        if offset >= len {
+            return Err(malformed(format!(
+                "offset {} past the end of the file",
+                offset
+            )));
+        }
+
+        let cached = windows
+            .borrow()
+            .iter()
+            .rev()
+            .find(|(start, window)| (*start..*start + window.len()).contains(&offset))
+            .cloned();
+        if let Some((start, window)) = cached {
+            match parse(&window, offset - start) {
+                Ok(parsed) => return Ok(parsed),
+                Err(e) if start + window.len() == len => return Err(e),
+                Err(_) => {}
+            }
+        }
+
+        let mut window_len = WINDOW_LEN;
+        loop {
+            let end = offset.saturating_add(window_len).min(len);
+            let window = Rc::new(reader.read(offset as u64, end - offset)?);
+            let parsed = parse(&window, 0);
+            if window_len == WINDOW_LEN {
+                let mut windows = windows.borrow_mut();
+                if windows.len() == MAX_WINDOWS {
+                    windows.remove(0);
+                }
+                windows.push((offset, window));
+            }
+            // A larger object than any stream may decode to is not read
+            if parsed.is_ok() || end == len || window_len as u64 >= MAX_STREAM_SIZE {
+                return parsed;
+            }
+            window_len *= 4;
+        }
+    }
+
+    fn read_xref(&mut self) -> Result<(), PdfError> {
+        // startxref comes last, so a file read in parts is searched from its end
+        let tail = match self.source {
+            Source::Memory(data) => Cow::Borrowed(data),
+            Source::Ranged { len, .. } => self.read(len.saturating_sub(TAIL_LEN), TAIL_LEN)?,
+        };
+        let at = rfind(&tail, b"startxref").ok_or_else(|| malformed("missing startxref"))?;
+        let mut lexer = Lexer::new(&tail, at + b"startxref".len());
+        let mut offset = match lexer.token()? {
+            Some(Token::Object(Object::Int(offset))) => {
+                usize::try_from(offset).map_err(|_| malformed("invalid startxref"))?
+            }
+            _ => return Err(malformed("invalid startxref")),
+        };
+
+        // Incremental updates chain sections through /Prev, newest first
+        let mut seen = HashSet::new();
+        loop {
+            if !seen.insert(offset) || seen.len() > MAX_DEPTH {
+                return Err(malformed("cross-reference sections loop"));
+            }
+            let trailer = self.read_xref_section(offset)?;
+            // Hybrid files list their compressed objects in an extra stream
+            if let Some(stream) = trailer.get("XRefStm").and_then(Object::as_int) {
+                let stream = usize::try_from(stream).map_err(|_| malformed("invalid /XRefStm"))?;
+                self.read_xref_section(stream)?;
+            }
+            let prev = trailer.get("Prev").and_then(Object::as_int);
+            if self.trailer.is_empty() {
+                self.trailer = trailer;
+            }
+            match prev.and_then(|prev| usize::try_from(prev).ok()) {
+                Some(prev) => offset = prev,
+                None => break,
+            }
+        }
+
+        if !self.trailer.contains_key("Root") {
+            return Err(malformed("trailer has no /Root"));
+        }
+        Ok(())
+    }
+
+    /// Reads one cross-reference table or stream and returns its trailer
+    /// dictionary. Entries already known from a newer section win.
+    fn read_xref_section(&mut self, offset: usize) -> Result<Dict, PdfError> {
+        let (dict, raw) = match self.parse_at(offset, xref_section)? {
+            XrefSection::Table(entries, trailer) => {
+                for (num, entry_offset) in entries {
+                    self.xref
+                        .entry(num)
+                        .or_insert(XrefEntry::Offset(entry_offset));
+                }
+                return Ok(trailer);
+            }
+            XrefSection::Stream(dict, raw) => (dict, raw),
+        };
+        if dict.get("Type").and_then(Object::as_name) != Some("XRef") {
+            return Err(malformed("cross-reference stream has the wrong type"));
+        }
+        let data = self.decode(&dict, &raw)?;
+
+        let widths: Vec<usize> = dict
+            .get("W")
+            .and_then(Object::as_array)
+            .map(|widths| {
+                widths
+                    .iter()
+                    .map(|w| w.as_int().unwrap_or(0).clamp(0, 8) as usize)
+                    .collect()
+            })
+            .filter(|widths: &Vec<usize>| widths.len() == 3)
+            .ok_or_else(|| malformed("cross-reference stream has no /W"))?;
+        let row_len: usize = widths.iter().sum();
+        if row_len == 0 {
+            return Err(malformed("cross-reference stream has empty rows"));
+        }
+        let size = dict.get("Size").and_then(Object::as_int).unwrap_or(0);
+        let index: Vec<i64> = match dict.get("Index").and_then(Object::as_array) {
+            Some(index) => index.iter().filter_map(Object::as_int).collect(),
+            None => vec![0, size],
+        };
+
+        let mut rows = data.chunks_exact(row_len);
+        for range in index.chunks_exact(2) {
+            let (first, count) = (range[0].max(0) as u64, range[1].max(0) as u64);
+            for num in first..first + count {
+                let Some(row) = rows.next() else {
+                    break;
+                };
+                let mut fields = [0u64; 3];
+                let mut at = 0;
+                for (field, &width) in fields.iter_mut().zip(&widths) {
+                    *field = row[at..at + width]
+                        .iter()
+                        .fold(0, |value, &b| value << 8 | u64::from(b));
+                    at += width;
+                }
+                // A missing type field means "in use"
+                let kind = if widths[0] == 0 { 1 } else { fields[0] };
+                let Ok(num) = u32::try_from(num) else {
+                    continue;
+                };
+                match kind {
+                    1 => {
+                        self.xref
+                            .entry(num)
+                            .or_insert(XrefEntry::Offset(fields[1] as usize));
+                    }
+                    2 => {
+                        self.xref
+                            .entry(num)
+                            .or_insert(XrefEntry::Compressed(fields[1] as u32));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(dict)
+    }
+
+    /// Rebuilds the cross-references by looking for `n g obj` throughout the
+    /// file. Later definitions win, as they would with incremental updates.
+    fn scan_objects(&mut self) -> Result<(), PdfError> {
+        let Source::Memory(data) = self.source else {
+            return Err(malformed("cannot scan a file read in parts"));
+        };
+        let mut at = 0;
+        while let Some(found) = find(data, b"obj", at) {
+            at = found + 3;
+            if !data
+                .get(at)
+                .map_or(true, |&b| is_whitespace(b) || is_delimiter(b))
+            {
+                continue;
+            }
+            if let Some((num, start)) = object_header_before(data, found) {
+                self.xref.insert(num, XrefEntry::Offset(start));
+            }
+        }
+
+        // Objects inside object streams do not show up in the scan
+        let mut nums: Vec<u32> = self.xref.keys().copied().collect();
+        nums.sort_unstable();
+        let mut xref_trailer = None;
+        for &num in &nums {
+            let Ok(Object::Stream(dict, _)) = self.object(num) else {
+                continue;
+            };
+            match dict.get("Type").and_then(Object::as_name) {
+                Some("ObjStm") => {
+                    if let Ok(stream) = self.object_stream(num) {
+                        for &contained in stream.offsets.keys() {
+                            self.xref
+                                .entry(contained)
+                                .or_insert(XrefEntry::Compressed(num));
+                        }
+                    }
+                }
+                Some("XRef") if dict.contains_key("Root") => xref_trailer = Some(dict),
+                _ => {}
+            }
+        }
+
+        let keyword_trailer = rfind(data, b"trailer").and_then(|at| {
+            match Lexer::new(data, at + b"trailer".len()).object() {
+                Ok(Object::Dict(dict)) if dict.contains_key("Root") => Some(dict),
+                _ => None,
+            }
+        });
+        if let Some(trailer) = keyword_trailer.or(xref_trailer) {
+            self.trailer = trailer;
+            return Ok(());
+        }
+
+        // No trailer survived; the catalog is enough to read the document
+        let mut nums: Vec<u32> = self.xref.keys().copied().collect();
+        nums.sort_unstable();
+        for num in nums.into_iter().rev() {
+            if let Ok(Object::Dict(dict)) = self.object(num) {
+                if dict.get("Type").and_then(Object::as_name) == Some("Catalog") {
+                    self.trailer.insert("Root".into(), Object::Ref(num, 0));
+                    return Ok(());
+                }
+            }
+        }
+        Err(malformed("no document catalog"))
+    }
+
+    /// Object `num`, or null when the file does not define it.
+    pub fn object(&self, num: u32) -> Result<Object, PdfError> {
+        match self.xref.get(&num) {
+            None => Ok(Object::Null),
+            Some(XrefEntry::Offset(offset)) => {
+                let (found, object) = self.parse_at(*offset, indirect_object)?;
+                if found != num {
+                    return Err(malformed(format!(
+                        "object {} expected at offset {}, found {}",
+                        num, offset, found
+                    )));
+                }
+                Ok(object)
+            }
+            Some(XrefEntry::Compressed(stream)) => {
+                let stream = self.object_stream(*stream)?;
+                let offset = *stream
+                    .offsets
+                    .get(&num)
+                    .ok_or_else(|| malformed(format!("object {} missing from its stream", num)))?;
+                Lexer::new(&stream.data, offset).object()
+            }
+        }
+    }
+
+    fn object_stream(&self, num: u32) -> Result<Rc<ObjectStream>, PdfError> {
+        if let Some(stream) = self.object_streams.borrow().get(&num) {
+            return Ok(stream.clone());
+        }
+        // Stored in itself, or in a stream whose /Filter is
+        if !self.opening.borrow_mut().insert(num) {
+            return Err(malformed(format!("object stream {} contains itself", num)));
+        }
+        let stream = self.read_object_stream(num);
+        self.opening.borrow_mut().remove(&num);
+
+        let stream = Rc::new(stream?);
+        self.object_streams.borrow_mut().insert(num, stream.clone());
+        Ok(stream)
+    }
+
+    fn read_object_stream(&self, num: u32) -> Result<ObjectStream, PdfError> {
+        let Object::Stream(dict, raw) = self.object(num)? else {
+            return Err(malformed(format!("object {} is not an object stream", num)));
+        };
+        let data = self.decode(&dict, &raw)?;
+        let count = dict.get("N").and_then(Object::as_int).unwrap_or(0);
+        let first = dict
+            .get("First")
+            .and_then(Object::as_int)
+            .and_then(|first| usize::try_from(first).ok())
+            .ok_or_else(|| malformed("object stream has no /First"))?;
+
+        let mut offsets = HashMap::new();
+        let mut lexer = Lexer::new(&data, 0);
+        for _ in 0..count {
+            match (lexer.token()?, lexer.token()?) {
+                (
+                    Some(Token::Object(Object::Int(contained))),
+                    Some(Token::Object(Object::Int(offset))),
+                ) if contained >= 0 && offset >= 0 => {
+                    let offset = usize::try_from(offset)
+                        .ok()
+                        .and_then(|offset| first.checked_add(offset))
+                        .ok_or_else(|| malformed("object stream offset out of range"))?;
+                    offsets.insert(contained as u32, offset);
+                }
+                _ => break,
+            }
+        }
+
+        Ok(ObjectStream { data, offsets })
+    }
+
+    /// Follows references until a direct object is reached.
+    pub fn resolve(&self, object: &Object) -> Result<Object, PdfError> {
+        let mut object = object.clone();
+        for _ in 0..MAX_DEPTH {
+            match object {
+                Object::Ref(num, _) => object = self.object(num)?,
+                object => return Ok(object),
+            }
+        }
+        Err(malformed("reference chain too long"))
+    }
+
+    /// Entry `key` of `dict`, resolved; null when absent.
+    pub fn get(&self, dict: &Dict, key: &str) -> Result<Object, PdfError> {
+        dict.get(key)
+            .map_or(Ok(Object::Null), |object| self.resolve(object))
+    }
+
+    /// Applies the stream's filters to its raw data.
+    pub fn decode(&self, dict: &Dict, raw: &[u8]) -> Result<Vec<u8>, PdfError> {
+        let filters: Vec<String> = match self.get(dict, "Filter")? {
+            Object::Name(name) => vec![name],
+            Object::Array(names) => names
+                .iter()
+                .filter_map(|name| self.resolve(name).ok()?.as_name().map(String::from))
+                .collect(),
+            _ => Vec::new(),
+        };
+        let params: Vec<Option<Dict>> = match self.get(dict, "DecodeParms")? {
+            Object::Dict(params) => vec![Some(params)],
+            Object::Array(params) => params
+                .iter()
+                .map(|params| match self.resolve(params) {
+                    Ok(Object::Dict(params)) => Some(params),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let mut data = raw.to_vec();
+        for (i, filter) in filters.iter().enumerate() {
+            let params = params.get(i).and_then(Option::as_ref);
+            data = match filter.as_str() {
+                "FlateDecode" | "Fl" => predict(inflate(&data)?, params)?,
+                "LZWDecode" | "LZW" => {
+                    let early_change = params
+                        .and_then(|params| params.get("EarlyChange"))
+                        .and_then(Object::as_int)
+                        .unwrap_or(1);
+                    predict(lzw_decode(&data, early_change != 0)?, params)?
+                }
+                "ASCIIHexDecode" | "AHx" => ascii_hex_decode(&data)?,
+                "ASCII85Decode" | "A85" => ascii85_decode(&data)?,
+                other => return Err(PdfError::Unsupported(format!("{} filter", other))),
+            };
+        }
+        Ok(data)
+    }
+
+    /// The document catalog, the root of the object graph.
+    pub fn catalog(&self) -> Result<Dict, PdfError> {
+        match self.get(&self.trailer, "Root")? {
+            Object::Dict(catalog) => Ok(catalog),
+            _ => Err(malformed("missing document catalog")),
+        }
+    }
+
+    /// Every page dictionary in order, with inherited attributes such as
+    /// /Resources and /MediaBox filled in from their ancestors.
+    pub fn pages(&self) -> Result<Vec<Dict>, PdfError> {
+        let catalog = self.catalog()?;
+        let root = catalog
+            .get("Pages")
+            .ok_or_else(|| malformed("catalog has no page tree"))?;
+
+        let mut pages = Vec::new();
+        let mut visited = HashSet::new();
+        self.collect_pages(root, &Dict::new(), &mut pages, &mut visited, 0)?;
+        Ok(pages)
+    }
+
+    /// Version, encryption, linearization and descriptive metadata. Strings
+    /// of encrypted files cannot be read, so those only get the first three.
+    pub fn info(&self) -> PdfInfo {
+        let catalog = self.catalog().ok();
+        let mut info = PdfInfo {
+            version: self.version.clone(),
+            encrypted: self.is_encrypted(),
+            linearized: self.is_linearized(),
+            ..Default::default()
+        };
+
+        // Since PDF 1.4 the catalog may declare a newer version than the header
+        let declared = catalog
+            .as_ref()
+            .and_then(|catalog| catalog.get("Version"))
+            .and_then(Object::as_name);
+        if let Some(declared) = declared {
+            let number = |version: &str| version.parse::<f64>().unwrap_or(0.0);
+            if number(declared) > number(&info.version) {
+                info.version = declared.to_string();
+            }
+        }
+        if info.encrypted {
+            return info;
+        }
+
+        if let Ok(Object::Dict(dict)) = self.get(&self.trailer, "Info") {
+            let text = |key: &str| match self.get(&dict, key) {
+                Ok(Object::String(bytes)) => clean(text_string(&bytes)),
+                _ => None,
+            };
+            info.title = text("Title");
+            info.author = text("Author");
+            info.subject = text("Subject");
+            info.keywords = text("Keywords");
+            info.creator = text("Creator");
+            info.producer = text("Producer");
+            info.created_at = text("CreationDate").and_then(|date| pdf_date(&date));
+            info.modified_at = text("ModDate").and_then(|date| pdf_date(&date));
+        }
+
+        let Some(xmp) = catalog.and_then(|catalog| self.xmp(&catalog)) else {
+            return info;
+        };
+        let value = |property: &str| clean(xmp_values(&xmp, property).join(", "));
+        let date = |property: &str| {
+            value(property)
+                .and_then(|date| chrono::DateTime::parse_from_rfc3339(&date).ok())
+                .map(|date| date.to_rfc3339())
+        };
+        info.title = value("dc:title").or(info.title);
+        info.author = value("dc:creator").or(info.author);
+        info.subject = value("dc:description").or(info.subject);
+        info.keywords = value("pdf:Keywords").or(info.keywords);
+        info.creator = value("xmp:CreatorTool").or(info.creator);
+        info.producer = value("pdf:Producer").or(info.producer);
+        info.created_at = date("xmp:CreateDate").or(info.created_at);
+        info.modified_at = date("xmp:ModifyDate").or(info.modified_at);
+        info
+    }
+
+    /// The XMP packet referenced by the catalog, if any.
+    fn xmp(&self, catalog: &Dict) -> Option<String> {
+        match self.get(catalog, "Metadata") {
+            Ok(Object::Stream(dict, raw)) => {
+                let data = self.decode(&dict, &raw).ok()?;
+                Some(String::from_utf8_lossy(&data).into_owned())
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the file is laid out for page-at-a-time loading. The
+    /// linearization dictionary must be the first object, and stops
+    /// applying once an update is appended and the length no longer matches.
+    fn is_linearized(&self) -> bool {
+        let Ok(head) = self.head() else {
+            return false;
+        };
+        let Some((_, start)) =
+            find(&head, b"obj", 0).and_then(|at| object_header_before(&head, at))
+        else {
+            return false;
+        };
+        match self.parse_at(start, indirect_object) {
+            Ok((_, Object::Dict(dict))) => {
+                dict.contains_key("Linearized")
+                    && dict.get("L").and_then(Object::as_int) == Some(self.len() as i64)
+            }
+            _ => false,
+        }
+    }
+
+    fn collect_pages(
+        &self,
+        node: &Object,
+        inherited: &Dict,
+        pages: &mut Vec<Dict>,
+        visited: &mut HashSet<u32>,
+        depth: usize,
+    ) -> Result<(), PdfError> {
+        if depth > MAX_DEPTH {
+            return Err(malformed("page tree nested too deeply"));
+        }
+        // A node reachable twice would make pages appear twice, or loop
+        if let Object::Ref(num, _) = node {
+            if !visited.insert(*num) {
+                return Ok(());
+            }
+        }
+        let Object::Dict(mut node) = self.resolve(node)? else {
+            return Ok(());
+        };
+
+        let mut attributes = inherited.clone();
+        for key in ["Resources", "MediaBox", "CropBox", "Rotate"] {
+            if let Some(value) = node.get(key) {
+                attributes.insert(key.into(), value.clone());
+            }
+        }
+
+        let is_page = node.get("Type").and_then(Object::as_name) == Some("Page");
+        match self.get(&node, "Kids")? {
+            Object::Array(kids) if !is_page => {
+                for kid in &kids {
+                    self.collect_pages(kid, &attributes, pages, visited, depth + 1)?;
+                }
+            }
+            _ => {
+                for (key, value) in attributes {
+                    node.entry(key).or_insert(value);
+                }
+                pages.push(node);
+            }
+        }
+        Ok(())
+    }
+
+    /// Text of each page, in content stream order. Pages whose content
+    /// cannot be read come back empty.
+    pub fn page_texts(&self) -> Result<Vec<String>, PdfError> {
+        if self.is_encrypted() {
+            return Err(PdfError::Encrypted);
+        }
+        Ok(self
+            .pages()?
+            .iter()
+            .map(|page| self.page_text(page).unwrap_or_default())
+            .collect())
+    }
+
+    fn page_text(&self, page: &Dict) -> Result<String, PdfError> {
+        let content = match self.get(page, "Contents")? {
+            Object::Stream(dict, raw) => self.decode(&dict, &raw)?,
+            // Content split across streams is concatenated
+            Object::Array(parts) => {
+                let mut content = Vec::new();
+                for part in &parts {
+                    if let Object::Stream(dict, raw) = self.resolve(part)? {
+                        content.extend(self.decode(&dict, &raw)?);
+                        content.push(b'\n');
+                    }
+                }
+                content
+            }
+            _ => Vec::new(),
+        };
+        let resources = match self.get(page, "Resources")? {
+            Object::Dict(resources) => resources,
+            _ => Dict::new(),
+        };
+
+        let mut text = TextWriter::default();
+        self.run_content(&content, &resources, &mut text, 0)?;
+        Ok(text.finish())
+    }
+
+    /// Interprets the text operators of a content stream. Graphics are
+    /// ignored except for form XObjects, which can contain text of their own.
+    fn run_content(
+        &self,
+        content: &[u8],
+        resources: &Dict,
+        text: &mut TextWriter,
+        depth: usize,
+    ) -> Result<(), PdfError> {
+        let font_dict = match self.get(resources, "Font")? {
+            Object::Dict(fonts) => fonts,
+            _ => Dict::new(),
+        };
+        let mut fonts: HashMap<String, Rc<Font>> = HashMap::new();
+        let mut font: Option<Rc<Font>> = None;
+        let mut line_y: Option<f64> = None;
+
+        let mut lexer = Lexer::new(content, 0);
+        let mut operands: Vec<Object> = Vec::new();
+        // Broken content keeps the text read up to the damage
+        while let Ok(Some(token)) = lexer.token() {
+            let operator = match token {
+                Token::Keyword(operator) => operator,
+                token => {
+                    match lexer.value(token, 0) {
+                        Ok(operand) => operands.push(operand),
+                        Err(_) => operands.clear(),
+                    }
+                    continue;
+                }
+            };
+
+            match operator.as_slice() {
+                b"Tf" => {
+                    font = operands.first().and_then(Object::as_name).map(|name| {
+                        fonts
+                            .entry(name.to_string())
+                            .or_insert_with(|| {
+                                let dict = font_dict
+                                    .get(name)
+                                    .and_then(|font| self.resolve(font).ok())
+                                    .and_then(|font| font.as_dict().cloned())
+                                    .unwrap_or_default();
+                                Rc::new(Font::load(self, &dict))
+                            })
+                            .clone()
+                    });
+                }
+                b"Tj" | b"'" | b"\"" => {
+                    if operator != b"Tj" {
+                        text.newline();
+                    }
+                    if let (Some(font), Some(bytes)) =
+                        (&font, operands.last().and_then(Object::as_bytes))
+                    {
+                        text.push(&font.decode(bytes));
+                    }
+                }
+                b"TJ" => {
+                    let items = operands
+                        .first()
+                        .and_then(Object::as_array)
+                        .unwrap_or_default();
+                    for item in items {
+                        match item {
+                            Object::String(bytes) => {
+                                if let Some(font) = &font {
+                                    text.push(&font.decode(bytes));
+                                }
+                            }
+                            gap => {
+                                if gap.as_f64().is_some_and(|gap| gap < -WORD_GAP) {
+                                    text.space();
+                                }
+                            }
+                        }
+                    }
+                }
+                b"Td" | b"TD" => {
+                    let ty = operands.get(1).and_then(Object::as_f64).unwrap_or(0.0);
+                    if ty.abs() > 0.01 {
+                        text.newline();
+                    } else {
+                        text.space();
+                    }
+                }
+                b"T*" => text.newline(),
+                b"Tm" => {
+                    let y = operands.get(5).and_then(Object::as_f64);
+                    match (line_y, y) {
+                        (Some(previous), Some(y)) if (previous - y).abs() < 0.5 => text.space(),
+                        _ => text.newline(),
+                    }
+                    line_y = y;
+                }
+                b"BT" | b"ET" => text.space(),
+                b"Do" if depth < MAX_DEPTH => {
+                    if let Some(name) = operands.first().and_then(Object::as_name) {
+                        self.run_form(name, resources, text, depth)?;
+                    }
+                }
+                // Inline image data is binary and runs up to the EI keyword
+                b"ID" => {
+                    let data_start = lexer.pos + 1;
+                    let mut at = data_start;
+                    lexer.pos = content.len();
+                    while let Some(found) = find(content, b"EI", at) {
+                        let before = found
+                            .checked_sub(1)
+                            .map_or(true, |i| is_whitespace(content[i]));
+                        let after = content.get(found + 2).map_or(true, |&b| is_whitespace(b));
+                        if before && after {
+                            lexer.pos = found + 2;
+                            break;
+                        }
+                        at = found + 2;
+                    }
+                }
+                _ => {}
+            }
+            operands.clear();
+        }
+        Ok(())
+    }
+
+    fn run_form(
+        &self,
+        name: &str,
+        resources: &Dict,
+        text: &mut TextWriter,
+        depth: usize,
+    ) -> Result<(), PdfError> {
+        let Object::Dict(xobjects) = self.get(resources, "XObject")? else {
+            return Ok(());
+        };
+        let Object::Stream(dict, raw) = self.get(&xobjects, name)? else {
+            return Ok(());
+        };
+        if dict.get("Subtype").and_then(Object::as_name) != Some("Form") {
+            return Ok(());
+        }
+
+        let content = self.decode(&dict, &raw)?;
+        let form_resources = match self.get(&dict, "Resources")? {
+            Object::Dict(form_resources) => form_resources,
+            _ => resources.clone(),
+        };
+        self.run_content(&content, &form_resources, text, depth + 1)
+    }
+}
+
+/// Version from the `%PDF-x.y` header, which may follow some junk bytes.
+fn header_version(data: &[u8]) -> Option<String> {
+    let head = &data[..data.len().min(1024)];
+    let at = find(head, b"%PDF-", 0)? + b"%PDF-".len();
+    let version: String = head[at..]
+        .iter()
+        .take_while(|b| b.is_ascii_digit() || **b == b'.')
+        .map(|&b| b as char)
+        .collect();
+    (!version.is_empty()).then_some(version)
+}
+
+/// A text string outside content streams: UTF-16BE or UTF-8 behind a byte
+/// order mark, or else PDFDocEncoding, which agrees with WinAnsi on nearly
+/// every character that turns up in metadata.
+fn text_string(bytes: &[u8]) -> String {
+    if let Some(utf16) = bytes.strip_prefix(&[0xfe, 0xff]) {
+        return utf16_be(utf16);
+    }
+    if let Some(utf8) = bytes.strip_prefix(&[0xef, 0xbb, 0xbf]) {
+        return String::from_utf8_lossy(utf8).into_owned();
+    }
+    let encoding = base_encoding(Some("WinAnsiEncoding"));
+    bytes
+        .iter()
+        .filter_map(|&b| encoding[usize::from(b)])
+        .collect()
+}
+
+/// Drops control characters, such as UTF-16 language escapes, and empty values.
+fn clean(text: String) -> Option<String> {
+    let text: String = text.chars().filter(|c| !c.is_control()).collect();
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// A `D:YYYYMMDDHHmmSSOHH'mm` date as RFC 3339. Everything after the year
+/// is optional; without an offset the time is taken as UTC.
+fn pdf_date(date: &str) -> Option<String> {
+    let date = date.trim();
+    let date = date.strip_prefix("D:").unwrap_or(date);
+    let digits_len = date.bytes().take(14).take_while(u8::is_ascii_digit).count();
+    let (digits, zone) = date.split_at(digits_len);
+    if digits.len() < 4 {
+        return None;
+    }
+    let field = |start: usize, len: usize, default: u32| {
+        digits
+            .get(start..start + len)
+            .map_or(Some(default), |value| value.parse().ok())
+    };
+
+    let mut offset = 0;
+    if let Some(sign @ ('+' | '-')) = zone.chars().next() {
+        let zone: Vec<i32> = zone[1..]
+            .split(|c: char| !c.is_ascii_digit())
+            .filter(|part| !part.is_empty())
+            .take(2)
+            .map(|part| part.parse().unwrap_or(0))
+            .collect();
+        offset = zone.first().unwrap_or(&0) * 3600 + zone.get(1).unwrap_or(&0) * 60;
+        if sign == '-' {
+            offset = -offset;
+        }
+    }
+
+    FixedOffset::east_opt(offset)?
+        .with_ymd_and_hms(
+            field(0, 4, 0)? as i32,
+            field(4, 2, 1)?,
+            field(6, 2, 1)?,
+            field(8, 2, 0)?,
+            field(10, 2, 0)?,
+            field(12, 2, 0)?,
+        )
+        .single()
+        .map(|date| date.to_rfc3339())
+}
+
+/// Values of an XMP property, written either as an element, whose
+/// `rdf:li` items are the values when it holds an array, or as an
+/// attribute of `rdf:Description`. This is a scan, not an XML parser, but
+/// XMP packets are machine-written and regular.
+fn xmp_values(xmp: &str, property: &str) -> Vec<String> {
+    let mut from = 0;
+    while let Some(found) = xmp[from..].find(property) {
+        let at = from + found;
+        from = at + property.len();
+        let rest = &xmp[from..];
+
+        match xmp[..at].chars().next_back() {
+            Some('<') if rest.starts_with(|c: char| c == '>' || c.is_whitespace()) => {
+                let Some(tag_end) = rest.find('>') else {
+                    break;
+                };
+                if rest[..tag_end].ends_with('/') {
+                    continue;
+                }
+                let content = &rest[tag_end + 1..];
+                let content = &content[..content
+                    .find(&format!("</{}>", property))
+                    .unwrap_or(content.len())];
+                if !content.contains("<rdf:li") {
+                    return vec![xml_unescape(content.trim())];
+                }
+                return content
+                    .split("<rdf:li")
+                    .skip(1)
+                    .filter_map(|item| {
+                        let item = &item[item.find('>')? + 1..];
+                        let item = &item[..item.find("</rdf:li>").unwrap_or(item.len())];
+                        Some(xml_unescape(item.trim()))
+                    })
+                    .collect();
+            }
+            Some(c) if c.is_whitespace() => {
+                let Some(value) = rest.trim_start().strip_prefix('=') else {
+                    continue;
+                };
+                let value = value.trim_start();
+                let Some(quote) = value.chars().next().filter(|&c| c == '"' || c == '\'') else {
+                    continue;
+                };
+                let value = &value[1..];
+                let end = value.find(quote).unwrap_or(value.len());
+                return vec![xml_unescape(&value[..end])];
+            }
+            _ => {}
+        }
+    }
+    Vec::new()
+}
+
+/// Resolves the predefined entities and character references.
+fn xml_unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let Some(semi) = rest.find(';').filter(|&semi| semi <= 10) else {
+            out.push('&');
+            rest = &rest[1..];
+            continue;
+        };
+        let decoded = match &rest[1..semi] {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            entity => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[semi + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The number and start of an `n g obj` header ending just before `at`.
+fn object_header_before(data: &[u8], at: usize) -> Option<(u32, usize)> {
+    let mut i = at;
+    let skip_back = |i: &mut usize, f: fn(u8) -> bool| {
+        let end = *i;
+        while *i > 0 && f(data[*i - 1]) {
+            *i -= 1;
+        }
+        end - *i
+    };
+
+    if skip_back(&mut i, is_whitespace) == 0 || skip_back(&mut i, |b| b.is_ascii_digit()) == 0 {
+        return None;
+    }
+    if skip_back(&mut i, is_whitespace) == 0 {
+        return None;
+    }
+    let num_end = i;
+    if skip_back(&mut i, |b| b.is_ascii_digit()) == 0 {
+        return None;
+    }
+    if i > 0 && !is_whitespace(data[i - 1]) && !is_delimiter(data[i - 1]) {
+        return None;
+    }
+    let num = std::str::from_utf8(&data[i..num_end]).ok()?.parse().ok()?;
+    Some((num, i))
+}
+
+fn inflate(data: &[u8]) -> Result<Vec<u8>, PdfError> {
+    let mut out = Vec::new();
+    let result = ZlibDecoder::new(data)
+        .take(MAX_STREAM_SIZE + 1)
+        .read_to_end(&mut out);
+    if result.is_err() && out.is_empty() {
+        // Some writers leave out the zlib header
+        out.clear();
+        let _ = DeflateDecoder::new(data)
+            .take(MAX_STREAM_SIZE + 1)
+            .read_to_end(&mut out);
+        if out.is_empty() {
+            return Err(malformed("corrupt Flate stream"));
+        }
+    }
+    // Truncated streams are common; whatever was inflated is kept
+    if out.len() as u64 > MAX_STREAM_SIZE {
+        return Err(PdfError::Unsupported("stream too large".into()));
+    }
+    Ok(out)
+}
+
+/// Undoes a PNG predictor from /DecodeParms. TIFF predictors are only
+/// used for images, which are never decoded here.
+fn predict(data: Vec<u8>, params: Option<&Dict>) -> Result<Vec<u8>, PdfError> {
+    let param = |key: &str, default: i64| {
+        params
+            .and_then(|params| params.get(key))
+            .and_then(Object::as_int)
+            .unwrap_or(default)
+    };
+    let predictor = param("Predictor", 1);
+    if predictor < 10 {
+        return Ok(data);
+    }
+
+    let colors = param("Colors", 1).clamp(1, 32) as usize;
+    let bits = param("BitsPerComponent", 8).clamp(1, 16) as usize;
+    let columns = param("Columns", 1).clamp(1, 1 << 20) as usize;
+    let bytes_per_pixel = (colors * bits).div_ceil(8);
+    let row_len = (columns * colors * bits).div_ceil(8);
+
+    let mut out = Vec::with_capacity(data.len());
+    let mut previous = vec![0u8; row_len];
+    for chunk in data.chunks(row_len + 1) {
+        let kind = chunk[0];
+        let mut row = chunk[1..].to_vec();
+        row.resize(row_len, 0);
+        for i in 0..row_len {
+            let left = if i >= bytes_per_pixel {
+                row[i - bytes_per_pixel]
+            } else {
+                0
+            };
+            let up = previous[i];
+            let up_left = if i >= bytes_per_pixel {
+                previous[i - bytes_per_pixel]
+            } else {
+                0
+            };
+            let prediction = match kind {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((u16::from(left) + u16::from(up)) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                _ => return Err(malformed("invalid PNG predictor")),
+            };
+            row[i] = row[i].wrapping_add(prediction);
+        }
+        out.extend_from_slice(&row);
+        previous = row;
+    }
+    Ok(out)
+}
+
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = i16::from(left) + i16::from(up) - i16::from(up_left);
+    let distance = |value: u8| (estimate - i16::from(value)).abs();
+    if distance(left) <= distance(up) && distance(left) <= distance(up_left) {
+        left
+    } else if distance(up) <= distance(up_left) {
+        up
+    } else {
+        up_left
+    }
+}
+
+fn lzw_decode(data: &[u8], early_change: bool) -> Result<Vec<u8>, PdfError> {
+    const CLEAR: usize = 256;
+    const END: usize = 257;
+
+    let mut table: Vec<Vec<u8>> = (0..=255u8).map(|b| vec![b]).collect();
+    table.extend([Vec::new(), Vec::new()]);
+    let mut out = Vec::new();
+    let mut previous: Option<Vec<u8>> = None;
+    let mut code_len = 9;
+    let (mut buffer, mut bits) = (0u32, 0);
+
+    for &byte in data {
+        buffer = buffer << 8 | u32::from(byte);
+        bits += 8;
+        while bits >= code_len {
+            bits -= code_len;
+            let code = ((buffer >> bits) & ((1 << code_len) - 1)) as usize;
+            buffer &= (1 << bits) - 1;
+
+            match code {
+                CLEAR => {
+                    table.truncate(END + 1);
+                    code_len = 9;
+                    previous = None;
+                    continue;
+                }
+                END => return Ok(out),
+                _ => {}
+            }
+            let entry = match (table.get(code), &previous) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(previous)) if code == table.len() => {
+                    let mut entry = previous.clone();
+                    entry.push(previous[0]);
+                    entry
+                }
+                _ => return Err(malformed("corrupt LZW stream")),
+            };
+            out.extend_from_slice(&entry);
+            if out.len() as u64 > MAX_STREAM_SIZE {
+                return Err(PdfError::Unsupported("stream too large".into()));
+            }
+            // Codes are at most 12 bits, so later entries could not be used
+            if let Some(mut previous) = previous.take().filter(|_| table.len() < 4096) {
+                previous.push(entry[0]);
+                table.push(previous);
+            }
+            previous = Some(entry);
+
+            let next = table.len() + usize::from(early_change);
+            code_len = match next {
+                0..=511 => 9,
+                512..=1023 => 10,
+                1024..=2047 => 11,
+                _ => 12,
+            };
+        }
+    }
+    Ok(out)
+}
+
+fn ascii_hex_decode(data: &[u8]) -> Result<Vec<u8>, PdfError> {
+    let mut digits: Vec<u8> = Vec::new();
+    for &b in data {
+        match b {
+            b'>' => break,
+            b if is_whitespace(b) => {}
+            b if b.is_ascii_hexdigit() => digits.push(b),
+            _ => return Err(malformed("invalid ASCIIHex data")),
+        }
+    }
+    if digits.len() % 2 == 1 {
+        digits.push(b'0');
+    }
+    Ok(digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap_or("00"), 16).unwrap_or(0))
+        .collect())
+}
+
+fn ascii85_decode(data: &[u8]) -> Result<Vec<u8>, PdfError> {
+    let mut out = Vec::new();
+    let mut group = Vec::with_capacity(5);
+    for &b in data {
+        match b {
+            b'~' => break,
+            b'z' if group.is_empty() => out.extend([0; 4]),
+            b'!'..=b'u' => {
+                group.push(b - b'!');
+                if group.len() == 5 {
+                    let value = group
+                        .iter()
+                        .fold(0u64, |value, &d| value * 85 + u64::from(d));
+                    out.extend_from_slice(&(value as u32).to_be_bytes());
+                    group.clear();
+                }
+            }
+            b if is_whitespace(b) => {}
+            _ => return Err(malformed("invalid ASCII85 data")),
+        }
+    }
+    // A final partial group is padded with 'u' and cut back to size
+    if group.len() > 1 {
+        let len = group.len();
+        group.resize(5, b'u' - b'!');
+        let value = group
+            .iter()
+            .fold(0u64, |value, &d| value * 85 + u64::from(d));
+        out.extend_from_slice(&(value as u32).to_be_bytes()[..len - 1]);
+    }
+    Ok(out)
+}
+
+/// Collects shown text and the breaks between it.
+#[derive(Default)]
+struct TextWriter {
+    text: String,
+    pending: Option<char>,
+}
+
+impl TextWriter {
+    fn push(&mut self, s: &str) {
+        if s.is_empty() {
+            return;
+        }
+        if let Some(separator) = self.pending.take() {
+            if !self.text.is_empty()
+                && !self.text.ends_with(char::is_whitespace)
+                && !s.starts_with(char::is_whitespace)
+            {
+                self.text.push(separator);
+            }
+        }
+        if self.text.len() < MAX_PAGE_TEXT {
+            self.text.push_str(s);
+        }
+    }
+
+    fn space(&mut self) {
+        if self.pending.is_none() {
+            self.pending = Some(' ');
+        }
+    }
+
+    fn newline(&mut self) {
+        self.pending = Some('\n');
+    }
+
+    fn finish(self) -> String {
+        let text: String = self
+            .text
+            .chars()
+            .map(|c| if c.is_control() && c != '\n' { ' ' } else { c })
+            .collect();
+        let mut end = text.len().min(MAX_PAGE_TEXT);
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text[..end].trim().to_string()
+    }
+}
+
+/// Maps the character codes of a font to Unicode.
+struct Font {
+    /// Type0 fonts use multi-byte codes.
+    composite: bool,
+    to_unicode: Option<CMap>,
+    /// Simple fonts only: the character of each single-byte code.
+    encoding: Vec<Option<char>>,
+}
+
+impl Font {
+    fn load(pdf: &Pdf, dict: &Dict) -> Font {
+        let composite = dict.get("Subtype").and_then(Object::as_name) == Some("Type0");
+        let to_unicode = match pdf.get(dict, "ToUnicode") {
+            Ok(Object::Stream(stream, raw)) => pdf
+                .decode(&stream, &raw)
+                .ok()
+                .map(|data| CMap::parse(&data)),
+            _ => None,
+        };
+
+        let mut encoding = base_encoding(None);
+        if !composite {
+            match pdf.get(dict, "Encoding") {
+                Ok(Object::Name(name)) => encoding = base_encoding(Some(&name)),
+                Ok(Object::Dict(encoding_dict)) => {
+                    let base = encoding_dict.get("BaseEncoding").and_then(Object::as_name);
+                    encoding = base_encoding(base);
+                    let differences = pdf
+                        .get(&encoding_dict, "Differences")
+                        .unwrap_or(Object::Null);
+                    let mut code = 0usize;
+                    for item in differences.as_array().unwrap_or_default() {
+                        match item {
+                            Object::Int(n) => code = (*n).clamp(0, 255) as usize,
+                            Object::Name(glyph) => {
+                                if code < 256 {
+                                    encoding[code] = glyph_char(glyph);
+                                }
+                                code += 1;
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Font {
+            composite,
+            to_unicode,
+            encoding,
+        }
+    }
+
+    fn decode(&self, bytes: &[u8]) -> String {
+        let mut text = String::new();
+        let mut i = 0;
+        while i < bytes.len() {
+            let default_len = if self.composite { 2 } else { 1 };
+            let len = self
+                .to_unicode
+                .as_ref()
+                .and_then(|cmap| cmap.code_len(&bytes[i..]))
+                .unwrap_or(default_len)
+                .min(bytes.len() - i);
+            let code = bytes[i..i + len]
+                .iter()
+                .fold(0u32, |code, &b| code << 8 | u32::from(b));
+
+            let mapped = self
+                .to_unicode
+                .as_ref()
+                .and_then(|cmap| cmap.map.get(&(len, code)));
+            match mapped {
+                Some(s) => text.push_str(s),
+                // Without a ToUnicode map, composite fonts use glyph ids that
+                // say nothing about the characters
+                None if !self.composite && len == 1 => {
+                    if let Some(c) = self.encoding[code as usize] {
+                        text.push(c);
+                    }
+                }
+                None => {}
+            }
+            i += len;
+        }
+        text
+    }
+}
+
+/// The parts of a ToUnicode CMap needed to decode text.
+struct CMap {
+    /// Byte length and inclusive code range of each codespace.
+    codespaces: Vec<(usize, u32, u32)>,
+    map: HashMap<(usize, u32), String>,
+}
+
+impl CMap {
+    fn parse(data: &[u8]) -> CMap {
+        let mut cmap = CMap {
+            codespaces: Vec::new(),
+            map: HashMap::new(),
+        };
+        let code = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .take(4)
+                .fold(0u32, |code, &b| code << 8 | u32::from(b))
+        };
+
+        let mut lexer = Lexer::new(data, 0);
+        let mut operands: Vec<Object> = Vec::new();
+        while let Ok(Some(token)) = lexer.token() {
+            let operator = match token {
+                Token::Keyword(operator) => operator,
+                token => {
+                    if let Ok(operand) = lexer.value(token, 0) {
+                        operands.push(operand);
+                    }
+                    continue;
+                }
+            };
+
+            match operator.as_slice() {
+                b"endcodespacerange" => {
+                    for pair in operands.chunks_exact(2) {
+                        if let (Some(low), Some(high)) = (pair[0].as_bytes(), pair[1].as_bytes()) {
+                            cmap.codespaces.push((low.len(), code(low), code(high)));
+                        }
+                    }
+                }
+                b"endbfchar" => {
+                    for pair in operands.chunks_exact(2) {
+                        if let (Some(source), Some(target)) =
+                            (pair[0].as_bytes(), pair[1].as_bytes())
+                        {
+                            cmap.map
+                                .insert((source.len(), code(source)), utf16_be(target));
+                        }
+                    }
+                }
+                b"endbfrange" => {
+                    for triple in operands.chunks_exact(3) {
+                        let (Some(low), Some(high)) = (triple[0].as_bytes(), triple[1].as_bytes())
+                        else {
+                            continue;
+                        };
+                        let (len, low, high) = (low.len(), code(low), code(high));
+                        // Ranges larger than the code space are corrupt
+                        let high = high.min(low.saturating_add(0xffff));
+                        match &triple[2] {
+                            Object::String(start) if start.len() >= 2 => {
+                                let mut units: Vec<u16> = start
+                                    .chunks(2)
+                                    .map(|pair| {
+                                        u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)])
+                                    })
+                                    .collect();
+                                let last = units.len() - 1;
+                                let first_unit = units[last];
+                                for code in low..=high {
+                                    units[last] = first_unit.wrapping_add((code - low) as u16);
+                                    cmap.map
+                                        .insert((len, code), String::from_utf16_lossy(&units));
+                                }
+                            }
+                            Object::Array(targets) => {
+                                for (code, target) in (low..=high).zip(targets) {
+                                    if let Some(target) = target.as_bytes() {
+                                        cmap.map.insert((len, code), utf16_be(target));
+                                    }
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+            operands.clear();
+        }
+        cmap
+    }
+
+    /// Length of the code at the start of `bytes`, from the codespaces.
+    fn code_len(&self, bytes: &[u8]) -> Option<usize> {
+        (1..=4.min(bytes.len())).find(|&len| {
+            let code = bytes[..len]
+                .iter()
+                .fold(0u32, |code, &b| code << 8 | u32::from(b));
+            self.codespaces
+                .iter()
+                .any(|&(space_len, low, high)| space_len == len && (low..=high).contains(&code))
+        })
+    }
+}
+
+fn utf16_be(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// Characters of the single-byte codes of a simple font. Anything other
+/// than MacRoman is read as WinAnsi, which also covers the ASCII part of
+/// StandardEncoding and the built-in encodings of most embedded fonts.
+fn base_encoding(name: Option<&str>) -> Vec<Option<char>> {
+    const WIN_ANSI_HIGH: [char; 32] = [
+        '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž',
+        '\u{8f}', '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}',
+        'ž', 'Ÿ',
+    ];
+    const MAC_ROMAN_HIGH: &str = "ÄÅÇÉÑÖÜáàâäãåçéèêëíìîïñóòôöõúùûü†°¢£§•¶ß®©™´¨≠ÆØ∞±≤≥¥µ∂∑∏π∫ªºΩæø¿¡¬√ƒ≈∆«»…\u{a0}ÀÃÕŒœ–—“”‘’÷◊ÿŸ⁄€‹›ﬁﬂ‡·‚„‰ÂÊÁËÈÍÎÏÌÓÔ\u{f8ff}ÒÚÛÙıˆ˜¯˘˙˚¸˝˛ˇ";
+
+    (0..=255u8)
+        .map(|b| match b {
+            b'\t' | b'\n' | b'\r' => Some(' '),
+            0..=0x1f | 0x7f => None,
+            0x20..=0x7e => Some(b as char),
+            0x80..=0xff if name == Some("MacRomanEncoding") => {
+                MAC_ROMAN_HIGH.chars().nth(usize::from(b - 0x80))
+            }
+            0x80..=0x9f => Some(WIN_ANSI_HIGH[usize::from(b - 0x80)]).filter(|c| !c.is_control()),
+            _ => char::from_u32(u32::from(b)),
+        })
+        .collect()
+}
+
+/// Unicode for a glyph name from an encoding's /Differences.
+fn glyph_char(name: &str) -> Option<char> {
+    #[rustfmt::skip]
+    const NAMED: &[(&str, char)] = &[
+        ("space", ' '), ("exclam", '!'), ("quotedbl", '"'), ("numbersign", '#'),
+        ("dollar", '$'), ("percent", '%'), ("ampersand", '&'), ("quotesingle", '\''),
+        ("parenleft", '('), ("parenright", ')'), ("asterisk", '*'), ("plus", '+'),
+        ("comma", ','), ("hyphen", '-'), ("period", '.'), ("slash", '/'), ("zero", '0'),
+        ("one", '1'), ("two", '2'), ("three", '3'), ("four", '4'), ("five", '5'), ("six", '6'),
+        ("seven", '7'), ("eight", '8'), ("nine", '9'), ("colon", ':'), ("semicolon", ';'),
+        ("less", '<'), ("equal", '='), ("greater", '>'), ("question", '?'), ("at", '@'),
+        ("bracketleft", '['), ("backslash", '\\'), ("bracketright", ']'), ("asciicircum", '^'),
+        ("underscore", '_'), ("grave", '`'), ("braceleft", '{'), ("bar", '|'),
+        ("braceright", '}'), ("asciitilde", '~'), ("quoteleft", '‘'), ("quoteright", '’'),
+        ("quotedblleft", '“'), ("quotedblright", '”'), ("quotesinglbase", '‚'),
+        ("quotedblbase", '„'), ("endash", '–'), ("emdash", '—'), ("bullet", '•'),
+        ("ellipsis", '…'), ("dagger", '†'), ("daggerdbl", '‡'), ("minus", '−'),
+        ("multiply", '×'), ("divide", '÷'), ("degree", '°'), ("copyright", '©'),
+        ("registered", '®'), ("trademark", '™'), ("section", '§'), ("paragraph", '¶'),
+        ("periodcentered", '·'), ("guillemotleft", '«'), ("guillemotright", '»'), ("fi", 'ﬁ'),
+        ("fl", 'ﬂ'), ("ff", 'ﬀ'), ("ffi", 'ﬃ'), ("ffl", 'ﬄ'), ("AE", 'Æ'), ("ae", 'æ'),
+        ("OE", 'Œ'), ("oe", 'œ'), ("Oslash", 'Ø'), ("oslash", 'ø'), ("germandbls", 'ß'),
+        ("dotlessi", 'ı'), ("Eth", 'Ð'), ("eth", 'ð'), ("Thorn", 'Þ'), ("thorn", 'þ'),
+        ("Lslash", 'Ł'), ("lslash", 'ł'), ("sterling", '£'), ("yen", '¥'), ("Euro", '€'),
+        ("cent", '¢'), ("nbspace", '\u{a0}'),
+    ];
+    const ACCENTED: &[(&str, &str)] = &[
+        ("grave", "AÀaàEÈeèIÌiìOÒoòUÙuù"),
+        ("acute", "AÁaáEÉeéIÍiíOÓoóUÚuúYÝyýCĆcćNŃnńSŚsśZŹzź"),
+        ("circumflex", "AÂaâEÊeêIÎiîOÔoôUÛuû"),
+        ("tilde", "AÃaãNÑnñOÕoõ"),
+        ("dieresis", "AÄaäEËeëIÏiïOÖoöUÜuüYŸyÿ"),
+        ("ring", "AÅaåUŮuů"),
+        ("cedilla", "CÇcçSŞsş"),
+        ("caron", "CČcčEĚeěNŇnňRŘrřSŠsšZŽzž"),
+    ];
+
+    // Subset fonts often name glyphs "uni0041" or "u1F600"
+    let hex = name
+        .strip_prefix("uni")
+        .filter(|hex| hex.len() == 4)
+        .or_else(|| {
+            name.strip_prefix('u')
+                .filter(|hex| (4..=6).contains(&hex.len()))
+        });
+    if let Some(c) = hex
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+        .and_then(char::from_u32)
+    {
+        return Some(c);
+    }
+
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return c.is_ascii_alphabetic().then_some(c);
+    }
+    if let Some(&(_, c)) = NAMED.iter().find(|(glyph, _)| *glyph == name) {
+        return Some(c);
+    }
+    ACCENTED.iter().find_map(|(accent, pairs)| {
+        let base = name.strip_suffix(accent)?;
+        let pairs: Vec<char> = pairs.chars().collect();
+        pairs
+            .chunks(2)
+            .find(|pair| base.len() == 1 && base.starts_with(pair[0]))
+            .map(|pair| pair[1])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stream object body with a correct /Length.
+    fn stream(dict: &str, data: &str) -> String {
+        format!(
+            "<< {} /Length {} >>\nstream\n{}\nendstream",
+            dict,
+            data.len(),
+            data
+        )
+    }
+
+    /// Numbers `objects` from 1 and writes them out with a cross-reference
+    /// table and a trailer rooted at object 1.
+    fn build(objects: &[String], trailer: &str) -> Vec<u8> {
+        let mut data = b"%PDF-1.7\n".to_vec();
+        let mut offsets = Vec::new();
+        for (i, body) in objects.iter().enumerate() {
+            offsets.push(data.len());
+            data.extend(format!("{} 0 obj\n{}\nendobj\n", i + 1, body).bytes());
+        }
+        let xref = data.len();
+        data.extend(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).bytes());
+        for offset in offsets {
+            data.extend(format!("{:010} 00000 n \n", offset).bytes());
+        }
+        data.extend(
+            format!(
+                "trailer\n<< /Size {} /Root 1 0 R {} >>\nstartxref\n{}\n%%EOF\n",
+                objects.len() + 1,
+                trailer,
+                xref
+            )
+            .bytes(),
+        );
+        data
+    }
+
+    /// One page per entry, each showing its text in Helvetica.
+    fn document(pages: &[&str], trailer: &str) -> Vec<u8> {
+        let kids: Vec<String> = (0..pages.len())
+            .map(|i| format!("{} 0 R", 4 + 2 * i))
+            .collect();
+        let mut objects = vec![
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            format!(
+                "<< /Type /Pages /Kids [{}] /Count {} /MediaBox [0 0 612 792] >>",
+                kids.join(" "),
+                pages.len()
+            ),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_string(),
+        ];
+        for (i, text) in pages.iter().enumerate() {
+            objects.push(format!(
+                "<< /Type /Page /Parent 2 0 R /Contents {} 0 R /Resources << /Font << /F1 3 0 R >> >> >>",
+                5 + 2 * i
+            ));
+            objects.push(stream(
+                "",
+                &format!("BT /F1 12 Tf 72 720 Td ({}) Tj ET", text),
+            ));
+        }
+        build(&objects, trailer)
+    }
+
+    /// Like `build`, with a cross-reference stream whose dictionary starts
+    /// with `xref` instead of a table. Each `(object, stream)` in
+    /// `compressed` is listed as stored in that object stream.
+    fn build_with_xref_stream(
+        objects: &[String],
+        compressed: &[(u32, u32)],
+        xref: &str,
+    ) -> Vec<u8> {
+        let mut data = b"%PDF-1.7\n".to_vec();
+        let mut rows = vec![0u8, 0, 0, 0, 0, 255];
+        for (i, body) in objects.iter().enumerate() {
+            let num = i as u32 + 1;
+            let (kind, field) = match compressed.iter().find(|(object, _)| *object == num) {
+                Some(&(_, stream)) => (2, stream),
+                None => (1, data.len() as u32),
+            };
+            rows.push(kind);
+            rows.extend(field.to_be_bytes());
+            rows.push(0);
+            data.extend(format!("{} 0 obj\n{}\nendobj\n", num, body).bytes());
+        }
+        let offset = data.len();
+        rows.push(1);
+        rows.extend((offset as u32).to_be_bytes());
+        rows.push(0);
+        data.extend(
+            format!(
+                "{} 0 obj\n<< {} /Size {} /Root 1 0 R /Length {} >>\nstream\n",
+                objects.len() + 1,
+                xref,
+                objects.len() + 2,
+                rows.len()
+            )
+            .bytes(),
+        );
+        data.extend(&rows);
+        data.extend(format!("\nendstream\nendobj\nstartxref\n{}\n%%EOF\n", offset).bytes());
+        data
+    }
+
+    /// Runs just the cross-reference reader, which `Pdf::load` would
+    /// otherwise fall back from.
+    fn read_xref(data: &[u8]) -> Result<(), PdfError> {
+        let mut pdf = Pdf {
+            source: Source::Memory(data),
+            version: "1.7".to_string(),
+            trailer: Dict::new(),
+            repaired: false,
+            xref: HashMap::new(),
+            object_streams: RefCell::new(HashMap::new()),
+            opening: RefCell::new(HashSet::new()),
+        };
+        pdf.read_xref()
+    }
+
+    /// Serves ranges of a file in memory and counts the bytes handed out.
+    struct Ranges<'a> {
+        data: &'a [u8],
+        read: &'a std::cell::Cell<usize>,
+    }
+
+    impl RangeReader for Ranges<'_> {
+        fn read(&self, offset: u64, len: usize) -> Result<Vec<u8>, PdfError> {
+            let offset = offset as usize;
+            assert!(offset + len <= self.data.len());
+            self.read.set(self.read.get() + len);
+            Ok(self.data[offset..offset + len].to_vec())
+        }
+    }
+
+    fn load_ranged<'a>(
+        data: &'a [u8],
+        read: &'a std::cell::Cell<usize>,
+    ) -> Result<Pdf<'a>, PdfError> {
+        Pdf::load_ranged(Ranges { data, read }, data.len() as u64)
+    }
+
+    #[test]
+    fn reads_text_page_by_page() {
+        let data = document(&["First page", "Second page", "Third page"], "");
+        let pdf = Pdf::load(&data).unwrap();
+        assert!(!pdf.repaired);
+        assert_eq!(pdf.pages().unwrap().len(), 3);
+        assert_eq!(
+            pdf.page_texts().unwrap(),
+            vec!["First page", "Second page", "Third page"]
+        );
+    }
+
+    #[test]
+    fn reads_document_information() {
+        let mut objects = vec![
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            "<< /Type /Pages /Kids [] /Count 0 >>".to_string(),
+        ];
+        objects.push(
+            "<< /Title (Annual report) /Author <FEFF004A006F> /CreationDate (D:20240102030405Z) >>"
+                .to_string(),
+        );
+        let data = build(&objects, "/Info 3 0 R");
+        let info = Pdf::load(&data).unwrap().info();
+        assert_eq!(info.version, "1.7");
+        assert!(!info.encrypted);
+        assert_eq!(info.title.as_deref(), Some("Annual report"));
+        assert_eq!(info.author.as_deref(), Some("Jo"));
+        assert_eq!(
+            info.created_at.as_deref(),
+            Some("2024-01-02T03:04:05+00:00")
+        );
+    }
+
+    #[test]
+    fn reads_large_files_in_parts() {
+        // An image-sized stream between the pages, which is never needed
+        let blob = "x".repeat(8 * WINDOW_LEN);
+        let objects = vec![
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            "<< /Type /Pages /Kids [3 0 R 5 0 R] /Count 2 >>".to_string(),
+            "<< /Type /Page /Parent 2 0 R >>".to_string(),
+            stream("/Subtype /Image", &blob),
+            "<< /Type /Page /Parent 2 0 R >>".to_string(),
+            "<< /Title (Large) >>".to_string(),
+        ];
+        let data = build(&objects, "/Info 6 0 R");
+        let read = std::cell::Cell::new(0);
+        let pdf = load_ranged(&data, &read).unwrap();
+        assert_eq!(pdf.pages().unwrap().len(), 2);
+        assert_eq!(pdf.info().title.as_deref(), Some("Large"));
+        assert!(read.get() < 4 * WINDOW_LEN, "read {} bytes", read.get());
+
+        let data = document(&["First page", "Second page"], "");
+        let pdf = load_ranged(&data, &read).unwrap();
+        assert_eq!(pdf.page_texts().unwrap(), vec!["First page", "Second page"]);
+    }
+
+    #[test]
+    fn files_read_in_parts_need_their_cross_references() {
+        let read = std::cell::Cell::new(0);
+        let data = document(&["Only page"], "");
+        let at = rfind(&data, b"startxref").unwrap();
+
+        let mut missing = data[..at].to_vec();
+        missing.extend(b"%%EOF\n");
+        let mut past_end = data[..at].to_vec();
+        past_end.extend(b"startxref\n999999\n%%EOF\n");
+        let mut wrong = data[..at].to_vec();
+        wrong.extend(b"startxref\n9\n%%EOF\n");
+        let mut no_root = data.clone();
+        let root = rfind(&no_root, b"/Root").unwrap();
+        no_root[root..root + 5].copy_from_slice(b"/Xoot");
+        for data in [missing, past_end, wrong, no_root] {
+            // In memory these would be repaired by scanning the whole file
+            assert!(matches!(
+                load_ranged(&data, &read),
+                Err(PdfError::Malformed(_))
+            ));
+        }
+
+        // A loop through /Prev is not repaired either
+        let mut looped = data.clone();
+        let first = looped.len();
+        let section = |prev: usize| {
+            format!(
+                "xref\n0 0\ntrailer\n<< /Size 6 /Root 1 0 R /Prev {:010} >>\n",
+                prev
+            )
+        };
+        let second = first + section(0).len();
+        looped.extend(section(second).bytes());
+        looped.extend(section(first).bytes());
+        looped.extend(format!("startxref\n{}\n%%EOF\n", first).bytes());
+        assert!(matches!(
+            load_ranged(&looped, &read),
+            Err(PdfError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn reads_cross_reference_tables_larger_than_a_window() {
+        let mut objects = vec![
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            "<< /Type /Pages /Kids [] /Count 0 >>".to_string(),
+        ];
+        objects.extend((0..WINDOW_LEN / 10).map(|_| "null".to_string()));
+        objects.push("<< /Title (Many objects) >>".to_string());
+        let info = format!("/Info {} 0 R", objects.len());
+        let data = build(&objects, &info);
+        let read = std::cell::Cell::new(0);
+        let pdf = load_ranged(&data, &read).unwrap();
+        assert_eq!(pdf.info().title.as_deref(), Some("Many objects"));
+    }
+
+    #[test]
+    fn rebuilds_broken_cross_references() {
+        let mut data = document(&["Only page"], "");
+        let at = rfind(&data, b"startxref").unwrap();
+        data.truncate(at);
+        data.extend(b"startxref\n999999\n%%EOF\n");
+        let pdf = Pdf::load(&data).unwrap();
+        assert!(pdf.repaired);
+        assert_eq!(pdf.page_texts().unwrap(), vec!["Only page"]);
+    }
+
+    #[test]
+    fn truncated_files_are_errors() {
+        let data = document(&["First page", "Second page"], "");
+        assert!(matches!(Pdf::load(&data[..5]), Err(PdfError::NotPdf)));
+        // Cut inside the catalog, before anything can be recovered
+        assert!(matches!(
+            Pdf::load(&data[..30]),
+            Err(PdfError::Malformed(_))
+        ));
+
+        // Any other cut must not panic, whatever it leaves readable
+        for end in 0..data.len() {
+            if let Ok(pdf) = Pdf::load(&data[..end]) {
+                let _ = pdf.info();
+                let _ = pdf.page_texts();
+            }
+        }
+    }
+
+    #[test]
+    fn looping_prev_chain_is_an_error() {
+        let mut data = document(&["Looped"], "");
+        // Two appended sections whose /Prev entries point at each other
+        let first = data.len();
+        let section = |prev: usize| {
+            format!(
+                "xref\n0 0\ntrailer\n<< /Size 6 /Root 1 0 R /Prev {:010} >>\n",
+                prev
+            )
+        };
+        let second = first + section(0).len();
+        data.extend(section(second).bytes());
+        data.extend(section(first).bytes());
+        data.extend(format!("startxref\n{}\n%%EOF\n", first).bytes());
+
+        assert!(matches!(read_xref(&data), Err(PdfError::Malformed(_))));
+        // Loading falls back to scanning for objects
+        let pdf = Pdf::load(&data).unwrap();
+        assert!(pdf.repaired);
+        assert_eq!(pdf.page_texts().unwrap(), vec!["Looped"]);
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error() {
+        let deep = format!("{}{}", "[".repeat(MAX_DEPTH + 2), "]".repeat(MAX_DEPTH + 2));
+        assert!(Lexer::new(deep.as_bytes(), 0).object().is_err());
+
+        let objects = vec![
+            format!("<< /Type /Catalog /Pages 2 0 R /Deep {} >>", deep),
+            "<< /Type /Pages /Kids [] /Count 0 >>".to_string(),
+        ];
+        let data = build(&objects, "");
+        let pdf = Pdf::load(&data).unwrap();
+        assert!(pdf.catalog().is_err());
+        assert!(pdf.page_texts().is_err());
+
+        // A page tree deeper than the limit
+        let mut objects = vec!["<< /Type /Catalog /Pages 2 0 R >>".to_string()];
+        for num in 2..MAX_DEPTH as u32 + 4 {
+            objects.push(format!("<< /Type /Pages /Kids [{} 0 R] >>", num + 1));
+        }
+        objects.push("<< /Type /Page >>".to_string());
+        let data = build(&objects, "");
+        assert!(Pdf::load(&data).unwrap().pages().is_err());
+
+        // References that only lead to each other
+        let objects = vec!["2 0 R".to_string(), "1 0 R".to_string()];
+        let data = build(&objects, "");
+        let pdf = Pdf::load(&data).unwrap();
+        assert!(pdf.resolve(&Object::Ref(1, 0)).is_err());
+        assert!(pdf.page_texts().is_err());
+    }
+
+    #[test]
+    fn wrong_length_falls_back_to_endstream() {
+        for length in ["-5", "3", "9223372036854775807"] {
+            let mut data = document(&["Measured"], "");
+            let at = find(&data, b"/Length ", 0).unwrap();
+            let end = find(&data, b" >>", at).unwrap();
+            data.splice(at..end, format!("/Length {}", length).bytes());
+            // The offsets no longer line up, so this also goes through repair
+            let pdf = Pdf::load(&data).unwrap();
+            assert_eq!(pdf.page_texts().unwrap(), vec!["Measured"]);
+        }
+    }
+
+    #[test]
+    fn stream_without_end_is_an_error() {
+        let data = b"1 0 obj\n<< /Length 1000 >>\nstream\nBT (cut) Tj";
+        assert!(matches!(
+            indirect_object(data, 0),
+            Err(PdfError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn object_stream_offsets_out_of_range_are_errors() {
+        for header in ["7 99", "7 9223372036854775807"] {
+            let objects = vec![
+                "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+                "<< /Type /Pages /Kids [] /Count 0 >>".to_string(),
+                stream(
+                    &format!("/Type /ObjStm /N 1 /First {}", header.len()),
+                    header,
+                ),
+            ];
+            // No usable cross-references, so the scan finds the stream
+            let mut data = build(&objects, "");
+            let at = rfind(&data, b"xref\n0 ").unwrap();
+            data.truncate(at);
+            let pdf = Pdf::load(&data).unwrap();
+            assert!(pdf.repaired);
+            assert!(pdf.object(7).is_err());
+        }
+    }
+
+    #[test]
+    fn malformed_cross_reference_tables_are_rebuilt() {
+        let data = document(&["Only page"], "");
+        let xref = rfind(&data, b"xref\n0 ").unwrap();
+        let entry = find(&data, b" 00000 n \n", xref).unwrap() - 10;
+        let trailer = rfind(&data, b"trailer\n").unwrap() + b"trailer\n".len();
+        let replacements: [(usize, usize, &[u8]); 6] = [
+            (entry, entry + 10, b"abcdefghij"),
+            (entry + 17, entry + 18, b"x"),
+            (xref + 5, xref + 7, b"0 99999999999"),
+            (xref + 5, xref + 7, b"0 -4"),
+            (xref + 5, xref + 7, b"x 6"),
+            (trailer, trailer, b"42 "),
+        ];
+        for (start, end, replacement) in replacements {
+            let mut data = data.clone();
+            data.splice(start..end, replacement.iter().copied());
+            let text = String::from_utf8_lossy(&data[xref..]).into_owned();
+            assert!(
+                matches!(read_xref(&data), Err(PdfError::Malformed(_))),
+                "{}",
+                text
+            );
+            let pdf = Pdf::load(&data).unwrap();
+            assert!(pdf.repaired);
+            assert_eq!(pdf.page_texts().unwrap(), vec!["Only page"], "{}", text);
+        }
+    }
+
+    #[test]
+    fn malformed_cross_reference_streams_are_rebuilt() {
+        let objects = [
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+            "<< /Type /Page /Parent 2 0 R /Contents 4 0 R /Resources << /Font << /F1 5 0 R >> >> >>"
+                .to_string(),
+            stream("", "BT /F1 12 Tf 72 720 Td (Streamed) Tj ET"),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_string(),
+        ];
+        let data = build_with_xref_stream(&objects, &[], "/Type /XRef /W [1 4 1]");
+        assert!(read_xref(&data).is_ok());
+        let pdf = Pdf::load(&data).unwrap();
+        assert!(!pdf.repaired);
+        assert_eq!(pdf.page_texts().unwrap(), vec!["Streamed"]);
+
+        for xref in [
+            "/Type /XRef /W [1 4]",
+            "/Type /XRef /W [0 0 0]",
+            "/Type /XRef",
+            "/Type /Catalog /W [1 4 1]",
+            "/Type /XRef /W [1 4 1] /Filter /FlateDecode",
+            "/Type /XRef /W [1 4 1] /Filter /JBIG2Decode",
+        ] {
+            let data = build_with_xref_stream(&objects, &[], xref);
+            assert!(read_xref(&data).is_err(), "{}", xref);
+            let pdf = Pdf::load(&data).unwrap();
+            assert!(pdf.repaired);
+            assert_eq!(pdf.page_texts().unwrap(), vec!["Streamed"], "{}", xref);
+        }
+
+        // Rows that run out early or do not fit the widths are not fatal
+        for xref in [
+            "/Type /XRef /W [1 4 1] /Index [0 9223372036854775807]",
+            "/Type /XRef /W [1 4 1] /Index [9223372036854775807 9223372036854775807]",
+            "/Type /XRef /W [1 99 1]",
+            "/Type /XRef /W [-1 4 1]",
+        ] {
+            let data = build_with_xref_stream(&objects, &[], xref);
+            let Ok(pdf) = Pdf::load(&data) else {
+                continue;
+            };
+            let _ = pdf.info();
+            let _ = pdf.page_texts();
+        }
+    }
+
+    #[test]
+    fn recursive_object_streams_are_errors() {
+        let objects = |filter: &str| {
+            vec![
+                "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+                "<< /Type /Pages /Kids [] /Count 0 >>".to_string(),
+                stream(
+                    &format!("/Type /ObjStm /N 1 /First 4 {}", filter),
+                    "4 0 (four)",
+                ),
+                "null".to_string(),
+                stream("/Type /ObjStm /N 1 /First 4", "3 0 (three)"),
+            ]
+        };
+        let data = build_with_xref_stream(&objects(""), &[(4, 3)], "/Type /XRef /W [1 4 1]");
+        let pdf = Pdf::load(&data).unwrap();
+        assert_eq!(pdf.object(4).unwrap().as_bytes(), Some(&b"four"[..]));
+
+        let cases = [
+            // Stored in itself
+            (objects(""), vec![(3, 3), (4, 3)]),
+            // Stored in each other
+            (objects(""), vec![(3, 5), (4, 3), (5, 3)]),
+            // Decoded with a filter name kept inside it
+            (objects("/Filter 4 0 R"), vec![(4, 3)]),
+        ];
+        for (objects, compressed) in cases {
+            let data = build_with_xref_stream(&objects, &compressed, "/Type /XRef /W [1 4 1]");
+            let pdf = Pdf::load(&data).unwrap();
+            assert!(matches!(pdf.object(4), Err(PdfError::Malformed(_))));
+            let _ = pdf.info();
+            assert!(pdf.page_texts().unwrap().is_empty());
+        }
+    }
+
+    /// Packs LZW codes the way `lzw_decode` reads them with EarlyChange 1.
+    /// Every code after the first must add a table entry.
+    fn lzw_codes(codes: impl IntoIterator<Item = usize>) -> Vec<u8> {
+        let (mut out, mut buffer, mut bits) = (Vec::new(), 0u32, 0);
+        for (i, code) in codes.into_iter().enumerate() {
+            let code_len = match (258 + i).min(4096) {
+                0..=511 => 9,
+                512..=1023 => 10,
+                1024..=2047 => 11,
+                _ => 12,
+            };
+            buffer = buffer << code_len | code as u32;
+            bits += code_len;
+            while bits >= 8 {
+                bits -= 8;
+                out.push((buffer >> bits) as u8);
+            }
+            buffer &= (1 << bits) - 1;
+        }
+        if bits > 0 {
+            out.push((buffer << (8 - bits)) as u8);
+        }
+        out
+    }
+
+    #[test]
+    fn decompression_bombs_are_refused() {
+        use flate2::{write::ZlibEncoder, Compression};
+        use std::io::Write;
+
+        let deflate = |data: &[u8]| {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        };
+        let data = document(&["Small"], "");
+        let pdf = Pdf::load(&data).unwrap();
+        let dict = |filter: &str| match Lexer::new(filter.as_bytes(), 0).object().unwrap() {
+            Object::Dict(dict) => dict,
+            _ => unreachable!(),
+        };
+
+        let bomb = deflate(&vec![0; MAX_STREAM_SIZE as usize + 1]);
+        assert!(bomb.len() < 1024 * 1024);
+        let flate = dict("<< /Filter /FlateDecode >>");
+        assert!(matches!(
+            pdf.decode(&flate, &bomb),
+            Err(PdfError::Unsupported(_))
+        ));
+        let twice = dict("<< /Filter [/FlateDecode /FlateDecode] >>");
+        assert!(matches!(
+            pdf.decode(&twice, &deflate(&bomb)),
+            Err(PdfError::Unsupported(_))
+        ));
+        // Raw deflate data, without the zlib header, takes the other path
+        assert!(matches!(
+            pdf.decode(&flate, &bomb[2..]),
+            Err(PdfError::Unsupported(_))
+        ));
+
+        let longest = 4095 - 257;
+        let repeats = MAX_STREAM_SIZE as usize / longest + 1;
+        // 0, then codes for ever longer runs of zeros until the table is
+        // full; repeating its longest entry then passes the limit
+        let run = std::iter::once(0).chain(258..4096);
+        let lzw = dict("<< /Filter /LZWDecode >>");
+        let decoded = pdf.decode(&lzw, &lzw_codes(run.clone())).unwrap();
+        assert_eq!(decoded.len(), 1 + (2..=longest + 1).sum::<usize>());
+        assert!(decoded.iter().all(|&b| b == 0));
+        let bomb = lzw_codes(run.chain(std::iter::repeat(4095).take(repeats)));
+        assert!(bomb.len() < 64 * 1024);
+        assert!(matches!(
+            pdf.decode(&lzw, &bomb),
+            Err(PdfError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn mutated_files_do_not_panic() {
+        let mut content = Vec::new();
+        flate2::read::ZlibEncoder::new(
+            &b"BT /F1 12 Tf 72 720 Td (Mutated) Tj ET"[..],
+            flate2::Compression::default(),
+        )
+        .read_to_end(&mut content)
+        .unwrap();
+        let hex = content.iter().fold(String::new(), |mut hex, b| {
+            hex.push_str(&format!("{:02x}", b));
+            hex
+        });
+        let objects = [
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+            "<< /Type /Page /Parent 2 0 R /Contents 6 0 R /Resources << /Font << /F1 5 0 R >> >> >>"
+                .to_string(),
+            stream(
+                "/Type /ObjStm /N 1 /First 4",
+                "5 0 << /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>",
+            ),
+            "null".to_string(),
+            stream("/Filter [/ASCIIHexDecode /FlateDecode]", &hex),
+        ];
+        let data = build_with_xref_stream(&objects, &[(5, 4)], "/Type /XRef /W [1 4 1]");
+        let pdf = Pdf::load(&data).unwrap();
+        assert_eq!(pdf.page_texts().unwrap(), vec!["Mutated"]);
+
+        for at in 0..data.len() {
+            for byte in [b'0', b'9', b'[', b'<', 0xff] {
+                let mut data = data.clone();
+                data[at] = byte;
+                let Ok(pdf) = Pdf::load(&data) else {
+                    continue;
+                };
+                let _ = pdf.info();
+                let _ = pdf.page_texts();
+            }
+        }
+    }
+}
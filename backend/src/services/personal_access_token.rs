@@ -0,0 +1,321 @@
+use crate::{
+    error::AppError,
+    models::{
+        personal_access_token::{self, Entity as PersonalAccessToken},
+        user::{self, Entity as User},
+    },
+};
+use chrono::{Duration, Utc};
+use rand::RngCore;
+use sea_orm::{
+    sea_query::Condition, ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait,
+    QueryFilter, QueryOrder, Set,
+};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+/// Every personal access token starts with this, which is how the auth
+/// middleware tells them apart from JWTs (and what secret scanners look for).
+pub const TOKEN_PREFIX: &str = "shelf_pat_";
+
+/// How much of the token is kept in clear text for display.
+const DISPLAY_PREFIX_LEN: usize = TOKEN_PREFIX.len() + 8;
+
+/// `last_used_at` is only written when it is older than this, so a busy
+/// script does not turn every request into a write.
+const LAST_USED_RESOLUTION_SECONDS: i64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    DocumentsRead,
+    DocumentsWrite,
+    PaymentsRead,
+    PaymentsWrite,
+    SubscriptionRead,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::DocumentsRead => "documents:read",
+            Scope::DocumentsWrite => "documents:write",
+            Scope::PaymentsRead => "payments:read",
+            Scope::PaymentsWrite => "payments:write",
+            Scope::SubscriptionRead => "subscription:read",
+        }
+    }
+
+    /// Scope a request needs, from the first path segment under `/api` and
+    /// whether the method is read-only. `None` means the endpoint is not
+    /// available to personal access tokens at all (account, 2FA, token
+    /// management and anything admin-only). Paths this does not know are
+    /// refused too.
+    pub fn required_for(path: &str, read_only: bool) -> Option<Scope> {
+        let mut segments = path.strip_prefix("/api/")?.split('/');
+        let area = segments.next()?;
+        let nested = segments.next().is_some();
+
+        match (area, read_only) {
+            ("documents" | "folders" | "tags" | "collections", true) => Some(Scope::DocumentsRead),
+            ("documents" | "folders" | "tags" | "collections", false) => {
+                Some(Scope::DocumentsWrite)
+            }
+            ("payments", true) => Some(Scope::PaymentsRead),
+            ("payments", false) => Some(Scope::PaymentsWrite),
+            // Only reading the subscription itself, not changing it
+            ("subscription", true) if !nested => Some(Scope::SubscriptionRead),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "documents:read" => Ok(Scope::DocumentsRead),
+            "documents:write" => Ok(Scope::DocumentsWrite),
+            "payments:read" => Ok(Scope::PaymentsRead),
+            "payments:write" => Ok(Scope::PaymentsWrite),
+            "subscription:read" => Ok(Scope::SubscriptionRead),
+            other => Err(AppError::BadRequest(format!("Unknown scope: {}", other))),
+        }
+    }
+}
+
+/// Parses the space separated `scopes` column, skipping anything unknown.
+pub fn parse_scopes(scopes: &str) -> Vec<Scope> {
+    scopes
+        .split_whitespace()
+        .filter_map(|scope| scope.parse().ok())
+        .collect()
+}
+
+fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("{}{}", TOKEN_PREFIX, hex::encode(bytes))
+}
+
+/// Tokens are only ever stored as their SHA-256 digest.
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Creates a token for `user_id` and returns it with its clear text value,
+/// which is not stored and cannot be shown again.
+pub async fn create(
+    db: &DatabaseConnection,
+    user_id: i32,
+    name: &str,
+    scopes: &[Scope],
+    expires_in: Option<Duration>,
+) -> Result<(personal_access_token::Model, String), AppError> {
+    let token = generate_token();
+    let now = Utc::now();
+
+    let mut scope_names: Vec<&str> = scopes.iter().map(Scope::as_str).collect();
+    scope_names.sort_unstable();
+    scope_names.dedup();
+
+    let model = personal_access_token::ActiveModel {
+        user_id: Set(user_id),
+        name: Set(name.to_string()),
+        token_hash: Set(hash_token(&token)),
+        token_prefix: Set(token[..DISPLAY_PREFIX_LEN].to_string()),
+        scopes: Set(scope_names.join(" ")),
+        expires_at: Set(expires_in.map(|ttl| (now + ttl).into())),
+        last_used_at: Set(None),
+        revoked_at: Set(None),
+        created_at: Set(now.into()),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+
+    Ok((model, token))
+}
+
+/// Tokens of a user that have not been revoked, newest first.
+pub async fn list(
+    db: &DatabaseConnection,
+    user_id: i32,
+) -> Result<Vec<personal_access_token::Model>, AppError> {
+    let tokens = PersonalAccessToken::find()
+        .filter(personal_access_token::Column::UserId.eq(user_id))
+        .filter(personal_access_token::Column::RevokedAt.is_null())
+        .order_by_desc(personal_access_token::Column::CreatedAt)
+        .all(db)
+        .await?;
+
+    Ok(tokens)
+}
+
+/// Revokes one of the user's tokens. Returns false if there was none to revoke.
+pub async fn revoke(
+    db: &DatabaseConnection,
+    user_id: i32,
+    token_id: i32,
+) -> Result<bool, AppError> {
+    let changes = personal_access_token::ActiveModel {
+        revoked_at: Set(Some(Utc::now().into())),
+        ..Default::default()
+    };
+
+    let result = PersonalAccessToken::update_many()
+        .set(changes)
+        .filter(personal_access_token::Column::Id.eq(token_id))
+        .filter(personal_access_token::Column::UserId.eq(user_id))
+        .filter(personal_access_token::Column::RevokedAt.is_null())
+        .exec(db)
+        .await?;
+
+    Ok(result.rows_affected == 1)
+}
+
+/// Resolves a presented token to its owner and scopes. Revoked and expired
+/// tokens, and tokens of disabled accounts, are rejected.
+pub async fn authenticate(
+    db: &DatabaseConnection,
+    token: &str,
+) -> Result<(user::Model, Vec<Scope>), AppError> {
+    let found = PersonalAccessToken::find()
+        .filter(personal_access_token::Column::TokenHash.eq(hash_token(token)))
+        .find_also_related(User)
+        .one(db)
+        .await?;
+
+    let (pat, user) = match found {
+        Some((pat, Some(user))) => (pat, user),
+        _ => return Err(AppError::Unauthorized("Invalid token".into())),
+    };
+
+    let now = Utc::now();
+    if pat.revoked_at.is_some()
+        || pat.expires_at.is_some_and(|expires_at| expires_at <= now)
+        || !user.is_active
+    {
+        return Err(AppError::Unauthorized("Token expired or revoked".into()));
+    }
+
+    let stale_before = now - Duration::seconds(LAST_USED_RESOLUTION_SECONDS);
+    let changes = personal_access_token::ActiveModel {
+        last_used_at: Set(Some(now.into())),
+        ..Default::default()
+    };
+    PersonalAccessToken::update_many()
+        .set(changes)
+        .filter(personal_access_token::Column::Id.eq(pat.id))
+        .filter(
+            Condition::any()
+                .add(personal_access_token::Column::LastUsedAt.is_null())
+                .add(personal_access_token::Column::LastUsedAt.lt(stale_before)),
+        )
+        .exec(db)
+        .await?;
+
+    Ok((user, parse_scopes(&pat.scopes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scope_follows_the_area_and_method() {
+        let cases = [
+            ("/api/documents", true, Some(Scope::DocumentsRead)),
+            ("/api/documents", false, Some(Scope::DocumentsWrite)),
+            (
+                "/api/documents/4/download",
+                true,
+                Some(Scope::DocumentsRead),
+            ),
+            ("/api/documents/4", false, Some(Scope::DocumentsWrite)),
+            ("/api/folders/2", false, Some(Scope::DocumentsWrite)),
+            ("/api/tags", true, Some(Scope::DocumentsRead)),
+            (
+                "/api/collections/1/documents",
+                false,
+                Some(Scope::DocumentsWrite),
+            ),
+            ("/api/payments/status/abc", true, Some(Scope::PaymentsRead)),
+            ("/api/payments/request", false, Some(Scope::PaymentsWrite)),
+            ("/api/subscription", true, Some(Scope::SubscriptionRead)),
+        ];
+        for (path, read_only, scope) in cases {
+            assert_eq!(Scope::required_for(path, read_only), scope, "{}", path);
+        }
+    }
+
+    #[test]
+    fn account_and_admin_endpoints_are_closed_to_tokens() {
+        // A token cannot manage the account, mint or revoke tokens, or use
+        // the admin API, whatever its scopes
+        for path in [
+            "/api/me",
+            "/api/me/password",
+            "/api/me/2fa/disable",
+            "/api/me/identities/google",
+            "/api/tokens",
+            "/api/tokens/3",
+            "/api/admin/users",
+            "/api/admin/users/1/plan",
+        ] {
+            for read_only in [true, false] {
+                assert_eq!(Scope::required_for(path, read_only), None, "{}", path);
+            }
+        }
+        assert_eq!(Scope::required_for("/api/subscription", false), None);
+    }
+
+    #[test]
+    fn unknown_paths_fail_closed() {
+        for path in [
+            "",
+            "/",
+            "/api",
+            "/api/",
+            "/api//documents",
+            "/api/Documents",
+            "/api/documentsx",
+            "/api/%64ocuments",
+            "/apix/documents",
+            "/documents",
+            "/api/auth/login",
+            "/api/reports",
+            "/api/subscription/update",
+            "/.well-known/jwks.json",
+        ] {
+            for read_only in [true, false] {
+                assert_eq!(Scope::required_for(path, read_only), None, "{}", path);
+            }
+        }
+    }
+
+    #[test]
+    fn scopes_round_trip_and_unknown_ones_are_skipped() {
+        for scope in [
+            Scope::DocumentsRead,
+            Scope::DocumentsWrite,
+            Scope::PaymentsRead,
+            Scope::PaymentsWrite,
+            Scope::SubscriptionRead,
+        ] {
+            assert_eq!(scope.as_str().parse::<Scope>().unwrap(), scope);
+        }
+        assert!("admin".parse::<Scope>().is_err());
+        assert_eq!(
+            parse_scopes("documents:read  admin payments:write"),
+            [Scope::DocumentsRead, Scope::PaymentsWrite]
+        );
+    }
+}
@@ -0,0 +1,312 @@
+use crate::{
+    error::AppError,
+    models::{
+        document::{self, Entity as Document},
+        plan::{self, BillingPeriod, Entity as Plan},
+        plan_change::{self, PlanChangeSource},
+        plan_price::{self, Entity as PlanPrice},
+        subscription::{self, Entity as Subscription},
+    },
+};
+use chrono::{DateTime, FixedOffset, Months, Utc};
+use sea_orm::prelude::Decimal;
+use sea_orm::{
+    sea_query::{Alias, Expr, Query},
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait,
+    FromQueryResult, QueryFilter, Set, TransactionTrait,
+};
+use std::collections::HashMap;
+
+/// Plan every user is on until they pay for another one.
+pub const FREE_PLAN_CODE: &str = "none";
+
+pub async fn find_by_code<C: ConnectionTrait>(db: &C, code: &str) -> Result<plan::Model, AppError> {
+    Plan::find()
+        .filter(plan::Column::Code.eq(code))
+        .filter(plan::Column::IsActive.eq(true))
+        .one(db)
+        .await?
+        .ok_or_else(|| AppError::BadRequest(format!("Invalid plan: {}", code)))
+}
+
+pub async fn find_by_id<C: ConnectionTrait>(db: &C, id: i32) -> Result<plan::Model, AppError> {
+    Plan::find_by_id(id)
+        .one(db)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Plan {} not found", id)))
+}
+
+/// Price of `plan` in `currency`. Plans without a price (such as the free
+/// plan) cannot be bought.
+pub async fn price_for<C: ConnectionTrait>(
+    db: &C,
+    plan: &plan::Model,
+    currency: &str,
+) -> Result<Decimal, AppError> {
+    PlanPrice::find()
+        .filter(plan_price::Column::PlanId.eq(plan.id))
+        .filter(plan_price::Column::Currency.eq(currency))
+        .one(db)
+        .await?
+        .map(|price| price.amount)
+        .ok_or_else(|| {
+            AppError::BadRequest(format!(
+                "Plan {} cannot be purchased in {}",
+                plan.code, currency
+            ))
+        })
+}
+
+/// Whether `subscription` still entitles the user to `plan`: it has to be
+/// active and, for billed plans, within the period that was paid for.
+pub fn is_in_effect(
+    subscription: &subscription::Model,
+    plan: &plan::Model,
+    now: DateTime<FixedOffset>,
+) -> bool {
+    subscription.status == "active"
+        && (plan.billing_period == BillingPeriod::None || subscription.current_period_end > now)
+}
+
+/// The plan a user's subscription points at, or the free plan when the user
+/// has no subscription or it is no longer in effect.
+pub async fn plan_for_user<C: ConnectionTrait>(
+    db: &C,
+    user_id: i32,
+) -> Result<plan::Model, AppError> {
+    let subscription = Subscription::find()
+        .filter(subscription::Column::UserId.eq(user_id))
+        .find_also_related(Plan)
+        .one(db)
+        .await?;
+
+    match subscription {
+        Some((subscription, Some(plan)))
+            if is_in_effect(&subscription, &plan, Utc::now().fixed_offset()) =>
+        {
+            Ok(plan)
+        }
+        _ => find_by_code(db, FREE_PLAN_CODE).await,
+    }
+}
+
+pub async fn storage_limit_for_user<C: ConnectionTrait>(
+    db: &C,
+    user_id: i32,
+) -> Result<i64, AppError> {
+    Ok(plan_for_user(db, user_id).await?.storage_limit_bytes)
+}
+
+#[derive(FromQueryResult)]
+struct UserUsage {
+    user_id: i32,
+    bytes: i64,
+}
+
+/// Bytes stored per user, for the given users only. A file that several
+/// documents are linked to counts once.
+pub async fn storage_used_by<C: ConnectionTrait>(
+    db: &C,
+    user_ids: &[i32],
+) -> Result<HashMap<i32, i64>, AppError> {
+    let files = Query::select()
+        .distinct()
+        .columns([
+            document::Column::UserId,
+            document::Column::S3Key,
+            document::Column::FileSize,
+        ])
+        .from(Document)
+        .and_where(document::Column::UserId.is_in(user_ids.to_vec()))
+        .to_owned();
+    let query = Query::select()
+        .column(document::Column::UserId)
+        .expr_as(
+            Expr::expr(Expr::col(document::Column::FileSize).sum()).cast_as(Alias::new("BIGINT")),
+            Alias::new("bytes"),
+        )
+        .from_subquery(files, Alias::new("files"))
+        .group_by_col(document::Column::UserId)
+        .to_owned();
+
+    let usage = UserUsage::find_by_statement(db.get_database_backend().build(&query))
+        .all(db)
+        .await?;
+    Ok(usage
+        .into_iter()
+        .map(|row| (row.user_id, row.bytes))
+        .collect())
+}
+
+/// End of a billing period for `plan` that starts at `start`. Plans that are
+/// not billed never expire, so the start is returned unchanged.
+pub fn period_end(plan: &plan::Model, start: DateTime<FixedOffset>) -> DateTime<FixedOffset> {
+    let months = match plan.billing_period {
+        BillingPeriod::None => return start,
+        BillingPeriod::Monthly => Months::new(1),
+        BillingPeriod::Yearly => Months::new(12),
+    };
+
+    start.checked_add_months(months).unwrap_or(start)
+}
+
+/// Who made a plan change and why, recorded in `plan_changes`.
+pub struct PlanChange {
+    pub changed_by: i32,
+    pub source: PlanChangeSource,
+    pub payment_id: Option<i32>,
+}
+
+/// Moves `user_id` onto `plan`, starting a new billing period, and records
+/// the change. Creates the subscription if the user has none yet.
+pub async fn change_plan(
+    db: &DatabaseConnection,
+    user_id: i32,
+    plan: &plan::Model,
+    change: PlanChange,
+) -> Result<subscription::Model, AppError> {
+    let now = Utc::now().fixed_offset();
+    let period_end = period_end(plan, now);
+
+    let transaction = db.begin().await?;
+
+    let current_sub = Subscription::find()
+        .filter(subscription::Column::UserId.eq(user_id))
+        .one(&transaction)
+        .await?;
+    let previous_plan_id = current_sub.as_ref().map(|sub| sub.plan_id);
+
+    let subscription = match current_sub {
+        Some(current_sub) => {
+            let mut updated_sub: subscription::ActiveModel = current_sub.into();
+            updated_sub.plan_id = Set(plan.id);
+            updated_sub.status = Set("active".to_string());
+            updated_sub.current_period_end = Set(period_end);
+            updated_sub.update(&transaction).await?
+        }
+        None => {
+            subscription::ActiveModel {
+                user_id: Set(user_id),
+                stripe_customer_id: Set("none".to_string()),
+                stripe_subscription_id: Set("none".to_string()),
+                status: Set("active".to_string()),
+                plan_id: Set(plan.id),
+                current_period_end: Set(period_end),
+                ..Default::default()
+            }
+            .insert(&transaction)
+            .await?
+        }
+    };
+
+    plan_change::ActiveModel {
+        user_id: Set(user_id),
+        previous_plan_id: Set(previous_plan_id),
+        new_plan_id: Set(plan.id),
+        changed_by: Set(Some(change.changed_by)),
+        source: Set(change.source),
+        payment_id: Set(change.payment_id),
+        ..Default::default()
+    }
+    .insert(&transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    println!(
+        "User {} moved from plan {:?} to {} by user {}",
+        user_id, previous_plan_id, plan.code, change.changed_by
+    );
+
+    Ok(subscription)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn plan(billing_period: BillingPeriod) -> plan::Model {
+        let now = Utc::now().fixed_offset();
+        plan::Model {
+            id: 2,
+            code: "premium".to_string(),
+            name: "Premium".to_string(),
+            storage_limit_bytes: 1 << 30,
+            billing_period,
+            features: serde_json::json!({}),
+            is_active: true,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    fn subscription(
+        status: &str,
+        current_period_end: DateTime<FixedOffset>,
+    ) -> subscription::Model {
+        subscription::Model {
+            id: 1,
+            user_id: 1,
+            stripe_customer_id: "none".to_string(),
+            stripe_subscription_id: "none".to_string(),
+            status: status.to_string(),
+            plan_id: 2,
+            current_period_end,
+            created_at: current_period_end,
+            updated_at: current_period_end,
+        }
+    }
+
+    #[test]
+    fn active_subscription_within_its_period_is_in_effect() {
+        let now = Utc::now().fixed_offset();
+        let subscription = subscription("active", now + Duration::days(3));
+        assert!(is_in_effect(
+            &subscription,
+            &plan(BillingPeriod::Monthly),
+            now
+        ));
+    }
+
+    #[test]
+    fn lapsed_period_ends_a_billed_plan() {
+        let now = Utc::now().fixed_offset();
+        let subscription = subscription("active", now - Duration::seconds(1));
+        assert!(!is_in_effect(
+            &subscription,
+            &plan(BillingPeriod::Monthly),
+            now
+        ));
+        assert!(!is_in_effect(
+            &subscription,
+            &plan(BillingPeriod::Yearly),
+            now
+        ));
+    }
+
+    #[test]
+    fn plans_without_billing_do_not_lapse() {
+        let now = Utc::now().fixed_offset();
+        let subscription = subscription("active", now - Duration::days(400));
+        assert!(is_in_effect(&subscription, &plan(BillingPeriod::None), now));
+    }
+
+    #[test]
+    fn inactive_subscription_is_not_in_effect() {
+        let now = Utc::now().fixed_offset();
+        for status in ["inactive", "cancelled", "past_due"] {
+            let subscription = subscription(status, now + Duration::days(3));
+            assert!(!is_in_effect(
+                &subscription,
+                &plan(BillingPeriod::Monthly),
+                now
+            ));
+            assert!(!is_in_effect(
+                &subscription,
+                &plan(BillingPeriod::None),
+                now
+            ));
+        }
+    }
+}
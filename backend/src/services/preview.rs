@@ -0,0 +1,378 @@
+//! Page images of PDF documents. Pages are rendered with poppler's
+//! `pdftoppm`, converted with `cwebp` when WebP is asked for, and cached in
+//! storage next to the document so each size is only rendered once.
+
+use crate::{
+    error::AppError,
+    models::document,
+    services::storage::{StorageError, StorageService},
+};
+use bytes::Bytes;
+use futures_util::{stream, StreamExt};
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::{fs, io::AsyncWriteExt, process::Command, sync::Semaphore};
+use uuid::Uuid;
+
+/// Larger documents are not rendered; they would have to be copied whole to disk.
+const MAX_FILE_SIZE: i64 = 100 * 1024 * 1024;
+/// A renderer still running after this long is killed.
+const RENDER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Renders running at once. Further requests wait for a slot before the
+/// document is fetched.
+static RENDER_SLOTS: Semaphore = Semaphore::const_new(4);
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PreviewSize {
+    Small,
+    Medium,
+    Large,
+}
+
+impl PreviewSize {
+    /// Width in pixels. The height follows from the page's proportions.
+    fn width(self) -> u32 {
+        match self {
+            PreviewSize::Small => 160,
+            PreviewSize::Medium => 320,
+            PreviewSize::Large => 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PreviewFormat {
+    #[default]
+    Png,
+    Webp,
+}
+
+impl PreviewFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            PreviewFormat::Png => "png",
+            PreviewFormat::Webp => "webp",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            PreviewFormat::Png => "image/png",
+            PreviewFormat::Webp => "image/webp",
+        }
+    }
+}
+
+/// Where the renders of a document are kept. Stored files are never
+/// overwritten, so the document's own key is enough to tell them apart.
+fn cache_prefix(s3_key: &str) -> String {
+    format!("{}.previews/", s3_key)
+}
+
+fn cache_key(s3_key: &str, page: u32, size: PreviewSize, format: PreviewFormat) -> String {
+    format!(
+        "{}page-{}-{}.{}",
+        cache_prefix(s3_key),
+        page,
+        size.width(),
+        format.extension()
+    )
+}
+
+/// A page of the document as an image, rendered on first request and
+/// served from the cache afterwards.
+pub async fn page_image(
+    storage: &StorageService,
+    document: &document::Model,
+    page: u32,
+    size: PreviewSize,
+    format: PreviewFormat,
+) -> Result<Bytes, AppError> {
+    if document.mime_type.split(';').next().map(str::trim) != Some("application/pdf") {
+        return Err(AppError::BadRequest(
+            "Previews are only available for PDF documents".into(),
+        ));
+    }
+    if page == 0 || document.page_count.is_some_and(|count| page > count as u32) {
+        return Err(AppError::NotFound("Page not found".into()));
+    }
+
+    let key = cache_key(&document.s3_key, page, size, format);
+    match read(storage, &key).await {
+        Ok(image) => return Ok(image),
+        Err(StorageError::NotFound(_)) => {}
+        Err(e) => return Err(AppError::InternalServerError(e.to_string())),
+    }
+
+    if document.file_size > MAX_FILE_SIZE {
+        return Err(AppError::BadRequest(
+            "Document is too large to preview".into(),
+        ));
+    }
+    let image = render(storage, document, page, size.width(), format).await?;
+
+    // Renders do not count against the user's storage limit
+    let body = stream::once({
+        let image = image.clone();
+        async move { Ok::<_, Infallible>(image) }
+    });
+    if let Err(e) = storage
+        .upload_stream(&key, format.content_type(), body, i64::MAX)
+        .await
+    {
+        println!("Failed to cache preview {}: {}", key, e);
+    }
+    Ok(image)
+}
+
+/// Deletes every cached render of a document.
+pub async fn remove_cached(storage: &StorageService, s3_key: &str) {
+    let renders = match storage.list_files(&cache_prefix(s3_key)).await {
+        Ok(renders) => renders,
+        Err(e) => {
+            println!("Failed to list previews of {}: {}", s3_key, e);
+            return;
+        }
+    };
+    for render in renders {
+        if let Err(e) = storage.delete_file(&render.key).await {
+            println!("Failed to delete preview {}: {}", render.key, e);
+        }
+    }
+}
+
+async fn read(storage: &StorageService, key: &str) -> Result<Bytes, StorageError> {
+    let mut body = storage.download_file(key, None).await?;
+    let mut data = Vec::new();
+    while let Some(chunk) = body.next().await {
+        data.extend_from_slice(&chunk?);
+    }
+    Ok(data.into())
+}
+
+/// Copies `key` to `path` chunk by chunk.
+async fn download(storage: &StorageService, key: &str, path: &Path) -> Result<(), AppError> {
+    let mut body = storage
+        .download_file(key, None)
+        .await
+        .map_err(|e| AppError::InternalServerError(e.to_string()))?;
+    let mut file = fs::File::create(path)
+        .await
+        .map_err(|e| AppError::InternalServerError(e.to_string()))?;
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| AppError::InternalServerError(e.to_string()))?;
+        file.write_all(&chunk)
+            .await
+            .map_err(|e| AppError::InternalServerError(e.to_string()))?;
+    }
+    file.flush()
+        .await
+        .map_err(|e| AppError::InternalServerError(e.to_string()))
+}
+
+/// Renders one page in a scratch directory, which is removed afterwards.
+async fn render(
+    storage: &StorageService,
+    document: &document::Model,
+    page: u32,
+    width: u32,
+    format: PreviewFormat,
+) -> Result<Bytes, AppError> {
+    let _slot = RENDER_SLOTS
+        .acquire()
+        .await
+        .map_err(|e| AppError::InternalServerError(e.to_string()))?;
+
+    let dir = std::env::temp_dir().join(format!("shelf-preview-{}", Uuid::new_v4()));
+    fs::create_dir(&dir)
+        .await
+        .map_err(|e| AppError::InternalServerError(e.to_string()))?;
+    let result = render_in(&dir, storage, document, page, width, format).await;
+    if let Err(e) = fs::remove_dir_all(&dir).await {
+        println!("Failed to remove {}: {}", dir.display(), e);
+    }
+    result
+}
+
+async fn render_in(
+    dir: &Path,
+    storage: &StorageService,
+    document: &document::Model,
+    page: u32,
+    width: u32,
+    format: PreviewFormat,
+) -> Result<Bytes, AppError> {
+    let input = dir.join("document.pdf");
+    download(storage, &document.s3_key, &input).await?;
+
+    // Without a page count from the upload, pages past the end are only
+    // found out here
+    if document.page_count.is_none() && page > page_count(&input).await? {
+        return Err(AppError::NotFound("Page not found".into()));
+    }
+
+    let page = page.to_string();
+    let width = width.to_string();
+    run(Command::new("pdftoppm")
+        .args(["-png", "-singlefile", "-f", &page, "-l", &page])
+        .args(["-scale-to-x", &width, "-scale-to-y", "-1"])
+        .arg(&input)
+        .arg(dir.join("page")))
+    .await?;
+
+    let mut output = dir.join("page.png");
+    if let PreviewFormat::Webp = format {
+        let webp = dir.join("page.webp");
+        run(Command::new("cwebp")
+            .args(["-quiet", "-q", "80"])
+            .arg(&output)
+            .arg("-o")
+            .arg(&webp))
+        .await?;
+        output = webp;
+    }
+
+    let image = fs::read(&output)
+        .await
+        .map_err(|e| AppError::InternalServerError(e.to_string()))?;
+    Ok(image.into())
+}
+
+/// Number of pages `pdfinfo` finds in the PDF at `input`.
+async fn page_count(input: &Path) -> Result<u32, AppError> {
+    let info = run(Command::new("pdfinfo").arg(input)).await?;
+    parse_page_count(&String::from_utf8_lossy(&info))
+        .ok_or_else(|| AppError::InternalServerError("pdfinfo reported no page count".into()))
+}
+
+fn parse_page_count(info: &str) -> Option<u32> {
+    info.lines()
+        .find_map(|line| line.strip_prefix("Pages:"))
+        .and_then(|count| count.trim().parse().ok())
+}
+
+/// Runs a converter to completion, killing it if it takes too long, and
+/// returns what it printed.
+async fn run(command: &mut Command) -> Result<Vec<u8>, AppError> {
+    let program = command
+        .as_std()
+        .get_program()
+        .to_string_lossy()
+        .into_owned();
+    let child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| AppError::InternalServerError(format!("Could not run {}: {}", program, e)))?;
+
+    let output = tokio::time::timeout(RENDER_TIMEOUT, child.wait_with_output())
+        .await
+        .map_err(|_| AppError::InternalServerError(format!("{} timed out", program)))?
+        .map_err(|e| AppError::InternalServerError(e.to_string()))?;
+    if !output.status.success() {
+        return Err(AppError::InternalServerError(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::document::TextStatus;
+    use crate::services::storage::MemoryBackend;
+    use std::sync::Arc;
+
+    fn document(mime_type: &str, file_size: i64, page_count: Option<i32>) -> document::Model {
+        let now = chrono::Utc::now().fixed_offset();
+        document::Model {
+            id: 1,
+            user_id: 1,
+            folder_id: None,
+            filename: "a.pdf".to_string(),
+            file_size,
+            mime_type: mime_type.to_string(),
+            s3_key: "1/a.pdf".to_string(),
+            content_hash: None,
+            title: None,
+            description: None,
+            author: None,
+            metadata: serde_json::json!({}),
+            text_status: TextStatus::Pending,
+            page_count,
+            pdf_info: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    async fn image(
+        storage: &StorageService,
+        document: &document::Model,
+        page: u32,
+    ) -> Result<Bytes, AppError> {
+        page_image(
+            storage,
+            document,
+            page,
+            PreviewSize::Small,
+            PreviewFormat::Png,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn pages_outside_the_document_are_not_found() {
+        let storage = StorageService::new(Arc::new(MemoryBackend::new()));
+        let document = document("application/pdf", 1000, Some(3));
+
+        for page in [0, 4] {
+            let result = image(&storage, &document, page).await;
+            assert!(matches!(result, Err(AppError::NotFound(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn only_pdfs_are_previewed() {
+        let storage = StorageService::new(Arc::new(MemoryBackend::new()));
+        let document = document("image/png", 1000, None);
+
+        let result = image(&storage, &document, 1).await;
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn cached_renders_are_served_even_for_large_documents() {
+        let storage = StorageService::new(Arc::new(MemoryBackend::new()));
+        let document = document("application/pdf", MAX_FILE_SIZE + 1, Some(3));
+        let key = cache_key(&document.s3_key, 2, PreviewSize::Small, PreviewFormat::Png);
+        let body = stream::once(async { Ok::<_, Infallible>(Bytes::from_static(b"png")) });
+        storage
+            .upload_stream(&key, "image/png", body, i64::MAX)
+            .await
+            .unwrap();
+
+        assert_eq!(image(&storage, &document, 2).await.unwrap(), "png");
+        // Not rendered before, and too large to render now
+        let result = image(&storage, &document, 3).await;
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+    }
+
+    #[test]
+    fn page_count_is_read_from_pdfinfo() {
+        let info = "Producer:       pdfTeX\nPages:          12\nEncrypted:      no\n";
+        assert_eq!(parse_page_count(info), Some(12));
+        assert_eq!(parse_page_count("Encrypted:      no\n"), None);
+    }
+}
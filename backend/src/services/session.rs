@@ -0,0 +1,225 @@
+use crate::{
+    error::AppError,
+    models::{
+        session::{self, Entity as Session},
+        user::{self, Entity as User},
+    },
+};
+use chrono::{Duration, Utc};
+use rand::RngCore;
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
+use sha2::{Digest, Sha256};
+
+/// Lifetime of access tokens, from `ACCESS_TOKEN_TTL_MINUTES` (default 15).
+pub fn access_token_ttl() -> Duration {
+    let minutes = std::env::var("ACCESS_TOKEN_TTL_MINUTES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(15);
+    Duration::minutes(minutes)
+}
+
+/// Lifetime of a session, from `REFRESH_TOKEN_TTL_DAYS` (default 30).
+/// Refreshing rotates the token but does not extend the session.
+fn refresh_token_ttl() -> Duration {
+    let days = std::env::var("REFRESH_TOKEN_TTL_DAYS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(30);
+    Duration::days(days)
+}
+
+fn generate_refresh_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Refresh tokens are only ever stored as their SHA-256 digest.
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Starts a session for `user_id` and returns it with its refresh token.
+pub async fn create(
+    db: &DatabaseConnection,
+    user_id: i32,
+    user_agent: Option<String>,
+) -> Result<(session::Model, String), AppError> {
+    let refresh_token = generate_refresh_token();
+    let now = Utc::now();
+
+    let session = session::ActiveModel {
+        user_id: Set(user_id),
+        refresh_token_hash: Set(hash_token(&refresh_token)),
+        previous_token_hash: Set(None),
+        user_agent: Set(user_agent),
+        expires_at: Set((now + refresh_token_ttl()).into()),
+        revoked_at: Set(None),
+        created_at: Set(now.into()),
+        last_used_at: Set(now.into()),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+
+    Ok((session, refresh_token))
+}
+
+/// Exchanges a refresh token for a new one.
+///
+/// Presenting a token that was already rotated away means it was copied, so
+/// the whole session is revoked and both holders have to log in again.
+pub async fn rotate(
+    db: &DatabaseConnection,
+    refresh_token: &str,
+) -> Result<(session::Model, user::Model, String), AppError> {
+    let token_hash = hash_token(refresh_token);
+    let now = Utc::now();
+
+    let (session, user) = match Session::find()
+        .filter(session::Column::RefreshTokenHash.eq(token_hash.clone()))
+        .find_also_related(User)
+        .one(db)
+        .await?
+    {
+        Some((session, Some(user))) => (session, user),
+        Some((_, None)) => return Err(AppError::Unauthorized("Invalid refresh token".into())),
+        None => {
+            if let Some(reused) = Session::find()
+                .filter(session::Column::PreviousTokenHash.eq(token_hash))
+                .one(db)
+                .await?
+            {
+                println!(
+                    "Refresh token reuse detected for session {}, revoking it",
+                    reused.id
+                );
+                revoke_by_id(db, reused.id).await?;
+            }
+            return Err(AppError::Unauthorized("Invalid refresh token".into()));
+        }
+    };
+
+    if session.revoked_at.is_some() || session.expires_at <= now || !user.is_active {
+        return Err(AppError::Unauthorized("Session expired or revoked".into()));
+    }
+
+    let new_token = generate_refresh_token();
+    let changes = session::ActiveModel {
+        refresh_token_hash: Set(hash_token(&new_token)),
+        previous_token_hash: Set(Some(session.refresh_token_hash.clone())),
+        last_used_at: Set(now.into()),
+        ..Default::default()
+    };
+
+    // Only one of two concurrent refreshes with the same token may win
+    let result = Session::update_many()
+        .set(changes)
+        .filter(session::Column::Id.eq(session.id))
+        .filter(session::Column::RefreshTokenHash.eq(session.refresh_token_hash.clone()))
+        .filter(session::Column::RevokedAt.is_null())
+        .exec(db)
+        .await?;
+
+    if result.rows_affected != 1 {
+        return Err(AppError::Unauthorized("Invalid refresh token".into()));
+    }
+
+    Ok((session, user, new_token))
+}
+
+/// Revokes the session a refresh token belongs to. Unknown tokens are ignored.
+pub async fn revoke(db: &DatabaseConnection, refresh_token: &str) -> Result<(), AppError> {
+    let token_hash = hash_token(refresh_token);
+
+    if let Some(session) = Session::find()
+        .filter(session::Column::RefreshTokenHash.eq(token_hash))
+        .one(db)
+        .await?
+    {
+        revoke_by_id(db, session.id).await?;
+        println!("Session {} of user {} revoked", session.id, session.user_id);
+    }
+
+    Ok(())
+}
+
+/// Revokes every open session of a user, e.g. after a password reset.
+pub async fn revoke_all_for_user(db: &DatabaseConnection, user_id: i32) -> Result<u64, AppError> {
+    let changes = session::ActiveModel {
+        revoked_at: Set(Some(Utc::now().into())),
+        ..Default::default()
+    };
+
+    let result = Session::update_many()
+        .set(changes)
+        .filter(session::Column::UserId.eq(user_id))
+        .filter(session::Column::RevokedAt.is_null())
+        .exec(db)
+        .await?;
+
+    Ok(result.rows_affected)
+}
+
+/// Revokes every open session of a user except `keep`, e.g. after a
+/// password change made from that session.
+pub async fn revoke_others(
+    db: &DatabaseConnection,
+    user_id: i32,
+    keep: Option<i32>,
+) -> Result<u64, AppError> {
+    let changes = session::ActiveModel {
+        revoked_at: Set(Some(Utc::now().into())),
+        ..Default::default()
+    };
+
+    let mut query = Session::update_many()
+        .set(changes)
+        .filter(session::Column::UserId.eq(user_id))
+        .filter(session::Column::RevokedAt.is_null());
+    if let Some(keep) = keep {
+        query = query.filter(session::Column::Id.ne(keep));
+    }
+
+    Ok(query.exec(db).await?.rows_affected)
+}
+
+async fn revoke_by_id(db: &DatabaseConnection, session_id: i32) -> Result<(), AppError> {
+    let changes = session::ActiveModel {
+        revoked_at: Set(Some(Utc::now().into())),
+        ..Default::default()
+    };
+
+    Session::update_many()
+        .set(changes)
+        .filter(session::Column::Id.eq(session_id))
+        .filter(session::Column::RevokedAt.is_null())
+        .exec(db)
+        .await?;
+
+    Ok(())
+}
+
+/// Whether an access token issued for `session_id` may still be used: the
+/// session is neither revoked nor expired and its user is still active.
+pub async fn is_active(
+    db: &DatabaseConnection,
+    session_id: i32,
+    user_id: i32,
+) -> Result<bool, AppError> {
+    let found = Session::find_by_id(session_id)
+        .find_also_related(User)
+        .one(db)
+        .await?;
+
+    Ok(match found {
+        Some((session, Some(user))) => {
+            session.user_id == user_id
+                && session.revoked_at.is_none()
+                && session.expires_at > Utc::now()
+                && user.is_active
+        }
+        _ => false,
+    })
+}
@@ -0,0 +1,201 @@
+use super::{DownloadStream, ObjectMeta, StorageBackend, StorageError, UploadStream};
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use std::io::{ErrorKind, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use tokio::fs::{self, File};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader};
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+/// Stores objects as plain files below a root directory, for single-node
+/// deployments without an object store. Keys map directly to relative paths.
+pub struct LocalBackend {
+    root: PathBuf,
+}
+
+impl LocalBackend {
+    pub async fn new(root: impl Into<PathBuf>) -> Result<Self, StorageError> {
+        let root = root.into();
+        println!("Initializing local storage in: {}", root.display());
+
+        fs::create_dir_all(&root).await?;
+
+        Ok(Self { root })
+    }
+
+    /// Resolves a key to a path below the root, rejecting anything that could
+    /// escape it.
+    fn path_for(&self, key: &str) -> Result<PathBuf, StorageError> {
+        let relative = Path::new(key);
+        let is_plain = relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+
+        if key.is_empty() || !is_plain {
+            return Err(StorageError::InvalidKey(key.to_string()));
+        }
+
+        Ok(self.root.join(relative))
+    }
+
+    async fn meta_for(&self, key: String, path: &Path) -> Result<ObjectMeta, StorageError> {
+        let metadata = fs::metadata(path).await.map_err(|e| match e.kind() {
+            ErrorKind::NotFound => StorageError::NotFound(key.clone()),
+            _ => e.into(),
+        })?;
+
+        Ok(ObjectMeta {
+            key,
+            size: metadata.len(),
+            content_type: None,
+            last_modified: metadata.modified().ok(),
+        })
+    }
+}
+
+#[async_trait(?Send)]
+impl StorageBackend for LocalBackend {
+    /// Writes to a hidden temporary file next to the target and renames it
+    /// into place once the whole body has arrived.
+    async fn put(
+        &self,
+        key: &str,
+        _content_type: &str,
+        mut body: UploadStream<'_>,
+    ) -> Result<u64, StorageError> {
+        let path = self.path_for(key)?;
+        let parent = path.parent().unwrap_or(&self.root);
+        fs::create_dir_all(parent).await?;
+
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let temp_path = parent.join(format!(".{}.{}.tmp", file_name, Uuid::new_v4()));
+
+        let result = async {
+            let mut file = File::create(&temp_path).await?;
+            let mut size: u64 = 0;
+            while let Some(chunk) = body.next().await {
+                let chunk = chunk?;
+                size += chunk.len() as u64;
+                file.write_all(&chunk).await?;
+            }
+            file.sync_all().await?;
+            fs::rename(&temp_path, &path).await?;
+            Ok(size)
+        }
+        .await;
+
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path).await;
+        }
+
+        result
+    }
+
+    async fn get(
+        &self,
+        key: &str,
+        range: Option<(u64, u64)>,
+    ) -> Result<DownloadStream, StorageError> {
+        let path = self.path_for(key)?;
+        let mut file = File::open(&path).await.map_err(|e| match e.kind() {
+            ErrorKind::NotFound => StorageError::NotFound(key.to_string()),
+            _ => e.into(),
+        })?;
+
+        match range {
+            Some((first, last)) => {
+                file.seek(SeekFrom::Start(first)).await?;
+                let reader = BufReader::new(file.take(last - first + 1));
+                Ok(Box::pin(ReaderStream::new(reader)))
+            }
+            None => Ok(Box::pin(ReaderStream::new(BufReader::new(file)))),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        let path = self.path_for(key)?;
+        match fs::remove_file(&path).await {
+            // Deleting a missing object is not an error, matching S3.
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn head(&self, key: &str) -> Result<ObjectMeta, StorageError> {
+        let path = self.path_for(key)?;
+        self.meta_for(key.to_string(), &path).await
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<ObjectMeta>, StorageError> {
+        let mut objects = Vec::new();
+        let mut pending = vec![self.root.clone()];
+
+        while let Some(dir) = pending.pop() {
+            let mut entries = match fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                // Skip in-flight uploads
+                if entry.file_name().to_string_lossy().starts_with('.') {
+                    continue;
+                }
+                if entry.file_type().await?.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+
+                let key = path
+                    .strip_prefix(&self.root)
+                    .unwrap_or(&path)
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                if key.starts_with(prefix) {
+                    objects.push(self.meta_for(key, &path).await?);
+                }
+            }
+        }
+
+        objects.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(objects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn backend() -> (LocalBackend, PathBuf) {
+        let root = std::env::temp_dir().join(format!("shelf-storage-test-{}", Uuid::new_v4()));
+        (LocalBackend::new(&root).await.unwrap(), root)
+    }
+
+    #[tokio::test]
+    async fn plain_keys_resolve_below_root() {
+        let (backend, root) = backend().await;
+        assert_eq!(backend.path_for("1/a.pdf").unwrap(), root.join("1/a.pdf"));
+        fs::remove_dir_all(root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn keys_that_escape_root_are_rejected() {
+        let (backend, root) = backend().await;
+        for key in ["", "../a", "1/../../a", "/etc/passwd", "./a"] {
+            assert!(
+                matches!(backend.path_for(key), Err(StorageError::InvalidKey(_))),
+                "{:?} was accepted",
+                key
+            );
+        }
+        fs::remove_dir_all(root).await.unwrap();
+    }
+}
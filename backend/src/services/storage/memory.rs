@@ -0,0 +1,107 @@
+use super::{DownloadStream, ObjectMeta, StorageBackend, StorageError, UploadStream};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use futures_util::{stream, StreamExt};
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+struct StoredObject {
+    data: Bytes,
+    content_type: String,
+    last_modified: SystemTime,
+}
+
+/// Keeps objects in process memory. Intended for tests and local
+/// experiments; everything is lost on restart.
+#[derive(Default)]
+pub struct MemoryBackend {
+    objects: RwLock<BTreeMap<String, StoredObject>>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn meta_for(key: &str, object: &StoredObject) -> ObjectMeta {
+        ObjectMeta {
+            key: key.to_string(),
+            size: object.data.len() as u64,
+            content_type: Some(object.content_type.clone()),
+            last_modified: Some(object.last_modified),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl StorageBackend for MemoryBackend {
+    async fn put(
+        &self,
+        key: &str,
+        content_type: &str,
+        mut body: UploadStream<'_>,
+    ) -> Result<u64, StorageError> {
+        let mut buffer = BytesMut::new();
+        while let Some(chunk) = body.next().await {
+            buffer.extend_from_slice(&chunk?);
+        }
+
+        let size = buffer.len() as u64;
+        self.objects.write().unwrap().insert(
+            key.to_string(),
+            StoredObject {
+                data: buffer.freeze(),
+                content_type: content_type.to_string(),
+                last_modified: SystemTime::now(),
+            },
+        );
+
+        Ok(size)
+    }
+
+    async fn get(
+        &self,
+        key: &str,
+        range: Option<(u64, u64)>,
+    ) -> Result<DownloadStream, StorageError> {
+        let objects = self.objects.read().unwrap();
+        let object = objects
+            .get(key)
+            .ok_or_else(|| StorageError::NotFound(key.to_string()))?;
+
+        let data = match range {
+            Some((first, last)) => {
+                let len = object.data.len();
+                let first = (first as usize).min(len);
+                let last = (last as usize + 1).min(len);
+                object.data.slice(first..last.max(first))
+            }
+            None => object.data.clone(),
+        };
+
+        Ok(Box::pin(stream::once(async move { Ok(data) })))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        self.objects.write().unwrap().remove(key);
+        Ok(())
+    }
+
+    async fn head(&self, key: &str) -> Result<ObjectMeta, StorageError> {
+        let objects = self.objects.read().unwrap();
+        objects
+            .get(key)
+            .map(|object| Self::meta_for(key, object))
+            .ok_or_else(|| StorageError::NotFound(key.to_string()))
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<ObjectMeta>, StorageError> {
+        let objects = self.objects.read().unwrap();
+        Ok(objects
+            .range(prefix.to_string()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, object)| Self::meta_for(key, object))
+            .collect())
+    }
+}
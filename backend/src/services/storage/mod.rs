@@ -0,0 +1,294 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use derive_more::Display;
+use futures_util::{Stream, StreamExt};
+use std::error::Error;
+use std::fmt::Display as FmtDisplay;
+use std::io::Error as IoError;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+pub mod local;
+pub mod memory;
+pub mod s3;
+
+pub use local::LocalBackend;
+pub use memory::MemoryBackend;
+pub use s3::S3Backend;
+
+/// Body handed to a backend on upload. Backends must stop and discard the
+/// partial object as soon as the stream yields an error.
+pub type UploadStream<'a> = Pin<Box<dyn Stream<Item = Result<Bytes, StorageError>> + 'a>>;
+
+/// Body returned by a backend on download.
+pub type DownloadStream = Pin<Box<dyn Stream<Item = Result<Bytes, IoError>> + Send>>;
+
+#[derive(Debug, Display)]
+pub enum StorageError {
+    #[display(fmt = "Storage limit exceeded")]
+    QuotaExceeded,
+
+    #[display(fmt = "Upload stream error: {}", _0)]
+    Stream(String),
+
+    #[display(fmt = "Object not found: {}", _0)]
+    NotFound(String),
+
+    #[display(fmt = "Invalid object key: {}", _0)]
+    InvalidKey(String),
+
+    #[display(fmt = "Storage backend error: {}", _0)]
+    Backend(String),
+}
+
+impl Error for StorageError {}
+
+impl From<IoError> for StorageError {
+    fn from(err: IoError) -> Self {
+        StorageError::Backend(err.to_string())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ObjectMeta {
+    pub key: String,
+    pub size: u64,
+    pub content_type: Option<String>,
+    pub last_modified: Option<SystemTime>,
+}
+
+/// Object store used for document contents.
+///
+/// Futures are not required to be `Send` so that uploads can be fed
+/// straight from an `actix_multipart` field.
+#[async_trait(?Send)]
+pub trait StorageBackend: Send + Sync {
+    /// Stores the stream under `key` and returns the number of bytes written.
+    async fn put(
+        &self,
+        key: &str,
+        content_type: &str,
+        body: UploadStream<'_>,
+    ) -> Result<u64, StorageError>;
+
+    /// Streams an object, or only the inclusive byte range `(first, last)`.
+    async fn get(
+        &self,
+        key: &str,
+        range: Option<(u64, u64)>,
+    ) -> Result<DownloadStream, StorageError>;
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError>;
+
+    async fn head(&self, key: &str) -> Result<ObjectMeta, StorageError>;
+
+    /// Lists every object whose key starts with `prefix`, ordered by key.
+    async fn list(&self, prefix: &str) -> Result<Vec<ObjectMeta>, StorageError>;
+}
+
+#[derive(Clone)]
+pub struct StorageService {
+    backend: Arc<dyn StorageBackend>,
+}
+
+impl StorageService {
+    pub fn new(backend: Arc<dyn StorageBackend>) -> Self {
+        Self { backend }
+    }
+
+    /// Streams `body` into the backend without buffering the whole object in
+    /// memory. The upload is aborted as soon as the running size would
+    /// exceed `remaining_quota` bytes.
+    ///
+    /// Returns the number of bytes stored.
+    pub async fn upload_stream<'a, S, E>(
+        &self,
+        key: &str,
+        content_type: &str,
+        body: S,
+        remaining_quota: i64,
+    ) -> Result<i64, StorageError>
+    where
+        S: Stream<Item = Result<Bytes, E>> + 'a,
+        E: FmtDisplay,
+    {
+        let mut size: i64 = 0;
+        let body = body.map(move |chunk| {
+            let chunk = chunk.map_err(|e| StorageError::Stream(e.to_string()))?;
+            size += chunk.len() as i64;
+            if size > remaining_quota {
+                return Err(StorageError::QuotaExceeded);
+            }
+            Ok(chunk)
+        });
+
+        let size = self.backend.put(key, content_type, Box::pin(body)).await?;
+        Ok(size as i64)
+    }
+
+    /// Streams an object. When `range` is given, only the inclusive byte
+    /// range `(first, last)` is fetched.
+    pub async fn download_file(
+        &self,
+        key: &str,
+        range: Option<(u64, u64)>,
+    ) -> Result<DownloadStream, StorageError> {
+        self.backend.get(key, range).await
+    }
+
+    pub async fn delete_file(&self, key: &str) -> Result<(), StorageError> {
+        self.backend.delete(key).await
+    }
+
+    pub async fn file_info(&self, key: &str) -> Result<ObjectMeta, StorageError> {
+        self.backend.head(key).await
+    }
+
+    pub async fn list_files(&self, prefix: &str) -> Result<Vec<ObjectMeta>, StorageError> {
+        self.backend.list(prefix).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream;
+    use std::convert::Infallible;
+
+    fn service() -> StorageService {
+        StorageService::new(Arc::new(MemoryBackend::new()))
+    }
+
+    fn chunks(parts: &[&'static [u8]]) -> impl Stream<Item = Result<Bytes, Infallible>> {
+        stream::iter(
+            parts
+                .iter()
+                .map(|part| Ok(Bytes::from_static(part)))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    async fn read(storage: &StorageService, key: &str, range: Option<(u64, u64)>) -> Vec<u8> {
+        let mut body = storage.download_file(key, range).await.unwrap();
+        let mut data = Vec::new();
+        while let Some(chunk) = body.next().await {
+            data.extend_from_slice(&chunk.unwrap());
+        }
+        data
+    }
+
+    #[tokio::test]
+    async fn upload_stores_every_chunk() {
+        let storage = service();
+        let size = storage
+            .upload_stream("1/a.txt", "text/plain", chunks(&[b"hello ", b"world"]), 100)
+            .await
+            .unwrap();
+
+        assert_eq!(size, 11);
+        assert_eq!(read(&storage, "1/a.txt", None).await, b"hello world");
+        let info = storage.file_info("1/a.txt").await.unwrap();
+        assert_eq!(info.size, 11);
+        assert_eq!(info.content_type.as_deref(), Some("text/plain"));
+    }
+
+    #[tokio::test]
+    async fn upload_over_quota_is_aborted() {
+        let storage = service();
+        let result = storage
+            .upload_stream("1/a.txt", "text/plain", chunks(&[b"12345", b"67890"]), 8)
+            .await;
+
+        assert!(matches!(result, Err(StorageError::QuotaExceeded)));
+        assert!(matches!(
+            storage.file_info("1/a.txt").await,
+            Err(StorageError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn upload_exactly_at_quota_succeeds() {
+        let storage = service();
+        let size = storage
+            .upload_stream("1/a.txt", "text/plain", chunks(&[b"12345", b"678"]), 8)
+            .await
+            .unwrap();
+        assert_eq!(size, 8);
+    }
+
+    #[tokio::test]
+    async fn upload_stream_error_stores_nothing() {
+        let storage = service();
+        let body = stream::iter(vec![
+            Ok(Bytes::from_static(b"part")),
+            Err("connection reset"),
+        ]);
+        let result = storage
+            .upload_stream("1/a.txt", "text/plain", body, 100)
+            .await;
+
+        assert!(matches!(result, Err(StorageError::Stream(_))));
+        assert!(storage.file_info("1/a.txt").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn ranged_download_returns_inclusive_range() {
+        let storage = service();
+        storage
+            .upload_stream("1/a.txt", "text/plain", chunks(&[b"0123456789"]), 100)
+            .await
+            .unwrap();
+
+        assert_eq!(read(&storage, "1/a.txt", Some((2, 5))).await, b"2345");
+        assert_eq!(read(&storage, "1/a.txt", Some((8, 100))).await, b"89");
+        assert_eq!(read(&storage, "1/a.txt", Some((20, 30))).await, b"");
+    }
+
+    #[tokio::test]
+    async fn download_of_missing_object_is_not_found() {
+        let storage = service();
+        assert!(matches!(
+            storage.download_file("1/missing", None).await,
+            Err(StorageError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn list_returns_prefix_in_key_order() {
+        let storage = service();
+        for key in ["2/c", "1/b", "1/a", "10/d"] {
+            storage
+                .upload_stream(key, "text/plain", chunks(&[b"x"]), 100)
+                .await
+                .unwrap();
+        }
+
+        let keys: Vec<String> = storage
+            .list_files("1/")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|object| object.key)
+            .collect();
+        assert_eq!(keys, ["1/a", "1/b"]);
+    }
+
+    #[tokio::test]
+    async fn delete_removes_object() {
+        let storage = service();
+        storage
+            .upload_stream("1/a", "text/plain", chunks(&[b"x"]), 100)
+            .await
+            .unwrap();
+
+        storage.delete_file("1/a").await.unwrap();
+        assert!(matches!(
+            storage.file_info("1/a").await,
+            Err(StorageError::NotFound(_))
+        ));
+        assert!(storage.list_files("1/").await.unwrap().is_empty());
+        // Deleting again is not an error
+        storage.delete_file("1/a").await.unwrap();
+    }
+}
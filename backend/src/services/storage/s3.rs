@@ -0,0 +1,322 @@
+use super::{DownloadStream, ObjectMeta, StorageBackend, StorageError, UploadStream};
+use async_trait::async_trait;
+use aws_config::Region;
+use aws_sdk_s3::config::Builder;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use aws_sdk_s3::Client;
+use bytes::{Bytes, BytesMut};
+use futures_util::StreamExt;
+use std::error::Error;
+use std::time::SystemTime;
+use tokio_util::io::ReaderStream;
+
+/// Size of each part sent during a multipart upload. S3 requires every part
+/// except the last one to be at least 5 MiB.
+const MULTIPART_PART_SIZE: usize = 8 * 1024 * 1024;
+
+pub struct S3Backend {
+    client: Client,
+    bucket: String,
+}
+
+impl S3Backend {
+    pub async fn new(
+        endpoint: String,
+        bucket: String,
+        region_str: String,
+    ) -> Result<Self, Box<dyn Error>> {
+        println!("Initializing storage service with endpoint: {}", endpoint);
+        println!("Using bucket: {}", bucket);
+        println!("Using region: {}", region_str);
+
+        let region = Region::new(region_str);
+
+        let config = aws_config::defaults(aws_config::BehaviorVersion::latest())
+            .endpoint_url(endpoint)
+            .region(region)
+            .load()
+            .await;
+
+        let s3_config = Builder::from(&config).force_path_style(true).build();
+
+        let client = Client::from_conf(s3_config);
+
+        // Ensure bucket exists
+        let backend = Self {
+            client,
+            bucket: bucket.clone(),
+        };
+        backend.ensure_bucket_exists().await?;
+
+        Ok(backend)
+    }
+
+    async fn ensure_bucket_exists(&self) -> Result<(), Box<dyn Error>> {
+        println!("Checking if bucket exists: {}", self.bucket);
+
+        // Try to get bucket location to check if it exists
+        match self
+            .client
+            .get_bucket_location()
+            .bucket(&self.bucket)
+            .send()
+            .await
+        {
+            Ok(_) => {
+                println!("Bucket already exists: {}", self.bucket);
+                Ok(())
+            }
+            Err(_) => {
+                println!("Creating bucket: {}", self.bucket);
+                self.client
+                    .create_bucket()
+                    .bucket(&self.bucket)
+                    .send()
+                    .await?;
+                println!("Bucket created successfully: {}", self.bucket);
+                Ok(())
+            }
+        }
+    }
+
+    async fn upload_parts(
+        &self,
+        key: &str,
+        upload_id: &str,
+        mut body: UploadStream<'_>,
+    ) -> Result<(Vec<CompletedPart>, u64), StorageError> {
+        let mut parts = Vec::new();
+        let mut size: u64 = 0;
+        let mut buffer = BytesMut::with_capacity(MULTIPART_PART_SIZE);
+
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk?;
+            size += chunk.len() as u64;
+
+            buffer.extend_from_slice(&chunk);
+            while buffer.len() >= MULTIPART_PART_SIZE {
+                let part = buffer.split_to(MULTIPART_PART_SIZE).freeze();
+                parts.push(
+                    self.upload_part(key, upload_id, parts.len() as i32 + 1, part)
+                        .await?,
+                );
+            }
+        }
+
+        // The last part may be smaller than the minimum part size. An empty
+        // body still needs one part for S3 to accept the upload.
+        if !buffer.is_empty() || parts.is_empty() {
+            let part = buffer.freeze();
+            parts.push(
+                self.upload_part(key, upload_id, parts.len() as i32 + 1, part)
+                    .await?,
+            );
+        }
+
+        Ok((parts, size))
+    }
+
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        data: Bytes,
+    ) -> Result<CompletedPart, StorageError> {
+        println!(
+            "Uploading part {} ({} bytes) for {}",
+            part_number,
+            data.len(),
+            key
+        );
+
+        let result = self
+            .client
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .body(ByteStream::from(data))
+            .send()
+            .await
+            .map_err(|e| StorageError::Backend(e.to_string()))?;
+
+        Ok(CompletedPart::builder()
+            .set_e_tag(result.e_tag().map(str::to_string))
+            .part_number(part_number)
+            .build())
+    }
+}
+
+#[async_trait(?Send)]
+impl StorageBackend for S3Backend {
+    /// Streams `body` into S3 as a multipart upload. The upload is aborted if
+    /// the stream or any part fails.
+    async fn put(
+        &self,
+        key: &str,
+        content_type: &str,
+        body: UploadStream<'_>,
+    ) -> Result<u64, StorageError> {
+        println!("Starting multipart upload to S3: {}", key);
+        println!("Content type: {}", content_type);
+        println!("Bucket: {}", self.bucket);
+
+        let upload = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type(content_type)
+            .send()
+            .await
+            .map_err(|e| StorageError::Backend(e.to_string()))?;
+
+        let upload_id = upload
+            .upload_id()
+            .ok_or_else(|| StorageError::Backend("Missing upload id".into()))?
+            .to_string();
+
+        let result = match self.upload_parts(key, &upload_id, body).await {
+            Ok((parts, size)) => self
+                .client
+                .complete_multipart_upload()
+                .bucket(&self.bucket)
+                .key(key)
+                .upload_id(&upload_id)
+                .multipart_upload(
+                    CompletedMultipartUpload::builder()
+                        .set_parts(Some(parts))
+                        .build(),
+                )
+                .send()
+                .await
+                .map(|_| size)
+                .map_err(|e| StorageError::Backend(e.to_string())),
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(size) => {
+                println!("Multipart upload completed: {} ({} bytes)", key, size);
+                Ok(size)
+            }
+            Err(e) => {
+                println!("Aborting multipart upload {}: {}", key, e);
+                if let Err(abort_err) = self
+                    .client
+                    .abort_multipart_upload()
+                    .bucket(&self.bucket)
+                    .key(key)
+                    .upload_id(&upload_id)
+                    .send()
+                    .await
+                {
+                    println!("Failed to abort multipart upload {}: {}", key, abort_err);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    async fn get(
+        &self,
+        key: &str,
+        range: Option<(u64, u64)>,
+    ) -> Result<DownloadStream, StorageError> {
+        println!("Downloading file from S3: {}", key);
+        println!("Bucket: {}", self.bucket);
+
+        let result = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .set_range(range.map(|(first, last)| format!("bytes={}-{}", first, last)))
+            .send()
+            .await
+            .map_err(|e| match e.into_service_error() {
+                e if e.is_no_such_key() => StorageError::NotFound(key.to_string()),
+                e => StorageError::Backend(e.to_string()),
+            })?;
+
+        println!("Download result: {:?}", result);
+
+        let async_read = result.body.into_async_read();
+        let buffered_reader = tokio::io::BufReader::new(async_read);
+        let stream = ReaderStream::new(buffered_reader);
+
+        Ok(Box::pin(stream))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        println!("Deleting file from S3: {}", key);
+        println!("Bucket: {}", self.bucket);
+
+        let result = self
+            .client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| StorageError::Backend(e.to_string()))?;
+
+        println!("Delete result: {:?}", result);
+
+        Ok(())
+    }
+
+    async fn head(&self, key: &str) -> Result<ObjectMeta, StorageError> {
+        let result = self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| match e.into_service_error() {
+                e if e.is_not_found() => StorageError::NotFound(key.to_string()),
+                e => StorageError::Backend(e.to_string()),
+            })?;
+
+        Ok(ObjectMeta {
+            key: key.to_string(),
+            size: result.content_length().unwrap_or(0) as u64,
+            content_type: result.content_type().map(str::to_string),
+            last_modified: result
+                .last_modified()
+                .and_then(|t| SystemTime::try_from(*t).ok()),
+        })
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<ObjectMeta>, StorageError> {
+        let mut objects = Vec::new();
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(prefix)
+            .into_paginator()
+            .send();
+
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(|e| StorageError::Backend(e.to_string()))?;
+            for object in page.contents() {
+                let Some(key) = object.key() else { continue };
+                objects.push(ObjectMeta {
+                    key: key.to_string(),
+                    size: object.size().unwrap_or(0) as u64,
+                    content_type: None,
+                    last_modified: object
+                        .last_modified()
+                        .and_then(|t| SystemTime::try_from(*t).ok()),
+                });
+            }
+        }
+
+        Ok(objects)
+    }
+}
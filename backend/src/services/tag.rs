@@ -0,0 +1,87 @@
+use crate::{
+    error::AppError,
+    models::{
+        document_tag::{self, Entity as DocumentTag},
+        tag::{self, Entity as Tag},
+    },
+};
+use chrono::Utc;
+use sea_orm::{
+    sea_query::OnConflict, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder,
+    Set,
+};
+use std::collections::HashMap;
+
+const MAX_NAME_LENGTH: usize = 64;
+
+/// Trims a tag name and checks it. Commas are reserved as the separator of
+/// the `tags` list filter.
+pub fn validate_name(name: &str) -> Result<String, AppError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(AppError::BadRequest(format!(
+            "Tag name must be between 1 and {} characters",
+            MAX_NAME_LENGTH
+        )));
+    }
+    if name.contains(',') || name.chars().any(char::is_control) {
+        return Err(AppError::BadRequest(
+            "Tag name cannot contain commas or control characters".into(),
+        ));
+    }
+    Ok(name.to_string())
+}
+
+/// The user's tags with these names, creating the ones that do not exist.
+pub async fn find_or_create(
+    db: &DatabaseConnection,
+    user_id: i32,
+    names: &[String],
+) -> Result<Vec<tag::Model>, AppError> {
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let new_tags = names.iter().map(|name| tag::ActiveModel {
+        user_id: Set(user_id),
+        name: Set(name.clone()),
+        created_at: Set(Utc::now().into()),
+        ..Default::default()
+    });
+    Tag::insert_many(new_tags)
+        .on_conflict(
+            OnConflict::columns([tag::Column::UserId, tag::Column::Name])
+                .do_nothing()
+                .to_owned(),
+        )
+        .do_nothing()
+        .exec(db)
+        .await?;
+
+    Ok(Tag::find()
+        .filter(tag::Column::UserId.eq(user_id))
+        .filter(tag::Column::Name.is_in(names.to_vec()))
+        .all(db)
+        .await?)
+}
+
+/// Tag names of each document, sorted. Documents without tags are left out.
+pub async fn names_by_document(
+    db: &DatabaseConnection,
+    document_ids: &[i32],
+) -> Result<HashMap<i32, Vec<String>>, AppError> {
+    let rows = DocumentTag::find()
+        .filter(document_tag::Column::DocumentId.is_in(document_ids.to_vec()))
+        .find_also_related(Tag)
+        .order_by_asc(tag::Column::Name)
+        .all(db)
+        .await?;
+
+    let mut names: HashMap<i32, Vec<String>> = HashMap::new();
+    for (link, tag) in rows {
+        if let Some(tag) = tag {
+            names.entry(link.document_id).or_default().push(tag.name);
+        }
+    }
+    Ok(names)
+}
@@ -0,0 +1,188 @@
+use crate::{
+    error::AppError,
+    models::{
+        document::{self, Entity as Document, TextStatus},
+        document_page::{self, Entity as DocumentPage},
+    },
+    services::{
+        pdf_processor::{Pdf, PdfError},
+        storage::StorageService,
+    },
+};
+use actix_web::web;
+use futures_util::StreamExt;
+use sea_orm::{
+    ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, QuerySelect, Set,
+    TransactionTrait,
+};
+
+/// Larger files are not read for text; they would have to be held in memory.
+const MAX_FILE_SIZE: i64 = 100 * 1024 * 1024;
+/// Plain text files are split into pages of at most this many bytes.
+const TEXT_PAGE_SIZE: usize = 64 * 1024;
+/// Pages written per insert statement.
+const INSERT_BATCH: usize = 50;
+
+/// MIME type without parameters such as `; charset=utf-8`.
+fn essence(mime_type: &str) -> &str {
+    mime_type.split(';').next().unwrap_or_default().trim()
+}
+
+/// Whether text can be extracted from files of this type.
+pub fn supports(mime_type: &str) -> bool {
+    matches!(essence(mime_type), "application/pdf" | "text/plain")
+}
+
+/// Extracts the text of a freshly uploaded document in the background.
+pub fn spawn(db: DatabaseConnection, storage: StorageService, document_id: i32) {
+    actix_web::rt::spawn(async move {
+        extract_and_record(&db, &storage, document_id).await;
+    });
+}
+
+/// Picks up documents whose extraction never finished, for instance
+/// because the server restarted in between. They are done one at a time.
+pub fn resume_pending(db: DatabaseConnection, storage: StorageService) {
+    actix_web::rt::spawn(async move {
+        let pending: Vec<i32> = match Document::find()
+            .select_only()
+            .column(document::Column::Id)
+            .filter(document::Column::TextStatus.eq(TextStatus::Pending))
+            .order_by_asc(document::Column::Id)
+            .into_tuple()
+            .all(&db)
+            .await
+        {
+            Ok(pending) => pending,
+            Err(e) => {
+                println!("Failed to list documents pending text extraction: {}", e);
+                return;
+            }
+        };
+
+        if !pending.is_empty() {
+            println!("Resuming text extraction of {} documents", pending.len());
+        }
+        for document_id in pending {
+            extract_and_record(&db, &storage, document_id).await;
+        }
+    });
+}
+
+async fn extract_and_record(db: &DatabaseConnection, storage: &StorageService, document_id: i32) {
+    let status = match extract(db, storage, document_id).await {
+        Ok(status) => status,
+        Err(e) => {
+            println!("Text extraction of document {} failed: {}", document_id, e);
+            TextStatus::Failed
+        }
+    };
+
+    let result = Document::update_many()
+        .set(document::ActiveModel {
+            text_status: Set(status),
+            ..Default::default()
+        })
+        .filter(document::Column::Id.eq(document_id))
+        .exec(db)
+        .await;
+    if let Err(e) = result {
+        println!(
+            "Failed to record text status of document {}: {}",
+            document_id, e
+        );
+    }
+}
+
+/// Reads the document's text and replaces its stored pages.
+async fn extract(
+    db: &DatabaseConnection,
+    storage: &StorageService,
+    document_id: i32,
+) -> Result<TextStatus, AppError> {
+    let document = Document::find_by_id(document_id)
+        .one(db)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Document not found".into()))?;
+    if !supports(&document.mime_type) || document.file_size > MAX_FILE_SIZE {
+        return Ok(TextStatus::Unsupported);
+    }
+
+    let mut body = storage
+        .download_file(&document.s3_key, None)
+        .await
+        .map_err(|e| AppError::InternalServerError(e.to_string()))?;
+    let mut data = Vec::with_capacity(document.file_size as usize);
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| AppError::InternalServerError(e.to_string()))?;
+        data.extend_from_slice(&chunk);
+    }
+
+    // Parsing is CPU-bound, so it stays off the async workers
+    let mime_type = essence(&document.mime_type).to_string();
+    let pages = web::block(move || match mime_type.as_str() {
+        "application/pdf" => Pdf::load(&data)?.page_texts(),
+        _ => Ok(text_pages(&String::from_utf8_lossy(&data))),
+    })
+    .await
+    .map_err(|e| AppError::InternalServerError(e.to_string()))?;
+    let pages = match pages {
+        Ok(pages) => pages,
+        Err(PdfError::Encrypted) => return Ok(TextStatus::Unsupported),
+        Err(e) => return Err(AppError::InternalServerError(e.to_string())),
+    };
+
+    let rows: Vec<document_page::ActiveModel> = pages
+        .into_iter()
+        .enumerate()
+        .filter(|(_, content)| !content.is_empty())
+        .map(|(index, content)| document_page::ActiveModel {
+            document_id: Set(document_id),
+            page_number: Set(index as i32 + 1),
+            content: Set(content),
+        })
+        .collect();
+
+    let txn = db.begin().await?;
+    DocumentPage::delete_many()
+        .filter(document_page::Column::DocumentId.eq(document_id))
+        .exec(&txn)
+        .await?;
+    for batch in rows.chunks(INSERT_BATCH) {
+        DocumentPage::insert_many(batch.to_vec()).exec(&txn).await?;
+    }
+    txn.commit().await?;
+
+    Ok(TextStatus::Completed)
+}
+
+/// Splits a text file into pages at form feeds, and long stretches further
+/// at line breaks, so each page stays small enough to index.
+fn text_pages(text: &str) -> Vec<String> {
+    // Postgres text cannot hold NUL, and other control characters do not help search
+    let text: String = text
+        .chars()
+        .map(|c| match c {
+            '\n' | '\x0c' => c,
+            c if c.is_control() => ' ',
+            c => c,
+        })
+        .collect();
+
+    let mut pages = Vec::new();
+    for mut section in text.split('\x0c') {
+        while section.len() > TEXT_PAGE_SIZE {
+            let mut end = TEXT_PAGE_SIZE;
+            while !section.is_char_boundary(end) {
+                end -= 1;
+            }
+            if let Some(newline) = section[..end].rfind('\n') {
+                end = newline + 1;
+            }
+            pages.push(section[..end].trim().to_string());
+            section = &section[end..];
+        }
+        pages.push(section.trim().to_string());
+    }
+    pages
+}
@@ -0,0 +1,115 @@
+//! Database fixtures for tests that need Postgres.
+//!
+//! Set `TEST_DATABASE_URL` to a database the tests may write to. Every test
+//! gets its own schema, created from `docker/postgres/init.sql` and dropped
+//! again afterwards. Without the variable these tests are skipped.
+
+use sea_orm::{
+    ConnectOptions, ConnectionTrait, Database, DatabaseConnection, DbBackend, Statement,
+};
+use std::env;
+use std::ops::Deref;
+
+const SCHEMA_SQL: &str = include_str!("../../docker/postgres/init.sql");
+
+pub struct TestDb {
+    db: DatabaseConnection,
+    url: String,
+    schema: String,
+}
+
+impl TestDb {
+    /// Creates a fresh schema, or returns `None` when `TEST_DATABASE_URL` is
+    /// not set.
+    pub async fn new() -> Option<Self> {
+        Self::with_schema(SCHEMA_SQL).await
+    }
+
+    /// Like `new`, with the tables created by `sql` instead of init.sql.
+    pub async fn with_schema(sql: &str) -> Option<Self> {
+        let Ok(url) = env::var("TEST_DATABASE_URL") else {
+            println!("TEST_DATABASE_URL is not set, skipping");
+            return None;
+        };
+        let schema = format!("test_{}", uuid::Uuid::new_v4().simple());
+
+        let admin = Database::connect(&url).await.unwrap();
+        admin
+            .execute_unprepared(&format!("CREATE SCHEMA {}", schema))
+            .await
+            .unwrap();
+        admin.close().await.unwrap();
+
+        // Named after the schema, so its connections can be told apart later
+        let separator = if url.contains('?') { '&' } else { '?' };
+        let mut options =
+            ConnectOptions::new(format!("{}{}application_name={}", url, separator, schema));
+        options
+            .max_connections(8)
+            .set_schema_search_path(schema.clone())
+            .sqlx_logging(false);
+        let db = Database::connect(options).await.unwrap();
+        db.execute_unprepared(sql).await.unwrap();
+
+        Some(Self { db, url, schema })
+    }
+
+    pub fn connection(&self) -> DatabaseConnection {
+        self.db.clone()
+    }
+
+    /// Inserts an active user and returns its id.
+    pub async fn user(&self, email: &str) -> i32 {
+        let row = self
+            .db
+            .query_one(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                "INSERT INTO users (email, full_name, email_verified) VALUES ($1, 'Test', TRUE) RETURNING id",
+                [email.into()],
+            ))
+            .await
+            .unwrap()
+            .unwrap();
+        row.try_get("", "id").unwrap()
+    }
+}
+
+impl Deref for TestDb {
+    type Target = DatabaseConnection;
+
+    fn deref(&self) -> &DatabaseConnection {
+        &self.db
+    }
+}
+
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        // Runs on its own runtime so the schema is also dropped after a
+        // failed assertion. The test's runtime is blocked meanwhile, so
+        // connections it has not returned yet (with their locks) are ended.
+        let url = self.url.clone();
+        let schema = self.schema.clone();
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(async {
+                if let Ok(db) = Database::connect(&url).await {
+                    let _ = db
+                        .execute_unprepared(&format!(
+                            "SELECT pg_terminate_backend(pid) FROM pg_stat_activity \
+                             WHERE application_name = '{}'",
+                            schema
+                        ))
+                        .await;
+                    let _ = db
+                        .execute_unprepared(&format!("DROP SCHEMA {} CASCADE", schema))
+                        .await;
+                }
+            });
+        })
+        .join()
+        .ok();
+    }
+}
@@ -21,6 +21,22 @@ print_info() {
     echo -e "${YELLOW}ℹ $1${NC}"
 }
 
+# Function to load the bearer token: a personal access token from
+# SHELF_TOKEN if set, otherwise the one saved by login
+load_token() {
+    if [ -n "$SHELF_TOKEN" ]; then
+        TOKEN="$SHELF_TOKEN"
+        return 0
+    fi
+
+    if [ ! -f .token ]; then
+        echo -e "${RED}Token file not found. Please run login first or set SHELF_TOKEN.${NC}"
+        return 1
+    fi
+
+    TOKEN=$(cat .token)
+}
+
 # Function to print results
 print_result() {
     if [ $? -eq 0 ]; then
@@ -79,12 +95,7 @@ login() {
 upload() {
     print_header "Document Upload"
     
-    if [ ! -f .token ]; then
-        echo -e "${RED}Token file not found. Please run login first.${NC}"
-        return 1
-    fi
-    
-    TOKEN=$(cat .token)
+    load_token || return 1
     print_info "Using token: ${TOKEN:0:20}..."
     print_info "Uploading test document"
     
@@ -125,12 +136,7 @@ upload() {
 list() {
     print_header "Document Listing"
     
-    if [ ! -f .token ]; then
-        echo -e "${RED}Token file not found. Please run login first.${NC}"
-        return 1
-    fi
-    
-    TOKEN=$(cat .token)
+    load_token || return 1
     print_info "Listing documents"
     
     LIST_RESPONSE=$(curl -s -X GET "$BASE_URL/documents" \
@@ -145,12 +151,7 @@ list() {
 download() {
     print_header "Document Download"
     
-    if [ ! -f .token ]; then
-        echo -e "${RED}Token file not found. Please run login first.${NC}"
-        return 1
-    fi
-    
-    TOKEN=$(cat .token)
+    load_token || return 1
     print_info "Downloading document"
     
     # Get the first document ID from the list
@@ -179,12 +180,7 @@ download() {
 delete() {
     print_header "Document Deletion"
     
-    if [ ! -f .token ]; then
-        echo -e "${RED}Token file not found. Please run login first.${NC}"
-        return 1
-    fi
-    
-    TOKEN=$(cat .token)
+    load_token || return 1
     print_info "Deleting document"
     
     # Get the first document ID from the list
@@ -1,54 +1,213 @@
+-- Migrations in backend/migrations that this schema already contains. A
+-- database created from this file skips them; older ones apply them when the
+-- server starts.
+CREATE TABLE IF NOT EXISTS schema_migrations (
+    version VARCHAR(100) PRIMARY KEY,
+    applied_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO schema_migrations (version) VALUES ('0001_payment_providers');
+INSERT INTO schema_migrations (version) VALUES ('0002_plan_catalog');
+INSERT INTO schema_migrations (version) VALUES ('0003_plan_changes');
+INSERT INTO schema_migrations (version) VALUES ('0004_sessions');
+INSERT INTO schema_migrations (version) VALUES ('0005_email_verification');
+INSERT INTO schema_migrations (version) VALUES ('0006_two_factor');
+INSERT INTO schema_migrations (version) VALUES ('0007_social_login');
+INSERT INTO schema_migrations (version) VALUES ('0008_personal_access_tokens');
+INSERT INTO schema_migrations (version) VALUES ('0009_login_throttles');
+INSERT INTO schema_migrations (version) VALUES ('0010_account_management');
+INSERT INTO schema_migrations (version) VALUES ('0011_document_details');
+INSERT INTO schema_migrations (version) VALUES ('0012_folders');
+INSERT INTO schema_migrations (version) VALUES ('0013_tags_and_collections');
+INSERT INTO schema_migrations (version) VALUES ('0014_document_text');
+INSERT INTO schema_migrations (version) VALUES ('0015_pdf_info');
+INSERT INTO schema_migrations (version) VALUES ('0016_content_hash');
+
 -- Create users table
 CREATE TABLE IF NOT EXISTS users (
     id SERIAL PRIMARY KEY,
     email VARCHAR(255) NOT NULL UNIQUE,
-    password_hash VARCHAR(255) NOT NULL,
+    password_hash VARCHAR(255),
     full_name VARCHAR(255) NOT NULL,
     is_admin BOOLEAN NOT NULL DEFAULT FALSE,
     is_active BOOLEAN NOT NULL DEFAULT TRUE,
+    email_verified BOOLEAN NOT NULL DEFAULT FALSE,
+    pending_email VARCHAR(255),
+    totp_secret VARCHAR(64),
+    totp_enabled BOOLEAN NOT NULL DEFAULT FALSE,
+    totp_last_step BIGINT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Create sessions table (one row per refresh token family)
+CREATE TABLE IF NOT EXISTS sessions (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    refresh_token_hash VARCHAR(64) NOT NULL UNIQUE,
+    previous_token_hash VARCHAR(64),
+    user_agent VARCHAR(255),
+    expires_at TIMESTAMPTZ NOT NULL,
+    revoked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Create failed login tracking table (per email address and per client IP)
+CREATE TABLE IF NOT EXISTS login_throttles (
+    key VARCHAR(300) PRIMARY KEY,
+    failures INTEGER NOT NULL DEFAULT 0,
+    last_failed_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    locked_until TIMESTAMPTZ
+);
+
+-- Create personal access tokens table (scripts and CLI clients)
+CREATE TABLE IF NOT EXISTS personal_access_tokens (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name VARCHAR(100) NOT NULL,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    token_prefix VARCHAR(20) NOT NULL,
+    scopes VARCHAR(255) NOT NULL,
+    expires_at TIMESTAMPTZ,
+    last_used_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Create external identities table (social login)
+CREATE TABLE IF NOT EXISTS user_identities (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    provider VARCHAR(50) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    email VARCHAR(255),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_login_at TIMESTAMPTZ,
+    UNIQUE (provider, subject),
+    UNIQUE (user_id, provider)
+);
+
+-- Create pending social login requests table
+CREATE TABLE IF NOT EXISTS oidc_states (
+    id SERIAL PRIMARY KEY,
+    state VARCHAR(64) NOT NULL UNIQUE,
+    provider VARCHAR(50) NOT NULL,
+    code_verifier VARCHAR(128) NOT NULL,
+    nonce VARCHAR(64) NOT NULL,
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Create two-factor recovery codes table
+CREATE TABLE IF NOT EXISTS recovery_codes (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    code_hash VARCHAR(64) NOT NULL,
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Create plan catalog
+CREATE TYPE billing_period AS ENUM ('none', 'monthly', 'yearly');
+
+CREATE TABLE IF NOT EXISTS plans (
+    id SERIAL PRIMARY KEY,
+    code VARCHAR(50) NOT NULL UNIQUE,
+    name VARCHAR(100) NOT NULL,
+    storage_limit_bytes BIGINT NOT NULL,
+    billing_period billing_period NOT NULL DEFAULT 'monthly',
+    features JSONB NOT NULL DEFAULT '{}',
+    is_active BOOLEAN NOT NULL DEFAULT TRUE,
     created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
     updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
 );
 
+CREATE TABLE IF NOT EXISTS plan_prices (
+    id SERIAL PRIMARY KEY,
+    plan_id INTEGER NOT NULL REFERENCES plans(id) ON DELETE CASCADE,
+    currency VARCHAR(3) NOT NULL,
+    amount DECIMAL(10,2) NOT NULL,
+    UNIQUE (plan_id, currency)
+);
+
+INSERT INTO plans (code, name, storage_limit_bytes, billing_period, features) VALUES
+    ('none', 'Free', 104857600, 'none',
+        '{"sharing": false, "support": "community"}'),
+    ('basic', 'Basic', 1073741824, 'monthly',
+        '{"sharing": "limited", "support": "basic"}'),
+    ('premium', 'Premium', 5368709120, 'monthly',
+        '{"sharing": "enhanced", "support": "priority"}'),
+    ('enterprise', 'Enterprise', 10737418240, 'monthly',
+        '{"sharing": "full", "support": "dedicated", "advanced_security": true}')
+ON CONFLICT (code) DO NOTHING;
+
+-- XAF is the production currency; the MoMo sandbox only accepts EUR
+INSERT INTO plan_prices (plan_id, currency, amount)
+SELECT plans.id, prices.currency, prices.amount
+FROM plans
+JOIN (VALUES
+    ('basic', 'XAF', 100), ('premium', 'XAF', 500), ('enterprise', 'XAF', 1000),
+    ('basic', 'EUR', 100), ('premium', 'EUR', 500), ('enterprise', 'EUR', 1000)
+) AS prices(code, currency, amount) ON prices.code = plans.code
+ON CONFLICT (plan_id, currency) DO NOTHING;
+
 -- Create payments table
-CREATE TYPE payment_status AS ENUM ('pending', 'successful', 'failed', 'cancelled');
+CREATE TYPE payment_status AS ENUM ('pending', 'successful', 'failed', 'cancelled', 'refunded');
 CREATE TYPE payment_provider AS ENUM ('mtn_momo', 'paypal');
 
 CREATE TABLE IF NOT EXISTS payments (
     id SERIAL PRIMARY KEY,
     user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    plan_id INTEGER NOT NULL REFERENCES plans(id),
     reference_id VARCHAR(255) NOT NULL UNIQUE,
-    mtn_reference_id VARCHAR(255) NOT NULL UNIQUE,
+    provider_reference_id VARCHAR(255) NOT NULL,
     amount DECIMAL(10,2) NOT NULL,
+    refunded_amount DECIMAL(10,2) NOT NULL DEFAULT 0, -- Total of all refunds so far
     currency VARCHAR(3) NOT NULL DEFAULT current_setting('app.currency', true),
-    phone_number VARCHAR(20) NOT NULL,
+    phone_number VARCHAR(20),
     provider payment_provider NOT NULL,
     status payment_status NOT NULL DEFAULT 'pending',
     provider_response JSONB,
     error_message TEXT,
     metadata JSONB DEFAULT '{}',
     created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
-    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (provider, provider_reference_id)
 );
 
 -- Create subscriptions table
 CREATE TABLE IF NOT EXISTS subscriptions (
     id SERIAL PRIMARY KEY,
-    user_id INTEGER NOT NULL REFERENCES users(id),
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
     stripe_customer_id VARCHAR(255) NOT NULL,
     stripe_subscription_id VARCHAR(255) NOT NULL,
     status VARCHAR(50) NOT NULL,
-    plan VARCHAR(50) NOT NULL DEFAULT 'none',
-    storage_limit_bytes BIGINT NOT NULL DEFAULT 0, -- No storage by default
+    plan_id INTEGER NOT NULL REFERENCES plans(id),
     current_period_end TIMESTAMPTZ NOT NULL,
     created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
     updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
 );
 
+-- Create plan change audit table
+CREATE TYPE plan_change_source AS ENUM ('payment', 'admin');
+
+CREATE TABLE IF NOT EXISTS plan_changes (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    previous_plan_id INTEGER REFERENCES plans(id),
+    new_plan_id INTEGER NOT NULL REFERENCES plans(id),
+    changed_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    source plan_change_source NOT NULL,
+    payment_id INTEGER REFERENCES payments(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
 -- Create pdfs table
 CREATE TABLE IF NOT EXISTS pdfs (
     id SERIAL PRIMARY KEY,
-    user_id INTEGER NOT NULL REFERENCES users(id),
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
     title VARCHAR(255) NOT NULL,
     file_path VARCHAR(255) NOT NULL,
     file_size BIGINT NOT NULL,
@@ -58,27 +217,112 @@ CREATE TABLE IF NOT EXISTS pdfs (
     updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
 );
 
+-- Create folders table
+CREATE TABLE IF NOT EXISTS folders (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    parent_id INTEGER REFERENCES folders(id),
+    name VARCHAR(255) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
 -- Create documents table
+CREATE TYPE text_status AS ENUM ('pending', 'completed', 'failed', 'unsupported');
+
 CREATE TABLE IF NOT EXISTS documents (
     id SERIAL PRIMARY KEY,
     user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    folder_id INTEGER REFERENCES folders(id),
     filename VARCHAR(255) NOT NULL,
     file_size BIGINT NOT NULL,
     mime_type VARCHAR(127) NOT NULL,
-    s3_key VARCHAR(255) NOT NULL UNIQUE,
+    s3_key VARCHAR(255) NOT NULL, -- Shared by documents linked to the same file
+    content_hash VARCHAR(64), -- SHA-256 of the file, hex-encoded
+    title VARCHAR(255),
+    description TEXT,
+    author VARCHAR(255),
+    metadata JSONB NOT NULL DEFAULT '{}',
+    text_status text_status NOT NULL DEFAULT 'pending',
+    page_count INTEGER,
+    pdf_info JSONB,
     created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
     updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
 );
 
+-- Create extracted text table, one row per page
+CREATE TABLE IF NOT EXISTS document_pages (
+    document_id INTEGER NOT NULL REFERENCES documents(id) ON DELETE CASCADE,
+    page_number INTEGER NOT NULL,
+    content TEXT NOT NULL,
+    content_tsv TSVECTOR GENERATED ALWAYS AS (to_tsvector('english', content)) STORED,
+    PRIMARY KEY (document_id, page_number)
+);
+
+-- Create tags tables
+CREATE TABLE IF NOT EXISTS tags (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name VARCHAR(64) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (user_id, name)
+);
+
+CREATE TABLE IF NOT EXISTS document_tags (
+    document_id INTEGER NOT NULL REFERENCES documents(id) ON DELETE CASCADE,
+    tag_id INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+    PRIMARY KEY (document_id, tag_id)
+);
+
+-- Create smart collections table
+CREATE TABLE IF NOT EXISTS collections (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name VARCHAR(255) NOT NULL,
+    filter JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (user_id, name)
+);
+
+-- Create account export table
+CREATE TYPE export_status AS ENUM ('pending', 'running', 'completed', 'failed');
+
+CREATE TABLE IF NOT EXISTS account_exports (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    status export_status NOT NULL DEFAULT 'pending',
+    storage_key VARCHAR(255),
+    file_size BIGINT,
+    error_message TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    completed_at TIMESTAMPTZ,
+    expires_at TIMESTAMPTZ
+);
+
 -- Add indexes
 CREATE INDEX IF NOT EXISTS idx_user_email ON users(email);
+CREATE INDEX IF NOT EXISTS idx_sessions_user ON sessions(user_id);
+CREATE INDEX IF NOT EXISTS idx_sessions_previous_token ON sessions(previous_token_hash);
+CREATE INDEX IF NOT EXISTS idx_personal_access_tokens_user ON personal_access_tokens(user_id);
+CREATE INDEX IF NOT EXISTS idx_recovery_codes_user ON recovery_codes(user_id);
 CREATE INDEX IF NOT EXISTS idx_pdf_user ON pdfs(user_id);
 CREATE INDEX IF NOT EXISTS idx_payments_user ON payments(user_id);
 CREATE INDEX IF NOT EXISTS idx_payments_reference ON payments(reference_id);
 CREATE INDEX IF NOT EXISTS idx_payments_status ON payments(status);
 CREATE INDEX IF NOT EXISTS idx_subscription_user ON subscriptions(user_id);
+CREATE INDEX IF NOT EXISTS idx_plan_changes_user ON plan_changes(user_id);
 CREATE INDEX IF NOT EXISTS idx_documents_user_id ON documents(user_id);
 CREATE INDEX IF NOT EXISTS idx_documents_s3_key ON documents(s3_key);
+CREATE INDEX IF NOT EXISTS idx_documents_folder ON documents(folder_id);
+CREATE INDEX IF NOT EXISTS idx_documents_content_hash ON documents(user_id, content_hash);
+CREATE INDEX IF NOT EXISTS idx_folders_user ON folders(user_id);
+CREATE INDEX IF NOT EXISTS idx_document_pages_tsv ON document_pages USING GIN (content_tsv);
+CREATE INDEX IF NOT EXISTS idx_document_tags_tag ON document_tags(tag_id);
+CREATE INDEX IF NOT EXISTS idx_collections_user ON collections(user_id);
+-- Folder names are unique among their siblings; top-level folders have no parent
+CREATE UNIQUE INDEX IF NOT EXISTS idx_folders_sibling_name ON folders(user_id, COALESCE(parent_id, 0), name);
+CREATE INDEX IF NOT EXISTS idx_account_exports_user ON account_exports(user_id);
 
 -- Create updated_at trigger function
 CREATE OR REPLACE FUNCTION update_updated_at_column()
@@ -89,8 +333,32 @@ BEGIN
 END;
 $$ LANGUAGE plpgsql;
 
+-- Add trigger for plans table
+CREATE TRIGGER update_plans_updated_at
+    BEFORE UPDATE ON plans
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
+
 -- Add trigger for payments table
 CREATE TRIGGER update_payments_updated_at
     BEFORE UPDATE ON payments
     FOR EACH ROW
-    EXECUTE FUNCTION update_updated_at_column(); 
\ No newline at end of file
+    EXECUTE FUNCTION update_updated_at_column();
+
+-- Add trigger for documents table
+CREATE TRIGGER update_documents_updated_at
+    BEFORE UPDATE ON documents
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
+
+-- Add trigger for folders table
+CREATE TRIGGER update_folders_updated_at
+    BEFORE UPDATE ON folders
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
+
+-- Add trigger for collections table
+CREATE TRIGGER update_collections_updated_at
+    BEFORE UPDATE ON collections
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
@@ -6,6 +6,12 @@ import { useToast } from '@/components/ui/use-toast';
 import api from '@/lib/api';
 import { useNavigate } from 'react-router-dom';
 
+// The server checks the content too; this only filters out obvious mistakes
+const ACCEPTED_EXTENSIONS = ['.pdf', '.docx', '.epub', '.txt', '.text', '.md', '.markdown', '.csv', '.log'];
+
+const isAccepted = (file: File) =>
+  ACCEPTED_EXTENSIONS.some(extension => file.name.toLowerCase().endsWith(extension));
+
 interface FileUploaderProps {
   onClose: () => void;
 }
@@ -73,7 +79,7 @@ const FileUploader: React.FC<FileUploaderProps> = ({ onClose }) => {
     
     if (e.dataTransfer.files && e.dataTransfer.files.length > 0) {
       const droppedFile = e.dataTransfer.files[0];
-      if (droppedFile.type === 'application/pdf') {
+      if (isAccepted(droppedFile)) {
         // Check if file size exceeds storage limit
         if (droppedFile.size + subscription.storageUsageBytes > subscription.storageLimitBytes) {
           toast({
@@ -87,7 +93,7 @@ const FileUploader: React.FC<FileUploaderProps> = ({ onClose }) => {
       } else {
         toast({
           title: "Invalid file type",
-          description: "Only PDF files are supported.",
+          description: "Only PDF, Word, EPUB and plain text files are supported.",
           variant: "destructive"
         });
       }
@@ -107,7 +113,7 @@ const FileUploader: React.FC<FileUploaderProps> = ({ onClose }) => {
     
     if (e.target.files && e.target.files.length > 0) {
       const selectedFile = e.target.files[0];
-      if (selectedFile.type === 'application/pdf') {
+      if (isAccepted(selectedFile)) {
         // Check if file size exceeds storage limit
         if (selectedFile.size + subscription.storageUsageBytes > subscription.storageLimitBytes) {
           toast({
@@ -121,7 +127,7 @@ const FileUploader: React.FC<FileUploaderProps> = ({ onClose }) => {
       } else {
         toast({
           title: "Invalid file type",
-          description: "Only PDF files are supported.",
+          description: "Only PDF, Word, EPUB and plain text files are supported.",
           variant: "destructive"
         });
       }
@@ -144,7 +150,7 @@ const FileUploader: React.FC<FileUploaderProps> = ({ onClose }) => {
     if (!file) {
       toast({
         title: "No file selected",
-        description: "Please select a PDF file to upload.",
+        description: "Please select a file to upload.",
         variant: "destructive"
       });
       return;
@@ -203,7 +209,7 @@ const FileUploader: React.FC<FileUploaderProps> = ({ onClose }) => {
               <input
                 type="file"
                 ref={fileInputRef}
-                accept=".pdf"
+                accept={ACCEPTED_EXTENSIONS.join(',')}
                 onChange={handleFileChange}
                 className="hidden"
               />
@@ -228,7 +234,7 @@ const FileUploader: React.FC<FileUploaderProps> = ({ onClose }) => {
               ) : (
                 <div className="text-center">
                   <Upload className="mx-auto mb-2 h-12 w-12 text-muted-foreground" />
-                  <p className="mb-1 font-medium">Drag and drop your PDF here</p>
+                  <p className="mb-1 font-medium">Drag and drop your document here</p>
                   <p className="mb-4 text-sm text-muted-foreground">
                     or
                   </p>
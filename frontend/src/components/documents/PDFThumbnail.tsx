@@ -1,9 +1,6 @@
-import { useEffect, useRef, useState } from "react";
+import { useEffect, useState } from "react";
 import { FileText } from "lucide-react";
-import * as pdfjsLib from "pdfjs-dist";
-
-// Set the worker source path
-pdfjsLib.GlobalWorkerOptions.workerSrc = '/pdf.worker.min.js';
+import { documentApi } from "@/lib/api";
 
 interface PDFThumbnailProps {
   documentId: number;
@@ -12,62 +9,36 @@ interface PDFThumbnailProps {
 }
 
 export default function PDFThumbnail({ documentId, className, isList = false }: PDFThumbnailProps) {
-  const canvasRef = useRef<HTMLCanvasElement>(null);
+  const [src, setSrc] = useState<string | null>(null);
   const [error, setError] = useState(false);
 
   useEffect(() => {
     let isMounted = true;
+    let objectUrl: string | null = null;
     setError(false);
+    setSrc(null);
 
     if (!documentId) {
       setError(true);
       return;
     }
 
-    const loadPdf = async () => {
-      try {
-        const token = localStorage.getItem('token');
-        if (!token) {
-          setError(true);
-          console.error("No authentication token found");
-          return;
-        }
-
-        const response = await fetch(`${import.meta.env.VITE_API_URL || 'http://localhost:8080/api'}/documents/${documentId}`, {
-          headers: {
-            'Authorization': `Bearer ${token}`
-          }
-        });
-
-        if (!response.ok) {
-          setError(true);
-          console.error(`Failed to load PDF: ${response.statusText}`);
-          return;
-        }
-
-        const arrayBuffer = await response.arrayBuffer();
-        const pdf = await pdfjsLib.getDocument({ data: arrayBuffer }).promise;
-        const page = await pdf.getPage(1);
-
-        if (canvasRef.current && isMounted) {
-          const viewport = page.getViewport({ scale: isList ? 0.35 : 0.28 });
-          const canvas = canvasRef.current;
-          const ctx = canvas.getContext("2d")!;
-          canvas.width = viewport.width;
-          canvas.height = viewport.height;
-          ctx.clearRect(0, 0, canvas.width, canvas.height);
-          page.render({ canvasContext: ctx, viewport });
-        }
-      } catch (err) {
-        console.error('Error processing PDF:', err);
-        setError(true);
-      }
-    };
-
-    loadPdf();
+    // The server renders and caches the first page, so only a small image is downloaded
+    documentApi
+      .thumbnail(documentId, isList ? 'small' : 'medium')
+      .then((blob) => {
+        if (!isMounted) return;
+        objectUrl = URL.createObjectURL(blob);
+        setSrc(objectUrl);
+      })
+      .catch((err) => {
+        console.error('Error loading thumbnail:', err);
+        if (isMounted) setError(true);
+      });
 
-    return () => { 
-      isMounted = false; 
+    return () => {
+      isMounted = false;
+      if (objectUrl) URL.revokeObjectURL(objectUrl);
     };
   }, [documentId, isList]);
 
@@ -78,10 +49,16 @@ export default function PDFThumbnail({ documentId, className, isList = false }:
       </div>
     );
   }
+  if (!src) {
+    return (
+      <div className={`rounded bg-muted animate-pulse ${isList ? "h-16 w-12" : ""} ${className ? className : ""}`} />
+    );
+  }
   return (
-    <canvas 
-      ref={canvasRef} 
-      className={`rounded bg-white border shadow ${isList ? "h-16 w-12 object-contain" : ""} ${className ? className : ""}`} 
+    <img
+      src={src}
+      alt="First page"
+      className={`rounded bg-white border shadow ${isList ? "h-16 w-12 object-contain" : ""} ${className ? className : ""}`}
       style={{ display: 'block', margin: '0 auto' }}
     />
   );
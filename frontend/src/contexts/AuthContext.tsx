@@ -11,7 +11,9 @@ interface User {
 interface AuthContextType {
   user: User | null;
   token: string | null;
-  login: (email: string, password: string) => Promise<void>;
+  /** Resolves to a challenge token when the account needs a second factor. */
+  login: (email: string, password: string) => Promise<string | null>;
+  loginTwoFactor: (challengeToken: string, code: string) => Promise<void>;
   register: (name: string, email: string, password: string) => Promise<void>;
   logout: () => void;
   isAuthenticated: boolean;
@@ -104,10 +106,28 @@ export const AuthProvider: React.FC<{ children: React.ReactNode }> = ({ children
     setIsLoading(true);
     setError(null);
       const response = await authApi.login(email, password);
+      if ('two_factor_required' in response) {
+        return response.challenge_token;
+      }
+      setToken(response.token);
+      setUser(response.user);
+      return null;
+    } catch (err) {
+      const errorMessage = getErrorMessage(err);
+      setError(errorMessage);
+      throw err;
+    } finally {
+      setIsLoading(false);
+    }
+  };
+
+  const loginTwoFactor = async (challengeToken: string, code: string) => {
+    try {
+      setIsLoading(true);
+      setError(null);
+      const response = await authApi.loginTwoFactor(challengeToken, code);
       setToken(response.token);
       setUser(response.user);
-      localStorage.setItem('token', response.token);
-      localStorage.setItem('user', JSON.stringify(response.user));
     } catch (err) {
       const errorMessage = getErrorMessage(err);
       setError(errorMessage);
@@ -121,11 +141,7 @@ export const AuthProvider: React.FC<{ children: React.ReactNode }> = ({ children
     try {
     setIsLoading(true);
     setError(null);
-      const response = await authApi.register(name, email, password);
-      setToken(response.token);
-      setUser(response.user);
-      localStorage.setItem('token', response.token);
-      localStorage.setItem('user', JSON.stringify(response.user));
+      await authApi.register(name, email, password);
     } catch (err) {
       const errorMessage = getErrorMessage(err);
       setError(errorMessage);
@@ -136,10 +152,13 @@ export const AuthProvider: React.FC<{ children: React.ReactNode }> = ({ children
   };
 
   const logout = () => {
+    // Revoke the session server-side; local state is cleared regardless
+    authApi.logout().catch((err) => console.error('Logout failed:', err));
     setToken(null);
     setUser(null);
     setError(null);
     localStorage.removeItem('token');
+    localStorage.removeItem('refresh_token');
     localStorage.removeItem('user');
   };
 
@@ -150,6 +169,7 @@ export const AuthProvider: React.FC<{ children: React.ReactNode }> = ({ children
         token, 
         login, 
         register, 
+        loginTwoFactor,
         logout, 
         isAuthenticated: !!token,
         isLoading, 
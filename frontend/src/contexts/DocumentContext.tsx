@@ -2,12 +2,35 @@ import React, { createContext, useContext, useState, useEffect } from 'react';
 import { documentApi } from '../lib/api';
 import { useAuth } from './AuthContext';
 
+interface PdfInfo {
+  version: string;
+  encrypted: boolean;
+  linearized: boolean;
+  title: string | null;
+  author: string | null;
+  subject: string | null;
+  keywords: string | null;
+  creator: string | null;
+  producer: string | null;
+  created_at: string | null;
+  modified_at: string | null;
+}
+
 interface Document {
   id: number;
+  folder_id: number | null;
   filename: string;
   file_size: number;
   mime_type: string;
-  s3_key: string;
+  content_hash: string | null;
+  title: string | null;
+  description: string | null;
+  author: string | null;
+  metadata: Record<string, string>;
+  tags: string[];
+  text_status: 'pending' | 'completed' | 'failed' | 'unsupported';
+  page_count: number | null;
+  pdf_info: PdfInfo | null;
   created_at: string;
   updated_at: string;
 }
@@ -57,7 +80,7 @@ export const DocumentProvider: React.FC<{ children: React.ReactNode }> = ({ chil
     setError(null);
     try {
       const response = await documentApi.list();
-      setDocuments(response);
+      setDocuments(response.documents);
     } catch (err) {
       setError('Failed to fetch documents');
       console.error(err);
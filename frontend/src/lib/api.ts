@@ -2,6 +2,8 @@ import axios from 'axios';
 
 interface AuthResponse {
   token: string;
+  refresh_token: string;
+  expires_in: number;
   user: {
     id: number;
     email: string;
@@ -9,6 +11,17 @@ interface AuthResponse {
   };
 }
 
+interface TwoFactorChallenge {
+  two_factor_required: true;
+  challenge_token: string;
+}
+
+const storeAuth = (data: AuthResponse) => {
+  localStorage.setItem('token', data.token);
+  localStorage.setItem('refresh_token', data.refresh_token);
+  localStorage.setItem('user', JSON.stringify(data.user));
+};
+
 // Set API URL with fallback for development
 const API_URL = import.meta.env.VITE_API_URL || 'http://localhost:8080/api';
 
@@ -28,18 +41,54 @@ api.interceptors.request.use((config) => {
   return config;
 });
 
+// Exchange the stored refresh token for a new token pair. Concurrent 401s
+// share one request, since each refresh token can only be used once.
+let refreshPromise: Promise<string> | null = null;
+
+const refreshAccessToken = (): Promise<string> => {
+  if (!refreshPromise) {
+    const refreshToken = localStorage.getItem('refresh_token');
+    refreshPromise = (refreshToken
+      ? axios
+          .post(`${API_URL}/auth/refresh`, { refresh_token: refreshToken })
+          .then((response) => {
+            localStorage.setItem('token', response.data.token);
+            localStorage.setItem('refresh_token', response.data.refresh_token);
+            return response.data.token as string;
+          })
+      : Promise.reject(new Error('No refresh token'))
+    ).finally(() => {
+      refreshPromise = null;
+    });
+  }
+  return refreshPromise;
+};
+
 // Add error interceptor
 api.interceptors.response.use(
   (response) => response,
-  (error) => {
+  async (error) => {
+    const originalRequest = error.config;
+    if (error.response?.status === 401 && originalRequest && !originalRequest._retry) {
+      originalRequest._retry = true;
+      try {
+        const token = await refreshAccessToken();
+        originalRequest.headers.Authorization = `Bearer ${token}`;
+        return api(originalRequest);
+      } catch {
+        // Fall through to the regular 401 handling below
+      }
+    }
+
     if (error.response) {
       // Get error message from response data
       const errorMessage = error.response.data?.message || error.response.data?.error || error.response.statusText;
       
       switch (error.response.status) {
         case 401:
-          // Handle unauthorized (token expired/invalid)
+          // Handle unauthorized (session expired/revoked)
           localStorage.removeItem('token');
+          localStorage.removeItem('refresh_token');
           localStorage.removeItem('user');
           if (!window.location.pathname.includes('/login')) {
             window.location.href = '/login';
@@ -67,25 +116,84 @@ api.interceptors.response.use(
 
 // Auth API
 export const authApi = {
-  login: async (email: string, password: string): Promise<AuthResponse> => {
-    const response = await api.post<AuthResponse>('/auth/login', { email, password });
-    // Store auth data immediately after successful login
-    localStorage.setItem('token', response.data.token);
-    localStorage.setItem('user', JSON.stringify(response.data.user));
+  login: async (email: string, password: string): Promise<AuthResponse | TwoFactorChallenge> => {
+    const response = await api.post<AuthResponse | TwoFactorChallenge>('/auth/login', { email, password });
+    // Store auth data immediately after successful login; accounts with
+    // two-factor authentication get a challenge for loginTwoFactor instead
+    if (!('two_factor_required' in response.data)) {
+      storeAuth(response.data);
+    }
+    return response.data;
+  },
+
+  loginTwoFactor: async (challengeToken: string, code: string): Promise<AuthResponse> => {
+    const response = await api.post<AuthResponse>('/auth/login/2fa', {
+      challenge_token: challengeToken,
+      code,
+    });
+    storeAuth(response.data);
     return response.data;
   },
   
-  register: async (name: string, email: string, password: string): Promise<AuthResponse> => {
-    const response = await api.post<AuthResponse>('/auth/register', { 
+  register: async (name: string, email: string, password: string): Promise<{ message: string }> => {
+    // No tokens yet: the account can log in once its email address is verified
+    const response = await api.post<{ message: string }>('/auth/register', {
       email, 
       password, 
       full_name: name 
     });
-    // Store auth data immediately after successful registration
-    localStorage.setItem('token', response.data.token);
-    localStorage.setItem('user', JSON.stringify(response.data.user));
     return response.data;
   },
+
+  verifyEmail: async (token: string) => {
+    const response = await api.post<{ message: string }>('/auth/verify-email', { token });
+    return response.data;
+  },
+
+  confirmEmailChange: async (token: string) => {
+    const response = await api.post<{ message: string }>('/auth/confirm-email', { token });
+    return response.data;
+  },
+
+  resendVerification: async (email: string) => {
+    const response = await api.post<{ message: string }>('/auth/resend-verification', { email });
+    return response.data;
+  },
+
+  forgotPassword: async (email: string) => {
+    const response = await api.post<{ message: string }>('/auth/forgot-password', { email });
+    return response.data;
+  },
+
+  resetPassword: async (token: string, password: string) => {
+    const response = await api.post<{ message: string }>('/auth/reset-password', { token, password });
+    return response.data;
+  },
+
+  oidcProviders: async (): Promise<string[]> => {
+    const response = await api.get<{ providers: string[] }>('/auth/oidc/providers');
+    return response.data.providers;
+  },
+
+  oidcAuthorize: async (provider: string): Promise<string> => {
+    const response = await api.post<{ authorization_url: string }>(`/auth/oidc/${provider}/authorize`);
+    return response.data.authorization_url;
+  },
+
+  oidcCallback: async (state: string, code: string): Promise<AuthResponse | TwoFactorChallenge | { linked: true }> => {
+    const response = await api.post<AuthResponse | TwoFactorChallenge | { linked: true }>('/auth/oidc/callback', { state, code });
+    if ('token' in response.data) {
+      storeAuth(response.data);
+    }
+    return response.data;
+  },
+
+  logout: async () => {
+    const refreshToken = localStorage.getItem('refresh_token');
+    if (refreshToken) {
+      await api.post('/auth/logout', { refresh_token: refreshToken });
+    }
+  },
 };
 
 // Document API
@@ -99,8 +207,13 @@ export const documentApi = {
     return response.data;
   },
 
-  list: async () => {
-    const response = await api.get('/documents');
+  list: async (params: Record<string, string | number> = { per_page: 100 }) => {
+    const response = await api.get('/documents', { params });
+    return response.data;
+  },
+
+  search: async (q: string, page = 1, perPage = 20) => {
+    const response = await api.get('/documents/search', { params: { q, page, per_page: perPage } });
     return response.data;
   },
 
@@ -109,17 +222,133 @@ export const documentApi = {
     return response.data;
   },
 
+  update: async (
+    id: string,
+    changes: {
+      filename?: string;
+      title?: string;
+      description?: string;
+      author?: string;
+      metadata?: Record<string, string>;
+      folder_id?: number | null;
+    }
+  ) => {
+    const response = await api.patch(`/documents/${id}`, changes);
+    return response.data;
+  },
+
   delete: async (id: string) => {
     const response = await api.delete(`/documents/${id}`);
     return response.data;
   },
+
+  thumbnail: async (id: number, size: 'small' | 'medium' | 'large' = 'medium') => {
+    const response = await api.get(`/documents/${id}/thumbnail`, {
+      params: { size, format: 'webp' },
+      responseType: 'blob',
+    });
+    return response.data as Blob;
+  },
+
+  pagePreview: async (id: number, page: number, size: 'small' | 'medium' | 'large' = 'large') => {
+    const response = await api.get(`/documents/${id}/pages/${page}/preview`, {
+      params: { size, format: 'webp' },
+      responseType: 'blob',
+    });
+    return response.data as Blob;
+  },
+};
+
+// Folder API
+export const folderApi = {
+  list: async () => {
+    const response = await api.get('/folders');
+    return response.data;
+  },
+
+  create: async (name: string, parentId?: number) => {
+    const response = await api.post('/folders', { name, parent_id: parentId });
+    return response.data;
+  },
+
+  update: async (id: number, changes: { name?: string; parent_id?: number | null }) => {
+    const response = await api.patch(`/folders/${id}`, changes);
+    return response.data;
+  },
+
+  delete: async (id: number) => {
+    await api.delete(`/folders/${id}`);
+  },
+};
+
+// Tag API
+export const tagApi = {
+  list: async () => {
+    const response = await api.get('/tags');
+    return response.data;
+  },
+
+  create: async (name: string) => {
+    const response = await api.post('/tags', { name });
+    return response.data;
+  },
+
+  rename: async (id: number, name: string) => {
+    const response = await api.patch(`/tags/${id}`, { name });
+    return response.data;
+  },
+
+  delete: async (id: number) => {
+    await api.delete(`/tags/${id}`);
+  },
+
+  apply: async (documentIds: number[], add: string[] = [], remove: string[] = []) => {
+    const response = await api.post('/documents/tags', { document_ids: documentIds, add, remove });
+    return response.data;
+  },
+};
+
+export interface DocumentFilter {
+  tags?: string[];
+  mime_type?: string;
+  min_size?: number;
+  max_size?: number;
+  created_from?: string;
+  created_to?: string;
+}
+
+// Smart collection API
+export const collectionApi = {
+  list: async () => {
+    const response = await api.get('/collections');
+    return response.data;
+  },
+
+  create: async (name: string, filter: DocumentFilter) => {
+    const response = await api.post('/collections', { name, filter });
+    return response.data;
+  },
+
+  update: async (id: number, changes: { name?: string; filter?: DocumentFilter }) => {
+    const response = await api.patch(`/collections/${id}`, changes);
+    return response.data;
+  },
+
+  delete: async (id: number) => {
+    await api.delete(`/collections/${id}`);
+  },
+
+  documents: async (id: number, params?: Record<string, string | number>) => {
+    const response = await api.get(`/collections/${id}/documents`, { params });
+    return response.data;
+  },
 };
 
 // Payment API
 export const paymentApi = {
-  request: async (amount: string, phoneNumber: string, payerMessage: string, payeeNote: string) => {
+  request: async (plan: string, phoneNumber: string, payerMessage: string, payeeNote: string) => {
     const response = await api.post('/payments/request', {
-      amount,
+      plan,
       phone_number: phoneNumber,
       payer_message: payerMessage,
       payee_note: payeeNote,
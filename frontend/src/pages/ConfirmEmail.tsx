@@ -0,0 +1,71 @@
+import { useEffect, useRef, useState } from 'react';
+import { Link, useSearchParams } from 'react-router-dom';
+import { Book } from 'lucide-react';
+import { Button } from '@/components/ui/button';
+import { authApi } from '@/lib/api';
+
+type Status = 'confirming' | 'confirmed' | 'failed';
+
+const ConfirmEmail = () => {
+  const [searchParams] = useSearchParams();
+  const [status, setStatus] = useState<Status>('confirming');
+  const [message, setMessage] = useState('');
+  const requested = useRef(false);
+
+  useEffect(() => {
+    // Tokens are single-use, so make sure StrictMode does not send it twice
+    if (requested.current) return;
+    requested.current = true;
+
+    const token = searchParams.get('token');
+    if (!token) {
+      setStatus('failed');
+      setMessage('This confirmation link is incomplete.');
+      return;
+    }
+
+    authApi
+      .confirmEmailChange(token)
+      .then(() => setStatus('confirmed'))
+      .catch((err) => {
+        setStatus('failed');
+        setMessage(err.message || 'This confirmation link is invalid or has expired.');
+      });
+  }, [searchParams]);
+
+  return (
+    <div className="flex min-h-screen flex-col items-center justify-center bg-gradient-to-b from-white to-shelf-50 p-4 dark:from-gray-900 dark:to-gray-800">
+      <div className="w-full max-w-md text-center">
+        <Link to="/" className="mb-4 inline-flex items-center gap-2">
+          <Book className="h-10 w-10 text-shelf-400" />
+          <span className="text-3xl font-bold">Shelf</span>
+        </Link>
+
+        {status === 'confirming' && (
+          <p className="mt-4 text-muted-foreground">Confirming your new email address...</p>
+        )}
+
+        {status === 'confirmed' && (
+          <>
+            <h1 className="mt-4 text-3xl font-bold">Email address updated</h1>
+            <p className="mt-2 text-muted-foreground">Use your new address the next time you sign in.</p>
+            <Button asChild className="mt-6 bg-shelf-400 hover:bg-shelf-600">
+              <Link to="/dashboard">Continue</Link>
+            </Button>
+          </>
+        )}
+
+        {status === 'failed' && (
+          <>
+            <h1 className="mt-4 text-3xl font-bold">Confirmation failed</h1>
+            <div className="mt-4 rounded-md bg-red-50 p-4 text-red-600 dark:bg-red-900/30 dark:text-red-400">
+              {message}
+            </div>
+          </>
+        )}
+      </div>
+    </div>
+  );
+};
+
+export default ConfirmEmail;
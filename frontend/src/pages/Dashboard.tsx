@@ -82,8 +82,11 @@ const Dashboard = () => {
   }, [toast, documents]);
   
   const filteredDocuments = documents.filter(doc => 
-    doc.filename.toLowerCase().includes(searchQuery.toLowerCase())
+    (doc.title || doc.filename).toLowerCase().includes(searchQuery.toLowerCase())
   );
+
+  const formatPageCount = (pageCount: number | null) =>
+    pageCount ? ` · ${pageCount} ${pageCount === 1 ? 'page' : 'pages'}` : '';
   
   const handleDeleteDocument = async (id: number) => {
     try {
@@ -250,11 +253,11 @@ const Dashboard = () => {
                       to={`/view/${document.id}`}
                       className="hover:text-shelf-600 hover:underline truncate"
                     >
-                      {document.filename}
+                      {document.title || document.filename}
                     </Link>
                   </h3>
                   <div className="mt-2 flex items-center justify-between text-xs text-muted-foreground">
-                    <span>{formatFileSize(document.file_size)}</span>
+                    <span>{formatFileSize(document.file_size)}{formatPageCount(document.page_count)}</span>
                     <span>{format(new Date(document.created_at), 'MMM d, yyyy')}</span>
                   </div>
                 </div>
@@ -293,11 +296,11 @@ const Dashboard = () => {
                                 to={`/view/${document.id}`}
                                 className="hover:text-shelf-600 hover:underline truncate"
                               >
-                                {document.filename}
+                                {document.title || document.filename}
                               </Link>
                             </div>
                             <div className="text-xs text-muted-foreground">
-                              {formatFileSize(document.file_size)}
+                              {formatFileSize(document.file_size)}{formatPageCount(document.page_count)}
                             </div>
                           </div>
                         </div>
@@ -396,7 +399,7 @@ const Dashboard = () => {
                 }}
               >
                 <FileText className="mr-2 h-4 w-4" />
-                <span>{doc.filename}</span>
+                <span>{doc.title || doc.filename}</span>
               </CommandItem>
             ))}
           </CommandGroup>
@@ -0,0 +1,89 @@
+import { useState } from 'react';
+import { Link } from 'react-router-dom';
+import { Book } from 'lucide-react';
+import { Button } from '@/components/ui/button';
+import { Input } from '@/components/ui/input';
+import { Label } from '@/components/ui/label';
+import { authApi } from '@/lib/api';
+
+const ForgotPassword = () => {
+  const [email, setEmail] = useState('');
+  const [isLoading, setIsLoading] = useState(false);
+  const [sent, setSent] = useState(false);
+  const [error, setError] = useState<string | null>(null);
+
+  const handleSubmit = async (e: React.FormEvent) => {
+    e.preventDefault();
+    setIsLoading(true);
+    setError(null);
+
+    try {
+      await authApi.forgotPassword(email);
+      setSent(true);
+    } catch (err) {
+      setError(err instanceof Error ? err.message : 'An unexpected error occurred');
+    } finally {
+      setIsLoading(false);
+    }
+  };
+
+  return (
+    <div className="flex min-h-screen flex-col items-center justify-center bg-gradient-to-b from-white to-shelf-50 p-4 dark:from-gray-900 dark:to-gray-800">
+      <div className="w-full max-w-md">
+        <div className="mb-8 flex flex-col items-center">
+          <Link to="/" className="mb-4 flex items-center gap-2">
+            <Book className="h-10 w-10 text-shelf-400" />
+            <span className="text-3xl font-bold">Shelf</span>
+          </Link>
+          <h1 className="text-center text-3xl font-bold">Reset your password</h1>
+          <p className="mt-2 text-center text-muted-foreground">
+            We will email you a link to choose a new one
+          </p>
+        </div>
+
+        {error && (
+          <div className="mb-4 rounded-md bg-red-50 p-4 text-red-600 dark:bg-red-900/30 dark:text-red-400">
+            {error}
+          </div>
+        )}
+
+        {sent ? (
+          <div className="rounded-md bg-shelf-50 p-4 text-center dark:bg-gray-800">
+            If {email} belongs to an account, a reset link is on its way. It expires in one hour.
+          </div>
+        ) : (
+          <form onSubmit={handleSubmit} className="space-y-4">
+            <div className="space-y-2">
+              <Label htmlFor="email">Email</Label>
+              <Input
+                id="email"
+                type="email"
+                placeholder="you@example.com"
+                value={email}
+                onChange={(e) => setEmail(e.target.value)}
+                required
+                disabled={isLoading}
+              />
+            </div>
+
+            <Button
+              type="submit"
+              className="w-full bg-shelf-400 hover:bg-shelf-600"
+              disabled={isLoading}
+            >
+              {isLoading ? "Sending..." : "Send reset link"}
+            </Button>
+          </form>
+        )}
+
+        <div className="mt-6 text-center">
+          <Link to="/login" className="text-sm text-shelf-600 hover:underline">
+            Back to sign in
+          </Link>
+        </div>
+      </div>
+    </div>
+  );
+};
+
+export default ForgotPassword;
@@ -1,24 +1,57 @@
-import { useState } from 'react';
-import { useNavigate, Link } from 'react-router-dom';
+import { useEffect, useState } from 'react';
+import { useNavigate, useLocation, Link } from 'react-router-dom';
 import { Book } from 'lucide-react';
 import { Button } from '@/components/ui/button';
 import { Input } from '@/components/ui/input';
 import { Label } from '@/components/ui/label';
 import { useAuth } from '@/contexts/AuthContext';
 import { useToast } from '@/components/ui/use-toast';
+import { authApi } from '@/lib/api';
 
 const Login = () => {
   const [email, setEmail] = useState('');
   const [password, setPassword] = useState('');
-  const { login, isLoading, error } = useAuth();
+  const location = useLocation();
+  // Social logins of accounts with 2FA arrive here from /oauth/callback
+  const [challengeToken, setChallengeToken] = useState<string | null>(
+    (location.state as { challengeToken?: string } | null)?.challengeToken ?? null
+  );
+  const [providers, setProviders] = useState<string[]>([]);
+  const [code, setCode] = useState('');
+  const { login, loginTwoFactor, isLoading, error } = useAuth();
   const navigate = useNavigate();
   const { toast } = useToast();
 
+  useEffect(() => {
+    authApi.oidcProviders().then(setProviders).catch(() => setProviders([]));
+  }, []);
+
+  const handleSocialLogin = async (provider: string) => {
+    try {
+      window.location.href = await authApi.oidcAuthorize(provider);
+    } catch (err) {
+      toast({
+        title: "Login failed",
+        description: err instanceof Error ? err.message : `Could not reach ${provider}`,
+        variant: "destructive",
+      });
+    }
+  };
+
   const handleSubmit = async (e: React.FormEvent) => {
     e.preventDefault();
     
     try {
-      await login(email, password);
+      if (challengeToken) {
+        await loginTwoFactor(challengeToken, code);
+      } else {
+        const challenge = await login(email, password);
+        if (challenge) {
+          // Password was right; ask for the authenticator code next
+          setChallengeToken(challenge);
+          return;
+        }
+      }
       toast({
         title: "Login successful",
         description: "Welcome back to Shelf!",
@@ -54,6 +87,26 @@ const Login = () => {
         )}
         
         <form onSubmit={handleSubmit} className="space-y-4">
+          {challengeToken ? (
+            <div className="space-y-2">
+              <Label htmlFor="code">Authentication code</Label>
+              <Input
+                id="code"
+                inputMode="numeric"
+                autoComplete="one-time-code"
+                placeholder="123456"
+                value={code}
+                onChange={(e) => setCode(e.target.value)}
+                required
+                autoFocus
+                disabled={isLoading}
+              />
+              <p className="text-xs text-muted-foreground">
+                Enter the code from your authenticator app, or one of your recovery codes.
+              </p>
+            </div>
+          ) : (
+          <>
           <div className="space-y-2">
             <Label htmlFor="email">Email</Label>
             <Input 
@@ -70,9 +123,9 @@ const Login = () => {
           <div className="space-y-2">
             <div className="flex items-center justify-between">
               <Label htmlFor="password">Password</Label>
-              <a href="#" className="text-xs text-shelf-600 hover:underline">
+              <Link to="/forgot-password" className="text-xs text-shelf-600 hover:underline">
                 Forgot password?
-              </a>
+              </Link>
             </div>
             <Input 
               id="password" 
@@ -84,15 +137,34 @@ const Login = () => {
               disabled={isLoading}
             />
           </div>
+          </>
+          )}
           
           <Button
             type="submit"
             className="w-full bg-shelf-400 hover:bg-shelf-600"
             disabled={isLoading}
           >
-            {isLoading ? "Logging in..." : "Sign in"}
+            {isLoading ? "Logging in..." : challengeToken ? "Verify" : "Sign in"}
           </Button>
         </form>
+
+        {!challengeToken && providers.length > 0 && (
+          <div className="mt-6 space-y-2">
+            <p className="text-center text-sm text-muted-foreground">or continue with</p>
+            {providers.map((provider) => (
+              <Button
+                key={provider}
+                type="button"
+                variant="outline"
+                className="w-full capitalize"
+                onClick={() => handleSocialLogin(provider)}
+              >
+                {provider}
+              </Button>
+            ))}
+          </div>
+        )}
         
         <div className="mt-6 text-center">
           <p className="text-sm text-muted-foreground">
@@ -0,0 +1,65 @@
+import { useEffect, useRef, useState } from 'react';
+import { Link, useNavigate, useSearchParams } from 'react-router-dom';
+import { Book } from 'lucide-react';
+import { authApi } from '@/lib/api';
+
+// Providers redirect here after a social login or after linking a provider.
+const OAuthCallback = () => {
+  const [searchParams] = useSearchParams();
+  const [error, setError] = useState<string | null>(null);
+  const navigate = useNavigate();
+  const requested = useRef(false);
+
+  useEffect(() => {
+    // The state is single-use, so make sure StrictMode does not send it twice
+    if (requested.current) return;
+    requested.current = true;
+
+    const state = searchParams.get('state');
+    const code = searchParams.get('code');
+    if (!state || !code) {
+      setError(searchParams.get('error_description') || searchParams.get('error') || 'Login was cancelled.');
+      return;
+    }
+
+    authApi
+      .oidcCallback(state, code)
+      .then((response) => {
+        if ('two_factor_required' in response) {
+          navigate('/login', { state: { challengeToken: response.challenge_token } });
+        } else if ('linked' in response) {
+          navigate('/dashboard');
+        } else {
+          // Reload so the auth context picks up the stored session
+          window.location.href = '/dashboard';
+        }
+      })
+      .catch((err) => setError(err.message || 'Login failed.'));
+  }, [searchParams, navigate]);
+
+  return (
+    <div className="flex min-h-screen flex-col items-center justify-center bg-gradient-to-b from-white to-shelf-50 p-4 dark:from-gray-900 dark:to-gray-800">
+      <div className="w-full max-w-md text-center">
+        <Link to="/" className="mb-4 inline-flex items-center gap-2">
+          <Book className="h-10 w-10 text-shelf-400" />
+          <span className="text-3xl font-bold">Shelf</span>
+        </Link>
+
+        {error ? (
+          <>
+            <div className="mt-4 rounded-md bg-red-50 p-4 text-red-600 dark:bg-red-900/30 dark:text-red-400">
+              {error}
+            </div>
+            <Link to="/login" className="mt-4 inline-block text-sm text-shelf-600 hover:underline">
+              Back to sign in
+            </Link>
+          </>
+        ) : (
+          <p className="mt-4 text-muted-foreground">Signing you in...</p>
+        )}
+      </div>
+    </div>
+  );
+};
+
+export default OAuthCallback;
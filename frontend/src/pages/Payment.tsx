@@ -115,7 +115,7 @@ const Payment = () => {
     try {
         // Initiate payment request to MTN MoMo via backend
         const response = await api.post('/payments/request', {
-          amount: amount,
+          plan: planId,
           phone_number: phoneNumber,
           payer_message: `Payment for ${planId} plan`,
           payee_note: `Subscription payment for ${planId}`
@@ -174,7 +174,7 @@ const Payment = () => {
           </div>
           <div className="flex justify-between mb-6 text-lg font-bold">
             <span>Total:</span>
-            <span>{planId === 'premium' ? '500 XAF' : planId === 'enterprise' ? '1000 XAF' : '100 XAF'}</span>
+            <span>{amount} XAF</span>
           </div>
 
           <Button 
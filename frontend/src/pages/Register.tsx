@@ -32,9 +32,9 @@ const Register = () => {
       await register(name, email, password);
       toast({
         title: "Registration successful",
-        description: "Welcome to Shelf!",
+        description: "Check your email to verify your address, then sign in.",
       });
-      navigate('/dashboard');
+      navigate('/login');
     } catch (err) {
       // The error is already handled in the AuthContext
     }
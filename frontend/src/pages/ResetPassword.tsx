@@ -0,0 +1,110 @@
+import { useState } from 'react';
+import { Link, useNavigate, useSearchParams } from 'react-router-dom';
+import { Book } from 'lucide-react';
+import { Button } from '@/components/ui/button';
+import { Input } from '@/components/ui/input';
+import { Label } from '@/components/ui/label';
+import { useToast } from '@/components/ui/use-toast';
+import { authApi } from '@/lib/api';
+
+const ResetPassword = () => {
+  const [searchParams] = useSearchParams();
+  const [password, setPassword] = useState('');
+  const [confirmPassword, setConfirmPassword] = useState('');
+  const [isLoading, setIsLoading] = useState(false);
+  const [error, setError] = useState<string | null>(null);
+  const navigate = useNavigate();
+  const { toast } = useToast();
+  const token = searchParams.get('token');
+
+  const handleSubmit = async (e: React.FormEvent) => {
+    e.preventDefault();
+
+    if (password !== confirmPassword) {
+      setError('Passwords do not match');
+      return;
+    }
+    if (!token) {
+      setError('This reset link is incomplete.');
+      return;
+    }
+
+    setIsLoading(true);
+    setError(null);
+
+    try {
+      await authApi.resetPassword(token, password);
+      toast({
+        title: "Password updated",
+        description: "Sign in with your new password.",
+      });
+      navigate('/login');
+    } catch (err) {
+      setError(err instanceof Error ? err.message : 'An unexpected error occurred');
+    } finally {
+      setIsLoading(false);
+    }
+  };
+
+  return (
+    <div className="flex min-h-screen flex-col items-center justify-center bg-gradient-to-b from-white to-shelf-50 p-4 dark:from-gray-900 dark:to-gray-800">
+      <div className="w-full max-w-md">
+        <div className="mb-8 flex flex-col items-center">
+          <Link to="/" className="mb-4 flex items-center gap-2">
+            <Book className="h-10 w-10 text-shelf-400" />
+            <span className="text-3xl font-bold">Shelf</span>
+          </Link>
+          <h1 className="text-center text-3xl font-bold">Choose a new password</h1>
+          <p className="mt-2 text-center text-muted-foreground">
+            You will be signed out on all devices
+          </p>
+        </div>
+
+        {error && (
+          <div className="mb-4 rounded-md bg-red-50 p-4 text-red-600 dark:bg-red-900/30 dark:text-red-400">
+            {error}
+          </div>
+        )}
+
+        <form onSubmit={handleSubmit} className="space-y-4">
+          <div className="space-y-2">
+            <Label htmlFor="password">New password</Label>
+            <Input
+              id="password"
+              type="password"
+              placeholder="••••••••"
+              minLength={8}
+              value={password}
+              onChange={(e) => setPassword(e.target.value)}
+              required
+              disabled={isLoading}
+            />
+          </div>
+
+          <div className="space-y-2">
+            <Label htmlFor="confirmPassword">Confirm password</Label>
+            <Input
+              id="confirmPassword"
+              type="password"
+              placeholder="••••••••"
+              value={confirmPassword}
+              onChange={(e) => setConfirmPassword(e.target.value)}
+              required
+              disabled={isLoading}
+            />
+          </div>
+
+          <Button
+            type="submit"
+            className="w-full bg-shelf-400 hover:bg-shelf-600"
+            disabled={isLoading}
+          >
+            {isLoading ? "Saving..." : "Update password"}
+          </Button>
+        </form>
+      </div>
+    </div>
+  );
+};
+
+export default ResetPassword;
@@ -0,0 +1,102 @@
+import { useEffect, useRef, useState } from 'react';
+import { Link, useSearchParams } from 'react-router-dom';
+import { Book } from 'lucide-react';
+import { Button } from '@/components/ui/button';
+import { Input } from '@/components/ui/input';
+import { authApi } from '@/lib/api';
+
+type Status = 'verifying' | 'verified' | 'failed';
+
+const VerifyEmail = () => {
+  const [searchParams] = useSearchParams();
+  const [status, setStatus] = useState<Status>('verifying');
+  const [message, setMessage] = useState('');
+  const [email, setEmail] = useState('');
+  const [resent, setResent] = useState(false);
+  const requested = useRef(false);
+
+  useEffect(() => {
+    // Tokens are single-use, so make sure StrictMode does not send it twice
+    if (requested.current) return;
+    requested.current = true;
+
+    const token = searchParams.get('token');
+    if (!token) {
+      setStatus('failed');
+      setMessage('This verification link is incomplete.');
+      return;
+    }
+
+    authApi
+      .verifyEmail(token)
+      .then(() => setStatus('verified'))
+      .catch((err) => {
+        setStatus('failed');
+        setMessage(err.message || 'This verification link is invalid or has expired.');
+      });
+  }, [searchParams]);
+
+  const handleResend = async (e: React.FormEvent) => {
+    e.preventDefault();
+    try {
+      await authApi.resendVerification(email);
+      setResent(true);
+    } catch (err) {
+      setMessage(err instanceof Error ? err.message : 'An unexpected error occurred');
+    }
+  };
+
+  return (
+    <div className="flex min-h-screen flex-col items-center justify-center bg-gradient-to-b from-white to-shelf-50 p-4 dark:from-gray-900 dark:to-gray-800">
+      <div className="w-full max-w-md text-center">
+        <Link to="/" className="mb-4 inline-flex items-center gap-2">
+          <Book className="h-10 w-10 text-shelf-400" />
+          <span className="text-3xl font-bold">Shelf</span>
+        </Link>
+
+        {status === 'verifying' && (
+          <p className="mt-4 text-muted-foreground">Verifying your email address...</p>
+        )}
+
+        {status === 'verified' && (
+          <>
+            <h1 className="mt-4 text-3xl font-bold">Email verified</h1>
+            <p className="mt-2 text-muted-foreground">Your account is ready to use.</p>
+            <Button asChild className="mt-6 bg-shelf-400 hover:bg-shelf-600">
+              <Link to="/login">Sign in</Link>
+            </Button>
+          </>
+        )}
+
+        {status === 'failed' && (
+          <>
+            <h1 className="mt-4 text-3xl font-bold">Verification failed</h1>
+            <div className="mt-4 rounded-md bg-red-50 p-4 text-red-600 dark:bg-red-900/30 dark:text-red-400">
+              {message}
+            </div>
+            {resent ? (
+              <p className="mt-4 text-sm text-muted-foreground">
+                If {email} belongs to an unverified account, a new link is on its way.
+              </p>
+            ) : (
+              <form onSubmit={handleResend} className="mt-6 flex gap-2">
+                <Input
+                  type="email"
+                  placeholder="you@example.com"
+                  value={email}
+                  onChange={(e) => setEmail(e.target.value)}
+                  required
+                />
+                <Button type="submit" className="bg-shelf-400 hover:bg-shelf-600">
+                  Send a new link
+                </Button>
+              </form>
+            )}
+          </>
+        )}
+      </div>
+    </div>
+  );
+};
+
+export default VerifyEmail;
@@ -203,10 +203,6 @@ const ViewPDF = () => {
                 <h4 className="text-sm font-medium text-muted-foreground">Pages</h4>
                 <p>{totalPages}</p>
               </div>
-              <div>
-                <h4 className="text-sm font-medium text-muted-foreground">Storage Location</h4>
-                <p className="break-all text-sm">{document.s3_key}</p>
-              </div>
             </div>
           </div>
         )}
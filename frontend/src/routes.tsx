@@ -8,6 +8,11 @@ import Dashboard from "./pages/Dashboard";
 import ViewPDF from "./pages/ViewPDF";
 import Login from "./pages/Login";
 import Register from "./pages/Register";
+import VerifyEmail from "./pages/VerifyEmail";
+import ConfirmEmail from "./pages/ConfirmEmail";
+import ForgotPassword from "./pages/ForgotPassword";
+import ResetPassword from "./pages/ResetPassword";
+import OAuthCallback from "./pages/OAuthCallback";
 import Subscription from '@/pages/Subscription';
 import Payment from '@/pages/Payment';
 import MainLayout from "./components/layouts/MainLayout";
@@ -26,6 +31,11 @@ export default function AppRoutes() {
             <Route path="/" element={<MainLayout><Index /></MainLayout>} />
             <Route path="/login" element={<Login />} />
             <Route path="/register" element={<Register />} />
+            <Route path="/verify-email" element={<VerifyEmail />} />
+            <Route path="/confirm-email" element={<ConfirmEmail />} />
+            <Route path="/forgot-password" element={<ForgotPassword />} />
+            <Route path="/reset-password" element={<ResetPassword />} />
+            <Route path="/oauth/callback" element={<OAuthCallback />} />
             <Route 
               path="/dashboard" 
               element={